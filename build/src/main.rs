@@ -21,9 +21,13 @@ enum BuildCommand {
         #[structopt(long)]
         target: BuildTarget,
 
-        /// The test-kind (unit, integration, tarpaulin)
+        /// The test-kind (unit, integration, tarpaulin, compute-units)
         #[structopt(long)]
         test_kind: TestKind,
+
+        /// Reduces the MT height
+        #[structopt(long)]
+        small_mt: bool,
     },
 }
 
@@ -48,6 +52,7 @@ enum TestKind {
     Unit,
     Integration,
     Tarpaulin,
+    ComputeUnits,
 }
 
 fn main() {
@@ -69,7 +74,11 @@ fn main() {
                 _ => {}
             }
         }
-        BuildCommand::Test { target, test_kind } => {
+        BuildCommand::Test {
+            target,
+            test_kind,
+            small_mt,
+        } => {
             build_target = target;
 
             match test_kind {
@@ -89,6 +98,16 @@ fn main() {
                     build_args = vec!["--lib"];
                     features.push("test-unit");
                 }
+                TestKind::ComputeUnits => {
+                    command = "test-bpf";
+                    build_args = vec!["--test", "*"];
+                    use_bpf = true;
+                    features.push("test-bpf,compute-unit-profiling");
+                }
+            }
+
+            if small_mt {
+                features.push("small-mt");
             }
         }
     }
@@ -110,9 +129,11 @@ fn main() {
     } else {
         vec![]
     };
-    if !features.is_empty() {
-        features.insert(0, "--features");
-    }
+    let features = if features.is_empty() {
+        vec![]
+    } else {
+        vec!["--features".to_string(), features.join(",")]
+    };
 
     let exit_code = Command::new("cargo")
         .arg(command)
@@ -471,6 +471,109 @@ impl Stmt {
         }
     }
 
+    /// Generates the stmt as plain (non-partial) code, used as reference implementation for the partial version
+    /// - all scopes are executed in a single call, so no RAM is involved
+    /// - vars are always declared mutable, since (non-mut) declarations can be assigned to in later scopes
+    pub fn to_monolithic_stream(&self) -> TokenStream {
+        match self {
+            Stmt::Collection(stmts) => {
+                let mut stream = quote! {};
+                for stmt in stmts {
+                    stream.extend(stmt.to_monolithic_stream());
+                }
+                stream
+            }
+
+            Stmt::IfElse(cond, t, f) => {
+                let cond: TokenStream = cond.into();
+                let body_true = t.to_monolithic_stream();
+
+                match f {
+                    Some(f) => {
+                        let body_false = f.to_monolithic_stream();
+                        quote! {
+                            if (#cond) {
+                                #body_true
+                            } else {
+                                #body_false
+                            }
+                        }
+                    }
+                    None => quote! {
+                        if (#cond) {
+                            #body_true
+                        }
+                    },
+                }
+            }
+
+            Stmt::For(SingleId(iter_id), SingleId(var_id), Expr::Array(arr), child) => {
+                let iterations = arr.len();
+                let iter_id: TokenStream = iter_id.parse().unwrap();
+                let var_id: TokenStream = var_id.parse().unwrap();
+                let arr: TokenStream = Expr::Array(arr.clone()).into();
+                let child_body = child.to_monolithic_stream();
+
+                quote! {
+                    for #iter_id in 0..#iterations {
+                        let #var_id = vec!#arr[#iter_id];
+
+                        #child_body
+                    }
+                }
+            }
+
+            Stmt::Partial(
+                SingleId(id),
+                Expr::Fn(Id::Single(SingleId(fn_id)), generics, fn_args),
+                child,
+            ) => {
+                let ident: TokenStream = id.parse().unwrap();
+                let fn_call: TokenStream = Expr::Fn(
+                    Id::Single(SingleId(format!("{}_monolithic", fn_id))),
+                    generics.clone(),
+                    fn_args.clone(),
+                )
+                .into();
+                let child_body = child.to_monolithic_stream();
+
+                quote! {
+                    {
+                        let #ident = #fn_call?;
+                        #child_body
+                    }
+                }
+            }
+
+            Stmt::Let(SingleId(id), _, Type(ty), expr) => {
+                let ident: TokenStream = id.parse().unwrap();
+                let ty: TokenStream = ty.parse().unwrap();
+                let value: TokenStream = expr.into();
+
+                quote! { let mut #ident: #ty = #value; }
+            }
+
+            Stmt::Assign(id, expr) => {
+                let ident: TokenStream = id.to_string().parse().unwrap();
+                let value: TokenStream = expr.into();
+
+                quote! { #ident = #value; }
+            }
+
+            Stmt::Return(expr) => {
+                let value: TokenStream = expr.into();
+
+                quote! { return Ok(#value); }
+            }
+
+            Stmt::ComputeUnitStmt(_cus, stmt) => stmt.to_monolithic_stream(),
+
+            _ => {
+                panic!("Invalid stmt: {:?}", self)
+            }
+        }
+    }
+
     pub fn get_compute_units(&self) -> CUs {
         match self {
             Stmt::ComputeUnitStmt(compute_units, _) => compute_units.clone(),
@@ -659,6 +762,29 @@ impl Stmt {
         }
     }
 
+    /// Returns the names of all partial computations called in a statement
+    pub fn all_partial_computations(&self) -> Vec<String> {
+        match self {
+            Stmt::Collection(s) => s
+                .iter()
+                .map(|s| s.all_partial_computations())
+                .fold(Vec::new(), merge),
+            Stmt::IfElse(_, t, f) => merge(
+                t.all_partial_computations(),
+                match f {
+                    Some(f) => f.all_partial_computations(),
+                    _ => vec![],
+                },
+            ),
+            Stmt::For(_, _, _, s) => s.all_partial_computations(),
+            Stmt::Partial(_, Expr::Fn(Id::Single(SingleId(id)), _, _), s) => {
+                merge(vec![id.clone()], s.all_partial_computations())
+            }
+            Stmt::ComputeUnitStmt(_, s) => s.all_partial_computations(),
+            _ => vec![],
+        }
+    }
+
     /// Returns all expressions in a statement
     pub fn all_exprs(&self) -> Vec<Expr> {
         match self {
@@ -745,4 +871,44 @@ mod tests {
             quote! { match fn_name() { Some(v) => v, None => return Err("Unwrap error") } }
         );
     }
+
+    #[test]
+    fn test_monolithic_stream() {
+        let stmt = Stmt::Collection(vec![
+            Stmt::Let(
+                SingleId(String::from("a")),
+                false,
+                Type(String::from("u32")),
+                Expr::Literal(String::from("1")),
+            ),
+            Stmt::Partial(
+                SingleId(String::from("v")),
+                Expr::Fn(
+                    Id::Single(SingleId(String::from("double"))),
+                    vec![],
+                    vec![
+                        Expr::Id(Id::Single(SingleId(String::from("storage")))),
+                        Expr::Id(Id::Single(SingleId(String::from("a")))),
+                    ],
+                ),
+                Box::new(Stmt::Assign(
+                    Id::Single(SingleId(String::from("a"))),
+                    Expr::Id(Id::Single(SingleId(String::from("v")))),
+                )),
+            ),
+            Stmt::Return(Expr::Id(Id::Single(SingleId(String::from("a"))))),
+        ]);
+
+        assert_eq_stream!(
+            stmt.to_monolithic_stream(),
+            quote! {
+                let mut a: u32 = 1;
+                {
+                    let v = double_monolithic(storage, a,)?;
+                    a = v;
+                }
+                return Ok(a);
+            }
+        );
+    }
 }
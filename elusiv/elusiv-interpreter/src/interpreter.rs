@@ -4,6 +4,7 @@ use proc_macro2::{Group, TokenStream, TokenTree};
 use quote::quote;
 use std::collections::HashMap;
 
+#[allow(clippy::too_many_arguments)]
 pub fn interpret(
     computation: Vec<TokenTree>,
     name: &str,
@@ -12,7 +13,14 @@ pub fn interpret(
     ty: TokenStream,
     previous_computation_rounds: &HashMap<String, usize>,
     previous_compute_units: &HashMap<String, Vec<usize>>,
-) -> (usize, Vec<usize>, TokenStream) {
+    previous_ram_sizes: &HashMap<String, RAMSizes>,
+) -> (usize, Vec<usize>, RAMSizes, TokenStream, TokenStream) {
+    let test = if generics.is_empty() {
+        partial_computation_test(name, parameters.clone())
+    } else {
+        quote! {}
+    };
+
     let groups: Vec<Group> = computation
         .iter()
         .map(|t| {
@@ -115,10 +123,17 @@ pub fn interpret(
         }
     }
 
+    // The monolithic version simply executes all scopes in sequence
+    let mut monolithic_body = quote! {};
+    for scope in &computation.scopes {
+        monolithic_body.extend(scope.stmt.to_monolithic_stream());
+    }
+
     // Construct the match arms by iterating over all scopes
     let mut m = quote! {};
     let mut rounds: usize = 0;
     let mut storage = StorageMappings { store: vec![] };
+    let mut ram_sizes = RAMSizes::default();
     for scope in &computation.scopes {
         let start_rounds = rounds;
         let result = scope
@@ -132,9 +147,24 @@ pub fn interpret(
         for r in &scope.read {
             read.extend(storage.read(r.clone()));
         }
+        ram_sizes.update_heights(&storage);
+
+        // Linear scan over the live ranges of the vars
+        // - in a single-round scope all reads happen before the writes, so the slots of vars that are not used afterwards can be reused directly
+        // - in a multi-round scope vars are read in each round, so their slots can only be reused by the writes of the last round
+        let single_round = result.rounds == 0;
+        if single_round {
+            for f in scope.free.clone() {
+                storage.free(f);
+            }
+        }
         for w in scope.write.clone() {
+            if single_round && scope.free.iter().any(|x| x.id == w.id) {
+                continue;
+            }
             write.extend(storage.write(w));
         }
+        ram_sizes.update_heights(&storage);
 
         let mut ram_in = quote! {};
         let mut ram_out = quote! {};
@@ -146,10 +176,17 @@ pub fn interpret(
                 ram_in.extend(quote! { #name.inc_frame(#height); });
                 ram_out.extend(quote! { #name.dec_frame(#height); });
             }
+
+            // The frames of the called computations are located above the current heights
+            for callee in scope.stmt.all_partial_computations() {
+                for (ty, &size) in &previous_ram_sizes[&callee].0 {
+                    ram_sizes.update(ty, storage.height(ty) + size);
+                }
+            }
         }
 
         // If we free memory and write, we only free in the last iteration and write to different locations
-        if !scope.free.is_empty() {
+        if !single_round && !scope.free.is_empty() {
             let mut write_after_free = quote! {};
             for f in scope.free.clone() {
                 storage.free(f);
@@ -161,19 +198,16 @@ pub fn interpret(
                 }
                 write_after_free.extend(storage.write(w.clone()));
             }
+            ram_sizes.update_heights(&storage);
 
-            if result.rounds == 0 {
-                write = write_after_free;
-            } else {
-                let r = result.rounds - 1;
-                write = quote! {
-                    if round < #r {
-                        #write
-                    } else {
-                        #write_after_free
-                    }
-                };
-            }
+            let r = result.rounds - 1;
+            write = quote! {
+                if round < #r {
+                    #write
+                } else {
+                    #write_after_free
+                }
+            };
         }
 
         let mut pattern = quote! { round if round == #start_rounds };
@@ -231,9 +265,20 @@ pub fn interpret(
     }
 
     let fn_name: TokenStream = format!("{}_partial", name).parse().unwrap();
+    let monolithic_fn_name: TokenStream = format!("{}_monolithic", name).parse().unwrap();
     let rounds_count_name: TokenStream = format!("{}_ROUNDS_COUNT", name.to_uppercase())
         .parse()
         .unwrap();
+    let ram_size_consts = ram_sizes.0.iter().fold(quote! {}, |acc, (ty, size)| {
+        let ram_size_name: TokenStream =
+            format!("{}_RAM_{}_SIZE", name.to_uppercase(), ty.to_uppercase())
+                .parse()
+                .unwrap();
+        quote! {
+            #acc
+            pub const #ram_size_name: usize = #size;
+        }
+    });
 
     // Check that all storage objects have been cleared (required to be able to move back to calling computation)
     for m in storage.store {
@@ -254,8 +299,10 @@ pub fn interpret(
     (
         rounds,
         compute_units,
+        ram_sizes,
         quote! {
             pub const #rounds_count_name: usize = #rounds;
+            #ram_size_consts
 
             #[allow(clippy::double_parens)]
             fn #fn_name #generics (round: usize, #parameters) -> Result<Option<#ty>, ElusivError> {
@@ -265,6 +312,107 @@ pub fn interpret(
                 }
                 Ok(None)
             }
+
+            /// Non-partial version of the computation (reference for testing the partial computation)
+            #[cfg(test)]
+            #[allow(clippy::double_parens, unreachable_code, unused_assignments, unused_mut, unused_variables)]
+            fn #monolithic_fn_name #generics (#parameters) -> Result<#ty, ElusivError> {
+                #monolithic_body
+                Err(PartialComputationError)
+            }
         },
+        test,
     )
 }
+
+enum ParameterKind {
+    Value,
+    Ref,
+    RefMut,
+}
+
+/// Splits the parameters (excluding the leading `storage` parameter) into their ids, (dereferenced) types and kinds
+fn parse_parameters(parameters: TokenStream) -> Vec<(TokenStream, TokenStream, ParameterKind)> {
+    let mut params = vec![vec![]];
+    let mut angle_depth = 0;
+    for t in parameters {
+        if let TokenTree::Punct(p) = &t {
+            match p.as_char() {
+                '<' => angle_depth += 1,
+                '>' => angle_depth -= 1,
+                ',' if angle_depth == 0 => {
+                    params.push(vec![]);
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        params.last_mut().unwrap().push(t);
+    }
+
+    params
+        .into_iter()
+        .filter(|p| !p.is_empty())
+        .skip(1)
+        .map(|p| match &p[..] {
+            [TokenTree::Ident(id), TokenTree::Punct(colon), ty @ ..] if colon.as_char() == ':' => {
+                let id = quote! { #id };
+                match ty {
+                    [TokenTree::Punct(r), TokenTree::Ident(m), ty @ ..]
+                        if r.as_char() == '&' && m == "mut" =>
+                    {
+                        (id, quote! { #(#ty)* }, ParameterKind::RefMut)
+                    }
+                    [TokenTree::Punct(r), ty @ ..] if r.as_char() == '&' => {
+                        (id, quote! { #(#ty)* }, ParameterKind::Ref)
+                    }
+                    ty => (id, quote! { #(#ty)* }, ParameterKind::Value),
+                }
+            }
+            p => panic!("Invalid parameter {:?}", p),
+        })
+        .collect()
+}
+
+/// Test comparing the partial computation with its monolithic version on random inputs
+/// - the inputs are generated with `PartialComputationInput::random`
+/// - the mutable reference parameters are compared after the computation as well
+fn partial_computation_test(name: &str, parameters: TokenStream) -> TokenStream {
+    let test_name: TokenStream = format!("test_{}_partial", name).parse().unwrap();
+    let fn_name: TokenStream = format!("{}_partial", name).parse().unwrap();
+    let monolithic_fn_name: TokenStream = format!("{}_monolithic", name).parse().unwrap();
+    let rounds_count_name: TokenStream = format!("{}_ROUNDS_COUNT", name.to_uppercase())
+        .parse()
+        .unwrap();
+
+    let mut inputs = quote! {};
+    let mut args = quote! {};
+    let mut mutable = quote! {};
+    for (id, ty, kind) in parse_parameters(parameters) {
+        inputs.extend(quote! { let #id: #ty = PartialComputationInput::random(&mut rng); });
+        match kind {
+            ParameterKind::Value => args.extend(quote! { #id, }),
+            ParameterKind::Ref => args.extend(quote! { &#id, }),
+            ParameterKind::RefMut => {
+                args.extend(quote! { #id, });
+                mutable.extend(quote! { #id, });
+            }
+        }
+    }
+
+    quote! {
+        #[test]
+        fn #test_name() {
+            let mut rng = <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(0);
+            for _ in 0..PARTIAL_COMPUTATION_TEST_ITERATIONS {
+                #inputs
+                assert_partial_computation(
+                    #rounds_count_name,
+                    &(#mutable),
+                    |round, storage, (#mutable)| #fn_name(round, storage, #args),
+                    |storage, (#mutable)| #monolithic_fn_name(storage, #args),
+                );
+            }
+        }
+    }
+}
@@ -11,6 +11,7 @@ use proc_macro2::{Delimiter, TokenTree, TokenTree::*};
 use quote::quote;
 use std::collections::HashMap;
 use std::iter::IntoIterator;
+use storage::RAMSizes;
 
 /// For computations that are so costly, that they cannot be performed in a single step
 /// - This macro splits the computation you describe into `n` separate steps, all within a specified compute-unit budget.
@@ -25,6 +26,11 @@ use std::iter::IntoIterator;
 /// - a function `name_partial(round: usize, param_0, .., param_k) -> Result<Option<ReturnType>, &'static str>`
 /// - the count of rounds `NAME_ROUNDS_COUNT: usize` (function calls) required to complete the computation
 /// - this means after `NAME_ROUNDS_COUNT` calls of `name_partial` it will return `Ok(Some(v))` if all went well
+/// - the minimum RAM sizes `NAME_RAM_TYPE_SIZE: usize` for each `Type` stored by the computation (including the frames of all called partial computations)
+///     - the RAM slots are allocated based on the live ranges of the vars (from their declaration up to the last scope they are used in)
+/// - a test-only function `name_monolithic(param_0, .., param_k) -> Result<ReturnType, &'static str>` performing the whole computation in a single call (used as reference for the partial version)
+/// - a test `test_name_partial` for each non-generic computation (in the module `fn_name_partial_computation_tests`) comparing `name_partial` with `name_monolithic` on random inputs
+///     - requires `PARTIAL_COMPUTATION_TEST_ITERATIONS`, `assert_partial_computation` and `PartialComputationInput` (implemented for all parameter types) in the calling module
 /// - **IMPORTANT**: it's the callers responsibility to make sure that if a single step of the computation return `Err(_)` no further computations are performed, otherwise undefined behavior would result
 ///
 /// # Syntax
@@ -104,7 +110,13 @@ fn impl_mult_step_computations(stream: proc_macro2::TokenStream) -> proc_macro2:
         {
             let mut rounds_map = HashMap::new();
             let mut compute_units_map = HashMap::new();
-            let stream = multi_step_computation(tail, &mut rounds_map, &mut compute_units_map);
+            let mut ram_sizes_map = HashMap::new();
+            let (stream, tests) = multi_step_computation(
+                tail,
+                &mut rounds_map,
+                &mut compute_units_map,
+                &mut ram_sizes_map,
+            );
 
             // Create compute unit stream for last partial computation
             let cus = compute_units_map[&fn_name.to_string()].clone();
@@ -117,6 +129,10 @@ fn impl_mult_step_computations(stream: proc_macro2::TokenStream) -> proc_macro2:
             let total_compute_units = optimization.total_compute_units;
             let computation_name: proc_macro2::TokenStream =
                 computation_name.to_string().parse().unwrap();
+            let tests_mod_name: proc_macro2::TokenStream =
+                format!("{}_partial_computation_tests", fn_name)
+                    .parse()
+                    .unwrap();
             let instructions = optimization
                 .instructions
                 .iter()
@@ -127,6 +143,10 @@ fn impl_mult_step_computations(stream: proc_macro2::TokenStream) -> proc_macro2:
                 });
             let tx_count =
                 batched_instructions_tx_count(optimization.instructions.len(), compute_budget);
+            let round_compute_units = cus.iter().fold(quote! {}, |acc, &cus| {
+                let cus: proc_macro2::TokenStream = cus.to_string().parse().unwrap();
+                quote! { #acc #cus, }
+            });
 
             quote! {
                 pub struct #computation_name { }
@@ -136,10 +156,18 @@ fn impl_mult_step_computations(stream: proc_macro2::TokenStream) -> proc_macro2:
                     const INSTRUCTION_ROUNDS: [u8; #size] = [ #instructions ];
                     const TOTAL_ROUNDS: u32 = #total_rounds;
                     const TOTAL_COMPUTE_UNITS: u32 = #total_compute_units;
+                    const ROUND_COMPUTE_UNITS: &'static [u32] = &[ #round_compute_units ];
                     const COMPUTE_BUDGET_PER_IX: u32 = #compute_budget;
                 }
 
                 #stream
+
+                #[cfg(test)]
+                mod #tests_mod_name {
+                    use super::*;
+
+                    #tests
+                }
             }
         }
         _ => panic!("Invalid syntax"),
@@ -150,7 +178,8 @@ fn multi_step_computation(
     input: &[TokenTree],
     previous_computation_rounds: &mut HashMap<String, usize>,
     previous_compute_units: &mut HashMap<String, Vec<usize>>,
-) -> proc_macro2::TokenStream {
+    previous_ram_sizes: &mut HashMap<String, RAMSizes>,
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
     match input {
         // matches: `name{<generics>}(params) -> ty, {computation}`
         [Ident(id), Group(generics), Group(p), Punct(arrow0), Punct(arrow1), Ident(ty), Group(c), tail @ ..] =>
@@ -181,7 +210,7 @@ fn multi_step_computation(
                     _ => quote::quote! {},
                 };
 
-            let (rounds, compute_units, stream) = interpreter::interpret(
+            let (rounds, compute_units, ram_sizes, stream, test) = interpreter::interpret(
                 computation,
                 id,
                 generics,
@@ -189,18 +218,30 @@ fn multi_step_computation(
                 ty,
                 previous_computation_rounds,
                 previous_compute_units,
+                previous_ram_sizes,
             );
             previous_computation_rounds.insert(id.clone(), rounds);
             previous_compute_units
                 .insert(format!("{}_zero", id.clone()), vec![0; compute_units.len()]);
             previous_compute_units.insert(id.clone(), compute_units);
-            let tail =
-                multi_step_computation(tail, previous_computation_rounds, previous_compute_units);
+            previous_ram_sizes.insert(id.clone(), ram_sizes);
+            let (tail, tail_tests) = multi_step_computation(
+                tail,
+                previous_computation_rounds,
+                previous_compute_units,
+                previous_ram_sizes,
+            );
 
-            quote! {
-                #stream
-                #tail
-            }
+            (
+                quote! {
+                    #stream
+                    #tail
+                },
+                quote! {
+                    #test
+                    #tail_tests
+                },
+            )
         }
 
         // matches: `name(params) -> ty, {computation}`
@@ -215,7 +256,7 @@ fn multi_step_computation(
             let params = p.stream();
             let ty = ty.to_string().parse().unwrap();
 
-            let (rounds, compute_units, stream) = interpreter::interpret(
+            let (rounds, compute_units, ram_sizes, stream, test) = interpreter::interpret(
                 computation,
                 id,
                 quote! {},
@@ -223,27 +264,42 @@ fn multi_step_computation(
                 ty,
                 previous_computation_rounds,
                 previous_compute_units,
+                previous_ram_sizes,
             );
             previous_computation_rounds.insert(id.clone(), rounds);
             previous_compute_units
                 .insert(format!("{}_zero", id.clone()), vec![0; compute_units.len()]);
             previous_compute_units.insert(id.clone(), compute_units);
-            let tail =
-                multi_step_computation(tail, previous_computation_rounds, previous_compute_units);
+            previous_ram_sizes.insert(id.clone(), ram_sizes);
+            let (tail, tail_tests) = multi_step_computation(
+                tail,
+                previous_computation_rounds,
+                previous_compute_units,
+                previous_ram_sizes,
+            );
 
-            quote! {
-                #stream
-                #tail
-            }
+            (
+                quote! {
+                    #stream
+                    #tail
+                },
+                quote! {
+                    #test
+                    #tail_tests
+                },
+            )
         }
 
-        [] => {
-            quote! {}
-        }
+        [] => (quote! {}, quote! {}),
         [Punct(comma), tail @ ..] => {
             assert_eq!(comma.to_string(), ",");
 
-            multi_step_computation(tail, previous_computation_rounds, previous_compute_units)
+            multi_step_computation(
+                tail,
+                previous_computation_rounds,
+                previous_compute_units,
+                previous_ram_sizes,
+            )
         }
 
         tree => panic!("Invalid macro input {:?}", tree),
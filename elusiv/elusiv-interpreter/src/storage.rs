@@ -1,5 +1,6 @@
 use proc_macro2::TokenStream;
 use quote::quote;
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone)]
 pub struct MemoryId {
@@ -124,6 +125,14 @@ impl StorageMappings {
         }
     }
 
+    /// Height of the storage mapping of a specific type (zero if the type is not used)
+    pub fn height(&self, ty: &str) -> usize {
+        self.store
+            .iter()
+            .find(|m| m.ty == ty)
+            .map_or(0, |m| m.height())
+    }
+
     fn get_mapping(&mut self, ty: &str) -> &mut StorageMapping {
         if let Some(i) = self.store.iter().position(|m| m.ty == ty) {
             &mut self.store[i]
@@ -201,6 +210,23 @@ impl StorageMapping {
     }
 }
 
+/// Minimum RAM size (in elements) per type required by a computation
+#[derive(Default)]
+pub struct RAMSizes(pub BTreeMap<String, usize>);
+
+impl RAMSizes {
+    pub fn update(&mut self, ty: &str, size: usize) {
+        let s = self.0.entry(String::from(ty)).or_insert(0);
+        *s = std::cmp::max(*s, size);
+    }
+
+    pub fn update_heights(&mut self, storage: &StorageMappings) {
+        for m in &storage.store {
+            self.update(&m.ty, m.height());
+        }
+    }
+}
+
 pub fn ram_name(ty: &str) -> TokenStream {
     format!("storage.ram_{}", ty.to_lowercase())
         .parse::<TokenStream>()
@@ -1,6 +1,13 @@
 use crate::error::ElusivError;
+use crate::processor::current_slot;
 use elusiv_types::BorshSerDeSized;
+use solana_program::program_error::ProgramError;
 
+/// A ring-buffer whose entries are stamped with the slot of their insertion
+///
+/// # Note
+///
+/// Entries are evicted in insertion order once the buffer is full, or precisely with [`RingBuffer::remove`].
 #[allow(clippy::len_without_is_empty)]
 pub trait RingBuffer<'a, N: BorshSerDeSized> {
     const CAPACITY: usize;
@@ -13,25 +20,71 @@ pub trait RingBuffer<'a, N: BorshSerDeSized> {
 
     fn set_value(&mut self, index: usize, value: &N);
 
-    fn contains(&self, value: &N) -> bool;
+    /// The slot in which the entry at `index` has been inserted
+    fn slot(&self, index: usize) -> u64;
+    fn set_slot(&mut self, index: usize, slot: u64);
+
+    /// The index of the oldest entry
+    fn oldest(&self) -> usize {
+        (self.ptr() + Self::CAPACITY - self.len()) % Self::CAPACITY
+    }
+
+    fn contains(&self, value: &N) -> bool {
+        self.find_position(value).is_some()
+    }
+
     fn find_position(&self, value: &N) -> Option<usize>;
 
-    fn push(&mut self, value: &N) {
+    /// Removes the entry at `index`, the remaining entries are kept in insertion order
+    fn remove(&mut self, index: usize);
+
+    /// Returns true if the entry at `index` has been inserted more than `slots` slots before `current_slot`
+    fn is_expired(&self, index: usize, slots: u64, current_slot: u64) -> bool {
+        self.slot(index).saturating_add(slots) < current_slot
+    }
+
+    /// Returns the position of `value`, if it has been inserted within the last `slots` slots before `current_slot`
+    fn find_position_within(&self, value: &N, slots: u64, current_slot: u64) -> Option<usize> {
+        self.find_position(value)
+            .filter(|&index| !self.is_expired(index, slots, current_slot))
+    }
+
+    /// Returns true if `value` has been inserted within the last `slots` slots
+    fn contains_within(&self, value: &N, slots: u64) -> Result<bool, ProgramError> {
+        Ok(self
+            .find_position_within(value, slots, current_slot()?)
+            .is_some())
+    }
+
+    fn push(&mut self, value: &N, slot: u64) {
         let ptr = self.ptr() % Self::CAPACITY;
         self.set_ptr((ptr + 1) % Self::CAPACITY);
 
         self.set_value(ptr, value);
+        self.set_slot(ptr, slot);
 
         let len = self.len();
         self.set_len(std::cmp::min(len + 1, Self::CAPACITY))
     }
 
-    fn try_insert(&mut self, value: &N) -> Result<(), ElusivError> {
-        if self.contains(value) {
+    /// Inserts `value` at `current_slot`, if it has not been inserted within the last `slots` slots
+    ///
+    /// # Note
+    ///
+    /// An expired entry of `value` is removed, so a value is never stored twice.
+    fn try_insert(&mut self, value: &N, slots: u64, current_slot: u64) -> Result<(), ElusivError> {
+        if self
+            .find_position_within(value, slots, current_slot)
+            .is_some()
+        {
             return Err(ElusivError::DuplicateValue);
         }
 
-        self.push(value);
+        if let Some(index) = self.find_position(value) {
+            self.remove(index);
+        }
+
+        self.push(value, current_slot);
 
         Ok(())
     }
@@ -39,8 +92,12 @@ pub trait RingBuffer<'a, N: BorshSerDeSized> {
 
 macro_rules! buffer_account {
     ($ident: ident, $ty: ty, $size: expr $(,)?) => {
+        /// # Versions
+        ///
+        /// - `0`: values without insertion slots
+        /// - `1`: added `slots`
         #[allow(dead_code)]
-        #[crate::macros::elusiv_account]
+        #[crate::macros::elusiv_account(version: 1)]
         pub struct $ident {
             #[no_getter]
             #[no_setter]
@@ -50,11 +107,40 @@ macro_rules! buffer_account {
             values: [$ty; $size as usize],
             length: u32,
             pointer: u32,
+
+            /// The insertion slot of each value
+            slots: [u64; $size as usize],
         }
 
         #[cfg(test)]
         const_assert!($size < u32::MAX as usize);
 
+        impl<'a> elusiv_types::accounts::MigratableAccount for $ident<'a> {
+            fn size_at_version(version: u8) -> Option<usize> {
+                match version {
+                    0 => Some(<Self as elusiv_types::SizedAccount>::SIZE - $size * 8),
+                    1 => Some(<Self as elusiv_types::SizedAccount>::SIZE),
+                    _ => None,
+                }
+            }
+
+            fn migrate_from(
+                version: u8,
+                data: &mut [u8],
+            ) -> solana_program::entrypoint::ProgramResult {
+                match version {
+                    // Legacy values never expire and are only evicted by the ring
+                    0 => {
+                        let offset = Self::size_at_version(0).unwrap();
+                        data[offset..<Self as elusiv_types::SizedAccount>::SIZE].fill(u8::MAX);
+                        Ok(())
+                    }
+
+                    _ => Err(solana_program::program_error::ProgramError::InvalidAccountData),
+                }
+            }
+        }
+
         impl<'a> crate::buffer::RingBuffer<'a, $ty> for $ident<'a> {
             const CAPACITY: usize = $size;
 
@@ -78,29 +164,61 @@ macro_rules! buffer_account {
                 self.set_values(index, value);
             }
 
-            fn contains(&self, value: &$ty) -> bool {
-                let len = self.len();
-                if len == 0 {
-                    return false;
-                }
+            fn slot(&self, index: usize) -> u64 {
+                self.get_slots(index)
+            }
 
-                crate::bytes::contains(
-                    value,
-                    &self.values[..len * <$ty as elusiv_types::bytes::BorshSerDeSized>::SIZE],
-                )
+            fn set_slot(&mut self, index: usize, slot: u64) {
+                self.set_slots(index, &slot)
             }
 
             fn find_position(&self, value: &$ty) -> Option<usize> {
+                const SIZE: usize = <$ty as elusiv_types::bytes::BorshSerDeSized>::SIZE;
+
                 let len = self.len();
                 if len == 0 {
                     return None;
                 }
 
+                // The entries are stored in `oldest..oldest + len` (wrapping around at the capacity)
+                let oldest = self.oldest();
+                let head = std::cmp::min(len, Self::CAPACITY - oldest);
                 crate::bytes::find(
                     value,
-                    &self.values[..len * <$ty as elusiv_types::bytes::BorshSerDeSized>::SIZE],
-                    len,
+                    &self.values[oldest * SIZE..(oldest + head) * SIZE],
+                    head,
                 )
+                .map(|i| oldest + i)
+                .or_else(|| {
+                    crate::bytes::find(value, &self.values[..(len - head) * SIZE], len - head)
+                })
+            }
+
+            fn remove(&mut self, index: usize) {
+                const SIZE: usize = <$ty as elusiv_types::bytes::BorshSerDeSized>::SIZE;
+
+                let len = self.len();
+                assert!((index + Self::CAPACITY - self.oldest()) % Self::CAPACITY < len);
+
+                // All newer entries are shifted back by one position
+                let end = self.ptr() % Self::CAPACITY;
+                let mut position = index;
+                let mut next = (position + 1) % Self::CAPACITY;
+                while next != end {
+                    self.values
+                        .copy_within(next * SIZE..(next + 1) * SIZE, position * SIZE);
+                    let slot = self.slot(next);
+                    self.set_slot(position, slot);
+
+                    position = next;
+                    next = (next + 1) % Self::CAPACITY;
+                }
+
+                self.values[position * SIZE..(position + 1) * SIZE].fill(0);
+                self.set_slot(position, 0);
+
+                self.set_len(len - 1);
+                self.set_ptr(position);
             }
         }
     };
@@ -111,7 +229,9 @@ pub(crate) use buffer_account;
 #[cfg(test)]
 mod test {
     use crate::{buffer::RingBuffer, error::ElusivError, macros::zero_program_account};
-    use elusiv_types::accounts::PDAAccountData;
+    use elusiv_types::BorshSerDeSized;
+    use elusiv_types::accounts::{MigratableAccount, PDAAccountData};
+    use elusiv_types::{ProgramAccount, SizedAccount, VersionedAccount};
 
     const TEST_BUFFER_ACCOUNT_SIZE: usize = 128;
     buffer_account!(TestBufferAccount, u32, TEST_BUFFER_ACCOUNT_SIZE);
@@ -140,7 +260,7 @@ mod test {
 
         for i in 0..TestBufferAccount::CAPACITY as u32 {
             assert!(buffer.find_position(&i).is_none());
-            buffer.try_insert(&i).unwrap();
+            buffer.try_insert(&i, u64::MAX, 0).unwrap();
             assert_eq!(buffer.find_position(&i).unwrap(), i as usize);
         }
     }
@@ -153,13 +273,13 @@ mod test {
         assert_eq!(buffer.ptr(), 0);
 
         for i in 1..=TestBufferAccount::CAPACITY {
-            buffer.push(&(i as u32));
+            buffer.push(&(i as u32), 0);
 
             assert_eq!(buffer.len(), i);
             assert_eq!(buffer.ptr(), i % TestBufferAccount::CAPACITY);
         }
 
-        buffer.push(&0);
+        buffer.push(&0, 0);
         assert_eq!(buffer.len(), TestBufferAccount::CAPACITY);
         assert_eq!(buffer.ptr(), 1);
     }
@@ -169,7 +289,7 @@ mod test {
         zero_program_account!(mut buffer, TestBufferAccount);
 
         for i in 1..=TestBufferAccount::CAPACITY {
-            assert_eq!(buffer.try_insert(&(i as u32)), Ok(()));
+            assert_eq!(buffer.try_insert(&(i as u32), u64::MAX, 0), Ok(()));
 
             assert_eq!(buffer.len(), i);
             assert_eq!(buffer.ptr(), i % TestBufferAccount::CAPACITY);
@@ -179,7 +299,7 @@ mod test {
         let values = buffer.values.to_vec();
         for i in 1..=TestBufferAccount::CAPACITY {
             assert_eq!(
-                buffer.try_insert(&(i as u32)),
+                buffer.try_insert(&(i as u32), u64::MAX, 0),
                 Err(ElusivError::DuplicateValue)
             );
 
@@ -192,7 +312,7 @@ mod test {
         for i in 1..=TestBufferAccount::CAPACITY {
             assert!(buffer.contains(&(i as u32)));
             assert_eq!(
-                buffer.try_insert(&((i + TestBufferAccount::CAPACITY) as u32)),
+                buffer.try_insert(&((i + TestBufferAccount::CAPACITY) as u32), u64::MAX, 0),
                 Ok(())
             );
             assert!(!buffer.contains(&(i as u32)));
@@ -201,4 +321,147 @@ mod test {
             assert_eq!(buffer.ptr(), i % TestBufferAccount::CAPACITY);
         }
     }
+
+    #[test]
+    fn test_try_insert_expired() {
+        zero_program_account!(mut buffer, TestBufferAccount);
+
+        buffer.try_insert(&1, 10, 100).unwrap();
+        assert_eq!(buffer.slot(0), 100);
+
+        // Within the window
+        for slot in [100, 105, 110] {
+            assert_eq!(
+                buffer.try_insert(&1, 10, slot),
+                Err(ElusivError::DuplicateValue)
+            );
+        }
+        assert_eq!(buffer.len(), 1);
+
+        // The expired entry is replaced
+        buffer.try_insert(&2, 10, 105).unwrap();
+        buffer.try_insert(&1, 10, 111).unwrap();
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.find_position(&2), Some(0));
+        assert_eq!(buffer.find_position(&1), Some(1));
+        assert_eq!(buffer.slot(1), 111);
+    }
+
+    #[test]
+    fn test_contains_within() {
+        zero_program_account!(mut buffer, TestBufferAccount);
+
+        buffer.push(&1, 0);
+        buffer.push(&2, 50);
+
+        // The clock is not available in unit tests (slot zero)
+        assert!(buffer.contains_within(&1, 0).unwrap());
+        assert!(buffer.contains_within(&2, 0).unwrap());
+        assert!(!buffer.contains_within(&3, u64::MAX).unwrap());
+
+        // Window boundaries
+        assert_eq!(buffer.find_position_within(&1, 10, 0), Some(0));
+        assert_eq!(buffer.find_position_within(&1, 10, 10), Some(0));
+        assert_eq!(buffer.find_position_within(&1, 10, 11), None);
+        assert_eq!(buffer.find_position_within(&2, 10, 60), Some(1));
+        assert_eq!(buffer.find_position_within(&2, 10, 61), None);
+        assert_eq!(buffer.find_position_within(&2, 0, 50), Some(1));
+        assert_eq!(buffer.find_position_within(&2, 0, 51), None);
+
+        // No overflow at the end of the slot range
+        assert_eq!(buffer.find_position_within(&2, u64::MAX, u64::MAX), Some(1));
+        buffer.push(&3, u64::MAX);
+        assert_eq!(buffer.find_position_within(&3, 1, u64::MAX), Some(2));
+
+        // Boundaries in a wrapped buffer
+        zero_program_account!(mut buffer, TestBufferAccount);
+        let capacity = TestBufferAccount::CAPACITY as u32;
+        for i in 0..capacity + 3 {
+            buffer.push(&i, i as u64 * 10);
+        }
+        assert_eq!(buffer.find_position_within(&2, u64::MAX, 0), None);
+        assert_eq!(buffer.find_position_within(&3, 0, 30), Some(3));
+        assert_eq!(buffer.find_position_within(&3, 0, 31), None);
+
+        let last = capacity + 2;
+        let last_slot = last as u64 * 10;
+        assert_eq!(buffer.find_position_within(&last, 5, last_slot + 5), Some(2));
+        assert_eq!(buffer.find_position_within(&last, 5, last_slot + 6), None);
+    }
+
+    #[test]
+    fn test_remove() {
+        zero_program_account!(mut buffer, TestBufferAccount);
+
+        for i in 0..4u32 {
+            buffer.push(&i, i as u64);
+        }
+
+        buffer.remove(1);
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.ptr(), 3);
+        assert!(!buffer.contains(&1));
+        for (index, value) in [0, 2, 3].iter().enumerate() {
+            assert_eq!(buffer.find_position(value), Some(index));
+            assert_eq!(buffer.slot(index), *value as u64);
+        }
+
+        // Removal from a wrapped buffer keeps the insertion order
+        zero_program_account!(mut buffer, TestBufferAccount);
+        let capacity = TestBufferAccount::CAPACITY as u32;
+        for i in 0..capacity + 3 {
+            buffer.push(&i, i as u64);
+        }
+        assert_eq!(buffer.ptr(), 3);
+
+        buffer.remove(5);
+        assert_eq!(buffer.len(), TestBufferAccount::CAPACITY - 1);
+        assert_eq!(buffer.ptr(), 2);
+        assert_eq!(buffer.oldest(), 3);
+        assert!(!buffer.contains(&5));
+        for (i, value) in (3..capacity + 3).filter(|&v| v != 5).enumerate() {
+            let index = (3 + i) % TestBufferAccount::CAPACITY;
+            assert_eq!(buffer.find_position(&value), Some(index));
+            assert_eq!(buffer.slot(index), value as u64);
+        }
+
+        // The oldest entry is evicted once the buffer is full again
+        buffer.push(&1000, 0);
+        buffer.push(&1001, 0);
+        assert_eq!(buffer.len(), TestBufferAccount::CAPACITY);
+        assert!(!buffer.contains(&3));
+        assert!(buffer.contains(&4));
+        assert!(buffer.contains(&1000));
+        assert!(buffer.contains(&1001));
+
+        // Removing the last entry
+        zero_program_account!(mut buffer, TestBufferAccount);
+        buffer.push(&1, 1);
+        buffer.remove(0);
+        assert_eq!(buffer.len(), 0);
+        assert_eq!(buffer.ptr(), 0);
+        assert!(!buffer.contains(&1));
+        assert_eq!(buffer.slot(0), 0);
+    }
+
+    #[test]
+    fn test_migrate_from() {
+        let legacy_size = TestBufferAccount::size_at_version(0).unwrap();
+        assert_eq!(
+            legacy_size,
+            PDAAccountData::SIZE + TEST_BUFFER_ACCOUNT_SIZE * 4 + 4 + 4
+        );
+        assert!(TestBufferAccount::size_at_version(2).is_none());
+
+        let mut data = vec![0; TestBufferAccount::SIZE];
+        TestBufferAccount::migrate_from(0, &mut data).unwrap();
+        data[PDAAccountData::VERSION_OFFSET] = TestBufferAccount::VERSION;
+
+        // Legacy entries never expire
+        let buffer = TestBufferAccount::new(&mut data).unwrap();
+        for i in 0..TestBufferAccount::CAPACITY {
+            assert_eq!(buffer.slot(i), u64::MAX);
+            assert!(!buffer.is_expired(i, 0, u64::MAX));
+        }
+    }
 }
@@ -6,10 +6,11 @@ use crate::{
     bytes::usize_as_u32_safe,
     commitment::poseidon_hash::{binary_poseidon_hash_partial, TOTAL_POSEIDON_ROUNDS},
     error::ElusivError,
+    macros::profile_compute_units,
     state::commitment::{BaseCommitmentHashingAccount, CommitmentHashingAccount},
 };
 use elusiv_computation::PartialComputation;
-use elusiv_proc_macros::elusiv_hash_compute_units;
+use elusiv_proc_macros::{elusiv_commitment_hash_compute_units, elusiv_hash_compute_units};
 use elusiv_utils::{guard, two_pow};
 use solana_program::program_error::ProgramError;
 
@@ -38,8 +39,10 @@ pub fn compute_base_commitment_hash_partial(
             round < BaseCommitmentHashComputation::TOTAL_ROUNDS,
             ElusivError::ComputationIsAlreadyFinished
         );
+        profile_compute_units!();
         binary_poseidon_hash_partial(round, &mut state);
     }
+    profile_compute_units!();
 
     hashing_account.set_state(&state);
     hashing_account.set_instruction(&(instruction + 1));
@@ -62,31 +65,28 @@ pub const MAX_COMMITMENT_BATCHING_RATE: usize = 4;
 /// Batch sizes range: `[0; MAX_COMMITMENT_BATCHING_RATE]`.
 struct CommitmentHashComputation<const BATCHING_RATE: usize>;
 
-/// Generates a [`CommitmentHashComputation`] with a specific `BATCHING_RATE`
+/// Generates the [`CommitmentHashComputation`]s of all batching rates for a MT of height `$mt_height`
 ///
 /// # Note
 ///
-/// The macro also verifies that `$hash_count` is valid.
+/// Proc macros cannot evaluate [`MT_HEIGHT`], so the macro verifies that `$mt_height` matches it.
 macro_rules! commitment_batch_hashing {
-    ($batching_rate: literal, $hash_count: literal, $instruction_count: literal) => {
-        elusiv_hash_compute_units!(CommitmentHashComputation<$batching_rate>, $hash_count);
-
-        #[cfg(test)]
-        const_assert_eq!($hash_count, hash_count_per_batch($batching_rate));
-
-        #[cfg(test)]
-        const_assert_eq!(
-            $instruction_count,
-            <CommitmentHashComputation<$batching_rate>>::IX_COUNT
+    ($mt_height: literal, $max_batching_rate: literal) => {
+        const _: () = assert!($mt_height == MT_HEIGHT);
+        const _: () = assert!($max_batching_rate == MAX_COMMITMENT_BATCHING_RATE);
+
+        elusiv_commitment_hash_compute_units!(
+            CommitmentHashComputation,
+            $mt_height,
+            $max_batching_rate
         );
     };
 }
 
-commitment_batch_hashing!(0, 20, 24);
-commitment_batch_hashing!(1, 20, 24);
-commitment_batch_hashing!(2, 21, 25);
-commitment_batch_hashing!(3, 24, 29);
-commitment_batch_hashing!(4, 31, 37);
+#[cfg(not(feature = "small-mt"))]
+commitment_batch_hashing!(20, 4);
+#[cfg(feature = "small-mt")]
+commitment_batch_hashing!(12, 4);
 
 macro_rules! commitment_hash_computation {
     ($batching_rate: ident, $field: ident) => {
@@ -114,6 +114,10 @@ pub fn commitment_hash_computation_rounds(batching_rate: u32) -> u32 {
     *commitment_hash_computation!(batching_rate, TOTAL_ROUNDS)
 }
 
+pub fn commitment_hash_computation_round_compute_units(batching_rate: u32) -> &'static [u32] {
+    commitment_hash_computation!(batching_rate, ROUND_COMPUTE_UNITS)
+}
+
 pub const MT_HEIGHT: usize = crate::state::storage::MT_HEIGHT as usize;
 
 /// Amount of commitments batched together to compute the MT root
@@ -164,9 +168,16 @@ pub fn compute_commitment_hash_partial(
         ElusivError::ComputationIsAlreadyFinished
     );
 
+    // The hashes above the HT require the siblings (and therefore all previous batches to be inserted)
+    guard!(
+        hashing_account.get_setup() || start_round + rounds < hashing_account.first_path_round(),
+        ElusivError::ComputationIsNotYetFinished
+    );
+
     let mut state = hashing_account.get_state();
 
     for round in start_round..start_round + rounds {
+        profile_compute_units!();
         binary_poseidon_hash_partial(round % TOTAL_POSEIDON_ROUNDS, &mut state);
 
         // A single hash is finished
@@ -182,6 +193,7 @@ pub fn compute_commitment_hash_partial(
             }
         }
     }
+    profile_compute_units!();
 
     hashing_account.set_state(&state);
     hashing_account.set_instruction(&(instruction + 1));
@@ -225,6 +237,20 @@ mod tests {
         assert_eq!(hash_count_per_batch(3), 4 + 2 + 1 + n - 3);
     }
 
+    #[test]
+    fn test_commitment_hash_computation_rounds() {
+        for batching_rate in 0..=MAX_COMMITMENT_BATCHING_RATE as u32 {
+            let rounds = commitment_hash_computation_rounds(batching_rate);
+            assert_eq!(
+                rounds as usize,
+                hash_count_per_batch(batching_rate) * TOTAL_POSEIDON_ROUNDS as usize
+            );
+
+            let instructions = commitment_hash_computation_instructions(batching_rate);
+            assert_eq!(instructions.iter().map(|&r| r as u32).sum::<u32>(), rounds);
+        }
+    }
+
     #[test]
     fn test_base_commitment_hash_computation() {
         zero_program_account!(mut account, BaseCommitmentHashingAccount);
@@ -242,7 +268,7 @@ mod tests {
             base_commitment_request(
                 "18586133768512220936620570745912940619677854269274689475585506675881198879027",
                 "21128387980949076499567732971523903199747404934809414689409667640726053688078",
-                two_pow!(20) as u32 - 1,
+                two_pow!(MT_HEIGHT as u32) as u32 - 1,
                 2,
                 1,
                 0,
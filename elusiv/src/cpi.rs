@@ -0,0 +1,256 @@
+//! Helpers for programs that interact with Elusiv through cross-program invocations
+
+use crate::instruction::ElusivInstruction;
+use crate::processor::{
+    program_token_account_address, recipient_callback_authority, BaseCommitmentHashRequest,
+};
+use crate::state::apa::apa_target_map_account;
+use crate::state::commitment::{BaseCommitmentBufferAccount, BaseCommitmentHashingAccount};
+use crate::state::governor::{FeeCollectorAccount, GovernorAccount, PoolAccount, RateLimitAccount};
+use crate::state::metadata::{CommitmentMetadata, EncryptedMetadataAccount};
+use crate::state::storage::StorageAccount;
+use crate::token::elusiv_token;
+use borsh::BorshSerialize;
+use elusiv_types::PDAAccount;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+};
+
+/// Builds the accounts of a [`ElusivInstruction::StoreBaseCommitment`] instruction with `sender` as its own fee payer
+///
+/// # Notes
+///
+/// - `sender` signs (using `invoke_signed` if it's a PDA) and pays the computation fee and rent in lamports.
+/// - `sender` and `sender_account` are passed twice, as the `sender` and as the `fee_payer` (see [`crate::processor::store_base_commitment`]).
+/// - `sender_account` is the `sender` itself for lamports, otherwise the `sender`'s token account.
+/// - `encrypted_metadata_child_account` is the child-account of the [`EncryptedMetadataAccount`] the next entry is added to
+///   (see `EncryptedMetadataAccount::next_entry_child_index`), it's only required for deposits with encrypted metadata.
+/// - `is_rate_limited` needs to be set if deposits of `token_id` are capped (see [`crate::state::governor::RateLimits`]).
+/// - `apa_proposal_account` only needs to be supplied if the warden network has an APA proposal for the `sender`.
+pub fn store_base_commitment_cpi_accounts(
+    hash_account_index: u32,
+    token_id: u16,
+    sender: Pubkey,
+    sender_account: Pubkey,
+    encrypted_metadata_child_account: Option<Pubkey>,
+    is_rate_limited: bool,
+    apa_proposal_account: Option<Pubkey>,
+) -> Result<Vec<AccountMeta>, ProgramError> {
+    let token = elusiv_token(token_id)?;
+    let (pool_account, fee_collector_account, token_program) = if token_id == 0 {
+        (
+            PoolAccount::find(None).0,
+            FeeCollectorAccount::find(None).0,
+            system_program::id(),
+        )
+    } else {
+        (
+            program_token_account_address::<PoolAccount>(token_id, None)?,
+            program_token_account_address::<FeeCollectorAccount>(token_id, None)?,
+            spl_token::id(),
+        )
+    };
+    let (apa_target_map, _) = apa_target_map_account(&sender);
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sender, true),
+        AccountMeta::new(sender_account, false),
+        AccountMeta::new(sender, true),
+        AccountMeta::new(sender_account, false),
+        AccountMeta::new(PoolAccount::find(None).0, false),
+        AccountMeta::new(pool_account, false),
+        AccountMeta::new(FeeCollectorAccount::find(None).0, false),
+        AccountMeta::new(fee_collector_account, false),
+        AccountMeta::new_readonly(elusiv_token(0)?.pyth_usd_price_key, false),
+        AccountMeta::new_readonly(token.pyth_usd_price_key, false),
+        AccountMeta::new_readonly(GovernorAccount::find(None).0, false),
+        AccountMeta::new_readonly(StorageAccount::find(None).0, false),
+        AccountMeta::new(
+            BaseCommitmentHashingAccount::find(Some(hash_account_index)).0,
+            false,
+        ),
+        AccountMeta::new(BaseCommitmentBufferAccount::find(None).0, false),
+        AccountMeta::new(EncryptedMetadataAccount::find(None).0, false),
+    ];
+    if let Some(encrypted_metadata_child_account) = encrypted_metadata_child_account {
+        accounts.push(AccountMeta::new(encrypted_metadata_child_account, false));
+    }
+    accounts.push(AccountMeta::new_readonly(token_program, false));
+    accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    if is_rate_limited {
+        accounts.push(AccountMeta::new(
+            RateLimitAccount::find(Some(token_id.into())).0,
+            false,
+        ));
+    }
+    accounts.push(AccountMeta::new_readonly(apa_target_map, false));
+    if let Some(apa_proposal_account) = apa_proposal_account {
+        accounts.push(AccountMeta::new_readonly(apa_proposal_account, false));
+    }
+
+    Ok(accounts)
+}
+
+/// Builds a [`ElusivInstruction::StoreBaseCommitment`] instruction to be invoked by another program
+#[allow(clippy::too_many_arguments)]
+pub fn store_base_commitment_cpi_instruction(
+    hash_account_index: u32,
+    request: BaseCommitmentHashRequest,
+    metadata: CommitmentMetadata,
+    encrypted_metadata: Vec<u8>,
+    sender: Pubkey,
+    sender_account: Pubkey,
+    encrypted_metadata_child_account: Option<Pubkey>,
+    is_rate_limited: bool,
+    apa_proposal_account: Option<Pubkey>,
+) -> Result<Instruction, ProgramError> {
+    let accounts = store_base_commitment_cpi_accounts(
+        hash_account_index,
+        request.token_id,
+        sender,
+        sender_account,
+        encrypted_metadata_child_account,
+        is_rate_limited,
+        apa_proposal_account,
+    )?;
+    let hash_account_bump = BaseCommitmentHashingAccount::find(Some(hash_account_index)).1;
+    let data = ElusivInstruction::StoreBaseCommitment {
+        hash_account_index,
+        hash_account_bump,
+        request,
+        metadata,
+        encrypted_metadata,
+    }
+    .try_to_vec()?;
+
+    Ok(Instruction::new_with_bytes(crate::id(), &data, accounts))
+}
+
+/// Builds the trailing accounts of a [`ElusivInstruction::FinalizeVerificationTransferLamports`] or
+/// [`ElusivInstruction::FinalizeVerificationTransferToken`] instruction of a send with a recipient callback
+///
+/// # Notes
+///
+/// - `forwarded_accounts` are passed to the recipient program as non-signers.
+/// - A forwarded account is only writable for the recipient program if it's writable here and bound by the send's `recipient_callback_writable_accounts`.
+/// - Without any accounts, the recipient program is not invoked (only possible after the [`crate::processor::RECIPIENT_CALLBACK_TIMEOUT_SLOTS`]).
+/// - The recipient program is invoked with the [`recipient_callback_authority`] (signer) and the recipient of the funds,
+///   followed by the `forwarded_accounts`.
+/// - If withdrawals of the token are capped, the writable [`RateLimitAccount`] of the token precedes these accounts.
+pub fn recipient_callback_accounts(
+    program_id: Pubkey,
+    forwarded_accounts: &[AccountMeta],
+) -> Vec<AccountMeta> {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(program_id, false),
+        AccountMeta::new_readonly(recipient_callback_authority().0, false),
+    ];
+    accounts.extend_from_slice(forwarded_accounts);
+    accounts
+}
+
+#[cfg(all(test, feature = "elusiv-client"))]
+mod tests {
+    use super::*;
+    use crate::instruction::{
+        SignerAccount, UserAccount, WritableSignerAccount, WritableUserAccount,
+    };
+    use crate::processor::deposit_metadata_hash;
+    use crate::token::{LAMPORTS_TOKEN_ID, USDC_TOKEN_ID};
+    use crate::types::RawU256;
+
+    #[test]
+    fn test_store_base_commitment_cpi_instruction() {
+        let sender = Pubkey::new_unique();
+        let sender_account = Pubkey::new_unique();
+
+        let encrypted_metadata_child_account = Pubkey::new_unique();
+
+        for (token_id, is_rate_limited, encrypted_metadata) in [
+            (LAMPORTS_TOKEN_ID, false, vec![]),
+            (USDC_TOKEN_ID, false, vec![]),
+            (USDC_TOKEN_ID, true, vec![]),
+            (LAMPORTS_TOKEN_ID, false, vec![1, 2, 3]),
+        ] {
+            let encrypted_metadata_child_accounts = if encrypted_metadata.is_empty() {
+                vec![]
+            } else {
+                vec![WritableUserAccount(encrypted_metadata_child_account)]
+            };
+            let request = BaseCommitmentHashRequest {
+                base_commitment: RawU256::new([1; 32]),
+                recent_commitment_index: 0,
+                amount: 1_000_000,
+                token_id,
+                commitment: RawU256::new([2; 32]),
+                fee_version: 0,
+                min_batching_rate: 0,
+                metadata_hash: deposit_metadata_hash(
+                    &CommitmentMetadata::default(),
+                    &encrypted_metadata,
+                ),
+            };
+            let ix = store_base_commitment_cpi_instruction(
+                3,
+                request.clone(),
+                CommitmentMetadata::default(),
+                encrypted_metadata.clone(),
+                sender,
+                sender_account,
+                encrypted_metadata_child_accounts
+                    .first()
+                    .map(|account| account.0),
+                is_rate_limited,
+                None,
+            )
+            .unwrap();
+
+            let (pool_account, fee_collector_account, token_program) = if token_id == 0 {
+                (
+                    PoolAccount::find(None).0,
+                    FeeCollectorAccount::find(None).0,
+                    system_program::id(),
+                )
+            } else {
+                (
+                    program_token_account_address::<PoolAccount>(token_id, None).unwrap(),
+                    program_token_account_address::<FeeCollectorAccount>(token_id, None).unwrap(),
+                    spl_token::id(),
+                )
+            };
+            let mut expected = ElusivInstruction::store_base_commitment_instruction(
+                3,
+                BaseCommitmentHashingAccount::find(Some(3)).1,
+                request,
+                CommitmentMetadata::default(),
+                encrypted_metadata,
+                SignerAccount(sender),
+                WritableUserAccount(sender_account),
+                WritableSignerAccount(sender),
+                WritableUserAccount(sender_account),
+                WritableUserAccount(pool_account),
+                WritableUserAccount(fee_collector_account),
+                UserAccount(elusiv_token(0).unwrap().pyth_usd_price_key),
+                UserAccount(elusiv_token(token_id).unwrap().pyth_usd_price_key),
+                &encrypted_metadata_child_accounts,
+                UserAccount(token_program),
+                &[],
+            );
+            if is_rate_limited {
+                expected.accounts.push(AccountMeta::new(
+                    RateLimitAccount::find(Some(token_id.into())).0,
+                    false,
+                ));
+            }
+            expected.accounts.push(AccountMeta::new_readonly(
+                apa_target_map_account(&sender).0,
+                false,
+            ));
+
+            assert_eq!(ix, expected);
+        }
+    }
+}
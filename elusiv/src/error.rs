@@ -32,10 +32,12 @@ pub enum ElusivError {
     NoRoomForCommitment,
     InvalidBatchingRate,
     InvalidRecentCommitmentIndex,
+    EncryptedMetadataTooLong,
 
     // Proof
     InvalidPublicInputs,
     CouldNotProcessProof,
+    InvalidRecipientCallback,
 
     // Queue
     QueueIsEmpty,
@@ -56,6 +58,12 @@ pub enum ElusivError {
     InvalidFee,
     InvalidFeeVersion,
 
+    // APA
+    ApaRestricted,
+
+    // Governor
+    RateLimitExceeded,
+
     // Accounts
     ChildAccountAlreadyExists,
     ChildAccouttDoesNotExists,
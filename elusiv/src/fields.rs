@@ -1,4 +1,4 @@
-use crate::bytes::slice_to_array;
+use crate::bytes::{read_pod, slice_to_array, write_pod};
 use crate::{
     bytes::BorshSerDeSized,
     types::{u256_to_le_limbs, U256},
@@ -87,6 +87,16 @@ fn write_base_montgomery<W: std::io::Write>(v: Fq, writer: &mut W) -> std::io::R
     writer.write_all(&u64::to_le_bytes(v.0 .0[3])[..])
 }
 
+/// Zero-copy reading of a little-endian montgomery represented base field element
+fn read_fq(data: &[u8]) -> Fq {
+    base_skip_mr(BigInteger256(read_pod(data)))
+}
+
+/// Zero-copy writing of a little-endian montgomery represented base field element
+fn write_fq(v: &Fq, data: &mut [u8]) {
+    write_pod(&v.0 .0, data)
+}
+
 /// Wraps foreign types into the local scope
 #[derive(Copy, PartialEq)]
 #[cfg_attr(test, derive(Debug))]
@@ -101,6 +111,15 @@ impl<T: Clone> Clone for Wrap<T> {
 // BigInteger256
 impl BorshSerDeSized for Wrap<BigInteger256> {
     const SIZE: usize = 32;
+
+    fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+        Ok(Wrap(BigInteger256(read_pod(data))))
+    }
+
+    fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+        write_pod(&self.0 .0, data);
+        Ok(())
+    }
 }
 impl BorshSerialize for Wrap<BigInteger256> {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
@@ -123,6 +142,15 @@ impl BorshDeserialize for Wrap<BigInteger256> {
 // Fr
 impl BorshSerDeSized for Wrap<Fr> {
     const SIZE: usize = 32;
+
+    fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+        Ok(Wrap(scalar_skip_mr(BigInteger256(read_pod(data)))))
+    }
+
+    fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+        write_pod(&self.0 .0 .0, data);
+        Ok(())
+    }
 }
 impl BorshSerialize for Wrap<Fr> {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
@@ -144,6 +172,15 @@ impl BorshDeserialize for Wrap<Fr> {
 // Fq
 impl BorshSerDeSized for Wrap<Fq> {
     const SIZE: usize = 32;
+
+    fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+        Ok(Wrap(read_fq(data)))
+    }
+
+    fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+        write_fq(&self.0, data);
+        Ok(())
+    }
 }
 impl BorshSerialize for Wrap<Fq> {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
@@ -162,6 +199,16 @@ impl BorshDeserialize for Wrap<Fq> {
 // Fq2
 impl BorshSerDeSized for Wrap<Fq2> {
     const SIZE: usize = 64;
+
+    fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+        Ok(Wrap(Fq2::new(read_fq(data), read_fq(&data[32..]))))
+    }
+
+    fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+        write_fq(&self.0.c0, data);
+        write_fq(&self.0.c1, &mut data[32..]);
+        Ok(())
+    }
 }
 impl BorshSerialize for Wrap<Fq2> {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
@@ -181,6 +228,24 @@ impl BorshDeserialize for Wrap<Fq2> {
 // Fq6
 impl BorshSerDeSized for Wrap<Fq6> {
     const SIZE: usize = 192;
+
+    fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+        Ok(Wrap(Fq6::new(
+            Fq2::new(read_fq(data), read_fq(&data[32..])),
+            Fq2::new(read_fq(&data[64..]), read_fq(&data[96..])),
+            Fq2::new(read_fq(&data[128..]), read_fq(&data[160..])),
+        )))
+    }
+
+    fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+        write_fq(&self.0.c0.c0, data);
+        write_fq(&self.0.c0.c1, &mut data[32..]);
+        write_fq(&self.0.c1.c0, &mut data[64..]);
+        write_fq(&self.0.c1.c1, &mut data[96..]);
+        write_fq(&self.0.c2.c0, &mut data[128..]);
+        write_fq(&self.0.c2.c1, &mut data[160..]);
+        Ok(())
+    }
 }
 impl BorshSerialize for Wrap<Fq6> {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
@@ -208,6 +273,18 @@ impl BorshDeserialize for Wrap<Fq6> {
 // Fq12
 impl BorshSerDeSized for Wrap<Fq12> {
     const SIZE: usize = 384;
+
+    fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+        Ok(Wrap(Fq12::new(
+            <Wrap<Fq6>>::read_sized(data)?.0,
+            <Wrap<Fq6>>::read_sized(&data[192..])?.0,
+        )))
+    }
+
+    fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+        Wrap(self.0.c0).write_sized(data)?;
+        Wrap(self.0.c1).write_sized(&mut data[192..])
+    }
 }
 impl BorshSerialize for Wrap<Fq12> {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
@@ -246,6 +323,20 @@ impl G2A {
 // G1A
 impl BorshSerDeSized for G1A {
     const SIZE: usize = 65;
+
+    fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+        Ok(G1A(G1Affine::new(
+            read_fq(data),
+            read_fq(&data[32..]),
+            bool::read_sized(&data[64..])?,
+        )))
+    }
+
+    fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+        write_fq(&self.0.x, data);
+        write_fq(&self.0.y, &mut data[32..]);
+        self.0.infinity.write_sized(&mut data[64..])
+    }
 }
 impl BorshSerialize for G1A {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
@@ -281,6 +372,22 @@ impl BorshDeserialize for Wrap<G1A> {
 // G2A
 impl BorshSerDeSized for G2A {
     const SIZE: usize = 129;
+
+    fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+        Ok(G2A(G2Affine::new(
+            Fq2::new(read_fq(data), read_fq(&data[32..])),
+            Fq2::new(read_fq(&data[64..]), read_fq(&data[96..])),
+            bool::read_sized(&data[128..])?,
+        )))
+    }
+
+    fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+        write_fq(&self.0.x.c0, data);
+        write_fq(&self.0.x.c1, &mut data[32..]);
+        write_fq(&self.0.y.c0, &mut data[64..]);
+        write_fq(&self.0.y.c1, &mut data[96..]);
+        self.0.infinity.write_sized(&mut data[128..])
+    }
 }
 impl BorshSerialize for G2A {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
@@ -317,7 +424,7 @@ impl BorshDeserialize for Wrap<G2A> {
 
 // Homogenous projective coordinates form
 #[derive(Clone, Copy)]
-#[cfg_attr(test, derive(Debug))]
+#[cfg_attr(test, derive(Debug, PartialEq))]
 pub struct G2HomProjective {
     pub x: Fq2,
     pub y: Fq2,
@@ -325,6 +432,24 @@ pub struct G2HomProjective {
 }
 impl BorshSerDeSized for G2HomProjective {
     const SIZE: usize = 192;
+
+    fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+        Ok(G2HomProjective {
+            x: Fq2::new(read_fq(data), read_fq(&data[32..])),
+            y: Fq2::new(read_fq(&data[64..]), read_fq(&data[96..])),
+            z: Fq2::new(read_fq(&data[128..]), read_fq(&data[160..])),
+        })
+    }
+
+    fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+        write_fq(&self.x.c0, data);
+        write_fq(&self.x.c1, &mut data[32..]);
+        write_fq(&self.y.c0, &mut data[64..]);
+        write_fq(&self.y.c1, &mut data[96..]);
+        write_fq(&self.z.c0, &mut data[128..]);
+        write_fq(&self.z.c1, &mut data[160..]);
+        Ok(())
+    }
 }
 impl BorshSerialize for G2HomProjective {
     fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
@@ -447,6 +572,13 @@ mod tests {
             let result = <$ty>::deserialize(&mut buf).unwrap();
             assert_eq!(v, result);
             assert_eq!(buf.len(), 0);
+
+            // The zero-copy representation is identical
+            let mut zero_copy_bytes = vec![0; <$ty>::SIZE + 1];
+            v.write_sized(&mut zero_copy_bytes).unwrap();
+            assert_eq!(zero_copy_bytes[..<$ty>::SIZE], bytes);
+            assert_eq!(zero_copy_bytes[<$ty>::SIZE], 0);
+            assert_eq!(<$ty>::read_sized(&zero_copy_bytes).unwrap(), v);
         };
     }
 
@@ -621,6 +753,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ser_de_g2_hom_projective() {
+        let fq = |s: &str| Fq::from_str(s).unwrap();
+        let v = G2HomProjective {
+            x: Fq2::new(
+                fq("10026859857882131638516328056627849627085232677511724829502598764489185541935"),
+                fq("19685960310506634721912121951341598678325833230508240750559904196809564625591"),
+            ),
+            y: Fq2::new(
+                fq("8337064132573119120838379738103457054645361649757131991036638108422638197362"),
+                fq("21186803555845400161937398579081414146527572885637089779856221229551142844794"),
+            ),
+            z: Fq2::new(
+                fq("139214303935475888711984321184227760578793579443975701453971046059378311483"),
+                fq("14744269619966411208579211824598458697587494354926760081771325075741142829156"),
+            ),
+        };
+
+        let bytes = v.try_to_vec().unwrap();
+        let mut zero_copy_bytes = vec![0; G2HomProjective::SIZE];
+        v.write_sized(&mut zero_copy_bytes).unwrap();
+        assert_eq!(zero_copy_bytes, bytes);
+
+        let result = G2HomProjective::read_sized(&bytes).unwrap();
+        assert_eq!((result.x, result.y, result.z), (v.x, v.y, v.z));
+    }
+
     #[test]
     fn test_fr_u256_parsing() {
         let f = Fr::from_str(
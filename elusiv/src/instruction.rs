@@ -1,20 +1,24 @@
 #![allow(clippy::too_many_arguments)]
 
 use super::processor;
-use super::processor::BaseCommitmentHashRequest;
+use super::processor::{BaseCommitmentHashRequest, MigratableAccountKind};
 use crate::macros::*;
 use crate::processor::{FinalizeSendData, ProofRequest, VKeyAccountDataPacket, MAX_MT_COUNT};
+use crate::proof::vkey::VerifyingKeyLayout;
 use crate::state::{
     commitment::{
-        BaseCommitmentBufferAccount, BaseCommitmentHashingAccount, CommitmentBufferAccount,
-        CommitmentHashingAccount, CommitmentQueueAccount,
+        commitment_hashing_account_offset, BaseCommitmentBufferAccount,
+        BaseCommitmentHashingAccount, CommitmentBufferAccount, CommitmentHashingAccount,
+        CommitmentQueueAccount,
     },
     fee::{FeeAccount, ProgramFee},
-    governor::{FeeCollectorAccount, GovernorAccount, PoolAccount},
-    metadata::{CommitmentMetadata, MetadataAccount, MetadataQueueAccount},
+    governor::{FeeCollectorAccount, GovernorAccount, PoolAccount, RateLimitAccount},
+    metadata::{
+        CommitmentMetadata, EncryptedMetadataAccount, MetadataAccount, MetadataQueueAccount,
+    },
     nullifier::NullifierAccount,
-    proof::VerificationAccount,
-    storage::StorageAccount,
+    proof::{BatchVerificationAccount, VerificationAccount},
+    storage::{RootHistoryAccount, StorageAccount},
     vkey::VKeyAccount,
 };
 use crate::types::Proof;
@@ -47,13 +51,18 @@ pub enum ElusivInstruction {
     #[pda(storage_account, StorageAccount)]
     #[pda(hashing_account, BaseCommitmentHashingAccount, pda_offset = Some(hash_account_index), { writable, skip_pda_verification, account_info })]
     #[pda(buffer, BaseCommitmentBufferAccount, { writable })]
+    #[pda(encrypted_metadata_account, EncryptedMetadataAccount, { writable, include_child_accounts })] // child-account of the next entry (only required with `encrypted_metadata`)
     #[acc(token_program)] // if `token_id = 0` { `system_program` } else { `token_program` }
     #[sys(system_program, key = system_program::ID)]
+    #[acc(trailing_accounts, { remaining })]
+    // `RateLimitAccount` of the token (only required if deposits are capped),
+    // followed by the `ApaTargetMapAccount` of the `sender` and the optional referenced `ApaProposalAccount`
     StoreBaseCommitment {
         hash_account_index: u32,
         hash_account_bump: u8,
         request: BaseCommitmentHashRequest,
         metadata: CommitmentMetadata,
+        encrypted_metadata: Vec<u8>,
     },
 
     #[pda(hashing_account, BaseCommitmentHashingAccount, pda_offset = Some(hash_account_index), { writable })]
@@ -72,25 +81,42 @@ pub enum ElusivInstruction {
 
     // -------- Commitment hashing --------
     /// Hashes commitments in a new MT-root
-    #[pda(commitment_hashing_account, CommitmentHashingAccount, { writable })]
+    #[pda(commitment_hashing_account, CommitmentHashingAccount, pda_offset = commitment_hashing_account_offset(hashing_account_index), { writable })]
     #[pda(storage_account, StorageAccount, { include_child_accounts })]
-    InitCommitmentHashSetup { insertion_can_fail: bool },
+    InitCommitmentHashSetup {
+        insertion_can_fail: bool,
+        hashing_account_index: u32,
+    },
 
+    #[pda(fee, FeeAccount, pda_offset = Some(fee_version))]
+    #[pda(pool, PoolAccount, { writable, account_info })]
+    #[pda(fee_collector, FeeCollectorAccount, { writable, account_info })]
     #[pda(commitment_hash_queue, CommitmentQueueAccount, { writable })]
     #[pda(metadata_queue, MetadataQueueAccount, { writable })]
-    #[pda(commitment_hashing_account, CommitmentHashingAccount, { writable })]
+    #[pda(commitment_hashing_account, CommitmentHashingAccount, pda_offset = commitment_hashing_account_offset(hashing_account_index), { writable })]
     #[pda(metadata_account, MetadataAccount, { writable, include_child_accounts })]
-    InitCommitmentHash { insertion_can_fail: bool },
+    #[pda(storage_account, StorageAccount, { writable })]
+    InitCommitmentHash {
+        fee_version: u32,
+        insertion_can_fail: bool,
+        hashing_account_index: u32,
+        adaptive_batching_rate: bool,
+    },
 
     #[acc(fee_payer, { writable, signer })]
     #[pda(fee, FeeAccount, pda_offset = Some(fee_version))]
     #[pda(pool, PoolAccount, { writable, account_info })]
-    #[pda(commitment_hashing_account, CommitmentHashingAccount, { writable })]
-    ComputeCommitmentHash { fee_version: u32, nonce: u32 },
+    #[pda(commitment_hashing_account, CommitmentHashingAccount, pda_offset = commitment_hashing_account_offset(hashing_account_index), { writable })]
+    ComputeCommitmentHash {
+        fee_version: u32,
+        nonce: u32,
+        hashing_account_index: u32,
+    },
 
-    #[pda(commitment_hashing_account, CommitmentHashingAccount, { writable })]
+    #[pda(commitment_hashing_account, CommitmentHashingAccount, pda_offset = commitment_hashing_account_offset(hashing_account_index), { writable })]
     #[pda(storage_account, StorageAccount, { include_child_accounts, writable })]
-    FinalizeCommitmentHash,
+    #[pda(root_history_account, RootHistoryAccount, { writable, include_child_accounts })]
+    FinalizeCommitmentHash { hashing_account_index: u32 },
 
     // -------- Proof Verification --------
     /// Proof verification initialization
@@ -104,6 +130,8 @@ pub enum ElusivInstruction {
     #[pda(buffer, CommitmentBufferAccount, { writable })]
     #[pda(nullifier_account0, NullifierAccount, pda_offset = Some(tree_indices[0]), { include_child_accounts })]
     #[pda(nullifier_account1, NullifierAccount, pda_offset = Some(tree_indices[1]), { include_child_accounts })]
+    #[pda(governor, GovernorAccount)]
+    #[pda(root_history_account, RootHistoryAccount, { include_child_accounts })]
     InitVerification {
         verification_account_index: u8,
         vkey_id: u32,
@@ -153,6 +181,9 @@ pub enum ElusivInstruction {
     #[pda(storage_account, StorageAccount)]
     #[pda(buffer, CommitmentBufferAccount, { writable })]
     #[sys(instructions_account, key = instructions::ID)]
+    #[pda(encrypted_metadata_account, EncryptedMetadataAccount, { writable, include_child_accounts })] // child-account of the next entry (only required with `encrypted_metadata`)
+    #[acc(trailing_accounts, { remaining })]
+    // additional transaction references, followed by the `ApaTargetMapAccount` of the `recipient` and the optional referenced `ApaProposalAccount`
     FinalizeVerificationSend {
         verification_account_index: u8,
         data: FinalizeSendData,
@@ -173,8 +204,11 @@ pub enum ElusivInstruction {
     #[pda(metadata_queue, MetadataQueueAccount, { writable })]
     #[pda(verification_account, VerificationAccount, pda_pubkey = original_fee_payer.pubkey(), pda_offset = Some(verification_account_index.into()), { writable, account_info })]
     #[acc(nullifier_duplicate_account, { writable, owned })]
+    #[pda(governor, GovernorAccount)]
     #[sys(system_program, key = system_program::ID, { ignore })]
     #[sys(instructions_account, key = instructions::ID)]
+    #[acc(trailing_accounts, { remaining })]
+    // optional: `RateLimitAccount` of the token (required if withdrawals are capped), followed by the recipient callback accounts
     FinalizeVerificationTransferLamports { verification_account_index: u8 },
 
     #[acc(original_fee_payer, { signer, writable })]
@@ -190,11 +224,14 @@ pub enum ElusivInstruction {
     #[pda(metadata_queue, MetadataQueueAccount, { writable })]
     #[pda(verification_account, VerificationAccount, pda_pubkey = original_fee_payer.pubkey(), pda_offset = Some(verification_account_index.into()), { writable, account_info })]
     #[acc(nullifier_duplicate_account, { writable, owned })]
+    #[pda(governor, GovernorAccount)]
     #[sys(a_token_program, key = spl_associated_token_account::ID, { ignore })]
     #[sys(token_program, key = spl_token::ID)]
     #[sys(system_program, key = system_program::ID, { ignore })]
     #[acc(mint_account)]
     #[sys(instructions_account, key = instructions::ID)]
+    #[acc(trailing_accounts, { remaining })]
+    // optional: `RateLimitAccount` of the token (required if withdrawals are capped), followed by the recipient callback accounts
     FinalizeVerificationTransferToken { verification_account_index: u8 },
 
     // -------- Verifying key management --------
@@ -205,6 +242,7 @@ pub enum ElusivInstruction {
         vkey_id: u32,
         public_inputs_count: u32,
         deploy_authority: ElusivOption<Pubkey>,
+        layout: VerifyingKeyLayout,
     },
 
     #[acc(signer, { signer })]
@@ -277,6 +315,15 @@ pub enum ElusivInstruction {
     #[acc(child_account, { owned, writable })]
     EnableMetadataChildAccount { child_index: u32 },
 
+    #[acc(payer, { writable, signer })]
+    #[pda(encrypted_metadata_account, EncryptedMetadataAccount, { writable, skip_pda_verification, account_info })]
+    #[sys(system_program, key = system_program::ID, { ignore })]
+    OpenEncryptedMetadataAccount,
+
+    #[pda(encrypted_metadata_account, EncryptedMetadataAccount, { writable })]
+    #[acc(child_account, { owned, writable })]
+    EnableEncryptedMetadataChildAccount { child_index: u32 },
+
     #[acc(payer, { writable, signer })]
     #[pda(governor, GovernorAccount, { writable, skip_pda_verification, account_info })]
     #[sys(system_program, key = system_program::ID, { ignore })]
@@ -299,6 +346,18 @@ pub enum ElusivInstruction {
         program_fee: ProgramFee,
     },
 
+    /// Configures the deposit and withdrawal caps of a token (`signer` needs to be the program's keypair)
+    #[acc(signer, { writable, signer })]
+    #[pda(governor, GovernorAccount, { writable })]
+    #[pda(rate_limit_account, RateLimitAccount, pda_offset = Some(token_id.into()), { writable, account_info, find_pda })]
+    #[sys(system_program, key = system_program::ID, { ignore })]
+    SetRateLimits {
+        window_slots: u64,
+        token_id: u16,
+        deposit_cap: u64,
+        withdrawal_cap: u64,
+    },
+
     #[cfg(not(feature = "mainnet"))]
     #[acc(payer, { signer })]
     #[acc(recipient, { writable })]
@@ -316,11 +375,113 @@ pub enum ElusivInstruction {
     #[sys(system_program, key = system_program::ID, { ignore })]
     CreateNewAccountsV1,
 
+    /// Opens an additional [`CommitmentHashingAccount`] (the account with index zero is a single instance account)
+    #[acc(payer, { writable, signer })]
+    #[pda(commitment_hashing_account, CommitmentHashingAccount, pda_offset = Some(hashing_account_index), { writable, skip_pda_verification, account_info })]
+    #[sys(system_program, key = system_program::ID, { ignore })]
+    OpenCommitmentHashingAccount { hashing_account_index: u32 },
+
+    /// Migrates a program account to the current version of its data layout
+    #[acc(payer, { writable, signer })]
+    #[acc(account, { writable, owned })]
+    #[sys(system_program, key = system_program::ID, { ignore })]
+    MigrateAccount {
+        kind: MigratableAccountKind,
+        pda_pubkey: Option<Pubkey>,
+        pda_offset: Option<u32>,
+    },
+
+    /// Reallocates a `NullifierAccount` child-account in the legacy map format
+    #[acc(payer, { writable, signer })]
+    #[pda(nullifier_account, NullifierAccount, pda_offset = Some(mt_index))]
+    #[acc(child_account, { owned, writable })]
+    #[sys(system_program, key = system_program::ID, { ignore })]
+    MigrateNullifierChildAccount { mt_index: u32, child_index: u32 },
+
+    /// Builds a part of the map index of a migrated `NullifierAccount` child-account
+    #[pda(nullifier_account, NullifierAccount, pda_offset = Some(mt_index))]
+    #[acc(child_account, { owned, writable })]
+    BuildNullifierChildAccountIndex { mt_index: u32, child_index: u32 },
+
     // -------- NOP --------
     /// NOP-instruction
     Nop,
+
+    // -------- Batch verification --------
+    // (appended after `Nop` to keep the indices of all previous instructions)
+    #[acc(warden, { writable, signer })]
+    #[acc(batch_account, { writable })]
+    #[sys(system_program, key = system_program::ID, { ignore })]
+    InitBatchVerification { batch_index: u8, vkey_id: u32 },
+
+    #[acc(warden, { signer })]
+    #[pda(batch_account, BatchVerificationAccount, pda_pubkey = warden.pubkey(), pda_offset = Some(batch_index.into()), { writable, account_info })]
+    #[pda(verification_account, VerificationAccount, pda_pubkey = warden.pubkey(), pda_offset = Some(verification_account_index.into()), { writable, account_info })]
+    AddBatchVerificationMember {
+        batch_index: u8,
+        verification_account_index: u8,
+    },
+
+    /// Evicts a stuck or invalid member from a batch
+    #[acc(warden, { signer })]
+    #[pda(batch_account, BatchVerificationAccount, pda_pubkey = warden.pubkey(), pda_offset = Some(batch_index.into()), { writable, account_info })]
+    #[pda(verification_account, VerificationAccount, pda_pubkey = warden.pubkey(), pda_offset = Some(verification_account_index.into()), { writable, account_info })]
+    RemoveBatchVerificationMember {
+        batch_index: u8,
+        member_index: u32,
+        verification_account_index: u8,
+    },
+
+    #[acc(warden, { signer })]
+    #[pda(batch_account, BatchVerificationAccount, pda_pubkey = warden.pubkey(), pda_offset = Some(batch_index.into()), { writable })]
+    #[pda(verification_account, VerificationAccount, pda_pubkey = warden.pubkey(), pda_offset = Some(verification_account_index.into()), { account_info })]
+    AccumulateBatchVerificationMember {
+        batch_index: u8,
+        member_index: u32,
+        verification_account_index: u8,
+    },
+
+    #[acc(warden, { signer })]
+    #[pda(batch_account, BatchVerificationAccount, pda_pubkey = warden.pubkey(), pda_offset = Some(batch_index.into()), { writable })]
+    #[pda(vkey_account, VKeyAccount, pda_offset = Some(vkey_id), { include_child_accounts })]
+    #[pda(leader_verification_account, VerificationAccount, pda_pubkey = warden.pubkey(), pda_offset = Some(leader_verification_account_index.into()), { writable, account_info })]
+    ComputeBatchVerification {
+        batch_index: u8,
+        vkey_id: u32,
+        leader_verification_account_index: u8,
+    },
+
+    #[acc(warden, { signer })]
+    #[pda(batch_account, BatchVerificationAccount, pda_pubkey = warden.pubkey(), pda_offset = Some(batch_index.into()), { writable })]
+    #[pda(verification_account, VerificationAccount, pda_pubkey = warden.pubkey(), pda_offset = Some(verification_account_index.into()), { writable, account_info })]
+    FinalizeBatchVerificationMember {
+        batch_index: u8,
+        member_index: u32,
+        verification_account_index: u8,
+    },
+
+    #[acc(warden, { writable, signer })]
+    #[pda(batch_account, BatchVerificationAccount, pda_pubkey = warden.pubkey(), pda_offset = Some(batch_index.into()), { writable, account_info })]
+    CloseBatchVerification { batch_index: u8 },
+
+    // -------- Root history --------
+    #[acc(payer, { writable, signer })]
+    #[pda(root_history_account, RootHistoryAccount, { writable, skip_pda_verification, account_info })]
+    #[sys(system_program, key = system_program::ID, { ignore })]
+    OpenRootHistoryAccount,
+
+    #[pda(root_history_account, RootHistoryAccount, { writable })]
+    #[acc(child_account, { owned, writable })]
+    EnableRootHistoryChildAccount { child_index: u32 },
+
+    /// Configures the previous roots of the active MT accepted for proofs (`signer` needs to be the program's keypair)
+    #[acc(signer, { signer })]
+    #[pda(governor, GovernorAccount, { writable })]
+    SetRootHistoryParameters { depth: u32, slot_window: u64 },
 }
 
+#[cfg(feature = "elusiv-client")]
+use crate::state::apa::apa_target_map_account;
 #[cfg(feature = "elusiv-client")]
 use elusiv_types::accounts::PDAAccount;
 
@@ -330,16 +491,25 @@ impl ElusivInstruction {
         hash_account_index: u32,
         request: BaseCommitmentHashRequest,
         metadata: CommitmentMetadata,
+        encrypted_metadata: Vec<u8>,
+        encrypted_metadata_child_account: Option<Pubkey>, // required if `encrypted_metadata` is not empty
         client: Pubkey,
         warden: Pubkey,
     ) -> solana_program::instruction::Instruction {
         let hash_account_bump = BaseCommitmentHashingAccount::find(Some(hash_account_index)).1;
+        let (apa_target_map, _) = apa_target_map_account(&client);
+        let encrypted_metadata_child_accounts: Vec<WritableUserAccount> =
+            encrypted_metadata_child_account
+                .into_iter()
+                .map(WritableUserAccount)
+                .collect();
 
         ElusivInstruction::store_base_commitment_instruction(
             hash_account_index,
             hash_account_bump,
             request,
             metadata,
+            encrypted_metadata,
             SignerAccount(client),
             WritableUserAccount(client),
             WritableSignerAccount(warden),
@@ -348,7 +518,9 @@ impl ElusivInstruction {
             WritableUserAccount(FeeCollectorAccount::find(None).0),
             UserAccount(system_program::id()),
             UserAccount(system_program::id()),
+            &encrypted_metadata_child_accounts,
             UserAccount(system_program::id()),
+            &[UserAccount(apa_target_map)],
         )
     }
 
@@ -3,6 +3,8 @@
 pub mod buffer;
 pub mod bytes;
 pub mod commitment;
+#[cfg(any(feature = "elusiv-client", feature = "no-entrypoint"))]
+pub mod cpi;
 pub mod entrypoint;
 mod error;
 pub mod fields;
@@ -26,3 +28,11 @@ extern crate static_assertions;
 compile_error!(
     "The 'devnet' and 'mainnet' features are mutually exclusive and cannot be used together."
 );
+
+#[cfg(all(
+    feature = "small-mt",
+    not(any(feature = "test-unit", feature = "test-bpf"))
+))]
+compile_error!(
+    "The 'small-mt' feature has no matching circuits and verifying keys and can only be used for tests."
+);
@@ -2,6 +2,20 @@ pub use elusiv_derive::*;
 pub use elusiv_proc_macros::*;
 pub use elusiv_utils::{guard, pda_account, two_pow};
 
+/// Logs the remaining compute units at a partial computation round boundary
+///
+/// # Notes
+///
+/// Only active with the `compute-unit-profiling` feature (see `ComputeUnitProfile` in `tests/common.rs`).
+///
+/// Has to be called before each round and once after the last round of an instruction.
+macro_rules! profile_compute_units {
+    () => {
+        #[cfg(feature = "compute-unit-profiling")]
+        solana_program::log::sol_log_compute_units();
+    };
+}
+
 /// Creates a dummy pyth-price-account [`solana_program::account_info::AccountInfo`] for testing
 ///
 /// # Usage
@@ -95,6 +109,27 @@ macro_rules! signing_test_account_info {
     };
 }
 
+/// Creates a [`solana_program::account_info::AccountInfo`] with the zeroed data of a `$ty` at its current version for testing
+///
+/// # Usage
+///
+/// - `program_account_info!($id: ident, $ty: ty)`
+/// - PDA with its bump: `program_account_info!($id: ident, $ty: ty, $offset: expr)`
+#[cfg(test)]
+macro_rules! program_account_info {
+    ($id: ident, $ty: ty) => {
+        let pk = solana_program::pubkey::Pubkey::new_unique();
+        crate::macros::zero_program_account!(internal $ty, data);
+        crate::macros::account_info!($id, pk, data)
+    };
+    ($id: ident, $ty: ty, $offset: expr) => {
+        let (pk, bump) = <$ty as elusiv_types::PDAAccount>::find($offset);
+        crate::macros::zero_program_account!(internal $ty, data);
+        data[0] = bump;
+        crate::macros::account_info!($id, pk, data)
+    };
+}
+
 #[cfg(test)]
 macro_rules! test_pda_account_info {
     ($id: ident, $ty: ty) => {
@@ -132,12 +167,18 @@ macro_rules! program_token_account_info {
 /// - mutable instance: `zero_program_account!(mut $id: ident, $ty: ty)`
 #[cfg(test)]
 macro_rules! zero_program_account {
+    (internal $ty: ty, $data: ident) => {
+        let mut $data = vec![0; <$ty as elusiv_types::SizedAccount>::SIZE];
+        $data[elusiv_types::PDAAccountData::VERSION_OFFSET] =
+            <$ty as elusiv_types::VersionedAccount>::VERSION;
+    };
+
     (mut $id: ident, $ty: ty) => {
-        let mut data = vec![0; <$ty as elusiv_types::SizedAccount>::SIZE];
+        crate::macros::zero_program_account!(internal $ty, data);
         let mut $id = <$ty as elusiv_types::ProgramAccount>::new(&mut data).unwrap();
     };
     ($id: ident, $ty: ty) => {
-        let mut data = vec![0; <$ty as elusiv_types::SizedAccount>::SIZE];
+        crate::macros::zero_program_account!(internal $ty, data);
         let $id = <$ty as elusiv_types::ProgramAccount>::new(&mut data).unwrap();
     };
 }
@@ -156,6 +197,8 @@ macro_rules! zero_program_account {
 macro_rules! parent_account {
     (internal $ty: ty, $child_accounts: ident, $data: ident) => {
         let mut $data = vec![0; <$ty as elusiv_types::accounts::SizedAccount>::SIZE];
+        $data[elusiv_types::PDAAccountData::VERSION_OFFSET] =
+            <$ty as elusiv_types::VersionedAccount>::VERSION;
 
         let mut child_accounts = Vec::with_capacity(<$ty as elusiv_types::accounts::ParentAccount>::COUNT);
         elusiv_proc_macros::repeat!({
@@ -181,6 +224,9 @@ macro_rules! parent_account {
 pub(crate) use account_info;
 #[cfg(test)]
 pub(crate) use parent_account;
+pub(crate) use profile_compute_units;
+#[cfg(test)]
+pub(crate) use program_account_info;
 #[cfg(test)]
 pub(crate) use program_token_account_info;
 #[cfg(test)]
@@ -45,22 +45,112 @@ pub enum ElusivMapError<V: ElusivMapValue> {
 
 const MID_PTR_HEIGHT: u32 = 3;
 const MID_PTR_COUNT: usize = two_pow!(MID_PTR_HEIGHT + 1) - 1;
-// const LAST_MID_PTR_INDEX: usize = MID_PTR_COUNT - 1;
-const SUBSECTIONS: u32 = MID_PTR_COUNT as u32 + 1;
+
+const INDEX_PTR_HEIGHT: u32 = 7;
+const INDEX_PTR_COUNT: usize = two_pow!(INDEX_PTR_HEIGHT + 1) - 1;
+
+/// The binary trees of pointers used to traverse an [`ElusivMap`]
+///
+/// # Note
+///
+/// The ptr with index `i` of a tree always points to the entry at [`PtrTree::compute_mid`].
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+enum PtrTree {
+    /// Legacy tree of height [`MID_PTR_HEIGHT`] (only used until the index tree has been built)
+    Mid,
+
+    /// Tree of height [`INDEX_PTR_HEIGHT`]
+    Index,
+}
+
+impl PtrTree {
+    const fn height(&self) -> u32 {
+        match self {
+            PtrTree::Mid => MID_PTR_HEIGHT,
+            PtrTree::Index => INDEX_PTR_HEIGHT,
+        }
+    }
+
+    const fn count(&self) -> usize {
+        match self {
+            PtrTree::Mid => MID_PTR_COUNT,
+            PtrTree::Index => INDEX_PTR_COUNT,
+        }
+    }
+
+    const fn subsections(&self) -> u32 {
+        self.count() as u32 + 1
+    }
+
+    fn compute_mid(&self, ptr_index: u32, len: u32) -> u32 {
+        ((1 + ptr_index) * len) / self.subsections()
+    }
+}
+
+/// The neighbouring keys around a key that is not contained in an [`ElusivMap`]
+#[derive(PartialEq, Clone)]
+#[cfg_attr(any(test, feature = "elusiv-client"), derive(Debug))]
+pub struct ElusivMapNeighbours<K: ElusivMapKey> {
+    /// The largest key smaller than the queried key
+    pub predecessor: Option<K>,
+
+    /// The smallest key larger than the queried key
+    pub successor: Option<K>,
+}
+
+/// Iterator over a range of entries of an [`ElusivMap`] (see [`ElusivMap::range`])
+pub struct ElusivMapRange<'m, 'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> {
+    map: &'m mut ElusivMap<'a, K, V, CAPACITY>,
+    ptr: ElusivMapPtr,
+
+    /// Number of entries between `ptr` and the end of the map
+    remaining: u32,
+    to: K,
+}
+
+impl<'m, 'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> Iterator
+    for ElusivMapRange<'m, 'a, K, V, CAPACITY>
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let key = self.map.key(&self.ptr);
+        if key >= self.to {
+            self.remaining = 0;
+            return None;
+        }
+
+        let value = self.map.value(&self.ptr);
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            self.ptr = self.map.get_next(&self.ptr);
+        }
+
+        Some((key, value))
+    }
+}
 
 /// We use pointers to increase read/write efficiency in the [`ElusivMap`]
 #[derive(BorshSerialize, BorshDeserialize, BorshSerDeSized, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(test, derive(Debug))]
+#[zero_copy]
 pub struct ElusivMapPtr(pub u16);
 
 /// A set storing values of type `K` utilizing [`ElusivMap`]
 pub type ElusivSet<'a, K, const CAPACITY: usize> = ElusivMap<'a, K, (), CAPACITY>;
 
-/// Write efficient, append only, JIT deserializing, insertion sorted map with a maximum capacity
+/// Write efficient, JIT deserializing, insertion sorted map with a maximum capacity
 ///
 /// # Note
 ///
 /// The upper bound (inclusive) for `CAPACITY` is `2^16` (size of the pointer).
+///
+/// Maps created before the `index_ptr` tree existed (see [`ElusivMap::LEGACY_SIZE`]) need to be reallocated, followed by [`ElusivMap::start_index_migration`] and [`ElusivMap::build_index`].
 #[derive(BorshSerDeSized, BorshSerDePlaceholder, ByteBackedJIT)]
 #[cfg_attr(test, derive(Debug))]
 pub struct ElusivMap<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> {
@@ -69,7 +159,7 @@ pub struct ElusivMap<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usi
     min_ptr: Lazy<'a, ElusivMapPtr>,
     max_ptr: Lazy<'a, ElusivMapPtr>,
 
-    /// All mid-ptrs form a binary tree of height [`MID_PTR_HEIGHT`].
+    /// All mid-ptrs form a binary tree of height [`MID_PTR_HEIGHT`] (legacy tree, only maintained until the index tree is built).
     mid_ptr: LazyArray<'a, ElusivMapPtr, MID_PTR_COUNT>,
 
     next: JITArray<'a, ElusivMapPtr, CAPACITY>,
@@ -77,6 +167,12 @@ pub struct ElusivMap<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usi
 
     keys: JITArray<'a, K, CAPACITY>,
     values: JITArray<'a, V, CAPACITY>,
+
+    /// Number of index-ptrs that still need to be built (only non-zero after a migration from the legacy format)
+    unindexed_count: Lazy<'a, u16>,
+
+    /// All index-ptrs form a binary tree of height [`INDEX_PTR_HEIGHT`].
+    index_ptr: LazyArray<'a, ElusivMapPtr, INDEX_PTR_COUNT>,
 }
 
 const MAX: u32 = two_pow!(16) as u32;
@@ -87,6 +183,13 @@ impl<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> ElusivMap<'a
         usize_as_u32_safe(CAPACITY)
     };
 
+    /// The size of a map in the legacy format (without the index tree)
+    pub const LEGACY_SIZE: usize =
+        <Self as BorshSerDeSized>::SIZE - u16::SIZE - ElusivMapPtr::SIZE * INDEX_PTR_COUNT;
+
+    /// The maximum number of entries traversed by [`Self::build_index`] per index-ptr
+    pub const MAX_TRAVERSAL_PER_INDEX_PTR: u32 = Self::CAPACITY / INDEX_PTR_COUNT as u32 + 1;
+
     /// Attempts to insert a new entry into the map
     ///
     /// # Note
@@ -123,6 +226,50 @@ impl<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> ElusivMap<'a
         }
     }
 
+    /// Returns the predecessor and successor of a key that is not contained in the map
+    ///
+    /// # Note
+    ///
+    /// A key larger than the max of a full map yields the max as predecessor and no successor.
+    ///
+    /// # Return
+    ///
+    /// Returns [`Err(ElusivMapError::Duplicate(value))`] if the key is contained in the map.
+    pub fn neighbours(&mut self, key: &K) -> Result<ElusivMapNeighbours<K>, ElusivMapError<V>> {
+        let index = match self.binary_search(key) {
+            Ok(index) => index,
+            Err(ElusivMapError::KeyTooLarge) => self.len.get(),
+            Err(e) => return Err(e),
+        };
+
+        if index == 0 {
+            let successor = if self.is_empty() {
+                None
+            } else {
+                Some(self.min())
+            };
+
+            return Ok(ElusivMapNeighbours {
+                predecessor: None,
+                successor,
+            });
+        }
+
+        let min_ptr = self.min_ptr.get();
+        let predecessor_ptr = self.get_ptr(&min_ptr, 0, index - 1);
+        let successor = if index < self.len.get() {
+            let successor_ptr = self.get_next(&predecessor_ptr);
+            Some(self.key(&successor_ptr))
+        } else {
+            None
+        };
+
+        Ok(ElusivMapNeighbours {
+            predecessor: Some(self.key(&predecessor_ptr)),
+            successor,
+        })
+    }
+
     /// Searches for the [`ElusivMapPtr`] at which the key can be inserted
     ///
     /// # Return
@@ -165,8 +312,6 @@ impl<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> ElusivMap<'a
         while low < high {
             mid = low + (high - low) / 2;
 
-            // TODO: keep a subset of traversed ptrs in memory for further speed increase (ptrs used after leaving the mid-ptr tree)
-
             // Compute the `mid_ptr` by moving `mid - low` pointers forward
             mid_ptr = self.get_ptr(&low_ptr, low, mid - low);
 
@@ -178,9 +323,7 @@ impl<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> ElusivMap<'a
                     low = mid + 1;
                     low_ptr = self.get_next(&mid_ptr);
                 }
-                Ordering::Equal => {
-                    return Err(ElusivMapError::Duplicate(self.values.get(mid as usize)))
-                }
+                Ordering::Equal => return Err(ElusivMapError::Duplicate(self.value(&mid_ptr))),
             }
         }
 
@@ -206,6 +349,13 @@ impl<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> ElusivMap<'a
         } else {
             None
         };
+        if is_full && index == self.len.get() - 1 {
+            // The new entry directly replaces the maximum entry (no ptrs change)
+            let max_ptr = self.max_ptr.get();
+            self.set(&max_ptr, key, value);
+            return Ok(Some((max_key.unwrap(), max_value.unwrap())));
+        }
+
         let max_ptr_predecessor = {
             let max_ptr = self.max_ptr.get();
             self.get_prev(&max_ptr)
@@ -242,17 +392,11 @@ impl<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> ElusivMap<'a
             self.link_ptrs(&new_ptr, &next);
         }
 
-        self.update_mid_ptrs(index);
+        self.update_ptr_trees(index);
 
         if is_full {
             // Update `max_ptr`
-            let prev = if index == self.len.get() {
-                new_ptr
-            } else {
-                max_ptr_predecessor
-            };
-
-            self.max_ptr.set(prev);
+            self.max_ptr.set(max_ptr_predecessor);
 
             // Return the previous max key and value
             return Ok(Some((max_key.unwrap(), max_value.unwrap())));
@@ -264,35 +408,299 @@ impl<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> ElusivMap<'a
         Ok(None)
     }
 
-    fn compute_mid(mid_ptr_index: u32, len: u32) -> u32 {
-        ((1 + mid_ptr_index) * len) / SUBSECTIONS
+    /// Removes an entry from the map and returns its value
+    ///
+    /// # Note
+    ///
+    /// The entry stored in the last used slot is moved into the freed slot, so that new entries are still appended linearly.
+    pub fn remove(&mut self, key: &K) -> Result<V, ElusivMapError<V>> {
+        let len = self.len.get();
+        let index = self.rank(key);
+        if index == len {
+            return Err(ElusivMapError::KeyNotFound);
+        }
+
+        let min_ptr = self.min_ptr.get();
+        let ptr = self.get_ptr(&min_ptr, 0, index);
+        if self.key(&ptr) != *key {
+            return Err(ElusivMapError::KeyNotFound);
+        }
+        let value = self.value(&ptr);
+
+        if len == 1 {
+            self.clear();
+            return Ok(value);
+        }
+
+        // The ptr trees are updated before unlinking, since removed entries can be pointed at
+        self.update_ptr_trees_on_removal(index);
+
+        let prev = self.get_prev(&ptr);
+        let next = self.get_next(&ptr);
+        if index == 0 {
+            self.min_ptr.set(next);
+        } else if index == len - 1 {
+            self.max_ptr.set(prev);
+        } else {
+            self.link_ptrs(&prev, &next);
+        }
+
+        // Move the entry of the last slot into the freed slot
+        let last_ptr = ElusivMapPtr((len - 1).try_into().unwrap());
+        if ptr != last_ptr {
+            let last_key = self.key(&last_ptr);
+            let last_value = self.value(&last_ptr);
+            self.set(&ptr, &last_key, &last_value);
+
+            if self.min_ptr.get() == last_ptr {
+                self.min_ptr.set(ptr);
+            } else {
+                let last_prev = self.get_prev(&last_ptr);
+                self.link_ptrs(&last_prev, &ptr);
+            }
+
+            if self.max_ptr.get() == last_ptr {
+                self.max_ptr.set(ptr);
+            } else {
+                let last_next = self.get_next(&last_ptr);
+                self.link_ptrs(&ptr, &last_next);
+            }
+
+            self.replace_tree_ptr(&last_ptr, &ptr);
+        }
+
+        self.len.set(len - 1);
+
+        Ok(value)
     }
 
-    fn update_mid_ptrs(&mut self, insertion_index: u32) {
+    /// Returns an iterator over all entries with `from <= key < to` in ascending order
+    ///
+    /// # Note
+    ///
+    /// Only the entries in the range (and the traversed ptrs) are deserialized.
+    pub fn range(&mut self, from: &K, to: &K) -> ElusivMapRange<'_, 'a, K, V, CAPACITY> {
+        let len = self.len.get();
+        let index = self.rank(from);
+        let ptr = if index < len {
+            let min_ptr = self.min_ptr.get();
+            self.get_ptr(&min_ptr, 0, index)
+        } else {
+            ElusivMapPtr(0)
+        };
+
+        ElusivMapRange {
+            map: self,
+            ptr,
+            remaining: len - index,
+            to: to.clone(),
+        }
+    }
+
+    /// Returns the number of keys smaller than `key`
+    fn rank(&mut self, key: &K) -> u32 {
+        let mut low = 0;
+        let mut high = self.len.get();
+        let mut low_ptr = self.min_ptr.get();
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let mid_ptr = self.get_ptr(&low_ptr, low, mid - low);
+
+            if self.key(&mid_ptr) < *key {
+                low = mid + 1;
+                low_ptr = self.get_next(&mid_ptr);
+            } else {
+                high = mid;
+            }
+        }
+
+        low
+    }
+
+    /// Resets the map and all ptrs
+    fn clear(&mut self) {
+        self.reset();
+        self.min_ptr.set(ElusivMapPtr(0));
+
+        for i in 0..MID_PTR_COUNT {
+            self.mid_ptr.set(i, ElusivMapPtr(0));
+        }
+
+        let indexed_count = INDEX_PTR_COUNT - self.unindexed_count.get() as usize;
+        for i in 0..indexed_count {
+            self.index_ptr.set(i, ElusivMapPtr(0));
+        }
+    }
+
+    /// The tree used for traversals
+    fn ptr_tree(&mut self) -> PtrTree {
+        if self.unindexed_count.get() == 0 {
+            PtrTree::Index
+        } else {
+            PtrTree::Mid
+        }
+    }
+
+    fn get_tree_ptr(&mut self, tree: PtrTree, ptr_index: usize) -> ElusivMapPtr {
+        match tree {
+            PtrTree::Mid => self.mid_ptr.get(ptr_index),
+            PtrTree::Index => self.index_ptr.get(ptr_index),
+        }
+    }
+
+    fn set_tree_ptr(&mut self, tree: PtrTree, ptr_index: usize, ptr: ElusivMapPtr) {
+        match tree {
+            PtrTree::Mid => self.mid_ptr.set(ptr_index, ptr),
+            PtrTree::Index => self.index_ptr.set(ptr_index, ptr),
+        }
+    }
+
+    fn update_ptr_trees(&mut self, insertion_index: u32) {
+        match self.ptr_tree() {
+            PtrTree::Index => {
+                self.update_tree_ptrs(PtrTree::Index, INDEX_PTR_COUNT, insertion_index)
+            }
+            PtrTree::Mid => {
+                self.update_tree_ptrs(PtrTree::Mid, MID_PTR_COUNT, insertion_index);
+
+                // Already built index-ptrs need to be maintained as well
+                let indexed_count = INDEX_PTR_COUNT - self.unindexed_count.get() as usize;
+                self.update_tree_ptrs(PtrTree::Index, indexed_count, insertion_index);
+            }
+        }
+    }
+
+    /// Updates the first `count` ptrs of a tree after an insertion
+    fn update_tree_ptrs(&mut self, tree: PtrTree, count: usize, insertion_index: u32) {
         let len = self.len.get();
         let is_full = self.is_full();
         let new_len = len + u32::from(!self.is_full());
 
-        for i in 0..MID_PTR_COUNT {
-            let mid = Self::compute_mid(i as u32, len);
-            let new_mid = Self::compute_mid(i as u32, new_len);
+        for i in 0..count {
+            let mid = tree.compute_mid(i as u32, len);
+            let new_mid = tree.compute_mid(i as u32, new_len);
 
             if insertion_index <= mid {
                 if mid == new_mid || is_full {
                     // Adjust by decreasing by one ptr
-                    let mid_ptr = self.mid_ptr.get(i);
+                    let mid_ptr = self.get_tree_ptr(tree, i);
                     let next_mid_ptr = self.get_prev(&mid_ptr);
-                    self.mid_ptr.set(i, next_mid_ptr);
+                    self.set_tree_ptr(tree, i, next_mid_ptr);
                 }
             } else if mid != new_mid {
                 // Adjust by increasing by one ptr
-                let mid_ptr = self.mid_ptr.get(i);
+                let mid_ptr = self.get_tree_ptr(tree, i);
                 let next_mid_ptr = self.get_next(&mid_ptr);
-                self.mid_ptr.set(i, next_mid_ptr);
+                self.set_tree_ptr(tree, i, next_mid_ptr);
+            }
+        }
+    }
+
+    fn update_ptr_trees_on_removal(&mut self, removal_index: u32) {
+        match self.ptr_tree() {
+            PtrTree::Index => {
+                self.update_tree_ptrs_on_removal(PtrTree::Index, INDEX_PTR_COUNT, removal_index)
+            }
+            PtrTree::Mid => {
+                self.update_tree_ptrs_on_removal(PtrTree::Mid, MID_PTR_COUNT, removal_index);
+
+                let indexed_count = INDEX_PTR_COUNT - self.unindexed_count.get() as usize;
+                self.update_tree_ptrs_on_removal(PtrTree::Index, indexed_count, removal_index);
+            }
+        }
+    }
+
+    /// Updates the first `count` ptrs of a tree before a removal
+    fn update_tree_ptrs_on_removal(&mut self, tree: PtrTree, count: usize, removal_index: u32) {
+        let len = self.len.get();
+
+        for i in 0..count {
+            let mid = tree.compute_mid(i as u32, len);
+            let new_mid = tree.compute_mid(i as u32, len - 1);
+
+            // Entries after the removed entry move one position down
+            let move_to_next = removal_index <= mid && mid == new_mid;
+            let move_to_prev = mid <= removal_index && mid != new_mid;
+
+            if move_to_next || move_to_prev {
+                let mid_ptr = self.get_tree_ptr(tree, i);
+                let ptr = if move_to_next {
+                    self.get_next(&mid_ptr)
+                } else {
+                    self.get_prev(&mid_ptr)
+                };
+                self.set_tree_ptr(tree, i, ptr);
+            }
+        }
+    }
+
+    /// Replaces all (maintained) tree ptrs pointing to `ptr` with `new_ptr`
+    fn replace_tree_ptr(&mut self, ptr: &ElusivMapPtr, new_ptr: &ElusivMapPtr) {
+        let mut trees = vec![(
+            PtrTree::Index,
+            INDEX_PTR_COUNT - self.unindexed_count.get() as usize,
+        )];
+        if self.ptr_tree() == PtrTree::Mid {
+            trees.push((PtrTree::Mid, MID_PTR_COUNT));
+        }
+
+        for (tree, count) in trees {
+            for i in 0..count {
+                if self.get_tree_ptr(tree, i) == *ptr {
+                    self.set_tree_ptr(tree, i, *new_ptr);
+                }
             }
         }
     }
 
+    /// Marks all index-ptrs as unbuilt (called once after reallocating a map in the legacy format)
+    pub fn start_index_migration(&mut self) {
+        self.unindexed_count.set(INDEX_PTR_COUNT as u16);
+    }
+
+    /// Builds up to `count` of the missing index-ptrs
+    ///
+    /// # Note
+    ///
+    /// Each index-ptr requires a traversal of at most [`Self::MAX_TRAVERSAL_PER_INDEX_PTR`] entries.
+    ///
+    /// # Return
+    ///
+    /// Returns `true` if the index tree is complete.
+    pub fn build_index(&mut self, count: u32) -> bool {
+        let len = self.len.get();
+
+        for _ in 0..count {
+            let unindexed_count = self.unindexed_count.get();
+            if unindexed_count == 0 {
+                break;
+            }
+
+            // Index-ptrs are built in ascending order, starting from the min or the previous index-ptr
+            let i = (INDEX_PTR_COUNT - unindexed_count as usize) as u32;
+            let (base_ptr, base_ptr_offset) = if i == 0 {
+                (self.min_ptr.get(), 0)
+            } else {
+                (
+                    self.index_ptr.get(i as usize - 1),
+                    PtrTree::Index.compute_mid(i - 1, len),
+                )
+            };
+
+            let mid = PtrTree::Index.compute_mid(i, len);
+            let ptr = self.get_next_ptr(&base_ptr, mid - base_ptr_offset);
+            self.index_ptr.set(i as usize, ptr);
+            self.unindexed_count.set(unindexed_count - 1);
+        }
+
+        self.is_indexed()
+    }
+
+    pub fn is_indexed(&mut self) -> bool {
+        self.unindexed_count.get() == 0
+    }
+
     /// Traverses the pointer graph and returns the pointer with a distance of `offset` from the `base_ptr`
     fn get_ptr(
         &mut self,
@@ -300,54 +708,51 @@ impl<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> ElusivMap<'a
         base_ptr_offset: u32,
         offset: u32,
     ) -> ElusivMapPtr {
-        let distance = self.len.get() / (MID_PTR_COUNT as u32 + 1);
+        let tree = self.ptr_tree();
+        let distance = self.len.get() / tree.subsections();
 
         if distance == 0 || offset <= 1 {
             self.get_next_ptr(base_ptr, offset)
         } else {
             let index = base_ptr_offset + offset;
             let len = self.len.get();
-            let mut step = MID_PTR_COUNT / 2;
+            let mut step = tree.count() / 2;
             let mut mid_ptr_index = step;
-            for i in 1..=MID_PTR_HEIGHT {
-                let mid = Self::compute_mid(mid_ptr_index as u32, len);
-                step = two_pow!(MID_PTR_HEIGHT - i);
+            for i in 1..=tree.height() {
+                let mid = tree.compute_mid(mid_ptr_index as u32, len);
+                step = two_pow!(tree.height() - i);
 
                 match index.cmp(&mid) {
                     Ordering::Less => {
                         mid_ptr_index -= step;
                     }
-                    Ordering::Equal => return self.mid_ptr.get(mid_ptr_index),
+                    Ordering::Equal => return self.get_tree_ptr(tree, mid_ptr_index),
                     Ordering::Greater => {
                         mid_ptr_index += step;
                     }
                 }
             }
 
-            // if offset <= 8 {
-            //     return self.get_next_ptr(base_ptr, offset);
-            // }
-
-            let mid = Self::compute_mid(mid_ptr_index as u32, len);
+            let mid = tree.compute_mid(mid_ptr_index as u32, len);
             let (d, ptr) = match index.cmp(&mid) {
                 Ordering::Less => {
                     let base = if mid_ptr_index == 0 {
                         0
                     } else {
-                        Self::compute_mid(mid_ptr_index as u32 - 1, len)
+                        tree.compute_mid(mid_ptr_index as u32 - 1, len)
                     };
 
                     let ptr = if mid_ptr_index == 0 {
                         self.min_ptr.get()
                     } else {
-                        self.mid_ptr.get(mid_ptr_index - 1)
+                        self.get_tree_ptr(tree, mid_ptr_index - 1)
                     };
 
                     (index - base, ptr)
                 }
-                Ordering::Equal => return self.mid_ptr.get(mid_ptr_index),
+                Ordering::Equal => return self.get_tree_ptr(tree, mid_ptr_index),
                 Ordering::Greater => {
-                    let ptr = self.mid_ptr.get(mid_ptr_index);
+                    let ptr = self.get_tree_ptr(tree, mid_ptr_index);
                     (index - mid, ptr)
                 }
             };
@@ -433,8 +838,8 @@ impl<'a, K: ElusivMapKey, V: ElusivMapValue, const CAPACITY: usize> ElusivMap<'a
 
     #[cfg(test)]
     fn mid(&mut self) -> K {
-        let mid_ptr = self.mid_ptr.get(MID_PTR_COUNT / 2);
-        // let mid_ptr = self.mid_ptr.get();
+        let tree = self.ptr_tree();
+        let mid_ptr = self.get_tree_ptr(tree, tree.count() / 2);
         self.keys.get(mid_ptr.0 as usize)
     }
 
@@ -507,8 +912,12 @@ pub fn permute<T: Clone + Sized>(v: &[T]) -> Vec<Vec<T>> {
 mod tests {
     use super::*;
 
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::collections::BTreeMap;
+
     impl_map_key!(u16);
     impl_map_value!(u16);
+    impl_map_value!(u32);
 
     type Map<'a> = ElusivMap<'a, u16, u16, 7>;
 
@@ -531,11 +940,27 @@ mod tests {
 
     #[test]
     fn test_compute_mid() {
-        assert_eq!(Map::compute_mid(0, 100), 100 / (MID_PTR_COUNT as u32 + 1));
         assert_eq!(
-            Map::compute_mid(1, 100),
+            PtrTree::Mid.compute_mid(0, 100),
+            100 / (MID_PTR_COUNT as u32 + 1)
+        );
+        assert_eq!(
+            PtrTree::Mid.compute_mid(1, 100),
             2 * 100 / (MID_PTR_COUNT as u32 + 1)
         );
+
+        assert_eq!(
+            PtrTree::Index.compute_mid(0, 1000),
+            1000 / (INDEX_PTR_COUNT as u32 + 1)
+        );
+
+        // Each mid-ptr has an equivalent index-ptr
+        for i in 0..MID_PTR_COUNT as u32 {
+            assert_eq!(
+                PtrTree::Mid.compute_mid(i, 1000),
+                PtrTree::Index.compute_mid((i + 1) * 16 - 1, 1000)
+            );
+        }
     }
 
     #[test]
@@ -720,6 +1145,17 @@ mod tests {
         assert_eq!(map.try_insert_default(0).unwrap().unwrap().0, 7);
         assert_eq!(map.min(), 0);
         assert_eq!(map.max(), 6);
+
+        // Replace the max
+        map!(map);
+        map.insert_multiple_default(&(0..7).map(|i| i * 2).collect::<Vec<u16>>());
+        assert_eq!(map.try_insert_default(11).unwrap().unwrap().0, 12);
+        assert_eq!(map.max(), 11);
+        assert_eq!(map.sorted_keys(), vec![0, 2, 4, 6, 8, 10, 11]);
+
+        assert_eq!(map.try_insert_default(9).unwrap().unwrap().0, 11);
+        assert_eq!(map.max(), 10);
+        assert_eq!(map.sorted_keys(), vec![0, 2, 4, 6, 8, 9, 10]);
     }
 
     #[test]
@@ -737,6 +1173,313 @@ mod tests {
         assert!(map.contains(&8).is_some());
     }
 
+    #[test]
+    fn test_neighbours() {
+        map!(map);
+
+        assert_eq!(
+            map.neighbours(&3).unwrap(),
+            ElusivMapNeighbours {
+                predecessor: None,
+                successor: None
+            }
+        );
+
+        map.insert_multiple_default(&[2, 4, 6, 8, 10, 12]);
+
+        assert_eq!(map.neighbours(&4), Err(ElusivMapError::Duplicate(0)));
+        assert_eq!(
+            map.neighbours(&1).unwrap(),
+            ElusivMapNeighbours {
+                predecessor: None,
+                successor: Some(2)
+            }
+        );
+        assert_eq!(
+            map.neighbours(&7).unwrap(),
+            ElusivMapNeighbours {
+                predecessor: Some(6),
+                successor: Some(8)
+            }
+        );
+        assert_eq!(
+            map.neighbours(&13).unwrap(),
+            ElusivMapNeighbours {
+                predecessor: Some(12),
+                successor: None
+            }
+        );
+
+        // Full map
+        map.try_insert_default(14).unwrap();
+        assert!(map.is_full());
+        assert_eq!(
+            map.neighbours(&15).unwrap(),
+            ElusivMapNeighbours {
+                predecessor: Some(14),
+                successor: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_neighbours_large_map() {
+        type Map<'a> = ElusivMap<'a, u32, (), 1000>;
+        let mut data = vec![0; Map::SIZE];
+        let mut map = Map::new(&mut data);
+
+        for i in (0..1000).rev() {
+            map.try_insert_default(i * 2 + 1).unwrap();
+        }
+
+        for i in 0..1000 {
+            let neighbours = map.neighbours(&(i * 2 + 2)).unwrap();
+            assert_eq!(neighbours.predecessor, Some(i * 2 + 1));
+            assert_eq!(
+                neighbours.successor,
+                if i < 999 { Some(i * 2 + 3) } else { None }
+            );
+        }
+    }
+
+    fn assert_ptr_tree<V: ElusivMapValue, const CAPACITY: usize>(
+        map: &mut ElusivMap<u32, V, CAPACITY>,
+        tree: PtrTree,
+        count: usize,
+    ) {
+        let keys = map.sorted_keys();
+        let len = map.len.get();
+        for i in 0..count {
+            let ptr = map.get_tree_ptr(tree, i);
+            let mid = tree.compute_mid(i as u32, len) as usize;
+            assert_eq!(map.key(&ptr), keys[mid]);
+        }
+    }
+
+    #[test]
+    fn test_index_migration() {
+        type Map<'a> = ElusivMap<'a, u32, (), 5000>;
+        let mut data = vec![0; Map::SIZE];
+        let mut map = Map::new(&mut data);
+
+        // Map in the legacy format
+        map.start_index_migration();
+        assert!(!map.is_indexed());
+        for i in (0..2000).rev() {
+            map.try_insert_default(i * 3).unwrap();
+        }
+        assert_ptr_tree(&mut map, PtrTree::Mid, MID_PTR_COUNT);
+
+        // Insertions during the migration
+        let mut i = 0;
+        while !map.build_index(16) {
+            map.try_insert_default(i * 3 + 1).unwrap();
+
+            let indexed_count = INDEX_PTR_COUNT - map.unindexed_count.get() as usize;
+            assert_ptr_tree(&mut map, PtrTree::Mid, MID_PTR_COUNT);
+            assert_ptr_tree(&mut map, PtrTree::Index, indexed_count);
+            i += 1;
+        }
+
+        assert!(map.is_indexed());
+        assert_eq!(map.ptr_tree(), PtrTree::Index);
+        assert!(map.build_index(1));
+        assert_ptr_tree(&mut map, PtrTree::Index, INDEX_PTR_COUNT);
+
+        // Insertions after the migration
+        for i in 0..1000 {
+            map.try_insert_default(i * 3 + 2).unwrap();
+        }
+        assert_ptr_tree(&mut map, PtrTree::Index, INDEX_PTR_COUNT);
+
+        let mut keys: Vec<u32> = (0..2000)
+            .map(|i| i * 3)
+            .chain((0..i).map(|i| i * 3 + 1))
+            .chain((0..1000).map(|i| i * 3 + 2))
+            .collect();
+        keys.sort();
+        assert_eq!(map.sorted_keys(), keys);
+
+        for key in keys {
+            assert!(map.contains(&key).is_some());
+        }
+    }
+
+    #[test]
+    fn test_legacy_size() {
+        assert_eq!(
+            Map::LEGACY_SIZE,
+            4 + 2 + 2 + 2 * MID_PTR_COUNT + 7 * (2 + 2 + 2 + 2)
+        );
+        assert_eq!(Map::SIZE, Map::LEGACY_SIZE + 2 + 2 * INDEX_PTR_COUNT);
+    }
+
+    #[test]
+    fn test_remove() {
+        map!(map);
+
+        assert_eq!(map.remove(&0), Err(ElusivMapError::KeyNotFound));
+
+        map.insert_multiple(&[(3, 103), (1, 101), (5, 105), (0, 100), (4, 104)]);
+        assert_eq!(map.remove(&2), Err(ElusivMapError::KeyNotFound));
+        assert_eq!(map.remove(&6), Err(ElusivMapError::KeyNotFound));
+
+        // Mid
+        assert_eq!(map.remove(&3), Ok(103));
+        assert_eq!(map.sorted_keys(), [0, 1, 4, 5]);
+        assert_eq!(map.values_sorted_by_keys(), [100, 101, 104, 105]);
+
+        // Min
+        assert_eq!(map.remove(&0), Ok(100));
+        assert_eq!(map.min(), 1);
+
+        // Max
+        assert_eq!(map.remove(&5), Ok(105));
+        assert_eq!(map.max(), 4);
+        assert_eq!(map.len.get(), 2);
+        assert!(map.contains(&5).is_none());
+
+        // Freed slots are reused
+        map.insert_multiple(&[(2, 102), (6, 106), (7, 107), (8, 108), (9, 109)]);
+        assert!(map.is_full());
+        assert_eq!(map.sorted_keys(), [1, 2, 4, 6, 7, 8, 9]);
+        assert_eq!(
+            map.values_sorted_by_keys(),
+            [101, 102, 104, 106, 107, 108, 109]
+        );
+
+        // Removing all entries
+        for key in [9, 1, 6, 2, 8, 4, 7] {
+            map.remove(&key).unwrap();
+        }
+        assert!(map.is_empty());
+
+        map.insert_multiple(&[(1, 1), (0, 0)]);
+        assert_eq!(map.sorted_keys(), [0, 1]);
+    }
+
+    #[test]
+    fn test_range() {
+        map!(map);
+
+        assert_eq!(map.range(&0, &10).count(), 0);
+
+        map.insert_multiple(&[(2, 102), (4, 104), (6, 106), (8, 108)]);
+        assert_eq!(map.range(&3, &7).collect::<Vec<_>>(), [(4, 104), (6, 106)]);
+        assert_eq!(map.range(&2, &4).collect::<Vec<_>>(), [(2, 102)]);
+        assert_eq!(
+            map.range(&0, &100).map(|(k, _)| k).collect::<Vec<_>>(),
+            [2, 4, 6, 8]
+        );
+        assert_eq!(map.range(&5, &5).count(), 0);
+        assert_eq!(map.range(&7, &3).count(), 0);
+        assert_eq!(map.range(&9, &100).count(), 0);
+    }
+
+    /// Applies random insertions and removals to both an [`ElusivMap`] and a [`BTreeMap`] model
+    ///
+    /// # Note
+    ///
+    /// The map is filled to its capacity in the second phase, so that insertions evict the maximum entry.
+    fn model_test(seed: u64, legacy: bool) {
+        const CAPACITY: usize = 1024;
+        type Map<'a> = ElusivMap<'a, u32, u32, CAPACITY>;
+        const KEYS: u32 = 4 * CAPACITY as u32;
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut data = vec![0; Map::SIZE];
+        let mut map = Map::new(&mut data);
+        let mut model = BTreeMap::new();
+        let mut evictions = 0;
+        if legacy {
+            map.start_index_migration();
+        }
+
+        for i in 0..4500 {
+            let key = rng.gen_range(0..KEYS);
+
+            // Insertions are more likely in the first phase, only insertions happen in the second phase (filling the map)
+            let insertion_probability = match i {
+                0..=1499 => 0.8,
+                1500..=2999 => 1.0,
+                _ => 0.3,
+            };
+            if rng.gen_bool(insertion_probability) {
+                let value = rng.gen();
+                match map.try_insert(key, &value) {
+                    Ok(None) => {
+                        assert!(model.len() < CAPACITY);
+                        assert!(model.insert(key, value).is_none());
+                    }
+                    Ok(Some(moved)) => {
+                        // The maximum entry (possibly the new one) is dropped
+                        assert_eq!(model.len(), CAPACITY);
+                        assert!(model.insert(key, value).is_none());
+                        let max = *model.keys().next_back().unwrap();
+                        assert_eq!(model.remove_entry(&max), Some(moved));
+                        evictions += 1;
+                    }
+                    Err(ElusivMapError::Duplicate(v)) => assert_eq!(model.get(&key), Some(&v)),
+                    _ => panic!(),
+                }
+            } else {
+                match map.remove(&key) {
+                    Ok(v) => assert_eq!(model.remove(&key), Some(v)),
+                    Err(ElusivMapError::KeyNotFound) => assert!(!model.contains_key(&key)),
+                    _ => panic!(),
+                }
+            }
+
+            assert_eq!(map.is_full(), model.len() == CAPACITY);
+            if !model.is_empty() {
+                assert_eq!(map.max(), *model.keys().next_back().unwrap());
+            }
+
+            let key = rng.gen_range(0..KEYS);
+            assert_eq!(map.contains(&key), model.get(&key).copied());
+
+            let from = rng.gen_range(0..KEYS);
+            let to = rng.gen_range(from..=KEYS);
+            assert_eq!(
+                map.range(&from, &to).collect::<Vec<_>>(),
+                model
+                    .range(from..to)
+                    .map(|(k, v)| (*k, *v))
+                    .collect::<Vec<_>>()
+            );
+
+            if i % 100 == 0 {
+                assert_eq!(map.sorted_keys(), model.keys().copied().collect::<Vec<_>>());
+                assert_eq!(
+                    map.values_sorted_by_keys(),
+                    model.values().copied().collect::<Vec<_>>()
+                );
+
+                if !map.is_empty() {
+                    let indexed_count = INDEX_PTR_COUNT - map.unindexed_count.get() as usize;
+                    assert_ptr_tree(&mut map, PtrTree::Index, indexed_count);
+                    if legacy {
+                        assert_ptr_tree(&mut map, PtrTree::Mid, MID_PTR_COUNT);
+                    }
+                }
+
+                if legacy {
+                    map.build_index(4);
+                }
+            }
+        }
+
+        assert!(evictions > 0);
+    }
+
+    #[test]
+    fn test_model() {
+        for seed in 0..8 {
+            model_test(seed, false);
+            model_test(seed, true);
+        }
+    }
+
     #[test]
     fn test_reset() {
         map!(map);
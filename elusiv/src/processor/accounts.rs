@@ -5,25 +5,32 @@ use crate::error::ElusivError;
 use crate::macros::*;
 use crate::state::commitment::{
     BaseCommitmentBufferAccount, CommitmentBufferAccount, CommitmentHashingAccount,
-    CommitmentQueue, CommitmentQueueAccount,
+    CommitmentQueue, CommitmentQueueAccount, COMMITMENT_HASHING_ACCOUNTS_COUNT,
 };
-use crate::state::metadata::{MetadataAccount, MetadataQueueAccount};
+use crate::state::metadata::{EncryptedMetadataAccount, MetadataAccount, MetadataQueueAccount};
 use crate::state::queue::RingQueue;
 use crate::state::{
     fee::{FeeAccount, ProgramFee},
-    governor::{FeeCollectorAccount, GovernorAccount, PoolAccount},
-    nullifier::{NullifierAccount, NullifierChildAccount},
+    governor::{
+        FeeCollectorAccount, GovernorAccount, PoolAccount, RateLimitAccount, RootHistoryParameters,
+    },
+    nullifier::{NullifierAccount, NullifierChildAccount, NullifierMap},
+    proof::VerificationAccount,
     queue::Queue,
-    storage::{StorageAccount, MT_COMMITMENT_COUNT},
+    storage::{RootHistoryAccount, StorageAccount, MAX_ROOT_HISTORY_DEPTH, MT_COMMITMENT_COUNT},
+    vkey::VKeyAccount,
 };
+use crate::token::SPL_TOKEN_COUNT;
 use crate::{bytes::usize_as_u32_safe, map::ElusivMap};
+use borsh::{BorshDeserialize, BorshSerialize};
 use elusiv_types::{
-    split_child_account_data_mut, ChildAccount, ChildAccountConfig, ParentAccount, SizedAccount,
+    child_account_size, split_child_account_data_mut, ChildAccount, ChildAccountConfig,
+    MigratableAccount, PDAAccount, PDAAccountData, ParentAccount, SizedAccount,
     UnverifiedAccountInfo,
 };
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError, rent::Rent,
-    sysvar::Sysvar,
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey, rent::Rent, sysvar::Sysvar,
 };
 
 /// Opens one single instance [`elusiv_types::PDAAccount`], as long this PDA does not already exist
@@ -91,6 +98,28 @@ pub fn open_nullifier_account<'b>(
     )
 }
 
+/// Opens an additional [`CommitmentHashingAccount`] for concurrent commitment hashing
+pub fn open_commitment_hashing_account<'b>(
+    payer: &AccountInfo<'b>,
+    commitment_hashing_account: UnverifiedAccountInfo<'_, 'b>,
+
+    hashing_account_index: u32,
+) -> ProgramResult {
+    // Index zero is the single instance account
+    guard!(
+        hashing_account_index > 0 && hashing_account_index < COMMITMENT_HASHING_ACCOUNTS_COUNT,
+        ElusivError::InvalidInstructionData
+    );
+
+    open_pda_account_with_offset::<CommitmentHashingAccount>(
+        &crate::id(),
+        payer,
+        commitment_hashing_account.get_unsafe(),
+        hashing_account_index,
+        None,
+    )
+}
+
 /// Enables the supplied child-account for the [`StorageAccount`]
 pub fn enable_storage_child_account(
     storage_account: &mut StorageAccount,
@@ -154,6 +183,66 @@ pub fn enable_metadata_child_account(
     )
 }
 
+/// Opens the [`EncryptedMetadataAccount`]
+pub fn open_encrypted_metadata_account<'b>(
+    payer: &AccountInfo<'b>,
+    encrypted_metadata_account: UnverifiedAccountInfo<'_, 'b>,
+) -> ProgramResult {
+    open_pda_account_without_offset::<EncryptedMetadataAccount>(
+        &crate::id(),
+        payer,
+        encrypted_metadata_account.get_unsafe(),
+        None,
+    )
+}
+
+/// Enables the supplied child-account for the [`EncryptedMetadataAccount`]
+pub fn enable_encrypted_metadata_child_account(
+    encrypted_metadata_account: &mut EncryptedMetadataAccount,
+    child_account: &AccountInfo,
+
+    child_index: u32,
+) -> ProgramResult {
+    // Note: we don't zero-check these accounts, since each entry is fully overwritten before it can be read
+    setup_child_account(
+        encrypted_metadata_account,
+        child_account,
+        child_index as usize,
+        false,
+        None,
+    )
+}
+
+/// Opens the [`RootHistoryAccount`]
+pub fn open_root_history_account<'b>(
+    payer: &AccountInfo<'b>,
+    root_history_account: UnverifiedAccountInfo<'_, 'b>,
+) -> ProgramResult {
+    open_pda_account_without_offset::<RootHistoryAccount>(
+        &crate::id(),
+        payer,
+        root_history_account.get_unsafe(),
+        None,
+    )
+}
+
+/// Enables the supplied child-account for the [`RootHistoryAccount`]
+pub fn enable_root_history_child_account(
+    root_history_account: &mut RootHistoryAccount,
+    child_account: &AccountInfo,
+
+    child_index: u32,
+) -> ProgramResult {
+    // Note: we don't zero-check these accounts, since entries are only read after being written (see `RootHistoryAccount::contains_root`)
+    setup_child_account(
+        root_history_account,
+        child_account,
+        child_index as usize,
+        false,
+        None,
+    )
+}
+
 /// Closes the active MT and activates the next one
 ///
 /// # Notes
@@ -161,6 +250,8 @@ pub fn enable_metadata_child_account(
 /// There are two scenarios in which this is required/allowed:
 ///     1. the active MT is full
 ///     2. the active MT is not full but the remaining places in the MT are < than the batching rate of the next commitment in the commitment queue
+///
+/// The leaves of the active MT remain available through the [`crate::state::storage::MerkleTreeInsertionEvent`]s emitted during their insertion.
 pub fn reset_active_merkle_tree(
     storage_account: &mut StorageAccount,
     queue: &mut CommitmentQueueAccount,
@@ -179,6 +270,12 @@ pub fn reset_active_merkle_tree(
         ElusivError::MerkleTreeIsNotFullYet
     );
 
+    // All claimed batches have to be inserted before the reset
+    guard!(
+        storage_account.no_claimed_batches(),
+        ElusivError::ComputationIsNotYetFinished
+    );
+
     storage_account.set_trees_count(&(active_merkle_tree_index.checked_add(1).ok_or(MATH_ERR)?));
     active_nullifier_account.set_root(&storage_account.get_root()?);
     storage_account.reset();
@@ -247,7 +344,7 @@ fn is_mt_full(
         return Ok(true);
     }
 
-    let commitments_count = storage_account.get_next_commitment_ptr() as usize;
+    let commitments_count = storage_account.next_claimable_commitment_ptr() as usize;
     let queue_len = queue.next_batch()?.0.len();
     if commitments_count + queue_len >= MT_COMMITMENT_COUNT {
         return Ok(true);
@@ -290,6 +387,7 @@ pub fn setup_governor_account<'b>(
 
     pda_account!(mut governor, GovernorAccount, governor_account.get_unsafe());
     governor.set_commitment_batching_rate(&usize_as_u32_safe(DEFAULT_COMMITMENT_BATCHING_RATE));
+    governor.set_root_history(&RootHistoryParameters::default());
 
     Ok(())
 }
@@ -343,6 +441,217 @@ pub fn init_new_fee_version<'b>(
     Ok(())
 }
 
+/// The program accounts that can be migrated to the current version of their data layout
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigratableAccountKind {
+    Governor,
+    BaseCommitmentBuffer,
+    CommitmentBuffer,
+    Storage,
+    VKey,
+    Verification,
+}
+
+/// Migrates a program account with an outdated data layout to its current [`elusiv_types::VersionedAccount::VERSION`]
+///
+/// # Note
+///
+/// `pda_pubkey` and `pda_offset` are used to verify the PDA of `account`.
+pub fn migrate_account<'a>(
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+
+    kind: MigratableAccountKind,
+    pda_pubkey: Option<Pubkey>,
+    pda_offset: Option<u32>,
+) -> ProgramResult {
+    match kind {
+        MigratableAccountKind::Governor => {
+            migrate_account_of_type::<GovernorAccount>(payer, account, pda_pubkey, pda_offset)
+        }
+        MigratableAccountKind::BaseCommitmentBuffer => {
+            migrate_account_of_type::<BaseCommitmentBufferAccount>(
+                payer, account, pda_pubkey, pda_offset,
+            )
+        }
+        MigratableAccountKind::CommitmentBuffer => {
+            migrate_account_of_type::<CommitmentBufferAccount>(
+                payer, account, pda_pubkey, pda_offset,
+            )
+        }
+        MigratableAccountKind::Storage => {
+            migrate_account_of_type::<StorageAccount>(payer, account, pda_pubkey, pda_offset)
+        }
+        MigratableAccountKind::VKey => {
+            migrate_account_of_type::<VKeyAccount>(payer, account, pda_pubkey, pda_offset)
+        }
+        MigratableAccountKind::Verification => {
+            migrate_account_of_type::<VerificationAccount>(payer, account, pda_pubkey, pda_offset)
+        }
+    }
+}
+
+fn migrate_account_of_type<'a, T: MigratableAccount + PDAAccount>(
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    pda_pubkey: Option<Pubkey>,
+    pda_offset: Option<u32>,
+) -> ProgramResult {
+    match pda_pubkey {
+        Some(pubkey) => T::verify_account_with_pubkey(account, pubkey, pda_offset)?,
+        None => T::verify_account(account, pda_offset)?,
+    }
+
+    let version = PDAAccountData::new(&account.data.borrow())?.version;
+    guard!(version < T::VERSION, ElusivError::InvalidAccountState);
+
+    migrate_pda_account::<T>(payer, account)
+}
+
+/// The number of index-ptrs built by a single [`build_nullifier_child_account_index`] instruction
+pub const NULLIFIER_MAP_INDEX_PTRS_PER_INSTRUCTION: u32 = 32;
+
+/// Migrates a [`NullifierAccount`] child-account from the legacy [`NullifierMap`] format
+///
+/// # Notes
+///
+/// - `payer` funds the additional rent.
+/// - Until the index has been built with [`build_nullifier_child_account_index`], the map uses its legacy mid-ptr tree.
+pub fn migrate_nullifier_child_account<'a>(
+    payer: &AccountInfo<'a>,
+    nullifier_account: &NullifierAccount,
+    child_account: &AccountInfo<'a>,
+
+    _mt_index: u32,
+    child_index: u32,
+) -> ProgramResult {
+    verify_nullifier_child_account(nullifier_account, child_account, child_index)?;
+    guard!(
+        child_account.data_len() == child_account_size(NullifierMap::LEGACY_SIZE),
+        ElusivError::InvalidAccountState
+    );
+
+    grow_account(payer, child_account, NullifierChildAccount::SIZE)?;
+
+    let data = &mut child_account.data.borrow_mut()[..];
+    let (_, inner_data) = split_child_account_data_mut(data)?;
+    NullifierMap::new(inner_data).start_index_migration();
+
+    Ok(())
+}
+
+/// Builds [`NULLIFIER_MAP_INDEX_PTRS_PER_INSTRUCTION`] index-ptrs of a migrated [`NullifierAccount`] child-account
+pub fn build_nullifier_child_account_index(
+    nullifier_account: &NullifierAccount,
+    child_account: &AccountInfo,
+
+    _mt_index: u32,
+    child_index: u32,
+) -> ProgramResult {
+    verify_nullifier_child_account(nullifier_account, child_account, child_index)?;
+    guard!(
+        child_account.data_len() == NullifierChildAccount::SIZE,
+        ElusivError::InvalidAccountState
+    );
+
+    let data = &mut child_account.data.borrow_mut()[..];
+    let (_, inner_data) = split_child_account_data_mut(data)?;
+    let mut map = NullifierMap::new(inner_data);
+    guard!(!map.is_indexed(), ElusivError::InvalidAccountState);
+    map.build_index(NULLIFIER_MAP_INDEX_PTRS_PER_INSTRUCTION);
+
+    Ok(())
+}
+
+fn verify_nullifier_child_account(
+    nullifier_account: &NullifierAccount,
+    child_account: &AccountInfo,
+    child_index: u32,
+) -> ProgramResult {
+    guard!(
+        (child_index as usize) < NullifierAccount::COUNT,
+        ElusivError::InvalidAccount
+    );
+    guard!(
+        nullifier_account.get_child_pubkey(child_index as usize) == Some(*child_account.key),
+        ElusivError::InvalidAccount
+    );
+
+    Ok(())
+}
+
+/// Configures the [`RateLimits`] of the [`GovernorAccount`] for a single token
+///
+/// # Notes
+///
+/// - `signer` needs to be the program's keypair (and funds the [`RateLimitAccount`] of `token_id`, if not yet opened).
+/// - Changing `window_slots` restarts the window of each token at its next deposit or withdrawal.
+pub fn set_rate_limits<'a>(
+    signer: &AccountInfo<'a>,
+    governor: &mut GovernorAccount,
+    rate_limit_account: &AccountInfo<'a>,
+
+    window_slots: u64,
+    token_id: u16,
+    deposit_cap: u64,
+    withdrawal_cap: u64,
+) -> ProgramResult {
+    guard!(
+        signer.is_signer && *signer.key == crate::ID,
+        ElusivError::InvalidAccount
+    );
+    guard!(
+        (token_id as usize) <= SPL_TOKEN_COUNT,
+        ElusivError::UnsupportedToken
+    );
+
+    // The window of `token_id` is tracked by its `RateLimitAccount`
+    if rate_limit_account.data_is_empty() {
+        open_pda_account_with_offset::<RateLimitAccount>(
+            &crate::id(),
+            signer,
+            rate_limit_account,
+            token_id.into(),
+            None,
+        )?;
+    }
+
+    let mut rate_limits = governor.get_rate_limits();
+    rate_limits.window_slots = window_slots;
+    rate_limits.deposit_caps[token_id as usize] = deposit_cap;
+    rate_limits.withdrawal_caps[token_id as usize] = withdrawal_cap;
+    governor.set_rate_limits(&rate_limits);
+
+    Ok(())
+}
+
+/// Configures the [`RootHistoryParameters`]
+///
+/// # Note
+///
+/// - `signer` needs to be the program's keypair.
+/// - Lowering the depth or the slot window immediately invalidates older roots.
+pub fn set_root_history_parameters(
+    signer: &AccountInfo,
+    governor: &mut GovernorAccount,
+
+    depth: u32,
+    slot_window: u64,
+) -> ProgramResult {
+    guard!(
+        signer.is_signer && *signer.key == crate::ID,
+        ElusivError::InvalidAccount
+    );
+    guard!(
+        depth as usize <= MAX_ROOT_HISTORY_DEPTH,
+        ElusivError::InvalidInstructionData
+    );
+
+    governor.set_root_history(&RootHistoryParameters { depth, slot_window });
+
+    Ok(())
+}
+
 /// Closes a program owned account in devnet and localhost
 ///
 /// # Note
@@ -454,8 +763,7 @@ mod tests {
 
     #[test]
     fn test_enable_storage_child_account() {
-        let mut data = vec![0; StorageAccount::SIZE];
-        let mut storage_account = StorageAccount::new(&mut data).unwrap();
+        zero_program_account!(mut storage_account, StorageAccount);
 
         // Account has invalid size
         account_info!(
@@ -538,10 +846,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_build_nullifier_child_account_index() {
+        let mut data = vec![0; NullifierAccount::SIZE];
+        let mut nullifier_account = NullifierAccount::new(&mut data).unwrap();
+
+        let pubkey = Pubkey::new_unique();
+        let mut child_data = vec![0; NullifierChildAccount::SIZE];
+        NullifierMap::new(&mut child_data[1..]).start_index_migration();
+        account_info!(child_account, pubkey, child_data);
+
+        // Unknown child-account
+        assert_eq!(
+            build_nullifier_child_account_index(&nullifier_account, &child_account, 0, 0),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        nullifier_account.set_child_pubkey(0, ElusivOption::Some(pubkey));
+
+        // Invalid child-account index
+        assert_eq!(
+            build_nullifier_child_account_index(
+                &nullifier_account,
+                &child_account,
+                0,
+                NullifierAccount::COUNT as u32
+            ),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        let mut instructions_count = 0;
+        while build_nullifier_child_account_index(&nullifier_account, &child_account, 0, 0).is_ok()
+        {
+            instructions_count += 1;
+        }
+
+        assert_eq!(instructions_count, 8);
+        assert!(NullifierMap::new(&mut child_account.data.borrow_mut()[1..]).is_indexed());
+    }
+
     #[test]
     fn test_is_mt_full() {
-        let mut data = vec![0; StorageAccount::SIZE];
-        let mut storage_account = StorageAccount::new(&mut data).unwrap();
+        zero_program_account!(mut storage_account, StorageAccount);
         storage_account.set_next_commitment_ptr(&(MT_COMMITMENT_COUNT as u32));
 
         let mut q_data = vec![0; CommitmentQueueAccount::SIZE];
@@ -571,12 +917,34 @@ mod tests {
         assert!(is_mt_full(&storage_account, &queue).unwrap());
     }
 
+    #[test]
+    fn test_reset_active_merkle_tree() {
+        parent_account!(mut storage_account, StorageAccount);
+        parent_account!(mut nullifier_account, NullifierAccount);
+        zero_program_account!(mut queue, CommitmentQueueAccount);
+
+        // The active MT is not full (and no batch is queued)
+        storage_account.set_next_commitment_ptr(&(MT_COMMITMENT_COUNT as u32 - 1));
+        assert_eq!(
+            reset_active_merkle_tree(&mut storage_account, &mut queue, &mut nullifier_account, 0),
+            Err(ElusivError::QueueIsEmpty.into())
+        );
+
+        storage_account.set_next_commitment_ptr(&(MT_COMMITMENT_COUNT as u32));
+        let root = storage_account.get_root().unwrap();
+        reset_active_merkle_tree(&mut storage_account, &mut queue, &mut nullifier_account, 0)
+            .unwrap();
+
+        assert_eq!(nullifier_account.get_root(), root);
+        assert_eq!(storage_account.get_trees_count(), 1);
+        assert_eq!(storage_account.get_next_commitment_ptr(), 0);
+    }
+
     #[test]
     #[should_panic]
     fn test_archive_closed_merkle_tree() {
         test_account_info!(payer, 0);
-        let mut data = vec![0; StorageAccount::SIZE];
-        let mut storage_account = StorageAccount::new(&mut data).unwrap();
+        zero_program_account!(mut storage_account, StorageAccount);
         let mut data = vec![0; NullifierAccount::SIZE];
         let mut nullifier_account = NullifierAccount::new(&mut data).unwrap();
         test_account_info!(archived_tree_account, 0);
@@ -601,6 +969,90 @@ mod tests {
         upgrade_governor_state(&authority, &mut governor_account, &commitment_queue, 1, 1).unwrap();
     }
 
+    #[test]
+    fn test_set_rate_limits() {
+        zero_program_account!(mut governor, GovernorAccount);
+        account_info!(signer, crate::ID, true);
+        account_info!(invalid_signer, Pubkey::new_unique(), true);
+        account_info!(non_signer, crate::ID, false);
+        test_pda_account_info!(rate_limit_account, RateLimitAccount, Some(0));
+
+        // Only the program's keypair can set the rate limits
+        for s in [&invalid_signer, &non_signer] {
+            assert_eq!(
+                set_rate_limits(s, &mut governor, &rate_limit_account, 10, 0, 1, 2),
+                Err(ElusivError::InvalidAccount.into())
+            );
+        }
+
+        // Invalid token_id
+        assert_eq!(
+            set_rate_limits(
+                &signer,
+                &mut governor,
+                &rate_limit_account,
+                10,
+                SPL_TOKEN_COUNT as u16 + 1,
+                1,
+                2
+            ),
+            Err(ElusivError::UnsupportedToken.into())
+        );
+
+        set_rate_limits(&signer, &mut governor, &rate_limit_account, 10, 0, 1, 2).unwrap();
+        set_rate_limits(&signer, &mut governor, &rate_limit_account, 10, 1, 3, 4).unwrap();
+
+        let rate_limits = governor.get_rate_limits();
+        assert_eq!(rate_limits.window_slots, 10);
+        assert_eq!(rate_limits.deposit_caps[..2], [1, 3]);
+        assert_eq!(rate_limits.withdrawal_caps[..2], [2, 4]);
+
+        // Changing the window keeps the caps
+        set_rate_limits(&signer, &mut governor, &rate_limit_account, 20, 0, 5, 6).unwrap();
+
+        let rate_limits = governor.get_rate_limits();
+        assert_eq!(rate_limits.window_slots, 20);
+        assert_eq!(rate_limits.deposit_caps[..2], [5, 3]);
+        assert_eq!(rate_limits.withdrawal_caps[..2], [6, 4]);
+    }
+
+    #[test]
+    fn test_set_root_history_parameters() {
+        zero_program_account!(mut governor, GovernorAccount);
+        account_info!(signer, crate::ID, true);
+        account_info!(invalid_signer, Pubkey::new_unique(), true);
+        account_info!(non_signer, crate::ID, false);
+
+        // Only the program's keypair can set the parameters
+        for s in [&invalid_signer, &non_signer] {
+            assert_eq!(
+                set_root_history_parameters(s, &mut governor, 10, 20),
+                Err(ElusivError::InvalidAccount.into())
+            );
+        }
+
+        // The depth is limited by the capacity of the `RootHistoryAccount`
+        assert_eq!(
+            set_root_history_parameters(
+                &signer,
+                &mut governor,
+                MAX_ROOT_HISTORY_DEPTH as u32 + 1,
+                20
+            ),
+            Err(ElusivError::InvalidInstructionData.into())
+        );
+
+        set_root_history_parameters(&signer, &mut governor, MAX_ROOT_HISTORY_DEPTH as u32, 20)
+            .unwrap();
+        assert_eq!(
+            governor.get_root_history(),
+            RootHistoryParameters {
+                depth: MAX_ROOT_HISTORY_DEPTH as u32,
+                slot_window: 20
+            }
+        );
+    }
+
     #[test]
     fn test_verify_extern_data_account() {
         let pk = Pubkey::new_unique();
@@ -0,0 +1,699 @@
+use crate::bytes::{usize_as_u32_safe, ElusivOption};
+use crate::error::ElusivError;
+use crate::fields::Wrap;
+use crate::macros::{guard, pda_account};
+use crate::proof::verifier::{batch_accumulation, batch_miller_loop, VerificationStep};
+use crate::proof::vkey::VerifyingKey;
+use crate::state::proof::{
+    BatchVerificationAccount, BatchVerificationState, VerificationAccount, VerificationState,
+    MAX_BATCH_VERIFICATION_MEMBERS,
+};
+use crate::state::vkey::VKeyAccount;
+use borsh::BorshSerialize;
+use elusiv_types::ParentAccount;
+use elusiv_utils::{close_account, open_pda_account_with_associated_pubkey};
+use solana_program::pubkey::Pubkey;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult};
+
+/// The member performing the final exponentiation
+const LEADER_INDEX: usize = 0;
+
+/// Opens a new [`BatchVerificationAccount`] for proofs of the verifying key `vkey_id`
+///
+/// # Note
+///
+/// - a batch only reduces costs if all member proofs are valid
+/// - if the batch is invalid, all members are released and have to be verified individually
+/// - wardens should therefore only batch proofs they verified off-chain
+pub fn init_batch_verification<'a>(
+    warden: &AccountInfo<'a>,
+    batch_account: &AccountInfo<'a>,
+
+    batch_index: u8,
+    vkey_id: u32,
+) -> ProgramResult {
+    open_pda_account_with_associated_pubkey::<BatchVerificationAccount>(
+        &crate::id(),
+        warden,
+        batch_account,
+        warden.key,
+        Some(batch_index as u32),
+        None,
+    )?;
+
+    pda_account!(mut batch_account, BatchVerificationAccount, batch_account);
+    batch_account.set_vkey_id(&vkey_id);
+    batch_account.set_state(&BatchVerificationState::Collecting);
+
+    Ok(())
+}
+
+/// Adds a setup proof verification to a [`BatchVerificationAccount`]
+pub fn add_batch_verification_member(
+    _warden: &AccountInfo,
+    batch_account_info: &AccountInfo,
+    verification_account_info: &AccountInfo,
+
+    _batch_index: u8,
+    _verification_account_index: u8,
+) -> ProgramResult {
+    pda_account!(
+        mut batch_account,
+        BatchVerificationAccount,
+        batch_account_info
+    );
+    pda_account!(
+        mut verification_account,
+        VerificationAccount,
+        verification_account_info
+    );
+
+    guard!(
+        batch_account.get_state() == BatchVerificationState::Collecting,
+        ElusivError::InvalidAccountState
+    );
+
+    let members_count = batch_account.get_members_count() as usize;
+    guard!(
+        members_count < MAX_BATCH_VERIFICATION_MEMBERS,
+        ElusivError::InvalidAccountState
+    );
+    guard!(
+        batch_account.get_vkey_id() == verification_account.get_vkey_id(),
+        ElusivError::InvalidAccount
+    );
+
+    // Only proofs with prepared public inputs that have not started the Miller loop can be added
+    guard!(
+        verification_account.get_state() == VerificationState::ProofSetup,
+        ElusivError::InvalidAccountState
+    );
+    guard!(
+        matches!(
+            verification_account.get_step(),
+            VerificationStep::CombinedMillerLoop
+        ),
+        ElusivError::InvalidAccountState
+    );
+    guard!(
+        verification_account.get_round() == 0 && verification_account.get_instruction() == 0,
+        ElusivError::InvalidAccountState
+    );
+    guard!(
+        verification_account.get_is_verified().option().is_none(),
+        ElusivError::InvalidAccountState
+    );
+    guard!(
+        verification_account.get_batch().option().is_none(),
+        ElusivError::InvalidAccountState
+    );
+
+    // The transcript binds all member proofs, which results in unpredictable scalars
+    let transcript = solana_program::hash::hashv(&[
+        &batch_account.get_transcript()[..],
+        &verification_account_info.key.to_bytes()[..],
+        &verification_account.a.get().try_to_vec().unwrap()[..],
+        &verification_account.b.get().try_to_vec().unwrap()[..],
+        &verification_account.c.get().try_to_vec().unwrap()[..],
+        &verification_account
+            .prepared_inputs
+            .get()
+            .try_to_vec()
+            .unwrap()[..],
+    ])
+    .to_bytes();
+
+    batch_account.set_transcript(&transcript);
+    batch_account.set_members(
+        members_count,
+        &ElusivOption::Some(*verification_account_info.key),
+    );
+    batch_account.set_members_count(&usize_as_u32_safe(members_count + 1));
+
+    verification_account.set_batch(&ElusivOption::Some(*batch_account_info.key));
+    verification_account.set_step(&VerificationStep::Batched);
+
+    Ok(())
+}
+
+/// Evicts a (stuck or invalid) member from a [`BatchVerificationAccount`]
+///
+/// # Notes
+///
+/// - members can be evicted until the final exponentiation has been started
+/// - the evicted proof continues with an individual verification
+/// - since the random scalars depend on all members, the batch restarts collecting and accumulating
+pub fn remove_batch_verification_member(
+    _warden: &AccountInfo,
+    batch_account_info: &AccountInfo,
+    verification_account_info: &AccountInfo,
+
+    _batch_index: u8,
+    member_index: u32,
+    _verification_account_index: u8,
+) -> ProgramResult {
+    pda_account!(
+        mut batch_account,
+        BatchVerificationAccount,
+        batch_account_info
+    );
+    pda_account!(
+        mut verification_account,
+        VerificationAccount,
+        verification_account_info
+    );
+    let member_index = member_index as usize;
+
+    guard!(
+        batch_account.get_state() != BatchVerificationState::FinalExponentiation,
+        ElusivError::InvalidAccountState
+    );
+    verify_member(&batch_account, member_index, verification_account_info.key)?;
+
+    let last_index = batch_account.get_members_count() as usize - 1;
+    batch_account.set_members(member_index, &batch_account.get_members(last_index));
+    batch_account.set_members(last_index, &ElusivOption::None);
+    batch_account.set_members_count(&usize_as_u32_safe(last_index));
+
+    let transcript = solana_program::hash::hashv(&[
+        &batch_account.get_transcript()[..],
+        &verification_account_info.key.to_bytes()[..],
+    ])
+    .to_bytes();
+    batch_account.set_transcript(&transcript);
+
+    batch_account.set_state(&BatchVerificationState::Collecting);
+    batch_account.set_accumulated_count(&0);
+    batch_account.set_accumulation_round(&0);
+    batch_account.set_round(&0);
+    batch_account.set_coeff_index(&0);
+
+    release_member(&mut verification_account);
+
+    Ok(())
+}
+
+/// Scales and accumulates the next member of a [`BatchVerificationAccount`] (seals the batch with the first call)
+pub fn accumulate_batch_verification_member(
+    _warden: &AccountInfo,
+    batch_account: &mut BatchVerificationAccount,
+    verification_account_info: &AccountInfo,
+
+    _batch_index: u8,
+    member_index: u32,
+    _verification_account_index: u8,
+) -> ProgramResult {
+    pda_account!(
+        mut verification_account,
+        VerificationAccount,
+        verification_account_info
+    );
+    let member_index = member_index as usize;
+
+    match batch_account.get_state() {
+        BatchVerificationState::Collecting => {
+            guard!(
+                batch_account.get_members_count() >= 2,
+                ElusivError::InvalidAccountState
+            );
+            batch_account.set_state(&BatchVerificationState::Accumulating);
+        }
+        BatchVerificationState::Accumulating => {}
+        _ => return Err(ElusivError::InvalidAccountState.into()),
+    }
+
+    verify_member(batch_account, member_index, verification_account_info.key)?;
+    guard!(
+        member_index == batch_account.get_accumulated_count() as usize,
+        ElusivError::InvalidAccountState
+    );
+
+    batch_accumulation(
+        batch_account,
+        &verification_account.a.get().0,
+        &verification_account.b.get(),
+        &verification_account.prepared_inputs.get().0,
+        &verification_account.c.get().0,
+    )?;
+
+    if batch_account.get_accumulated_count() == batch_account.get_members_count() {
+        batch_account.set_state(&BatchVerificationState::MillerLoop);
+    }
+
+    Ok(())
+}
+
+/// Computes the single Miller loop over all members of a [`BatchVerificationAccount`]
+///
+/// # Note
+///
+/// After the last round, the Miller loop result is passed to the leader for the final exponentiation.
+pub fn compute_batch_verification(
+    _warden: &AccountInfo,
+    batch_account: &mut BatchVerificationAccount,
+    vkey_account: &VKeyAccount,
+    leader_verification_account_info: &AccountInfo,
+
+    _batch_index: u8,
+    vkey_id: u32,
+    _leader_verification_account_index: u8,
+) -> ProgramResult {
+    guard!(vkey_account.is_setup(), ElusivError::InvalidAccount);
+    guard!(
+        batch_account.get_vkey_id() == vkey_id,
+        ElusivError::InvalidAccount
+    );
+    guard!(
+        batch_account.get_state() == BatchVerificationState::MillerLoop,
+        ElusivError::InvalidAccountState
+    );
+    verify_member(
+        batch_account,
+        LEADER_INDEX,
+        leader_verification_account_info.key,
+    )?;
+
+    let result = vkey_account.execute_on_child_account_mut(0, |data| {
+        let vkey = VerifyingKey::new(
+            data,
+            vkey_account.get_public_inputs_count() as usize,
+            vkey_account.get_layout(),
+        )
+        .ok_or(ElusivError::InvalidAccountState)?;
+
+        batch_miller_loop(batch_account, &vkey)
+    })??;
+
+    if let Some(f) = result {
+        pda_account!(
+            mut leader,
+            VerificationAccount,
+            leader_verification_account_info
+        );
+        leader.f.set(Wrap(f));
+        leader.set_step(&VerificationStep::FinalExponentiation);
+        leader.set_round(&0);
+        leader.set_instruction(&0);
+
+        batch_account.set_state(&BatchVerificationState::FinalExponentiation);
+    }
+
+    Ok(())
+}
+
+/// Propagates the batch verification result to a member
+///
+/// # Notes
+///
+/// - the leader has to be finalized first, since its [`VerificationAccount`] holds the result of the final exponentiation
+/// - if the batch is invalid, the member is released and continues with an individual verification
+pub fn finalize_batch_verification_member(
+    _warden: &AccountInfo,
+    batch_account: &mut BatchVerificationAccount,
+    verification_account_info: &AccountInfo,
+
+    _batch_index: u8,
+    member_index: u32,
+    _verification_account_index: u8,
+) -> ProgramResult {
+    pda_account!(
+        mut verification_account,
+        VerificationAccount,
+        verification_account_info
+    );
+    let member_index = member_index as usize;
+
+    guard!(
+        batch_account.get_state() == BatchVerificationState::FinalExponentiation,
+        ElusivError::InvalidAccountState
+    );
+    verify_member(batch_account, member_index, verification_account_info.key)?;
+    guard!(
+        !batch_account.get_finalized(member_index),
+        ElusivError::InvalidAccountState
+    );
+
+    if member_index == LEADER_INDEX {
+        let is_verified = verification_account.get_is_verified();
+        guard!(
+            is_verified.option().is_some(),
+            ElusivError::ComputationIsNotYetFinished
+        );
+        batch_account.set_is_verified(&is_verified);
+    }
+
+    match batch_account.get_is_verified().option() {
+        None => return Err(ElusivError::ComputationIsNotYetFinished.into()),
+        Some(true) => {
+            verification_account.set_is_verified(&ElusivOption::Some(true));
+            verification_account.set_batch(&ElusivOption::None);
+        }
+        Some(false) => release_member(&mut verification_account),
+    }
+
+    batch_account.set_finalized(member_index, &true);
+
+    Ok(())
+}
+
+/// Closes a [`BatchVerificationAccount`] after all members have been finalized (or evicted)
+pub fn close_batch_verification<'a>(
+    warden: &AccountInfo<'a>,
+    batch_account_info: &AccountInfo<'a>,
+
+    _batch_index: u8,
+) -> ProgramResult {
+    {
+        pda_account!(batch_account, BatchVerificationAccount, batch_account_info);
+        let members_count = batch_account.get_members_count() as usize;
+        guard!(
+            (0..members_count).all(|i| batch_account.get_finalized(i)),
+            ElusivError::InvalidAccountState
+        );
+    }
+
+    close_account(warden, batch_account_info)
+}
+
+fn verify_member(
+    batch_account: &BatchVerificationAccount,
+    member_index: usize,
+    verification_account: &Pubkey,
+) -> ProgramResult {
+    guard!(
+        member_index < batch_account.get_members_count() as usize,
+        ElusivError::InvalidInstructionData
+    );
+    guard!(
+        batch_account.get_members(member_index).option() == Some(*verification_account),
+        ElusivError::InvalidAccount
+    );
+
+    Ok(())
+}
+
+/// Removes a proof from its batch, s.t. it can be verified individually
+fn release_member(verification_account: &mut VerificationAccount) {
+    verification_account.set_batch(&ElusivOption::None);
+    verification_account.set_is_verified(&ElusivOption::None);
+    verification_account.set_step(&VerificationStep::CombinedMillerLoop);
+    verification_account.set_round(&0);
+    verification_account.set_instruction(&0);
+    verification_account.set_coeff_index(&0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::{G1A, G2A};
+    use crate::macros::{
+        program_account_info, signing_test_account_info, test_account_info, zero_program_account,
+    };
+    use crate::proof::verifier::{
+        batch_accumulation_instructions_count, batch_verification_scalar,
+    };
+    use ark_bn254::{G1Affine, G2Affine};
+    use ark_ec::AffineCurve;
+    use elusiv_types::SizedAccount;
+
+    macro_rules! verification_account {
+        ($id: ident, $info: ident) => {
+            pda_account!(mut $id, VerificationAccount, $info);
+        };
+    }
+
+    #[test]
+    fn test_add_batch_verification_member() -> ProgramResult {
+        signing_test_account_info!(warden);
+        test_account_info!(batch_account, BatchVerificationAccount::SIZE);
+        program_account_info!(verification_account, VerificationAccount);
+
+        // Proof not setup
+        assert_eq!(
+            add_batch_verification_member(&warden, &batch_account, &verification_account, 0, 0),
+            Err(ElusivError::InvalidAccountState.into())
+        );
+
+        {
+            verification_account!(v_acc, verification_account);
+            v_acc.set_state(&VerificationState::ProofSetup);
+            v_acc.set_step(&VerificationStep::CombinedMillerLoop);
+            v_acc.set_vkey_id(&1);
+        }
+
+        // Invalid vkey
+        assert_eq!(
+            add_batch_verification_member(&warden, &batch_account, &verification_account, 0, 0),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        {
+            verification_account!(v_acc, verification_account);
+            v_acc.set_vkey_id(&0);
+        }
+
+        assert_eq!(
+            add_batch_verification_member(&warden, &batch_account, &verification_account, 0, 0),
+            Ok(())
+        );
+
+        {
+            verification_account!(v_acc, verification_account);
+            assert_eq!(v_acc.get_step(), VerificationStep::Batched);
+            assert_eq!(v_acc.get_batch().option(), Some(*batch_account.key));
+
+            pda_account!(b_acc, BatchVerificationAccount, batch_account);
+            assert_eq!(b_acc.get_members_count(), 1);
+            assert_eq!(b_acc.member_index(verification_account.key), Some(0));
+            assert_ne!(b_acc.get_transcript(), [0; 32]);
+        }
+
+        // Proof already in a batch
+        assert_eq!(
+            add_batch_verification_member(&warden, &batch_account, &verification_account, 0, 0),
+            Err(ElusivError::InvalidAccountState.into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_batch_verification_member() -> ProgramResult {
+        signing_test_account_info!(warden);
+        test_account_info!(batch_account, BatchVerificationAccount::SIZE);
+        program_account_info!(member0, VerificationAccount);
+        program_account_info!(member1, VerificationAccount);
+        program_account_info!(member2, VerificationAccount);
+
+        let transcript = {
+            pda_account!(mut b_acc, BatchVerificationAccount, batch_account);
+            for (i, member) in [&member0, &member1, &member2].iter().enumerate() {
+                b_acc.set_members(i, &ElusivOption::Some(*member.key));
+            }
+            b_acc.set_members_count(&3);
+            b_acc.set_state(&BatchVerificationState::MillerLoop);
+            b_acc.set_accumulated_count(&3);
+            b_acc.set_round(&10);
+            b_acc.get_transcript()
+        };
+
+        for member in [&member0, &member1, &member2] {
+            verification_account!(v_acc, member);
+            v_acc.set_step(&VerificationStep::Batched);
+            v_acc.set_batch(&ElusivOption::Some(*batch_account.key));
+        }
+
+        // Invalid member
+        assert_eq!(
+            remove_batch_verification_member(&warden, &batch_account, &member1, 0, 0, 0),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        assert_eq!(
+            remove_batch_verification_member(&warden, &batch_account, &member0, 0, 0, 0),
+            Ok(())
+        );
+
+        {
+            pda_account!(b_acc, BatchVerificationAccount, batch_account);
+            assert_eq!(b_acc.get_members_count(), 2);
+            assert_eq!(b_acc.member_index(member2.key), Some(0));
+            assert_eq!(b_acc.member_index(member1.key), Some(1));
+            assert_eq!(b_acc.member_index(member0.key), None);
+            assert_eq!(b_acc.get_state(), BatchVerificationState::Collecting);
+            assert_eq!(b_acc.get_accumulated_count(), 0);
+            assert_eq!(b_acc.get_round(), 0);
+            assert_ne!(b_acc.get_transcript(), transcript);
+
+            verification_account!(v_acc, member0);
+            assert_eq!(v_acc.get_step(), VerificationStep::CombinedMillerLoop);
+            assert_eq!(v_acc.get_batch().option(), None);
+        }
+
+        // No eviction after the final exponentiation has been started
+        {
+            pda_account!(mut b_acc, BatchVerificationAccount, batch_account);
+            b_acc.set_state(&BatchVerificationState::FinalExponentiation);
+        }
+        assert_eq!(
+            remove_batch_verification_member(&warden, &batch_account, &member1, 0, 1, 0),
+            Err(ElusivError::InvalidAccountState.into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_accumulate_batch_verification_member() -> ProgramResult {
+        signing_test_account_info!(warden);
+        zero_program_account!(mut batch_account, BatchVerificationAccount);
+        program_account_info!(member0, VerificationAccount);
+        program_account_info!(member1, VerificationAccount);
+
+        let a = G1A(G1Affine::prime_subgroup_generator());
+        for member in [&member0, &member1] {
+            verification_account!(v_acc, member);
+            v_acc.a.set(a);
+            v_acc.b.set(G2A(G2Affine::prime_subgroup_generator()));
+            v_acc.c.set(a);
+            v_acc.prepared_inputs.set(a);
+        }
+
+        batch_account.set_members_count(&1);
+        batch_account.set_members(0, &ElusivOption::Some(*member0.key));
+
+        // Single member batch
+        assert_eq!(
+            accumulate_batch_verification_member(&warden, &mut batch_account, &member0, 0, 0, 0),
+            Err(ElusivError::InvalidAccountState.into())
+        );
+
+        batch_account.set_members_count(&2);
+        batch_account.set_members(1, &ElusivOption::Some(*member1.key));
+        batch_account.set_transcript(&[1; 32]);
+
+        // Members are accumulated in order
+        assert_eq!(
+            accumulate_batch_verification_member(&warden, &mut batch_account, &member1, 0, 1, 0),
+            Err(ElusivError::InvalidAccountState.into())
+        );
+        assert_eq!(
+            batch_account.get_state(),
+            BatchVerificationState::Accumulating
+        );
+
+        for (i, member) in [&member0, &member1].iter().enumerate() {
+            let scalar = batch_verification_scalar(&[1; 32], i, 2);
+            for _ in 0..batch_accumulation_instructions_count(&scalar) {
+                accumulate_batch_verification_member(
+                    &warden,
+                    &mut batch_account,
+                    member,
+                    0,
+                    i as u32,
+                    0,
+                )?;
+            }
+            assert_eq!(batch_account.get_accumulated_count(), i as u32 + 1);
+        }
+
+        assert_eq!(
+            batch_account.get_state(),
+            BatchVerificationState::MillerLoop
+        );
+        assert_eq!(
+            accumulate_batch_verification_member(&warden, &mut batch_account, &member1, 0, 1, 0),
+            Err(ElusivError::InvalidAccountState.into())
+        );
+
+        // Member accounts are not modified
+        verification_account!(v_acc, member0);
+        assert_eq!(v_acc.a.get(), a);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_finalize_batch_verification_member() -> ProgramResult {
+        signing_test_account_info!(warden);
+        program_account_info!(member0, VerificationAccount);
+        program_account_info!(member1, VerificationAccount);
+
+        for is_verified in [true, false] {
+            zero_program_account!(mut batch_account, BatchVerificationAccount);
+            batch_account.set_members_count(&2);
+            batch_account.set_members(0, &ElusivOption::Some(*member0.key));
+            batch_account.set_members(1, &ElusivOption::Some(*member1.key));
+            batch_account.set_state(&BatchVerificationState::MillerLoop);
+
+            for member in [&member0, &member1] {
+                verification_account!(v_acc, member);
+                v_acc.set_is_verified(&ElusivOption::None);
+                v_acc.set_step(&VerificationStep::Batched);
+                v_acc.set_batch(&ElusivOption::Some(Pubkey::new_unique()));
+            }
+
+            // Final exponentiation not yet started
+            assert_eq!(
+                finalize_batch_verification_member(&warden, &mut batch_account, &member0, 0, 0, 0),
+                Err(ElusivError::InvalidAccountState.into())
+            );
+
+            batch_account.set_state(&BatchVerificationState::FinalExponentiation);
+
+            // Leader has to be finalized first
+            for i in 0..2 {
+                let member = [&member0, &member1][i];
+                assert_eq!(
+                    finalize_batch_verification_member(
+                        &warden,
+                        &mut batch_account,
+                        member,
+                        0,
+                        i as u32,
+                        0
+                    ),
+                    Err(ElusivError::ComputationIsNotYetFinished.into())
+                );
+            }
+
+            {
+                verification_account!(v_acc, member0);
+                v_acc.set_step(&VerificationStep::FinalExponentiation);
+                v_acc.set_is_verified(&ElusivOption::Some(is_verified));
+            }
+
+            for (i, member) in [&member0, &member1].iter().enumerate() {
+                finalize_batch_verification_member(
+                    &warden,
+                    &mut batch_account,
+                    member,
+                    0,
+                    i as u32,
+                    0,
+                )?;
+            }
+
+            // Already finalized
+            assert_eq!(
+                finalize_batch_verification_member(&warden, &mut batch_account, &member1, 0, 1, 0),
+                Err(ElusivError::InvalidAccountState.into())
+            );
+
+            for member in [&member0, &member1] {
+                verification_account!(v_acc, member);
+                assert_eq!(v_acc.get_batch().option(), None);
+
+                if is_verified {
+                    assert_eq!(v_acc.get_is_verified().option(), Some(true));
+                } else {
+                    // Released for individual verification
+                    assert_eq!(v_acc.get_is_verified().option(), None);
+                    assert_eq!(v_acc.get_step(), VerificationStep::CombinedMillerLoop);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use crate::error::ElusivError;
+use crate::macros::guard;
+use crate::processor::utils::current_slot;
+use crate::state::proof::{RecipientCallback, VerificationAccountData};
+use crate::types::U256;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::program::invoke_signed;
+use solana_program::pubkey::Pubkey;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult};
+
+pub const RECIPIENT_CALLBACK_VAULT_SEED: &[u8] = b"ElusivRecipientVault";
+const RECIPIENT_CALLBACK_AUTHORITY_SEED: &[u8] = b"RecipientCallbackAuthority";
+
+/// Number of slots after the finalization of a send, after which its recipient callback can be skipped (~ one hour)
+pub const RECIPIENT_CALLBACK_TIMEOUT_SLOTS: u64 = 9000;
+
+/// The PDA of the recipient program that receives the funds of a send with a recipient callback
+pub fn recipient_callback_vault(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[RECIPIENT_CALLBACK_VAULT_SEED], program_id)
+}
+
+/// The PDA signing all recipient callbacks
+///
+/// # Note
+///
+/// Recipient programs should only accept invocations signed by this account.
+pub fn recipient_callback_authority() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[RECIPIENT_CALLBACK_AUTHORITY_SEED], &crate::id())
+}
+
+/// Returns the address receiving the funds of a send
+///
+/// # Note
+///
+/// If the send invokes the recipient program, the funds flow to the [`recipient_callback_vault`] of the program.
+pub fn recipient_address(data: &VerificationAccountData) -> Option<U256> {
+    let recipient = data.recipient_wallet.option()?.skip_mr();
+
+    match data.recipient_callback.option() {
+        Some(_) => Some(
+            recipient_callback_vault(&Pubkey::new_from_array(recipient))
+                .0
+                .to_bytes(),
+        ),
+        None => Some(recipient),
+    }
+}
+
+/// Invokes the recipient program with the callback payload, unless no `callback_accounts` are supplied after the timeout
+///
+/// # Note
+///
+/// The funds are transferred to the recipient before, so a failing recipient program can only delay the finalization
+/// by [`RECIPIENT_CALLBACK_TIMEOUT_SLOTS`] (the funds then remain in the [`recipient_callback_vault`]).
+pub fn invoke_recipient_callback_or_timeout<'a>(
+    program_id: &Pubkey,
+    callback: &RecipientCallback,
+    recipient: &AccountInfo<'a>,
+    callback_accounts: &[&AccountInfo<'a>],
+    finalized_slot: u64,
+) -> ProgramResult {
+    if callback_accounts.is_empty() {
+        guard!(
+            recipient_callback_timed_out(finalized_slot, current_slot()?),
+            ElusivError::InvalidRecipientCallback
+        );
+        return Ok(());
+    }
+
+    invoke_recipient_callback(program_id, callback, recipient, callback_accounts)
+}
+
+pub fn recipient_callback_timed_out(finalized_slot: u64, slot: u64) -> bool {
+    slot >= finalized_slot.saturating_add(RECIPIENT_CALLBACK_TIMEOUT_SLOTS)
+}
+
+/// Invokes the recipient program with the callback payload
+///
+/// # Notes
+///
+/// - `callback_accounts` consists of the recipient program and the [`recipient_callback_authority`],
+///   followed by any accounts that are forwarded to the recipient program.
+/// - The recipient program receives the [`recipient_callback_authority`] (signer) and the `recipient` of the funds,
+///   followed by the forwarded accounts.
+/// - Forwarded accounts are never passed as signers and only as writable if the `callback` binds them.
+pub fn invoke_recipient_callback<'a>(
+    program_id: &Pubkey,
+    callback: &RecipientCallback,
+    recipient: &AccountInfo<'a>,
+    callback_accounts: &[&AccountInfo<'a>],
+) -> ProgramResult {
+    guard!(callback_accounts.len() >= 2, ElusivError::MissingValue);
+    let program = callback_accounts[0];
+    let authority = callback_accounts[1];
+    let forwarded_accounts = &callback_accounts[2..];
+
+    guard!(program.key == program_id, ElusivError::InvalidAccount);
+
+    let (authority_pubkey, authority_bump) = recipient_callback_authority();
+    guard!(
+        *authority.key == authority_pubkey,
+        ElusivError::InvalidAccount
+    );
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(authority_pubkey, true),
+        AccountMeta::new_readonly(*recipient.key, false),
+    ];
+    accounts.extend(forwarded_account_metas(callback, forwarded_accounts));
+
+    let mut account_infos = vec![authority.clone(), recipient.clone()];
+    account_infos.extend(forwarded_accounts.iter().map(|account| (*account).clone()));
+    account_infos.push(program.clone());
+
+    invoke_signed(
+        &Instruction {
+            program_id: *program_id,
+            accounts,
+            data: callback.payload().to_vec(),
+        },
+        &account_infos,
+        &[&[RECIPIENT_CALLBACK_AUTHORITY_SEED, &[authority_bump]]],
+    )
+}
+
+/// Forwarded accounts are never signers and only writable if bound by the `callback`
+fn forwarded_account_metas(
+    callback: &RecipientCallback,
+    forwarded_accounts: &[&AccountInfo],
+) -> Vec<AccountMeta> {
+    forwarded_accounts
+        .iter()
+        .map(|account| AccountMeta {
+            pubkey: *account.key,
+            is_signer: false,
+            is_writable: account.is_writable && callback.is_writable(account.key),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytes::ElusivOption;
+    use crate::types::RawU256;
+
+    #[test]
+    fn test_recipient_address() {
+        let recipient = Pubkey::new_unique();
+        let mut data = VerificationAccountData::default();
+        assert_eq!(recipient_address(&data), None);
+
+        data.recipient_wallet = ElusivOption::Some(RawU256::new(recipient.to_bytes()));
+        assert_eq!(recipient_address(&data), Some(recipient.to_bytes()));
+
+        data.recipient_callback =
+            ElusivOption::Some(RecipientCallback::new(&[1, 2, 3], &[]).unwrap());
+        assert_eq!(
+            recipient_address(&data),
+            Some(recipient_callback_vault(&recipient).0.to_bytes())
+        );
+    }
+
+    #[test]
+    fn test_forwarded_account_metas() {
+        let keys: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let owner = Pubkey::new_unique();
+        let mut lamports = [0; 3];
+        let mut data = vec![vec![]; 3];
+        let accounts: Vec<AccountInfo> = keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .enumerate()
+            .map(|(i, ((key, lamports), data))| {
+                AccountInfo::new(key, true, i != 2, lamports, data, &owner, false, 0)
+            })
+            .collect();
+        let accounts: Vec<&AccountInfo> = accounts.iter().collect();
+
+        // Only the first and last account are bound as writable, the last account is passed as readonly
+        let callback =
+            RecipientCallback::new(&[1], &[keys[0].to_bytes(), keys[2].to_bytes()]).unwrap();
+
+        assert_eq!(
+            forwarded_account_metas(&callback, &accounts),
+            vec![
+                AccountMeta::new(keys[0], false),
+                AccountMeta::new_readonly(keys[1], false),
+                AccountMeta::new_readonly(keys[2], false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recipient_callback_timed_out() {
+        assert!(!recipient_callback_timed_out(100, 100));
+        assert!(!recipient_callback_timed_out(
+            100,
+            100 + RECIPIENT_CALLBACK_TIMEOUT_SLOTS - 1
+        ));
+        assert!(recipient_callback_timed_out(
+            100,
+            100 + RECIPIENT_CALLBACK_TIMEOUT_SLOTS
+        ));
+        assert!(!recipient_callback_timed_out(u64::MAX, u64::MAX - 1));
+    }
+}
@@ -10,18 +10,22 @@ use crate::error::ElusivError;
 use crate::fields::{fr_to_u256_le, is_element_scalar_field, u256_to_big_uint, u256_to_fr_skip_mr};
 use crate::macros::{guard, pda_account, BorshSerDeSized};
 use crate::processor::utils::{
-    transfer_lamports_from_pda_checked, transfer_token, transfer_token_from_pda,
+    current_slot, transfer_lamports_from_pda_checked, transfer_token, transfer_token_from_pda,
     transfer_with_system_program, verify_program_token_account,
 };
+use crate::state::apa::enforce_trailing_apa_restrictions;
 use crate::state::commitment::{
     BaseCommitmentBufferAccount, BaseCommitmentHashingAccount, CommitmentHashingAccount,
     CommitmentQueue, CommitmentQueueAccount, COMMITMENT_BUFFER_LEN,
 };
-use crate::state::governor::FeeCollectorAccount;
+use crate::state::governor::{split_rate_limit_account, FeeCollectorAccount, RateLimitAccount};
 use crate::state::metadata::{
-    CommitmentMetadata, MetadataAccount, MetadataQueue, MetadataQueueAccount,
+    CommitmentMetadata, EncryptedMetadataAccount, MetadataAccount, MetadataQueue,
+    MetadataQueueAccount, MAX_ENCRYPTED_METADATA_LEN,
+};
+use crate::state::storage::{
+    MerkleTreeInsertionEvent, RootHistoryAccount, StorageAccount, MT_COMMITMENT_COUNT,
 };
-use crate::state::storage::{StorageAccount, MT_COMMITMENT_COUNT};
 use crate::state::{
     fee::FeeAccount,
     governor::GovernorAccount,
@@ -48,6 +52,14 @@ pub struct BaseCommitmentHashRequest {
 
     /// The minimum allowed batching rate (since the fee is precomputed with the concrete batching rate)
     pub min_batching_rate: u32,
+
+    /// Binds the metadata of the deposit to the request (see [`deposit_metadata_hash`])
+    pub metadata_hash: U256,
+}
+
+/// Hashes the `metadata` and `encrypted_metadata` of a deposit
+pub fn deposit_metadata_hash(metadata: &CommitmentMetadata, encrypted_metadata: &[u8]) -> U256 {
+    solana_program::hash::hashv(&[metadata, encrypted_metadata]).to_bytes()
 }
 
 #[derive(
@@ -93,6 +105,10 @@ pub const ZERO_COMMITMENT_RAW: U256 = [
 ///     - opens a [`BaseCommitmentHashingAccount`] for the computation,
 ///     - performs the hash computation,
 ///     - swaps fee from token into lamports (for tx compensation of the commitment hash).
+///
+/// `sender` and `fee_payer` (and their token accounts) can be the same account (e.g. a PDA depositing through a CPI):
+///     - the fee transfer from `sender` to `fee_payer` is a self-transfer (which the system and token programs accept),
+///     - the `sender` then pays the computation fee and rent in lamports and receives the subvention.
 #[allow(clippy::too_many_arguments)]
 pub fn store_base_commitment<'a, 'b>(
     sender: &AccountInfo<'a>,
@@ -111,18 +127,38 @@ pub fn store_base_commitment<'a, 'b>(
     storage: &StorageAccount,
     mut hashing_account: UnverifiedAccountInfo<'b, 'a>,
     base_commitment_buffer: &mut BaseCommitmentBufferAccount,
+    encrypted_metadata_account: &mut EncryptedMetadataAccount,
     token_program: &AccountInfo<'a>,
     system_program: &AccountInfo<'a>,
+    trailing_accounts: &[&AccountInfo],
 
     hash_account_index: u32,
     hash_account_bump: u8,
     request: BaseCommitmentHashRequest,
     metadata: CommitmentMetadata,
+    encrypted_metadata: Vec<u8>,
 ) -> ProgramResult {
     let token_id = request.token_id;
     let amount = Token::new_checked(token_id, request.amount)?;
+    guard!(
+        encrypted_metadata.len() <= MAX_ENCRYPTED_METADATA_LEN,
+        ElusivError::EncryptedMetadataTooLong
+    );
+    guard!(
+        request.metadata_hash == deposit_metadata_hash(&metadata, &encrypted_metadata),
+        ElusivError::InputsMismatch
+    );
     let price = TokenPrice::new(sol_usd_price_account, token_usd_price_account, token_id)?;
 
+    let rate_limits = governor.get_rate_limits();
+    let (rate_limit_account, apa_accounts) = split_rate_limit_account(
+        token_id,
+        rate_limits.is_deposit_limited(token_id),
+        trailing_accounts,
+    )?;
+
+    enforce_trailing_apa_restrictions(sender.key, token_id, apa_accounts)?;
+
     guard!(
         is_element_scalar_field(u256_to_big_uint(&request.base_commitment.skip_mr())),
         ElusivError::NonScalarValue
@@ -153,6 +189,15 @@ pub fn store_base_commitment<'a, 'b>(
         ElusivError::InvalidBatchingRate
     );
 
+    // The deposited amount is capped per rate limit window
+    let slot = current_slot()?;
+    if let Some(rate_limit_account) = rate_limit_account {
+        pda_account!(mut rate_limit_account, RateLimitAccount, rate_limit_account);
+        let mut window = rate_limit_account.get_window();
+        window.add_deposit(&rate_limits, token_id, request.amount, slot)?;
+        rate_limit_account.set_window(&window);
+    }
+
     let fee = governor.get_program_fee();
     let subvention = fee
         .base_commitment_subvention
@@ -214,8 +259,8 @@ pub fn store_base_commitment<'a, 'b>(
         None,
     )?;
 
-    // Buffer duplicate check and insertion
-    base_commitment_buffer.try_insert(&request.base_commitment.skip_mr())?;
+    // Buffer duplicate check and insertion (base-commitments never expire)
+    base_commitment_buffer.try_insert(&request.base_commitment.skip_mr(), u64::MAX, slot)?;
 
     // `hashing_account` setup
     pda_account!(
@@ -223,7 +268,15 @@ pub fn store_base_commitment<'a, 'b>(
         BaseCommitmentHashingAccount,
         hashing_account.get_safe()?
     );
-    hashing_account.setup(request, metadata, fee_payer.key.to_bytes())
+    let commitment = request.commitment.reduce();
+    hashing_account.setup(request, metadata, fee_payer.key.to_bytes())?;
+
+    // Publish the encrypted metadata for the recipient scanning for incoming commitments
+    if !encrypted_metadata.is_empty() {
+        encrypted_metadata_account.add_encrypted_metadata(&commitment, &encrypted_metadata)?;
+    }
+
+    Ok(())
 }
 
 pub fn verify_recent_commitment_index(
@@ -332,11 +385,17 @@ pub fn enqueue_commitment(
 }
 
 /// Places the hash siblings into the hashing account
+///
+/// # Note
+///
+/// - either before claiming a batch (only possible if no other batch is being hashed)
+/// - or after claiming a batch, once all previously claimed batches have been inserted into the MT
 pub fn init_commitment_hash_setup(
     hashing_account: &mut CommitmentHashingAccount,
     storage_account: &StorageAccount,
 
     insertion_can_fail: bool,
+    _hashing_account_index: u32,
 ) -> ProgramResult {
     match init_commitment_hash_setup_inner(hashing_account, storage_account) {
         Ok(()) => Ok(()),
@@ -355,31 +414,63 @@ fn init_commitment_hash_setup_inner(
     hashing_account: &mut CommitmentHashingAccount,
     storage_account: &StorageAccount,
 ) -> ProgramResult {
-    guard!(
-        !hashing_account.get_is_active(),
-        ElusivError::ComputationIsNotYetFinished
-    );
-
     let ordering = storage_account.get_next_commitment_ptr();
+
+    if hashing_account.get_is_active() {
+        // Previous batches have to be inserted first
+        guard!(
+            !hashing_account.get_setup() && hashing_account.get_ordering() == ordering,
+            ElusivError::ComputationIsNotYetFinished
+        );
+    } else {
+        guard!(
+            storage_account.no_claimed_batches(),
+            ElusivError::ComputationIsNotYetFinished
+        );
+    }
+
     let siblings = storage_account.get_mt_opening(ordering as usize)?;
 
     hashing_account.setup(ordering, &siblings)
 }
 
-/// Places the next batch from the commitment queue in the [`CommitmentHashingAccount`]
-pub fn init_commitment_hash(
-    commitment_queue: &mut CommitmentQueueAccount,
-    metadata_queue: &mut MetadataQueueAccount,
-    hashing_account: &mut CommitmentHashingAccount,
-    metadata_account: &mut MetadataAccount,
+/// Claims the next batch from the commitment queue and places it in the [`CommitmentHashingAccount`]
+///
+/// # Note
+///
+/// - If `adaptive_batching_rate` is set, the largest batch fully contained in the queue is used (see [`CommitmentQueue::next_adaptive_batch`]).
+/// - Commitment hash fees paid in excess of the batch's hashing cost are credited to the `fee_collector` (see [`crate::state::fee::ProgramFee::commitment_hash_computation_fee_surplus`]).
+#[allow(clippy::too_many_arguments)]
+pub fn init_commitment_hash<'a, 'b, 'c, 'd>(
+    fee: &FeeAccount,
+    pool: &AccountInfo<'a>,
+    fee_collector: &AccountInfo<'a>,
+    commitment_queue: &mut CommitmentQueueAccount<'b>,
+    metadata_queue: &mut MetadataQueueAccount<'b>,
+    hashing_account: &mut CommitmentHashingAccount<'b>,
+    metadata_account: &mut MetadataAccount<'b, 'c, 'd>,
+    storage_account: &mut StorageAccount<'b, 'c, 'd>,
 
+    fee_version: u32,
     insertion_can_fail: bool,
+    _hashing_account_index: u32,
+    adaptive_batching_rate: bool,
 ) -> ProgramResult {
-    match init_commitment_hash_inner(
+    let accounts = CommitmentBatchAccounts {
         commitment_queue,
         metadata_queue,
         hashing_account,
         metadata_account,
+        storage_account,
+    };
+
+    match init_commitment_hash_inner(
+        fee,
+        pool,
+        fee_collector,
+        accounts,
+        fee_version,
+        adaptive_batching_rate,
     ) {
         Ok(()) => Ok(()),
         Err(e) => {
@@ -393,45 +484,91 @@ pub fn init_commitment_hash(
     }
 }
 
-fn init_commitment_hash_inner(
-    commitment_queue: &mut CommitmentQueueAccount,
-    metadata_queue: &mut MetadataQueueAccount,
-    hashing_account: &mut CommitmentHashingAccount,
-    metadata_account: &mut MetadataAccount,
+/// The accounts modified by claiming a commitment batch
+struct CommitmentBatchAccounts<'a, 'b, 'c, 'd> {
+    commitment_queue: &'a mut CommitmentQueueAccount<'b>,
+    metadata_queue: &'a mut MetadataQueueAccount<'b>,
+    hashing_account: &'a mut CommitmentHashingAccount<'b>,
+    metadata_account: &'a mut MetadataAccount<'b, 'c, 'd>,
+    storage_account: &'a mut StorageAccount<'b, 'c, 'd>,
+}
+
+fn init_commitment_hash_inner<'a>(
+    fee: &FeeAccount,
+    pool: &AccountInfo<'a>,
+    fee_collector: &AccountInfo<'a>,
+    accounts: CommitmentBatchAccounts,
+    fee_version: u32,
+    adaptive_batching_rate: bool,
 ) -> ProgramResult {
+    let CommitmentBatchAccounts {
+        commitment_queue,
+        metadata_queue,
+        hashing_account,
+        metadata_account,
+        storage_account,
+    } = accounts;
+
     guard!(
         !hashing_account.get_is_active(),
         ElusivError::ComputationIsNotYetFinished
     );
-    guard!(
-        hashing_account.get_setup(),
-        ElusivError::ComputationIsNotYetFinished
-    );
 
-    let mut commitment_queue = CommitmentQueue::new(commitment_queue);
-    let (batch, batching_rate) = commitment_queue.next_batch()?;
-    commitment_queue.remove(usize_as_u32_safe(batch.len()))?;
+    // All preconditions are verified before the first write, so a failing (`insertion_can_fail`) instruction leaves all accounts untouched
 
-    let mut metadata_queue = MetadataQueue::new(metadata_queue);
-    for _ in 0..batch.len() {
-        let metadata = metadata_queue.dequeue_first()?;
-        metadata_account.add_commitment_metadata(&metadata)?;
-    }
+    // Batches are claimed in the order of their insertion into the MT
+    let ordering = storage_account.next_claimable_commitment_ptr();
+    hashing_account.can_claim(ordering)?;
+
+    let mut commitment_queue = CommitmentQueue::new(commitment_queue);
+    let (batch, batching_rate) = if adaptive_batching_rate {
+        commitment_queue.next_adaptive_batch()?
+    } else {
+        commitment_queue.next_batch()?
+    };
 
     // The fee/batch-upgrader logic has to guarantee that there are no lower fees in a batch
-    let fee_version = batch.first().unwrap().fee_version;
+    guard!(
+        batch.first().unwrap().fee_version == fee_version,
+        ElusivError::InvalidFeeVersion
+    );
 
     // Check for room for the commitment batch
     guard!(
-        hashing_account.get_ordering() as usize + batch.len() <= MT_COMMITMENT_COUNT,
+        ordering as usize + batch.len() <= MT_COMMITMENT_COUNT,
         ElusivError::NoRoomForCommitment
     );
 
+    // Both queues are only modified once the batch is available in both of them
+    let batch_len = usize_as_u32_safe(batch.len());
+    let mut metadata_queue = MetadataQueue::new(metadata_queue);
+    guard!(
+        metadata_queue.len() >= batch_len,
+        ElusivError::InvalidQueueAccess
+    );
+    metadata_account.can_add_commitment_metadata(batch.len())?;
+
+    // `pool` transfers the commitment hash fee surplus to `fee_collector` (lamports)
+    let min_batching_rates: Vec<u32> = batch.iter().map(|r| r.min_batching_rate).collect();
+    let surplus = fee
+        .get_program_fee()
+        .commitment_hash_computation_fee_surplus(&min_batching_rates, batching_rate);
+    transfer_lamports_from_pda_checked(pool, fee_collector, surplus.0)?;
+
+    hashing_account.claim(ordering)?;
+    commitment_queue.dequeue_n(batch_len)?;
+
+    for metadata in metadata_queue.dequeue_n(batch_len)? {
+        metadata_account.add_commitment_metadata(&metadata)?;
+    }
+
     let mut commitments = [[0; 32]; MAX_HT_COMMITMENTS];
     for i in 0..batch.len() {
         commitments[i] = batch[i].commitment;
     }
 
+    storage_account.claim_commitments(commitments_per_batch(batching_rate));
+
     hashing_account.reset(batching_rate, fee_version, &commitments)
 }
 
@@ -443,6 +580,7 @@ pub fn compute_commitment_hash<'a>(
 
     fee_version: u32,
     _nonce: u32,
+    _hashing_account_index: u32,
 ) -> ProgramResult {
     guard!(
         hashing_account.get_is_active(),
@@ -463,9 +601,16 @@ pub fn compute_commitment_hash<'a>(
 }
 
 /// Requires `batching_rate + 1` calls
+///
+/// # Note
+///
+/// Only the batch directly following the last inserted commitment can be finalized, so the MT insertions are serialized.
 pub fn finalize_commitment_hash(
     hashing_account: &mut CommitmentHashingAccount,
     storage_account: &mut StorageAccount,
+    root_history_account: &mut RootHistoryAccount,
+
+    _hashing_account_index: u32,
 ) -> ProgramResult {
     guard!(
         hashing_account.get_is_active(),
@@ -493,11 +638,33 @@ pub fn finalize_commitment_hash(
         ElusivError::NoRoomForCommitment
     );
 
-    hashing_account.update_mt(storage_account, finalization_ix);
+    // Previous batches have to be inserted first
+    guard!(
+        hashing_account.get_ordering() == storage_account.get_next_commitment_ptr(),
+        ElusivError::ComputationIsNotYetFinished
+    );
+
+    hashing_account.update_mt(
+        storage_account,
+        root_history_account,
+        finalization_ix,
+        current_slot()?,
+    );
     hashing_account.set_finalization_ix(&(finalization_ix + 1));
     if finalization_ix == batching_rate {
         hashing_account.set_is_active(&false);
         hashing_account.set_setup(&false);
+
+        // The leaves are not preserved on-chain once the active MT is reset
+        MerkleTreeInsertionEvent {
+            mt_index: storage_account.get_trees_count(),
+            leaves_index: hashing_account.get_ordering(),
+            leaves: (0..commitments_per_batch(batching_rate))
+                .map(|i| hashing_account.get_hash_tree(i))
+                .collect(),
+            root: storage_account.get_root()?,
+        }
+        .emit();
     }
     Ok(())
 }
@@ -510,11 +677,13 @@ mod tests {
         big_uint_to_u256, fr_to_u256_le_repr, u256_from_str_skip_mr, SCALAR_MODULUS_RAW,
     };
     use crate::macros::{
-        account_info, parent_account, program_token_account_info, pyth_price_account_info,
-        test_account_info, test_pda_account_info, zero_program_account,
+        account_info, parent_account, program_account_info, program_token_account_info,
+        pyth_price_account_info, test_account_info, test_pda_account_info, zero_program_account,
     };
     use crate::processor::mutate;
-    use crate::state::governor::PoolAccount;
+    use crate::state::apa::apa_target_map_account;
+    use crate::state::fee::ProgramFee;
+    use crate::state::governor::{PoolAccount, RateLimitAccount, RateLimits};
     use crate::state::program_account::{PDAAccount, SizedAccount};
     use crate::state::storage::{EMPTY_TREE, MT_HEIGHT};
     use crate::token::{lamports_token, usdc_token, LAMPORTS_TOKEN_ID, USDC_TOKEN_ID};
@@ -576,6 +745,7 @@ mod tests {
         zero_program_account!(mut governor, GovernorAccount);
         zero_program_account!(storage, StorageAccount);
         zero_program_account!(mut buffer, BaseCommitmentBufferAccount);
+        parent_account!(mut encrypted_metadata_account, EncryptedMetadataAccount);
         test_account_info!(sender, 0);
         test_account_info!(fee_payer, 0);
         test_account_info!(pool, 0);
@@ -583,6 +753,13 @@ mod tests {
         test_account_info!(any, 0);
         account_info!(sys, system_program::id(), vec![]);
         account_info!(spl, spl_token::id(), vec![]);
+        account_info!(
+            apa,
+            apa_target_map_account(sender.key).0,
+            vec![],
+            system_program::id(),
+            false
+        );
         let (hasing_account_pubkey, bump) = BaseCommitmentHashingAccount::find(Some(0));
         account_info!(
             hashing_acc,
@@ -601,6 +778,7 @@ mod tests {
             commitment: RawU256::new(u256_from_str_skip_mr("1")),
             fee_version: 1,
             min_batching_rate: 4,
+            metadata_hash: deposit_metadata_hash(&CommitmentMetadata::default(), &[]),
         };
         let metadata = CommitmentMetadata::default();
 
@@ -682,17 +860,160 @@ mod tests {
                     // The UnverifiedAccountInfo needs to be constructed for every single call since it might get modified
                     UnverifiedAccountInfo::new(&hashing_acc),
                     &mut buffer,
+                    &mut encrypted_metadata_account,
                     &sys,
                     &sys,
+                    &[&apa],
                     0,
                     bump,
                     request,
                     metadata,
+                    Vec::new(),
                 ),
                 Err(err)
             );
         }
 
+        // Encrypted metadata too long
+        assert_eq!(
+            store_base_commitment(
+                &sender,
+                &sender,
+                &fee_payer,
+                &fee_payer,
+                &pool,
+                &pool,
+                &fee_collector,
+                &fee_collector,
+                &any,
+                &any,
+                &governor,
+                &storage,
+                UnverifiedAccountInfo::new(&hashing_acc),
+                &mut buffer,
+                &mut encrypted_metadata_account,
+                &sys,
+                &sys,
+                &[&apa],
+                0,
+                bump,
+                mutate(&request, |request| {
+                    request.metadata_hash =
+                        deposit_metadata_hash(&metadata, &[1; MAX_ENCRYPTED_METADATA_LEN + 1]);
+                }),
+                metadata,
+                vec![1; MAX_ENCRYPTED_METADATA_LEN + 1],
+            ),
+            Err(ElusivError::EncryptedMetadataTooLong.into())
+        );
+
+        // Metadata not bound to the request
+        for (metadata, encrypted_metadata) in
+            [([1; CommitmentMetadata::SIZE], vec![]), (metadata, vec![1])]
+        {
+            assert_eq!(
+                store_base_commitment(
+                    &sender,
+                    &sender,
+                    &fee_payer,
+                    &fee_payer,
+                    &pool,
+                    &pool,
+                    &fee_collector,
+                    &fee_collector,
+                    &any,
+                    &any,
+                    &governor,
+                    &storage,
+                    UnverifiedAccountInfo::new(&hashing_acc),
+                    &mut buffer,
+                    &mut encrypted_metadata_account,
+                    &sys,
+                    &sys,
+                    &[&apa],
+                    0,
+                    bump,
+                    request.clone(),
+                    metadata,
+                    encrypted_metadata,
+                ),
+                Err(ElusivError::InputsMismatch.into())
+            );
+        }
+
+        // Deposit cap exceeded
+        let mut rate_limits = RateLimits {
+            window_slots: 10,
+            ..Default::default()
+        };
+        rate_limits.deposit_caps[LAMPORTS_TOKEN_ID as usize] = LAMPORTS_PER_SOL - 1;
+        governor.set_rate_limits(&rate_limits);
+
+        // Missing `RateLimitAccount`
+        assert_eq!(
+            store_base_commitment(
+                &sender,
+                &sender,
+                &fee_payer,
+                &fee_payer,
+                &pool,
+                &pool,
+                &fee_collector,
+                &fee_collector,
+                &any,
+                &any,
+                &governor,
+                &storage,
+                UnverifiedAccountInfo::new(&hashing_acc),
+                &mut buffer,
+                &mut encrypted_metadata_account,
+                &sys,
+                &sys,
+                &[&apa],
+                0,
+                bump,
+                request.clone(),
+                metadata,
+                Vec::new(),
+            ),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        program_account_info!(
+            rate_limit_account,
+            RateLimitAccount,
+            Some(LAMPORTS_TOKEN_ID.into())
+        );
+        assert_eq!(
+            store_base_commitment(
+                &sender,
+                &sender,
+                &fee_payer,
+                &fee_payer,
+                &pool,
+                &pool,
+                &fee_collector,
+                &fee_collector,
+                &any,
+                &any,
+                &governor,
+                &storage,
+                UnverifiedAccountInfo::new(&hashing_acc),
+                &mut buffer,
+                &mut encrypted_metadata_account,
+                &sys,
+                &sys,
+                &[&rate_limit_account, &apa],
+                0,
+                bump,
+                request.clone(),
+                metadata,
+                Vec::new(),
+            ),
+            Err(ElusivError::RateLimitExceeded.into())
+        );
+        governor.set_rate_limits(&RateLimits::default());
+
         // Invalid pool_account
         assert_eq!(
             store_base_commitment(
@@ -710,12 +1031,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &sys,
                 &sys,
+                &[&apa],
                 0,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(ElusivError::InvalidAccount.into())
         );
@@ -737,12 +1061,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &sys,
                 &sys,
+                &[&apa],
                 0,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(ElusivError::InvalidAccount.into())
         );
@@ -764,12 +1091,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &spl,
                 &sys,
+                &[&apa],
                 0,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(ElusivError::InvalidAccount.into())
         );
@@ -791,12 +1121,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &sys,
                 &sys,
+                &[&apa],
                 1,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(ProgramError::InvalidSeeds)
         );
@@ -818,12 +1151,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &sys,
                 &sys,
+                &[&apa],
                 0,
                 0,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(ProgramError::InvalidSeeds)
         );
@@ -844,16 +1180,32 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &sys,
                 &sys,
+                &[&apa],
                 0,
                 bump,
-                request.clone(),
+                mutate(&request, |request| {
+                    request.metadata_hash =
+                        deposit_metadata_hash(&metadata, &[1; MAX_ENCRYPTED_METADATA_LEN]);
+                }),
                 metadata,
+                vec![1; MAX_ENCRYPTED_METADATA_LEN],
             ),
             Ok(())
         );
 
+        // The encrypted metadata is available for scanning
+        assert_eq!(encrypted_metadata_account.get_entries_count(), 1);
+        assert_eq!(
+            encrypted_metadata_account.get_encrypted_metadata(0),
+            Ok((
+                request.commitment.reduce(),
+                vec![1; MAX_ENCRYPTED_METADATA_LEN]
+            ))
+        );
+
         // Duplicate insertion will fail
         assert_eq!(
             store_base_commitment(
@@ -871,22 +1223,96 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &sys,
                 &sys,
+                &[&apa],
                 0,
                 bump,
                 request,
                 metadata,
+                Vec::new(),
             ),
             Err(ElusivError::DuplicateValue.into())
         );
     }
 
+    #[test]
+    fn test_store_base_commitment_sender_as_fee_payer() -> ProgramResult {
+        zero_program_account!(governor, GovernorAccount);
+        zero_program_account!(storage, StorageAccount);
+        zero_program_account!(mut buffer, BaseCommitmentBufferAccount);
+        parent_account!(mut encrypted_metadata_account, EncryptedMetadataAccount);
+        test_account_info!(sender, 0);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
+        test_account_info!(any, 0);
+        account_info!(sys, system_program::id(), vec![]);
+        account_info!(
+            apa,
+            apa_target_map_account(sender.key).0,
+            vec![],
+            system_program::id(),
+            false
+        );
+        let (hasing_account_pubkey, bump) = BaseCommitmentHashingAccount::find(Some(0));
+        account_info!(
+            hashing_acc,
+            hasing_account_pubkey,
+            vec![0; BaseCommitmentHashingAccount::SIZE]
+        );
+
+        let request = BaseCommitmentHashRequest {
+            base_commitment: RawU256::new(u256_from_str_skip_mr("1")),
+            recent_commitment_index: 0,
+            amount: LAMPORTS_PER_SOL,
+            token_id: LAMPORTS_TOKEN_ID,
+            commitment: RawU256::new(u256_from_str_skip_mr("1")),
+            fee_version: 0,
+            min_batching_rate: 0,
+            metadata_hash: deposit_metadata_hash(&CommitmentMetadata::default(), &[]),
+        };
+
+        // The `sender` is passed as its own `fee_payer` (as done by `store_base_commitment_cpi_accounts`)
+        store_base_commitment(
+            &sender,
+            &sender,
+            &sender,
+            &sender,
+            &pool,
+            &pool,
+            &fee_collector,
+            &fee_collector,
+            &any,
+            &any,
+            &governor,
+            &storage,
+            UnverifiedAccountInfo::new(&hashing_acc),
+            &mut buffer,
+            &mut encrypted_metadata_account,
+            &sys,
+            &sys,
+            &[&apa],
+            0,
+            bump,
+            request,
+            CommitmentMetadata::default(),
+            Vec::new(),
+        )?;
+
+        pda_account!(hashing_account, BaseCommitmentHashingAccount, hashing_acc);
+        assert_eq!(hashing_account.get_fee_payer(), sender.key.to_bytes());
+        assert!(hashing_account.get_is_active());
+
+        Ok(())
+    }
+
     #[test]
     fn test_store_base_commitment_token() {
         zero_program_account!(governor, GovernorAccount);
         zero_program_account!(storage, StorageAccount);
         zero_program_account!(mut buffer, BaseCommitmentBufferAccount);
+        parent_account!(mut encrypted_metadata_account, EncryptedMetadataAccount);
         test_account_info!(sender);
         test_account_info!(fee_payer);
         test_account_info!(sender_token, 0, spl_token::id());
@@ -897,6 +1323,13 @@ mod tests {
         program_token_account_info!(fee_c_token, FeeCollectorAccount, USDC_TOKEN_ID);
         account_info!(sys, system_program::id(), vec![]);
         account_info!(spl, spl_token::id(), vec![]);
+        account_info!(
+            apa,
+            apa_target_map_account(sender.key).0,
+            vec![],
+            system_program::id(),
+            false
+        );
         let (hasing_account_pubkey, bump) = BaseCommitmentHashingAccount::find(Some(0));
         account_info!(
             hashing_acc,
@@ -925,6 +1358,7 @@ mod tests {
             commitment: RawU256::new(u256_from_str_skip_mr("1")),
             fee_version: 0,
             min_batching_rate: 0,
+            metadata_hash: deposit_metadata_hash(&CommitmentMetadata::default(), &[]),
         };
 
         let requests = [
@@ -962,12 +1396,15 @@ mod tests {
                     &storage,
                     UnverifiedAccountInfo::new(&hashing_acc),
                     &mut buffer,
+                    &mut encrypted_metadata_account,
                     &spl,
                     &sys,
+                    &[&apa],
                     0,
                     bump,
                     request,
                     metadata,
+                    Vec::new(),
                 ),
                 Err(err)
             );
@@ -990,12 +1427,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &spl,
                 &sys,
+                &[&apa],
                 0,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(ElusivError::InvalidAccount.into())
         );
@@ -1017,12 +1457,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &spl,
                 &sys,
+                &[&apa],
                 0,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(ElusivError::InvalidAccount.into())
         );
@@ -1044,12 +1487,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &sys,
                 &sys,
+                &[&apa],
                 0,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(ElusivError::InvalidAccount.into())
         );
@@ -1071,12 +1517,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &spl,
                 &sys,
+                &[&apa],
                 1,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(ProgramError::InvalidSeeds)
         );
@@ -1098,12 +1547,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &spl,
                 &sys,
+                &[&apa],
                 0,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(ElusivError::InvalidAccount.into())
         );
@@ -1125,12 +1577,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &spl,
                 &sys,
+                &[&apa],
                 0,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(ElusivError::InvalidAccount.into())
         );
@@ -1152,12 +1607,15 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &spl,
                 &sys,
+                &[&apa],
                 0,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(TokenError::InvalidPriceAccount.into())
         );
@@ -1179,16 +1637,20 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &spl,
                 &sys,
+                &[&apa],
                 0,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
             Err(TokenError::InvalidPriceAccount.into())
         );
 
+        // Missing APA accounts
         assert_eq!(
             store_base_commitment(
                 &sender,
@@ -1205,17 +1667,19 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &spl,
                 &sys,
+                &[],
                 0,
                 bump,
                 request.clone(),
                 metadata,
+                Vec::new(),
             ),
-            Ok(())
+            Err(ElusivError::InvalidAccount.into())
         );
 
-        // Duplicate insertion will fail
         assert_eq!(
             store_base_commitment(
                 &sender,
@@ -1232,24 +1696,57 @@ mod tests {
                 &storage,
                 UnverifiedAccountInfo::new(&hashing_acc),
                 &mut buffer,
+                &mut encrypted_metadata_account,
                 &spl,
                 &sys,
+                &[&apa],
                 0,
                 bump,
-                request,
+                request.clone(),
                 metadata,
+                Vec::new(),
             ),
-            Err(ElusivError::DuplicateValue.into())
+            Ok(())
         );
-    }
-
-    #[test]
-    fn test_compute_base_commitment_hash() {
-        zero_program_account!(mut hashing_account, BaseCommitmentHashingAccount);
 
-        // Inactive
+        // Duplicate insertion will fail
         assert_eq!(
-            compute_base_commitment_hash(&mut hashing_account, 0),
+            store_base_commitment(
+                &sender,
+                &sender_token,
+                &fee_payer,
+                &fee_payer_token,
+                &pool,
+                &pool_token,
+                &fee_c,
+                &fee_c_token,
+                &sol,
+                &usdc,
+                &governor,
+                &storage,
+                UnverifiedAccountInfo::new(&hashing_acc),
+                &mut buffer,
+                &mut encrypted_metadata_account,
+                &spl,
+                &sys,
+                &[&apa],
+                0,
+                bump,
+                request,
+                metadata,
+                Vec::new(),
+            ),
+            Err(ElusivError::DuplicateValue.into())
+        );
+    }
+
+    #[test]
+    fn test_compute_base_commitment_hash() {
+        zero_program_account!(mut hashing_account, BaseCommitmentHashingAccount);
+
+        // Inactive
+        assert_eq!(
+            compute_base_commitment_hash(&mut hashing_account, 0),
             Err(ElusivError::ComputationIsNotYetStarted.into())
         );
 
@@ -1412,21 +1909,53 @@ mod tests {
         Ok(())
     }
 
+    /// Enqueues the commitments `[i; 32]` (and default metadata) with the given `min_batching_rates`
+    fn enqueue_test_commitments(
+        commitment_queue: &mut CommitmentQueueAccount,
+        metadata_queue: &mut MetadataQueueAccount,
+        min_batching_rates: &[u32],
+        fee_version: u32,
+    ) {
+        let mut commitment_queue = CommitmentQueue::new(commitment_queue);
+        let mut metadata_queue = MetadataQueue::new(metadata_queue);
+        for (i, &min_batching_rate) in min_batching_rates.iter().enumerate() {
+            enqueue_commitment(
+                &mut commitment_queue,
+                &mut metadata_queue,
+                [i as u8; 32],
+                CommitmentMetadata::default(),
+                fee_version,
+                min_batching_rate,
+            )
+            .unwrap();
+        }
+    }
+
     #[test]
     fn test_init_commitment_hash_empty_queue() {
-        parent_account!(storage_account, StorageAccount);
+        zero_program_account!(fee, FeeAccount);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
+        parent_account!(mut storage_account, StorageAccount);
         parent_account!(mut metadata_account, MetadataAccount);
         zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
         zero_program_account!(mut metadata_queue, MetadataQueueAccount);
         zero_program_account!(mut hashing_account, CommitmentHashingAccount);
 
-        init_commitment_hash_setup(&mut hashing_account, &storage_account, false).unwrap();
+        init_commitment_hash_setup(&mut hashing_account, &storage_account, false, 0).unwrap();
         assert_eq!(
             init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
                 &mut commitment_queue,
                 &mut metadata_queue,
                 &mut hashing_account,
                 &mut metadata_account,
+                &mut storage_account,
+                0,
+                false,
+                0,
                 false
             ),
             Err(ElusivError::QueueIsEmpty.into())
@@ -1435,33 +1964,32 @@ mod tests {
 
     #[test]
     fn test_init_commitment_hash_active_computation() {
+        zero_program_account!(fee, FeeAccount);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
+        parent_account!(mut storage_account, StorageAccount);
         parent_account!(mut metadata_account, MetadataAccount);
         zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
         zero_program_account!(mut metadata_queue, MetadataQueueAccount);
         zero_program_account!(mut hashing_account, CommitmentHashingAccount);
 
-        {
-            let mut commitment_queue = CommitmentQueue::new(&mut commitment_queue);
-            let mut metadata_queue = MetadataQueue::new(&mut metadata_queue);
-            enqueue_commitment(
-                &mut commitment_queue,
-                &mut metadata_queue,
-                [0; 32],
-                CommitmentMetadata::default(),
-                0,
-                0,
-            )
-            .unwrap();
-        }
+        enqueue_test_commitments(&mut commitment_queue, &mut metadata_queue, &[0], 0);
 
         hashing_account.set_is_active(&true);
         hashing_account.set_setup(&true);
         assert_eq!(
             init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
                 &mut commitment_queue,
                 &mut metadata_queue,
                 &mut hashing_account,
                 &mut metadata_account,
+                &mut storage_account,
+                0,
+                false,
+                0,
                 false
             ),
             Err(ElusivError::ComputationIsNotYetFinished.into())
@@ -1470,34 +1998,32 @@ mod tests {
 
     #[test]
     fn test_init_commitment_hash_full_storage() {
+        zero_program_account!(fee, FeeAccount);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
         parent_account!(mut storage_account, StorageAccount);
         parent_account!(mut metadata_account, MetadataAccount);
         zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
         zero_program_account!(mut metadata_queue, MetadataQueueAccount);
         zero_program_account!(mut hashing_account, CommitmentHashingAccount);
 
-        {
-            let mut commitment_queue = CommitmentQueue::new(&mut commitment_queue);
-            let mut metadata_queue = MetadataQueue::new(&mut metadata_queue);
-            enqueue_commitment(
-                &mut commitment_queue,
-                &mut metadata_queue,
-                [0; 32],
-                CommitmentMetadata::default(),
-                0,
-                0,
-            )
-            .unwrap();
-        }
+        enqueue_test_commitments(&mut commitment_queue, &mut metadata_queue, &[0], 0);
 
         storage_account.set_next_commitment_ptr(&(MT_COMMITMENT_COUNT as u32));
-        init_commitment_hash_setup(&mut hashing_account, &storage_account, false).unwrap();
+        init_commitment_hash_setup(&mut hashing_account, &storage_account, false, 0).unwrap();
         assert_eq!(
             init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
                 &mut commitment_queue,
                 &mut metadata_queue,
                 &mut hashing_account,
                 &mut metadata_account,
+                &mut storage_account,
+                0,
+                false,
+                0,
                 false
             ),
             Err(ElusivError::NoRoomForCommitment.into())
@@ -1506,71 +2032,107 @@ mod tests {
 
     #[test]
     fn test_init_commitment_hash_incomplete_batch() {
-        parent_account!(storage_account, StorageAccount);
+        zero_program_account!(fee, FeeAccount);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
+        parent_account!(mut storage_account, StorageAccount);
         parent_account!(mut metadata_account, MetadataAccount);
         zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
         zero_program_account!(mut metadata_queue, MetadataQueueAccount);
         zero_program_account!(mut hashing_account, CommitmentHashingAccount);
 
-        {
-            let mut commitment_queue = CommitmentQueue::new(&mut commitment_queue);
-            let mut metadata_queue = MetadataQueue::new(&mut metadata_queue);
-            enqueue_commitment(
+        enqueue_test_commitments(&mut commitment_queue, &mut metadata_queue, &[1], 0);
+
+        init_commitment_hash_setup(&mut hashing_account, &storage_account, false, 0).unwrap();
+        assert_eq!(
+            init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
                 &mut commitment_queue,
                 &mut metadata_queue,
-                [0; 32],
-                CommitmentMetadata::default(),
+                &mut hashing_account,
+                &mut metadata_account,
+                &mut storage_account,
                 0,
-                1,
-            )
+                false,
+                0,
+                false
+            ),
+            Err(ElusivError::InvalidQueueAccess.into())
+        );
+    }
+
+    #[test]
+    fn test_init_commitment_hash_missing_metadata() {
+        zero_program_account!(fee, FeeAccount);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
+        parent_account!(mut storage_account, StorageAccount);
+        parent_account!(mut metadata_account, MetadataAccount);
+        zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
+        zero_program_account!(mut metadata_queue, MetadataQueueAccount);
+        zero_program_account!(mut hashing_account, CommitmentHashingAccount);
+
+        CommitmentQueue::new(&mut commitment_queue)
+            .enqueue(CommitmentHashRequest {
+                commitment: [0; 32],
+                min_batching_rate: 0,
+                fee_version: 0,
+            })
             .unwrap();
-        }
 
-        init_commitment_hash_setup(&mut hashing_account, &storage_account, false).unwrap();
+        init_commitment_hash_setup(&mut hashing_account, &storage_account, false, 0).unwrap();
         assert_eq!(
             init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
                 &mut commitment_queue,
                 &mut metadata_queue,
                 &mut hashing_account,
                 &mut metadata_account,
+                &mut storage_account,
+                0,
+                false,
+                0,
                 false
             ),
             Err(ElusivError::InvalidQueueAccess.into())
         );
+
+        // The commitment queue is not modified
+        assert_eq!(CommitmentQueue::new(&mut commitment_queue).len(), 1);
     }
 
     #[test]
     fn test_init_commitment_hash_batch_too_big() {
+        zero_program_account!(fee, FeeAccount);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
         parent_account!(mut storage_account, StorageAccount);
         parent_account!(mut metadata_account, MetadataAccount);
         zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
         zero_program_account!(mut metadata_queue, MetadataQueueAccount);
         zero_program_account!(mut hashing_account, CommitmentHashingAccount);
 
-        {
-            let mut commitment_queue = CommitmentQueue::new(&mut commitment_queue);
-            let mut metadata_queue = MetadataQueue::new(&mut metadata_queue);
-            for _ in 0..2 {
-                enqueue_commitment(
-                    &mut commitment_queue,
-                    &mut metadata_queue,
-                    [0; 32],
-                    CommitmentMetadata::default(),
-                    0,
-                    1,
-                )
-                .unwrap();
-            }
-        }
+        enqueue_test_commitments(&mut commitment_queue, &mut metadata_queue, &[1, 1], 0);
 
         storage_account.set_next_commitment_ptr(&(MT_COMMITMENT_COUNT as u32 - 1));
-        init_commitment_hash_setup(&mut hashing_account, &storage_account, false).unwrap();
+        init_commitment_hash_setup(&mut hashing_account, &storage_account, false, 0).unwrap();
         assert_eq!(
             init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
                 &mut commitment_queue,
                 &mut metadata_queue,
                 &mut hashing_account,
                 &mut metadata_account,
+                &mut storage_account,
+                0,
+                false,
+                0,
                 false
             ),
             Err(ElusivError::NoRoomForCommitment.into())
@@ -1580,7 +2142,10 @@ mod tests {
     #[test]
     #[allow(clippy::needless_range_loop)]
     fn test_init_commitment_hash_valid() {
-        parent_account!(storage_account, StorageAccount);
+        zero_program_account!(fee, FeeAccount);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
+        parent_account!(mut storage_account, StorageAccount);
         parent_account!(mut metadata_account, MetadataAccount);
         zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
         zero_program_account!(mut metadata_queue, MetadataQueueAccount);
@@ -1599,12 +2164,19 @@ mod tests {
             m_queue.enqueue([i; CommitmentMetadata::SIZE]).unwrap();
         }
 
-        init_commitment_hash_setup(&mut hashing_account, &storage_account, false).unwrap();
+        init_commitment_hash_setup(&mut hashing_account, &storage_account, false, 0).unwrap();
         init_commitment_hash(
+            &fee,
+            &pool,
+            &fee_collector,
             &mut commitment_queue,
             &mut metadata_queue,
             &mut hashing_account,
             &mut metadata_account,
+            &mut storage_account,
+            0,
+            false,
+            0,
             false,
         )
         .unwrap();
@@ -1628,31 +2200,123 @@ mod tests {
         zero_program_account!(mut hashing_account, CommitmentHashingAccount);
 
         hashing_account.set_is_active(&true);
+        hashing_account.set_setup(&true);
 
         assert_eq!(
-            init_commitment_hash_setup(&mut hashing_account, &storage_account, false),
+            init_commitment_hash_setup(&mut hashing_account, &storage_account, false, 0),
             Err(ElusivError::ComputationIsNotYetFinished.into())
         );
 
         assert_eq!(
-            init_commitment_hash_setup(&mut hashing_account, &storage_account, true),
+            init_commitment_hash_setup(&mut hashing_account, &storage_account, true, 0),
             Ok(())
         );
     }
 
+    #[test]
+    fn test_init_commitment_hash_adaptive_batching_rate() {
+        zero_program_account!(mut fee, FeeAccount);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
+        parent_account!(mut storage_account, StorageAccount);
+        parent_account!(mut metadata_account, MetadataAccount);
+        zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
+        zero_program_account!(mut metadata_queue, MetadataQueueAccount);
+        zero_program_account!(mut hashing_account, CommitmentHashingAccount);
+
+        let program_fee = ProgramFee::new(5000, 11, 100, 33, 44, 300, 555).unwrap();
+        fee.set_program_fee(&program_fee);
+
+        enqueue_test_commitments(&mut commitment_queue, &mut metadata_queue, &[0, 0, 0], 1);
+
+        // Invalid fee_version
+        assert_eq!(
+            init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
+                &mut commitment_queue,
+                &mut metadata_queue,
+                &mut hashing_account,
+                &mut metadata_account,
+                &mut storage_account,
+                0,
+                false,
+                0,
+                true,
+            ),
+            Err(ElusivError::InvalidFeeVersion.into())
+        );
+
+        let pool_lamports = pool.lamports();
+        let fee_collector_lamports = fee_collector.lamports();
+
+        // Two of the three commitments are hashed in a single batch
+        init_commitment_hash(
+            &fee,
+            &pool,
+            &fee_collector,
+            &mut commitment_queue,
+            &mut metadata_queue,
+            &mut hashing_account,
+            &mut metadata_account,
+            &mut storage_account,
+            1,
+            false,
+            0,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(hashing_account.get_batching_rate(), 1);
+        assert_eq!(hashing_account.get_fee_version(), 1);
+        assert_eq!(hashing_account.get_hash_tree(0), [0; 32]);
+        assert_eq!(hashing_account.get_hash_tree(1), [1; 32]);
+        assert_eq!(storage_account.next_claimable_commitment_ptr(), 2);
+        assert_eq!(CommitmentQueue::new(&mut commitment_queue).len(), 1);
+
+        // Both commitments paid for their own batch, the surplus is credited to the fee collector
+        let surplus = 2 * program_fee.commitment_hash_computation_fee(0).0
+            - commitment_hash_computation_instructions(1).len() as u64
+                * program_fee.hash_tx_compensation().0;
+        assert!(surplus > 0);
+        assert_eq!(
+            surplus,
+            program_fee
+                .commitment_hash_computation_fee_surplus(&[0, 0], 1)
+                .0
+        );
+        assert_eq!(pool.lamports(), pool_lamports - surplus);
+        assert_eq!(fee_collector.lamports(), fee_collector_lamports + surplus);
+    }
+
     #[test]
     fn test_init_commitment_hash_insertion_can_fail() {
+        zero_program_account!(fee, FeeAccount);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
+        parent_account!(mut storage_account, StorageAccount);
         parent_account!(mut metadata_account, MetadataAccount);
         zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
         zero_program_account!(mut metadata_queue, MetadataQueueAccount);
         zero_program_account!(mut hashing_account, CommitmentHashingAccount);
 
+        // Active computation
+        hashing_account.set_is_active(&true);
+
         assert_eq!(
             init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
                 &mut commitment_queue,
                 &mut metadata_queue,
                 &mut hashing_account,
                 &mut metadata_account,
+                &mut storage_account,
+                0,
+                false,
+                0,
                 false
             ),
             Err(ElusivError::ComputationIsNotYetFinished.into())
@@ -1660,14 +2324,247 @@ mod tests {
 
         assert_eq!(
             init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
+                &mut commitment_queue,
+                &mut metadata_queue,
+                &mut hashing_account,
+                &mut metadata_account,
+                &mut storage_account,
+                0,
+                true,
+                0,
+                false
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_init_commitment_hash_insertion_can_fail_unmodified() {
+        zero_program_account!(fee, FeeAccount);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
+        parent_account!(mut storage_account, StorageAccount);
+        parent_account!(mut metadata_account, MetadataAccount);
+        zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
+        zero_program_account!(mut metadata_queue, MetadataQueueAccount);
+        zero_program_account!(mut hashing_account, CommitmentHashingAccount);
+
+        // Stale values of a previous computation
+        hashing_account.set_instruction(&5);
+        hashing_account.set_round(&3);
+        hashing_account.set_ordering(&7);
+
+        // Missing metadata
+        CommitmentQueue::new(&mut commitment_queue)
+            .enqueue(CommitmentHashRequest {
+                commitment: [0; 32],
+                min_batching_rate: 0,
+                fee_version: 0,
+            })
+            .unwrap();
+
+        assert_eq!(
+            init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
                 &mut commitment_queue,
                 &mut metadata_queue,
                 &mut hashing_account,
                 &mut metadata_account,
-                true
+                &mut storage_account,
+                0,
+                true,
+                0,
+                false
             ),
             Ok(())
         );
+
+        assert_eq!(hashing_account.get_instruction(), 5);
+        assert_eq!(hashing_account.get_round(), 3);
+        assert_eq!(hashing_account.get_ordering(), 7);
+        assert!(!hashing_account.get_is_active());
+        assert_eq!(CommitmentQueue::new(&mut commitment_queue).len(), 1);
+        assert_eq!(storage_account.next_claimable_commitment_ptr(), 0);
+
+        // Missing metadata child-account
+        MetadataQueue::new(&mut metadata_queue)
+            .enqueue([0; CommitmentMetadata::SIZE])
+            .unwrap();
+        zero_program_account!(mut metadata_account, MetadataAccount);
+
+        assert_eq!(
+            init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
+                &mut commitment_queue,
+                &mut metadata_queue,
+                &mut hashing_account,
+                &mut metadata_account,
+                &mut storage_account,
+                0,
+                false,
+                0,
+                false
+            ),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        assert_eq!(hashing_account.get_ordering(), 7);
+        assert_eq!(CommitmentQueue::new(&mut commitment_queue).len(), 1);
+        assert_eq!(MetadataQueue::new(&mut metadata_queue).len(), 1);
+        assert_eq!(storage_account.next_claimable_commitment_ptr(), 0);
+    }
+
+    #[test]
+    fn test_concurrent_commitment_hashing() {
+        zero_program_account!(fee, FeeAccount);
+        test_account_info!(pool, 0);
+        test_account_info!(fee_collector, 0);
+        parent_account!(mut storage_account, StorageAccount);
+        parent_account!(mut root_history_account, RootHistoryAccount);
+        parent_account!(mut metadata_account, MetadataAccount);
+        zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
+        zero_program_account!(mut metadata_queue, MetadataQueueAccount);
+        zero_program_account!(mut hashing_account0, CommitmentHashingAccount);
+        zero_program_account!(mut hashing_account1, CommitmentHashingAccount);
+
+        let commitments = [u256_from_str_skip_mr("123"), u256_from_str_skip_mr("456")];
+        {
+            let mut commitment_queue = CommitmentQueue::new(&mut commitment_queue);
+            let mut metadata_queue = MetadataQueue::new(&mut metadata_queue);
+            for commitment in commitments {
+                enqueue_commitment(
+                    &mut commitment_queue,
+                    &mut metadata_queue,
+                    commitment,
+                    CommitmentMetadata::default(),
+                    0,
+                    0,
+                )
+                .unwrap();
+            }
+        }
+
+        // Both batches are claimed (without siblings)
+        for (i, hashing_account) in [&mut hashing_account0, &mut hashing_account1]
+            .into_iter()
+            .enumerate()
+        {
+            init_commitment_hash(
+                &fee,
+                &pool,
+                &fee_collector,
+                &mut commitment_queue,
+                &mut metadata_queue,
+                hashing_account,
+                &mut metadata_account,
+                &mut storage_account,
+                0,
+                false,
+                i as u32,
+                false,
+            )
+            .unwrap();
+            assert_eq!(hashing_account.get_ordering(), i as u32);
+        }
+        assert_eq!(storage_account.next_claimable_commitment_ptr(), 2);
+        assert!(!storage_account.no_claimed_batches());
+
+        // Pre-claim setup is not possible while batches are being hashed
+        zero_program_account!(mut hashing_account2, CommitmentHashingAccount);
+        assert_eq!(
+            init_commitment_hash_setup(&mut hashing_account2, &storage_account, false, 2),
+            Err(ElusivError::ComputationIsNotYetFinished.into())
+        );
+
+        // The second batch requires the first batch to be inserted
+        assert_eq!(
+            init_commitment_hash_setup(&mut hashing_account1, &storage_account, false, 1),
+            Err(ElusivError::ComputationIsNotYetFinished.into())
+        );
+
+        // Without a HT (batching rate zero) the hashing requires the siblings
+        assert_eq!(
+            compute_commitment_hash_partial(&mut hashing_account1),
+            Err(ElusivError::ComputationIsNotYetFinished.into())
+        );
+
+        let instructions = commitment_hash_computation_instructions(0).len();
+        init_commitment_hash_setup(&mut hashing_account0, &storage_account, false, 0).unwrap();
+        for _ in 0..instructions {
+            compute_commitment_hash_partial(&mut hashing_account0).unwrap();
+        }
+        finalize_commitment_hash(
+            &mut hashing_account0,
+            &mut storage_account,
+            &mut root_history_account,
+            0,
+        )
+        .unwrap();
+        assert!(!hashing_account0.get_is_active());
+        assert_eq!(storage_account.get_next_commitment_ptr(), 1);
+
+        init_commitment_hash_setup(&mut hashing_account1, &storage_account, false, 1).unwrap();
+        for _ in 0..instructions {
+            compute_commitment_hash_partial(&mut hashing_account1).unwrap();
+        }
+        finalize_commitment_hash(
+            &mut hashing_account1,
+            &mut storage_account,
+            &mut root_history_account,
+            1,
+        )
+        .unwrap();
+        assert_eq!(storage_account.get_next_commitment_ptr(), 2);
+        assert!(storage_account.no_claimed_batches());
+
+        let mut hash = full_poseidon2_hash(
+            u256_to_fr_skip_mr(&commitments[0]),
+            u256_to_fr_skip_mr(&commitments[1]),
+        );
+        for empty in EMPTY_TREE.iter().take(MT_HEIGHT as usize).skip(1) {
+            hash = full_poseidon2_hash(hash, u256_to_fr_skip_mr(empty));
+        }
+        assert_eq!(fr_to_u256_le(&hash), storage_account.get_root().unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_commitment_hashing_ht() {
+        zero_program_account!(mut hashing_account, CommitmentHashingAccount);
+
+        let batching_rate = 2;
+        hashing_account
+            .reset(batching_rate, 0, &[[1; 32], [2; 32], [3; 32], [4; 32]])
+            .unwrap();
+
+        // The HT hashes can be computed without the siblings
+        let first_path_round = hashing_account.first_path_round();
+        let instructions = commitment_hash_computation_instructions(batching_rate);
+        let mut round = 0;
+        for &rounds in instructions {
+            if round + rounds as u32 >= first_path_round {
+                break;
+            }
+            compute_commitment_hash_partial(&mut hashing_account).unwrap();
+            round += rounds as u32;
+        }
+        assert!(round > 0);
+
+        assert_eq!(
+            compute_commitment_hash_partial(&mut hashing_account),
+            Err(ElusivError::ComputationIsNotYetFinished.into())
+        );
+
+        hashing_account
+            .setup(0, &[[0; 32]; MT_HEIGHT as usize])
+            .unwrap();
+        compute_commitment_hash_partial(&mut hashing_account).unwrap();
     }
 
     #[test]
@@ -1679,30 +2576,37 @@ mod tests {
 
         // Inactive account
         assert_eq!(
-            compute_commitment_hash(&fee_payer, &fee, &pool, &mut hashing_account, 0, 0),
+            compute_commitment_hash(&fee_payer, &fee, &pool, &mut hashing_account, 0, 0, 0),
             Err(ElusivError::ComputationIsNotYetStarted.into())
         );
 
         // Invalid fee_version
         hashing_account.set_is_active(&true);
+        hashing_account.set_setup(&true);
         assert_eq!(
-            compute_commitment_hash(&fee_payer, &fee, &pool, &mut hashing_account, 1, 0),
+            compute_commitment_hash(&fee_payer, &fee, &pool, &mut hashing_account, 1, 0, 0),
             Err(ElusivError::InvalidFeeVersion.into())
         );
 
-        compute_commitment_hash(&fee_payer, &fee, &pool, &mut hashing_account, 0, 0).unwrap();
+        compute_commitment_hash(&fee_payer, &fee, &pool, &mut hashing_account, 0, 0, 0).unwrap();
     }
 
     #[test]
     fn test_finalize_commitment_hash() {
         parent_account!(mut storage_account, StorageAccount);
+        parent_account!(mut root_history_account, RootHistoryAccount);
         zero_program_account!(mut hashing_account, CommitmentHashingAccount);
 
         // Computation not finished
         hashing_account.set_is_active(&true);
         hashing_account.set_instruction(&0);
         assert_eq!(
-            finalize_commitment_hash(&mut hashing_account, &mut storage_account),
+            finalize_commitment_hash(
+                &mut hashing_account,
+                &mut storage_account,
+                &mut root_history_account,
+                0
+            ),
             Err(ElusivError::ComputationIsNotYetFinished.into())
         );
 
@@ -1711,7 +2615,12 @@ mod tests {
         hashing_account
             .set_instruction(&(commitment_hash_computation_instructions(0).len() as u32));
         assert_eq!(
-            finalize_commitment_hash(&mut hashing_account, &mut storage_account),
+            finalize_commitment_hash(
+                &mut hashing_account,
+                &mut storage_account,
+                &mut root_history_account,
+                0
+            ),
             Err(ElusivError::ComputationIsNotYetStarted.into())
         );
 
@@ -1719,17 +2628,29 @@ mod tests {
         hashing_account.set_is_active(&true);
         storage_account.set_next_commitment_ptr(&(MT_COMMITMENT_COUNT as u32));
         assert_eq!(
-            finalize_commitment_hash(&mut hashing_account, &mut storage_account),
+            finalize_commitment_hash(
+                &mut hashing_account,
+                &mut storage_account,
+                &mut root_history_account,
+                0
+            ),
             Err(ElusivError::NoRoomForCommitment.into())
         );
 
         storage_account.set_next_commitment_ptr(&0);
-        finalize_commitment_hash(&mut hashing_account, &mut storage_account).unwrap();
+        finalize_commitment_hash(
+            &mut hashing_account,
+            &mut storage_account,
+            &mut root_history_account,
+            0,
+        )
+        .unwrap();
     }
 
     #[test]
     fn test_finalize_commitment_hash_valid() {
         parent_account!(mut storage_account, StorageAccount);
+        parent_account!(mut root_history_account, RootHistoryAccount);
         zero_program_account!(mut hashing_account, CommitmentHashingAccount);
 
         let batching_rate = 4;
@@ -1752,7 +2673,13 @@ mod tests {
         }
 
         for _ in 0..=batching_rate {
-            finalize_commitment_hash(&mut hashing_account, &mut storage_account).unwrap();
+            finalize_commitment_hash(
+                &mut hashing_account,
+                &mut storage_account,
+                &mut root_history_account,
+                0,
+            )
+            .unwrap();
         }
 
         assert!(!hashing_account.get_is_active());
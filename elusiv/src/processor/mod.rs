@@ -1,11 +1,16 @@
 mod accounts;
+mod batch;
+mod callback;
 mod commitment;
 mod proof;
 mod utils;
 mod vkey;
 
 pub use accounts::*;
+pub use batch::*;
+pub use callback::*;
 pub use commitment::*;
 pub use proof::*;
+pub(crate) use utils::current_slot;
 pub use utils::{nop, program_token_account_address};
 pub use vkey::*;
@@ -5,22 +5,33 @@ use crate::error::ElusivError;
 use crate::instruction::ElusivInstruction;
 use crate::macros::{guard, pda_account, BorshSerDeSized, EnumVariantIndex};
 use crate::processor::utils::{
-    close_account, create_associated_token_account, spl_token_account_rent,
+    close_account, create_associated_token_account, current_slot, spl_token_account_rent,
     system_program_account_rent, transfer_lamports_from_pda_checked, transfer_token,
     transfer_token_from_pda, verify_program_token_account,
 };
-use crate::processor::{enqueue_commitment, verify_recent_commitment_index, ZERO_COMMITMENT_RAW};
+use crate::processor::{
+    enqueue_commitment, invoke_recipient_callback_or_timeout, recipient_address,
+    verify_recent_commitment_index, ZERO_COMMITMENT_RAW,
+};
 use crate::proof::verifier::{prepare_public_inputs_instructions, verify_partial};
 use crate::proof::vkey::{MigrateUnaryVKey, SendQuadraVKey, VerifyingKey, VerifyingKeyInfo};
-use crate::state::commitment::{CommitmentBufferAccount, CommitmentQueue, CommitmentQueueAccount};
-use crate::state::governor::{FeeCollectorAccount, GovernorAccount, PoolAccount};
-use crate::state::metadata::{MetadataQueue, MetadataQueueAccount};
+use crate::state::apa::{enforce_trailing_apa_restrictions, split_apa_accounts};
+use crate::state::commitment::{
+    CommitmentBufferAccount, CommitmentQueue, CommitmentQueueAccount,
+    COMMITMENT_BUFFER_EXPIRY_SLOTS,
+};
+use crate::state::governor::{
+    split_rate_limit_account, FeeCollectorAccount, GovernorAccount, PoolAccount, RateLimitAccount,
+    RateLimits, RootHistoryParameters,
+};
+use crate::state::metadata::{EncryptedMetadataAccount, MetadataQueue, MetadataQueueAccount};
 use crate::state::nullifier::NullifierAccount;
 use crate::state::proof::{
-    NullifierDuplicateAccount, VerificationAccount, VerificationAccountData, VerificationState,
+    NullifierDuplicateAccount, RecipientCallback, VerificationAccount, VerificationAccountData,
+    VerificationState,
 };
 use crate::state::queue::{Queue, RingQueue};
-use crate::state::storage::{StorageAccount, MT_COMMITMENT_COUNT};
+use crate::state::storage::{RootHistoryAccount, StorageAccount, MT_COMMITMENT_COUNT};
 use crate::state::vkey::VKeyAccount;
 use crate::token::{
     elusiv_token, verify_associated_token_account, verify_token_account, Lamports, Token,
@@ -28,7 +39,7 @@ use crate::token::{
 };
 use crate::types::{
     generate_hashed_inputs, InputCommitment, JoinSplitPublicInputs, MigratePublicInputs, Proof,
-    PublicInputs, RawU256, SendPublicInputs, JOIN_SPLIT_MAX_N_ARITY, U256,
+    PublicInputs, RawU256, SendPublicInputs, HASHED_INPUTS_V1_DOMAIN, JOIN_SPLIT_MAX_N_ARITY, U256,
 };
 use borsh::{BorshDeserialize, BorshSerialize};
 use elusiv_types::ParentAccount;
@@ -104,6 +115,8 @@ pub fn init_verification<'a, 'b, 'c, 'd>(
     commitment_buffer: &mut CommitmentBufferAccount,
     nullifier_account0: &NullifierAccount<'b, 'c, 'd>,
     nullifier_account1: &NullifierAccount<'b, 'c, 'd>,
+    governor: &GovernorAccount,
+    root_history_account: &RootHistoryAccount,
 
     verification_account_index: u8,
     vkey_id: u32,
@@ -129,6 +142,7 @@ pub fn init_verification<'a, 'b, 'c, 'd>(
             public_inputs.public_signals_skip_mr()
         ),
         vkey_account.get_public_inputs_count() as usize,
+        vkey_account.get_layout(),
     );
 
     // TODO: reject zero-commitment nullifier
@@ -150,11 +164,15 @@ pub fn init_verification<'a, 'b, 'c, 'd>(
         }
     };
 
+    let slot = current_slot()?;
     check_join_split_public_inputs(
         join_split,
         storage_account,
+        root_history_account,
+        &governor.get_root_history(),
         [nullifier_account0, nullifier_account1],
         &tree_indices,
+        slot,
     )?;
 
     // Open [`NullifierDuplicateAccount`]
@@ -193,7 +211,11 @@ pub fn init_verification<'a, 'b, 'c, 'd>(
     )?;
 
     // Add the output commitment into the commitment-buffer
-    commitment_buffer.try_insert(&join_split.output_commitment.reduce())?;
+    commitment_buffer.try_insert(
+        &join_split.output_commitment.reduce(),
+        COMMITMENT_BUFFER_EXPIRY_SLOTS,
+        slot,
+    )?;
 
     pda_account!(
         mut verification_account,
@@ -344,6 +366,10 @@ pub fn init_verification_transfer_fee<'a>(
         commitment_hash_fee_token: commitment_hash_fee_token.amount(),
         proof_verification_fee: proof_verification_fee.amount(),
         associated_token_account_rent: associated_token_account_rent_token,
+        recipient_callback: ElusivOption::None,
+        transferred_amount: 0,
+        transferred_associated_token_account_rent: ElusivOption::None,
+        finalized_slot: 0,
     });
 
     verification_account.set_state(&VerificationState::FeeTransferred);
@@ -425,8 +451,12 @@ pub fn compute_verification(
     };
 
     let result = vkey_account.execute_on_child_account_mut(0, |data| {
-        let vkey = VerifyingKey::new(data, vkey_account.get_public_inputs_count() as usize)
-            .ok_or(ElusivError::InvalidAccountState)?;
+        let vkey = VerifyingKey::new(
+            data,
+            vkey_account.get_public_inputs_count() as usize,
+            vkey_account.get_layout(),
+        )
+        .ok_or(ElusivError::InvalidAccountState)?;
 
         verify_partial(verification_account, &vkey, instruction_index)
     })?;
@@ -466,6 +496,15 @@ pub struct FinalizeSendData {
 
     pub iv: U256,
     pub encrypted_owner: U256,
+
+    /// Variable-length encrypted note of the recipient (bound to the `hashed_inputs`)
+    pub encrypted_metadata: Vec<u8>,
+
+    /// If set, the recipient is a program that is invoked with this payload after the transfer (bound to the `hashed_inputs`)
+    pub recipient_callback: Option<Vec<u8>>,
+
+    /// The forwarded accounts the recipient program is allowed to write to (bound to the `hashed_inputs`)
+    pub recipient_callback_writable_accounts: Vec<U256>,
 }
 
 const SPL_MEMO_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
@@ -496,6 +535,8 @@ pub fn finalize_verification_send(
     storage_account: &StorageAccount,
     buffer: &mut CommitmentBufferAccount,
     instructions_account: &AccountInfo,
+    encrypted_metadata_account: &mut EncryptedMetadataAccount,
+    trailing_accounts: &[&AccountInfo], // additional transaction references, followed by the APA accounts of `recipient`
 
     verification_account_index: u8,
     data: FinalizeSendData,
@@ -512,6 +553,9 @@ pub fn finalize_verification_send(
         _ => return Err(ElusivError::FeatureNotAvailable.into()),
     };
 
+    let (additional_transaction_references, apa_accounts) =
+        split_apa_accounts(recipient.key, trailing_accounts)?;
+
     // Check spl-memo-instruction
     let memo = if uses_memo {
         Some(get_memo_from_instructions(
@@ -522,32 +566,74 @@ pub fn finalize_verification_send(
         None
     };
 
-    // Verify `hashed_inputs`
+    // Verify `hashed_inputs` (a `recipient` equal to the versioned encoding's domain could collide with the original encoding)
+    guard!(
+        recipient.key.to_bytes() != *HASHED_INPUTS_V1_DOMAIN,
+        ElusivError::InvalidRecipient
+    );
     let hash = generate_hashed_inputs(
         &recipient.key.to_bytes(),
         &identifier_account.key.to_bytes(),
         &data.iv,
         &data.encrypted_owner,
-        &(if transaction_reference.key != instructions_account.key {
-            transaction_reference.key.to_bytes()
-        } else {
-            [0; 32]
-        }),
+        &transaction_references(
+            transaction_reference,
+            instructions_account,
+            additional_transaction_references,
+        )?,
         public_inputs.recipient_is_associated_token_account,
         &public_inputs.join_split.metadata,
         &public_inputs.join_split.optional_fee,
         &memo,
+        &data.encrypted_metadata,
+        &data.recipient_callback,
+        &data.recipient_callback_writable_accounts,
     );
     guard!(
         hash == public_inputs.hashed_inputs,
         ElusivError::InputsMismatch
     );
 
-    // Set `recipient_wallet`
+    let recipient_callback = match &data.recipient_callback {
+        Some(payload) => {
+            guard!(recipient.executable, ElusivError::InvalidRecipientCallback);
+            guard!(
+                !public_inputs.solana_pay_transfer,
+                ElusivError::InvalidRecipientCallback
+            );
+
+            ElusivOption::Some(
+                RecipientCallback::new(payload, &data.recipient_callback_writable_accounts)
+                    .ok_or(ElusivError::InvalidRecipientCallback)?,
+            )
+        }
+        None => {
+            guard!(
+                data.recipient_callback_writable_accounts.is_empty(),
+                ElusivError::InvalidRecipientCallback
+            );
+            ElusivOption::None
+        }
+    };
+
+    enforce_trailing_apa_restrictions(
+        recipient.key,
+        public_inputs.join_split.token_id,
+        apa_accounts,
+    )?;
+
+    // Set `recipient_wallet` and `recipient_callback`
     verification_account.set_other_data(&mutate(&verification_account.get_other_data(), |data| {
-        data.recipient_wallet = ElusivOption::Some(RawU256::new(recipient.key.to_bytes()))
+        data.recipient_wallet = ElusivOption::Some(RawU256::new(recipient.key.to_bytes()));
+        data.recipient_callback = recipient_callback;
     }));
 
+    // Batch members (including the leader) receive their result through `finalize_batch_verification_member`
+    guard!(
+        verification_account.get_batch().option().is_none(),
+        ElusivError::ComputationIsNotYetFinished
+    );
+
     match verification_account.get_is_verified() {
         ElusivOption::None => return Err(ElusivError::ComputationIsNotYetFinished.into()),
         ElusivOption::Some(false) => {
@@ -557,7 +643,7 @@ pub fn finalize_verification_send(
             if let Some(index) =
                 buffer.find_position(&public_inputs.join_split.output_commitment.reduce())
             {
-                buffer.set_value(index, &[0; 32]);
+                buffer.remove(index);
             }
 
             return Ok(());
@@ -590,12 +676,59 @@ pub fn finalize_verification_send(
     );
     guard!(data.mt_index == mt_index, ElusivError::InputsMismatch);
 
+    // Publish the encrypted metadata for recipients scanning for incoming commitments
+    if !data.encrypted_metadata.is_empty() {
+        encrypted_metadata_account.add_encrypted_metadata(
+            &public_inputs.join_split.output_commitment.reduce(),
+            &data.encrypted_metadata,
+        )?;
+    }
+
     verification_account.set_state(&VerificationState::InsertNullifiers);
     verification_account.set_instruction(&0);
 
     Ok(())
 }
 
+/// Maximum number of Solana Pay references (including the `transaction_reference`) of a single send
+pub const MAX_TRANSACTION_REFERENCES: usize = 4;
+
+/// Returns the keys of all Solana Pay references in order
+///
+/// # Note
+///
+/// Additional references can only be used in combination with the `transaction_reference`.
+fn transaction_references(
+    transaction_reference: &AccountInfo,
+    instructions_account: &AccountInfo,
+    additional_transaction_references: &[&AccountInfo],
+) -> Result<Vec<U256>, ProgramError> {
+    if transaction_reference.key == instructions_account.key {
+        guard!(
+            additional_transaction_references.is_empty(),
+            ElusivError::InvalidAccount
+        );
+        return Ok(Vec::new());
+    }
+
+    guard!(
+        additional_transaction_references.len() < MAX_TRANSACTION_REFERENCES,
+        ElusivError::InvalidAccount
+    );
+
+    let mut references = vec![transaction_reference.key.to_bytes()];
+    for reference in additional_transaction_references {
+        let reference = reference.key.to_bytes();
+        guard!(
+            !references.contains(&reference),
+            ElusivError::DuplicateValue
+        );
+        references.push(reference);
+    }
+
+    Ok(references)
+}
+
 pub fn finalize_verification_insert_nullifier(
     verification_account: &mut VerificationAccount,
     nullifier_account: &mut NullifierAccount,
@@ -655,6 +788,11 @@ pub fn finalize_verification_insert_nullifier(
     if input_commitment_index >= public_inputs.join_split.input_commitments.len() - 1
         && nullifier_account.is_moved_nullifier_empty()
     {
+        let slot = current_slot()?;
+        verification_account
+            .set_other_data(&mutate(&verification_account.get_other_data(), |data| {
+                data.finalized_slot = slot
+            }));
         verification_account.set_state(&VerificationState::Finalized);
     }
 
@@ -672,7 +810,9 @@ pub fn finalize_verification_transfer_lamports<'a>(
     metadata_queue: &mut MetadataQueueAccount,
     verification_account_info: &AccountInfo<'a>,
     nullifier_duplicate_account: &AccountInfo<'a>,
+    governor: &GovernorAccount,
     instructions_account: &AccountInfo,
+    trailing_accounts: &[&AccountInfo<'a>],
 
     _verification_account_index: u8,
 ) -> ProgramResult {
@@ -681,12 +821,19 @@ pub fn finalize_verification_transfer_lamports<'a>(
         VerificationAccount,
         verification_account_info
     );
-    let data = verification_account.get_other_data();
+    let mut data = verification_account.get_other_data();
     let request = verification_account.get_request();
     let join_split = proof_request!(&request, public_inputs, public_inputs.join_split_inputs());
 
     guard!(join_split.token_id == 0, ElusivError::InvalidAccountState);
 
+    let rate_limits = governor.get_rate_limits();
+    let (rate_limit_account, callback_accounts) = split_rate_limit_account(
+        join_split.token_id,
+        rate_limits.is_withdrawal_limited(join_split.token_id),
+        trailing_accounts,
+    )?;
+
     guard!(
         verification_account.get_state() == VerificationState::Finalized,
         ElusivError::InvalidAccountState
@@ -723,17 +870,38 @@ pub fn finalize_verification_transfer_lamports<'a>(
     if let ProofRequest::Send(public_inputs) = &request {
         if public_inputs.join_split.amount > 0 {
             guard!(
-                recipient.key.to_bytes() == data.recipient_wallet.option().unwrap().skip_mr(),
+                Some(recipient.key.to_bytes()) == recipient_address(&data),
                 ElusivError::InvalidRecipient
             );
 
-            // Subtract the optional fee from the amount
+            // Subtract the optional fee and the already transferred amount from the amount
             let amount = public_inputs
                 .join_split
                 .amount
                 .checked_sub(public_inputs.join_split.optional_fee.amount)
+                .ok_or(ElusivError::InvalidAmount)?
+                .checked_sub(data.transferred_amount)
                 .ok_or(ElusivError::InvalidAmount)?;
 
+            let (amount, is_complete) = rate_limited_withdrawal(
+                &rate_limits,
+                rate_limit_account,
+                join_split.token_id,
+                amount,
+                public_inputs.join_split.optional_fee.amount,
+                !public_inputs.solana_pay_transfer,
+            )?;
+
+            if !is_complete {
+                // `pool` transfers the partial `amount` to `recipient` (lamports), the remainder is transferred in a later window
+                transfer_lamports_from_pda_checked(pool, recipient, amount)?;
+
+                data.transferred_amount += amount;
+                verification_account.set_other_data(&data);
+
+                return Ok(());
+            }
+
             if public_inputs.solana_pay_transfer {
                 // `pool` transfers `amount` to `original_fee_payer` (lamports)
                 transfer_lamports_from_pda_checked(
@@ -803,6 +971,18 @@ pub fn finalize_verification_transfer_lamports<'a>(
 
     verification_account.set_state(&VerificationState::Closed);
 
+    // The recipient program is invoked after all transfers have been completed
+    if let ElusivOption::Some(callback) = data.recipient_callback {
+        let program_id = Pubkey::new_from_array(data.recipient_wallet.option().unwrap().skip_mr());
+        invoke_recipient_callback_or_timeout(
+            &program_id,
+            &callback,
+            recipient,
+            callback_accounts,
+            data.finalized_slot,
+        )?;
+    }
+
     Ok(())
 }
 
@@ -821,9 +1001,11 @@ pub fn finalize_verification_transfer_token<'a>(
     metadata_queue: &mut MetadataQueueAccount,
     verification_account_info: &AccountInfo<'a>,
     nullifier_duplicate_account: &AccountInfo<'a>,
+    governor: &GovernorAccount,
     token_program: &AccountInfo<'a>,
     mint_account: &AccountInfo<'a>,
     instructions_account: &AccountInfo,
+    trailing_accounts: &[&AccountInfo<'a>],
 
     _verification_account_index: u8,
 ) -> ProgramResult {
@@ -832,14 +1014,21 @@ pub fn finalize_verification_transfer_token<'a>(
         VerificationAccount,
         verification_account_info
     );
-    let data = verification_account.get_other_data();
+    let mut data = verification_account.get_other_data();
     let request = verification_account.get_request();
     let join_split = proof_request!(&request, public_inputs, public_inputs.join_split_inputs());
-    let recipient_address = data.recipient_wallet.option().unwrap().skip_mr();
+    let recipient_address = recipient_address(&data).unwrap();
 
     let token_id = join_split.token_id;
     guard!(token_id > 0, ElusivError::InvalidAccountState);
 
+    let rate_limits = governor.get_rate_limits();
+    let (rate_limit_account, callback_accounts) = split_rate_limit_account(
+        token_id,
+        rate_limits.is_withdrawal_limited(token_id),
+        trailing_accounts,
+    )?;
+
     guard!(
         verification_account.get_state() == VerificationState::Finalized,
         ElusivError::InvalidAccountState
@@ -921,7 +1110,10 @@ pub fn finalize_verification_transfer_token<'a>(
                     ElusivError::InvalidRecipient
                 );
 
-                if recipient.lamports() == 0 {
+                if let Some(rent) = data.transferred_associated_token_account_rent.option() {
+                    // Determined by a previous partial transfer
+                    associated_token_account_rent_token = Some(rent);
+                } else if recipient.lamports() == 0 {
                     // Check if associated token accounts exists
                     guard!(
                         *mint_account.key == elusiv_token(token_id)?.mint,
@@ -947,17 +1139,46 @@ pub fn finalize_verification_transfer_token<'a>(
 
             let optional_fee = Token::new(token_id, public_inputs.join_split.optional_fee.amount);
 
-            // Subtract the optional fee and optional associated_token_account_rent from the amount
-            let token = Token::new(
+            // Subtract the optional fee, optional associated_token_account_rent and the already transferred amount from the amount
+            let amount = public_inputs
+                .join_split
+                .amount
+                .checked_sub(associated_token_account_rent_token.unwrap_or(0))
+                .ok_or(ElusivError::InvalidAmount)?
+                .checked_sub(public_inputs.join_split.optional_fee.amount)
+                .ok_or(ElusivError::InvalidAmount)?
+                .checked_sub(data.transferred_amount)
+                .ok_or(ElusivError::InvalidAmount)?;
+
+            let (amount, is_complete) = rate_limited_withdrawal(
+                &rate_limits,
+                rate_limit_account,
                 token_id,
-                public_inputs
-                    .join_split
-                    .amount
-                    .checked_sub(associated_token_account_rent_token.unwrap_or(0))
-                    .ok_or(ElusivError::InvalidAmount)?
-                    .checked_sub(public_inputs.join_split.optional_fee.amount)
-                    .ok_or(ElusivError::InvalidAmount)?,
-            );
+                amount,
+                optional_fee.amount(),
+                !public_inputs.solana_pay_transfer,
+            )?;
+            let token = Token::new(token_id, amount);
+
+            if !is_complete {
+                // `pool` transfers the partial `amount` to `recipient` (token), the remainder is transferred in a later window
+                transfer_token_from_pda::<PoolAccount>(
+                    pool,
+                    pool_account,
+                    actual_recipient,
+                    token_program,
+                    token,
+                    None,
+                    None,
+                )?;
+
+                data.transferred_amount += amount;
+                data.transferred_associated_token_account_rent =
+                    associated_token_account_rent_token.into();
+                verification_account.set_other_data(&data);
+
+                return Ok(());
+            }
 
             if public_inputs.solana_pay_transfer {
                 // `pool` transfers `amount` to `original_fee_payer_account` (token)
@@ -1069,9 +1290,66 @@ pub fn finalize_verification_transfer_token<'a>(
 
     verification_account.set_state(&VerificationState::Closed);
 
+    // The recipient program is invoked after all transfers have been completed
+    if let ElusivOption::Some(callback) = data.recipient_callback {
+        let program_id = Pubkey::new_from_array(data.recipient_wallet.option().unwrap().skip_mr());
+        invoke_recipient_callback_or_timeout(
+            &program_id,
+            &callback,
+            recipient,
+            callback_accounts,
+            data.finalized_slot,
+        )?;
+    }
+
     Ok(())
 }
 
+/// Applies the withdrawal rate limit of `token_id` to the transfer of the `remaining` amount and the `optional_fee`
+///
+/// # Notes
+///
+/// - Returns the amount that can be transferred to the recipient and whether the transfer completes the send.
+/// - If the rate limit would be exceeded, a splittable send only transfers a part of the `remaining` amount
+///   (the `optional_fee` is transferred alongside the final part).
+/// - Without a `rate_limit_account`, withdrawals of `token_id` are not capped (see [`split_rate_limit_account`]).
+fn rate_limited_withdrawal(
+    rate_limits: &RateLimits,
+    rate_limit_account: Option<&AccountInfo>,
+    token_id: u16,
+    remaining: u64,
+    optional_fee: u64,
+    is_splittable: bool,
+) -> Result<(u64, bool), ProgramError> {
+    let rate_limit_account = match rate_limit_account {
+        Some(rate_limit_account) => rate_limit_account,
+        None => return Ok((remaining, true)),
+    };
+
+    pda_account!(mut rate_limit_account, RateLimitAccount, rate_limit_account);
+    let mut window = rate_limit_account.get_window();
+    let slot = current_slot()?;
+    let available = window.remaining_withdrawal(rate_limits, token_id, slot);
+    let total = remaining
+        .checked_add(optional_fee)
+        .ok_or(ElusivError::InvalidAmount)?;
+
+    let (amount, is_complete) = if total <= available {
+        window.add_withdrawal(rate_limits, token_id, total, slot)?;
+        (remaining, true)
+    } else {
+        let amount = available.min(remaining);
+        guard!(is_splittable && amount > 0, ElusivError::RateLimitExceeded);
+
+        window.add_withdrawal(rate_limits, token_id, amount, slot)?;
+        (amount, false)
+    };
+
+    rate_limit_account.set_window(&window);
+
+    Ok((amount, is_complete))
+}
+
 fn close_verification_pdas<'a>(
     beneficiary: &AccountInfo<'a>,
     verification_account: &AccountInfo<'a>,
@@ -1112,8 +1390,11 @@ fn minimum_commitment_mt_index(
 fn check_join_split_public_inputs(
     public_inputs: &JoinSplitPublicInputs,
     storage_account: &StorageAccount,
+    root_history_account: &RootHistoryAccount,
+    root_history: &RootHistoryParameters,
     nullifier_accounts: [&NullifierAccount; MAX_MT_COUNT],
     tree_indices: &[u32; MAX_MT_COUNT],
+    current_slot: u64,
 ) -> ProgramResult {
     // Check that the resulting commitment is not the zero-commitment
     guard!(
@@ -1161,7 +1442,12 @@ fn check_join_split_public_inputs(
                 if tree_indices[index] == active_tree_index {
                     // Active tree
                     guard!(
-                        storage_account.is_root_valid(&root.reduce()),
+                        storage_account.is_root_valid(
+                            &root.reduce(),
+                            root_history_account,
+                            root_history,
+                            current_slot
+                        ),
                         ElusivError::InvalidMerkleRoot
                     );
                 } else {
@@ -1408,7 +1694,7 @@ macro_rules! vkey_account {
             source
         );
 
-        let mut data = vec![0; <VKeyAccount as elusiv_types::accounts::SizedAccount>::SIZE];
+        crate::macros::zero_program_account!(internal VKeyAccount, data);
         let mut $id =
             <VKeyAccount as elusiv_types::accounts::ParentAccount>::new_with_child_accounts(
                 &mut data,
@@ -1419,6 +1705,7 @@ macro_rules! vkey_account {
         $id.set_public_inputs_count(
             &<$vkey as crate::proof::vkey::VerifyingKeyInfo>::PUBLIC_INPUTS_COUNT,
         );
+        $id.set_layout(&<$vkey as crate::proof::vkey::VerifyingKeyInfo>::LAYOUT);
 
         $id.set_child_pubkey(0, Some(*vkey_account.key).into());
         $id.set_child_pubkey(0, Some(*vkey_account1.key).into());
@@ -1439,6 +1726,7 @@ mod tests {
     use crate::proof::verifier::{
         proof_from_str, COMBINED_MILLER_LOOP_IXS, FINAL_EXPONENTIATION_IXS,
     };
+    use crate::state::apa::apa_target_map_account;
     use crate::state::commitment::COMMITMENT_BUFFER_LEN;
     use crate::state::fee::ProgramFee;
     use crate::state::governor::PoolAccount;
@@ -1467,13 +1755,16 @@ mod tests {
 
         parent_account!(storage, StorageAccount);
         parent_account!(mut nullifier, NullifierAccount);
+        zero_program_account!(governor, GovernorAccount);
+        parent_account!(root_history, RootHistoryAccount);
         zero_program_account!(mut buffer, CommitmentBufferAccount);
         test_account_info!(fee_payer, 0);
         test_account_info!(identifier, 0);
+        zero_program_account!(internal VerificationAccount, v_acc_data);
         account_info!(
             v_acc,
             VerificationAccount::find_with_pubkey(*fee_payer.key, Some(0)).0,
-            vec![0; VerificationAccount::SIZE]
+            v_acc_data
         );
 
         let mut inputs = SendPublicInputs {
@@ -1495,7 +1786,7 @@ mod tests {
             hashed_inputs: u256_from_str_skip_mr("1"),
             solana_pay_transfer: false,
         };
-        compute_fee_rec_lamports::<SendQuadraVKey, _>(&mut inputs, &fee());
+        compute_fee_rec_lamports::<SendQuadraVKey, _>(&mut inputs, SendQuadraVKey::LAYOUT, &fee());
 
         account_info!(
             n_duplicate_acc,
@@ -1504,7 +1795,7 @@ mod tests {
         );
 
         let vkey_id = SendQuadraVKey::VKEY_ID;
-        let mut data = vec![0; VKeyAccount::SIZE];
+        zero_program_account!(internal VKeyAccount, data);
         let mut vkey = VKeyAccount::new(&mut data).unwrap();
         vkey.set_public_inputs_count(&SendQuadraVKey::PUBLIC_INPUTS_COUNT);
         vkey.set_version(&1);
@@ -1525,6 +1816,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 RESERVED_VERIFICATION_ACCOUNT_IDS + 1,
                 vkey_id,
                 [0, 1],
@@ -1546,6 +1839,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 vkey_id,
                 [0, 1],
@@ -1569,6 +1864,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 vkey_id,
                 [0, 1],
@@ -1593,6 +1890,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 vkey_id,
                 [0, 1],
@@ -1616,12 +1915,18 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 vkey_id,
                 [0, 1],
                 Send(mutate(&inputs, |inputs| {
                     inputs.join_split.recent_commitment_index = 1;
-                    compute_fee_rec_lamports::<SendQuadraVKey, _>(inputs, &fee());
+                    compute_fee_rec_lamports::<SendQuadraVKey, _>(
+                        inputs,
+                        SendQuadraVKey::LAYOUT,
+                        &fee(),
+                    );
                 })),
                 false,
             ),
@@ -1640,6 +1945,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 vkey_id,
                 [1, 0],
@@ -1661,6 +1968,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 vkey_id,
                 [0, 1],
@@ -1691,6 +2000,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 vkey_id,
                 [0, 1],
@@ -1718,6 +2029,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 vkey_id,
                 [0, 1],
@@ -1739,6 +2052,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 vkey_id,
                 [0, 1],
@@ -1748,7 +2063,7 @@ mod tests {
             Err(ElusivError::InvalidAccount.into())
         );
 
-        let mut data = vec![0; VKeyAccount::SIZE];
+        zero_program_account!(internal VKeyAccount, data);
         let mut migrate_vkey = VKeyAccount::new(&mut data).unwrap();
         migrate_vkey.set_public_inputs_count(&MigrateUnaryVKey::PUBLIC_INPUTS_COUNT);
         migrate_vkey.set_version(&1);
@@ -1765,6 +2080,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 MigrateUnaryVKey::VKEY_ID,
                 [0, 1],
@@ -1789,6 +2106,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 vkey_id,
                 [0, 1],
@@ -1801,7 +2120,7 @@ mod tests {
         let mut inputs = inputs.clone();
         inputs.join_split.input_commitments[0].nullifier_hash =
             RawU256::new(u256_from_str_skip_mr("2"));
-        compute_fee_rec_lamports::<SendQuadraVKey, _>(&mut inputs, &fee());
+        compute_fee_rec_lamports::<SendQuadraVKey, _>(&mut inputs, SendQuadraVKey::LAYOUT, &fee());
 
         account_info!(
             n_duplicate_acc,
@@ -1822,6 +2141,8 @@ mod tests {
                     &mut buffer,
                     &nullifier,
                     &nullifier,
+                    &governor,
+                    &root_history,
                     0,
                     vkey_id,
                     [0, 1],
@@ -1831,7 +2152,9 @@ mod tests {
                 Err(ElusivError::DuplicateValue.into())
             );
 
-            buffer.try_insert(&[i as u8; 32]).unwrap();
+            buffer
+                .try_insert(&[i as u8; 32], COMMITMENT_BUFFER_EXPIRY_SLOTS, 0)
+                .unwrap();
         }
 
         assert_eq!(
@@ -1845,6 +2168,8 @@ mod tests {
                 &mut buffer,
                 &nullifier,
                 &nullifier,
+                &governor,
+                &root_history,
                 0,
                 vkey_id,
                 [0, 1],
@@ -1860,13 +2185,16 @@ mod tests {
     fn test_init_verification_commitment_count_too_high() {
         parent_account!(storage, StorageAccount);
         parent_account!(nullifier, NullifierAccount);
+        zero_program_account!(governor, GovernorAccount);
+        parent_account!(root_history, RootHistoryAccount);
         zero_program_account!(mut buffer, CommitmentBufferAccount);
         test_account_info!(fee_payer, 0);
         test_account_info!(identifier, 0);
+        zero_program_account!(internal VerificationAccount, v_acc_data);
         account_info!(
             v_acc,
             VerificationAccount::find_with_pubkey(*fee_payer.key, Some(0)).0,
-            vec![0; VerificationAccount::SIZE]
+            v_acc_data
         );
 
         let mut inputs = SendPublicInputs {
@@ -1888,7 +2216,7 @@ mod tests {
             hashed_inputs: u256_from_str_skip_mr("1"),
             solana_pay_transfer: false,
         };
-        compute_fee_rec_lamports::<SendQuadraVKey, _>(&mut inputs, &fee());
+        compute_fee_rec_lamports::<SendQuadraVKey, _>(&mut inputs, SendQuadraVKey::LAYOUT, &fee());
 
         account_info!(
             n_duplicate_acc,
@@ -1896,7 +2224,7 @@ mod tests {
             vec![1]
         );
 
-        let mut data = vec![0; VKeyAccount::SIZE];
+        zero_program_account!(internal VKeyAccount, data);
         let mut vkey = VKeyAccount::new(&mut data).unwrap();
         vkey.set_public_inputs_count(&SendQuadraVKey::PUBLIC_INPUTS_COUNT);
         vkey.set_is_frozen(&true);
@@ -1918,6 +2246,8 @@ mod tests {
             &mut buffer,
             &nullifier,
             &nullifier,
+            &governor,
+            &root_history,
             0,
             0,
             [0, 1],
@@ -1956,10 +2286,11 @@ mod tests {
             hashed_inputs: u256_from_str_skip_mr("1"),
             solana_pay_transfer: false,
         };
-        compute_fee_rec_lamports::<SendQuadraVKey, _>(&mut inputs, &fee());
+        compute_fee_rec_lamports::<SendQuadraVKey, _>(&mut inputs, SendQuadraVKey::LAYOUT, &fee());
         let instructions = prepare_public_inputs_instructions(
             &inputs.public_signals_skip_mr(),
             SendQuadraVKey::public_inputs_count(),
+            SendQuadraVKey::LAYOUT,
         );
 
         zero_program_account!(mut verification_acc, VerificationAccount);
@@ -2061,7 +2392,7 @@ mod tests {
 
         // Invalid system_program
         inputs.join_split.fee = 0;
-        compute_fee_rec_lamports::<SendQuadraVKey, _>(&mut inputs, &fee());
+        compute_fee_rec_lamports::<SendQuadraVKey, _>(&mut inputs, SendQuadraVKey::LAYOUT, &fee());
         verification_acc.set_request(&ProofRequest::Send(inputs));
         assert_eq!(
             init_verification_transfer_fee(
@@ -2208,10 +2539,11 @@ mod tests {
             hashed_inputs: u256_from_str_skip_mr("1"),
             solana_pay_transfer: false,
         };
-        compute_fee_rec::<SendQuadraVKey, _>(&mut inputs, &fee(), &price);
+        compute_fee_rec::<SendQuadraVKey, _>(&mut inputs, SendQuadraVKey::LAYOUT, &fee(), &price);
         let instructions = prepare_public_inputs_instructions(
             &inputs.public_signals_skip_mr(),
             SendQuadraVKey::public_inputs_count(),
+            SendQuadraVKey::LAYOUT,
         );
 
         zero_program_account!(mut verification_acc, VerificationAccount);
@@ -2245,7 +2577,7 @@ mod tests {
         );
 
         inputs.join_split.fee = 0;
-        compute_fee_rec::<SendQuadraVKey, _>(&mut inputs, &fee(), &price);
+        compute_fee_rec::<SendQuadraVKey, _>(&mut inputs, SendQuadraVKey::LAYOUT, &fee(), &price);
         verification_acc.set_request(&ProofRequest::Send(inputs.clone()));
 
         // Invalid system_program
@@ -2484,6 +2816,7 @@ mod tests {
         let instructions = prepare_public_inputs_instructions(
             &public_inputs,
             SendQuadraVKey::public_inputs_count(),
+            SendQuadraVKey::LAYOUT,
         );
         verification_account.set_prepare_inputs_instructions_count(&(instructions.len() as u32));
         for (i, &ix) in instructions.iter().enumerate() {
@@ -2607,6 +2940,7 @@ mod tests {
             let encrypted_owner = Pubkey::new_unique().to_bytes();
 
             let metadata = CommitmentMetadata::default();
+            let encrypted_metadata = vec![1, 2, 3];
             let $optional_fee_collector = Pubkey::new_unique();
             let optional_fee = OptionalFee {
                 collector: $optional_fee_collector,
@@ -2633,16 +2967,19 @@ mod tests {
                     &$identifier,
                     &iv,
                     &encrypted_owner,
-                    &$reference,
+                    &[$reference],
                     false,
                     &metadata,
                     &optional_fee,
                     &None,
+                    &encrypted_metadata,
+                    &None,
+                    &[],
                 ),
                 solana_pay_transfer: false,
             };
 
-            let mut $v_data = vec![0; VerificationAccount::SIZE];
+            zero_program_account!(internal VerificationAccount, $v_data);
             let mut v_account = VerificationAccount::new(&mut $v_data).unwrap();
             let fee_payer = RawU256::new(Pubkey::new_unique().to_bytes());
             v_account
@@ -2672,13 +3009,16 @@ mod tests {
                 commitment_index: 0,
                 encrypted_owner,
                 iv,
+                encrypted_metadata,
+                recipient_callback: None,
+                recipient_callback_writable_accounts: Vec::new(),
             };
         };
     }
 
     macro_rules! simple_storage_account {
         ($id: ident) => {
-            let mut data = vec![0; StorageAccount::SIZE];
+            zero_program_account!(internal StorageAccount, data);
             let $id =
                 <StorageAccount as elusiv_types::accounts::ProgramAccount>::new(&mut data).unwrap();
         };
@@ -2702,8 +3042,17 @@ mod tests {
         let mut queue = CommitmentQueueAccount::new(&mut data).unwrap();
         simple_storage_account!(storage);
         zero_program_account!(mut buffer, CommitmentBufferAccount);
+        parent_account!(mut encrypted_metadata_account, EncryptedMetadataAccount);
 
         account_info!(recipient, Pubkey::new_from_array(recipient_bytes));
+        account_info!(
+            apa,
+            apa_target_map_account(recipient.key).0,
+            vec![],
+            system_program::id(),
+            false
+        );
+        account_info!(additional_reference, Pubkey::new_unique());
         account_info!(identifier, Pubkey::new_from_array(identifier_bytes));
         account_info!(reference, Pubkey::new_from_array(reference_bytes));
         test_account_info!(any, 0);
@@ -2720,6 +3069,8 @@ mod tests {
                 &storage,
                 &mut buffer,
                 &any,
+                &mut encrypted_metadata_account,
+                &[&apa],
                 0,
                 finalize_data.clone(),
                 false,
@@ -2729,9 +3080,36 @@ mod tests {
 
         verification_acc.set_is_verified(&ElusivOption::Some(true));
 
+        // Missing APA accounts
+        assert_eq!(
+            finalize_verification_send(
+                &recipient,
+                &identifier,
+                &reference,
+                &mut queue,
+                &mut verification_acc,
+                &storage,
+                &mut buffer,
+                &any,
+                &mut encrypted_metadata_account,
+                &[&additional_reference],
+                0,
+                finalize_data.clone(),
+                false,
+            ),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
         // Invalid recipient
         {
             account_info!(recipient, Pubkey::new_from_array(identifier_bytes));
+            account_info!(
+                apa,
+                apa_target_map_account(recipient.key).0,
+                vec![],
+                system_program::id(),
+                false
+            );
             assert_eq!(
                 finalize_verification_send(
                     &recipient,
@@ -2742,6 +3120,8 @@ mod tests {
                     &storage,
                     &mut buffer,
                     &any,
+                    &mut encrypted_metadata_account,
+                    &[&apa],
                     0,
                     finalize_data.clone(),
                     false,
@@ -2763,6 +3143,8 @@ mod tests {
                     &storage,
                     &mut buffer,
                     &any,
+                    &mut encrypted_metadata_account,
+                    &[&apa],
                     0,
                     finalize_data.clone(),
                     false,
@@ -2784,6 +3166,63 @@ mod tests {
                     &storage,
                     &mut buffer,
                     &any,
+                    &mut encrypted_metadata_account,
+                    &[&apa],
+                    0,
+                    finalize_data.clone(),
+                    false,
+                ),
+                Err(ElusivError::InputsMismatch.into())
+            );
+        }
+
+        // Recipient equal to the domain of the versioned hashed inputs encoding
+        {
+            account_info!(
+                domain_recipient,
+                Pubkey::new_from_array(*HASHED_INPUTS_V1_DOMAIN)
+            );
+            account_info!(
+                apa,
+                apa_target_map_account(domain_recipient.key).0,
+                vec![],
+                system_program::id(),
+                false
+            );
+            assert_eq!(
+                finalize_verification_send(
+                    &domain_recipient,
+                    &identifier,
+                    &reference,
+                    &mut queue,
+                    &mut verification_acc,
+                    &storage,
+                    &mut buffer,
+                    &any,
+                    &mut encrypted_metadata_account,
+                    &[&apa],
+                    0,
+                    finalize_data.clone(),
+                    false,
+                ),
+                Err(ElusivError::InvalidRecipient.into())
+            );
+        }
+
+        // Invalid additional reference
+        {
+            assert_eq!(
+                finalize_verification_send(
+                    &recipient,
+                    &identifier,
+                    &reference,
+                    &mut queue,
+                    &mut verification_acc,
+                    &storage,
+                    &mut buffer,
+                    &any,
+                    &mut encrypted_metadata_account,
+                    &[&additional_reference, &apa],
                     0,
                     finalize_data.clone(),
                     false,
@@ -2802,6 +3241,8 @@ mod tests {
             mutate(&finalize_data, |d| d.mt_index = 1),
             mutate(&finalize_data, |d| d.encrypted_owner = d.iv),
             mutate(&finalize_data, |d| d.iv = d.encrypted_owner),
+            mutate(&finalize_data, |d| d.encrypted_metadata = vec![]),
+            mutate(&finalize_data, |d| d.encrypted_metadata = vec![1, 2, 4]),
         ] {
             assert_eq!(
                 finalize_verification_send(
@@ -2813,6 +3254,8 @@ mod tests {
                     &storage,
                     &mut buffer,
                     &any,
+                    &mut encrypted_metadata_account,
+                    &[&apa],
                     0,
                     invalid_data,
                     false,
@@ -2832,6 +3275,8 @@ mod tests {
                 &storage,
                 &mut buffer,
                 &any,
+                &mut encrypted_metadata_account,
+                &[&apa],
                 0,
                 finalize_data.clone(),
                 false,
@@ -2843,6 +3288,14 @@ mod tests {
             verification_acc.get_state(),
             VerificationState::InsertNullifiers
         );
+        assert_eq!(encrypted_metadata_account.get_entries_count(), 1);
+        assert_eq!(
+            encrypted_metadata_account.get_encrypted_metadata(0),
+            Ok((
+                public_inputs.join_split.output_commitment.reduce(),
+                vec![1, 2, 3]
+            ))
+        );
 
         // Called twice
         assert_eq!(
@@ -2855,6 +3308,8 @@ mod tests {
                 &storage,
                 &mut buffer,
                 &any,
+                &mut encrypted_metadata_account,
+                &[&apa],
                 0,
                 finalize_data,
                 false,
@@ -2863,6 +3318,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_transaction_references() {
+        let keys: Vec<Pubkey> = (0..=MAX_TRANSACTION_REFERENCES)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+        account_info!(instructions, keys[0]);
+        account_info!(a, keys[1]);
+        account_info!(b, keys[2]);
+        account_info!(c, keys[3]);
+        account_info!(d, keys[4]);
+
+        // No reference
+        assert_eq!(
+            transaction_references(&instructions, &instructions, &[]),
+            Ok(vec![])
+        );
+
+        // Additional references without a reference
+        assert_eq!(
+            transaction_references(&instructions, &instructions, &[&a]),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        // Multiple references in order
+        assert_eq!(
+            transaction_references(&a, &instructions, &[&c, &b]),
+            Ok(vec![
+                keys[1].to_bytes(),
+                keys[3].to_bytes(),
+                keys[2].to_bytes()
+            ])
+        );
+        assert_eq!(
+            transaction_references(&a, &instructions, &[&b, &c, &d]),
+            Ok(keys[1..].iter().map(|k| k.to_bytes()).collect())
+        );
+
+        // Too many references
+        assert_eq!(
+            transaction_references(&a, &instructions, &[&b, &c, &d, &instructions]),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        // Duplicate reference
+        assert_eq!(
+            transaction_references(&a, &instructions, &[&b, &a]),
+            Err(ElusivError::DuplicateValue.into())
+        );
+    }
+
     #[test]
     fn test_finalize_verification_send_invalid() {
         finalize_send_test!(
@@ -2881,14 +3386,27 @@ mod tests {
         let mut queue = CommitmentQueueAccount::new(&mut data).unwrap();
         simple_storage_account!(storage);
         zero_program_account!(mut buffer, CommitmentBufferAccount);
+        parent_account!(mut encrypted_metadata_account, EncryptedMetadataAccount);
         test_account_info!(any, 0);
 
         account_info!(recipient, Pubkey::new_from_array(recipient_bytes));
+        account_info!(
+            apa,
+            apa_target_map_account(recipient.key).0,
+            vec![],
+            system_program::id(),
+            false
+        );
         account_info!(identifier, Pubkey::new_from_array(identifier_bytes));
         account_info!(reference, Pubkey::new_from_array(reference_bytes));
 
         verification_acc.set_is_verified(&ElusivOption::Some(false));
 
+        let output_commitment = public_inputs.join_split.output_commitment.reduce();
+        buffer.push(&[1; 32], 0);
+        buffer.push(&output_commitment, 0);
+        buffer.push(&[2; 32], 0);
+
         assert_eq!(
             finalize_verification_send(
                 &recipient,
@@ -2899,6 +3417,8 @@ mod tests {
                 &storage,
                 &mut buffer,
                 &any,
+                &mut encrypted_metadata_account,
+                &[&apa],
                 0,
                 finalize_data,
                 false,
@@ -2906,6 +3426,12 @@ mod tests {
             Ok(())
         );
         assert_eq!(verification_acc.get_state(), VerificationState::Finalized);
+
+        // The output commitment is removed from the buffer
+        assert_eq!(buffer.len(), 2);
+        assert!(!buffer.contains(&output_commitment));
+        assert_eq!(buffer.find_position(&[1; 32]), Some(0));
+        assert_eq!(buffer.find_position(&[2; 32]), Some(1));
     }
 
     #[test]
@@ -2932,7 +3458,7 @@ mod tests {
         let pk = Pubkey::new_unique();
         account_info!(acc, pk);
 
-        let mut data = vec![0; VerificationAccount::SIZE];
+        zero_program_account!(internal VerificationAccount, data);
         let mut v_account = VerificationAccount::new(&mut data).unwrap();
         v_account.set_request(&ProofRequest::Migrate(migrate_public_inputs));
         v_account.set_state(&VerificationState::ProofSetup);
@@ -2944,7 +3470,15 @@ mod tests {
         let finalize_data = FinalizeSendData::default();
         simple_storage_account!(storage);
         zero_program_account!(mut buffer, CommitmentBufferAccount);
+        parent_account!(mut encrypted_metadata_account, EncryptedMetadataAccount);
         test_account_info!(any, 0);
+        account_info!(
+            apa,
+            apa_target_map_account(acc.key).0,
+            vec![],
+            system_program::id(),
+            false
+        );
 
         assert_eq!(
             finalize_verification_send(
@@ -2956,6 +3490,8 @@ mod tests {
                 &storage,
                 &mut buffer,
                 &any,
+                &mut encrypted_metadata_account,
+                &[&apa],
                 0,
                 finalize_data,
                 false,
@@ -3059,6 +3595,7 @@ mod tests {
         account_info!(v_acc, Pubkey::new_unique(), verification_acc_data);
         zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
         zero_program_account!(mut metadata_queue, MetadataQueueAccount);
+        zero_program_account!(governor, GovernorAccount);
 
         {
             pda_account!(mut v_acc, VerificationAccount, v_acc);
@@ -3078,7 +3615,9 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::InvalidAccountState.into())
@@ -3106,7 +3645,9 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &invalid_n_pda,
+                &governor,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::InvalidAccount.into())
@@ -3124,7 +3665,9 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::InvalidAccount.into())
@@ -3142,7 +3685,9 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::InvalidRecipient.into())
@@ -3161,7 +3706,9 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::InvalidAccount.into())
@@ -3191,7 +3738,9 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::QueueIsFull.into())
@@ -3210,7 +3759,9 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &any,
+                &[],
                 0
             ),
             Ok(())
@@ -3261,6 +3812,7 @@ mod tests {
         account_info!(v_acc, Pubkey::new_unique(), verification_acc_data);
         zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
         zero_program_account!(mut metadata_queue, MetadataQueueAccount);
+        zero_program_account!(governor, GovernorAccount);
 
         {
             pda_account!(mut v_acc, VerificationAccount, v_acc);
@@ -3281,7 +3833,9 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &any,
+                &[],
                 0
             ),
             Ok(())
@@ -3348,6 +3902,7 @@ mod tests {
         account_info!(v_acc, Pubkey::new_unique(), verification_acc_data);
         zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
         zero_program_account!(mut metadata_queue, MetadataQueueAccount);
+        zero_program_account!(governor, GovernorAccount);
 
         {
             pda_account!(mut v_acc, VerificationAccount, v_acc);
@@ -3371,9 +3926,11 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &spl,
                 &any,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::InvalidAccount.into())
@@ -3395,9 +3952,11 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &spl,
                 &any,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::InvalidAccount.into())
@@ -3420,9 +3979,11 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &spl,
                 &any,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::InvalidAccount.into())
@@ -3444,9 +4005,11 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &any,
                 &any,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::InvalidAccount.into())
@@ -3468,9 +4031,11 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &spl,
                 &any,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::InvalidAccount.into())
@@ -3492,9 +4057,11 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &spl,
                 &any,
                 &any,
+                &[],
                 0
             ),
             Err(ElusivError::InvalidRecipient.into())
@@ -3515,9 +4082,11 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &spl,
                 &any,
                 &any,
+                &[],
                 0
             ),
             Ok(())
@@ -3578,6 +4147,7 @@ mod tests {
         account_info!(v_acc, Pubkey::new_unique(), verification_acc_data);
         zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
         zero_program_account!(mut metadata_queue, MetadataQueueAccount);
+        zero_program_account!(governor, GovernorAccount);
 
         {
             pda_account!(mut v_acc, VerificationAccount, v_acc);
@@ -3602,9 +4172,11 @@ mod tests {
                 &mut metadata_queue,
                 &v_acc,
                 &n_pda,
+                &governor,
                 &spl,
                 &any,
                 &any,
+                &[],
                 0
             ),
             Ok(())
@@ -3655,6 +4227,8 @@ mod tests {
     #[test]
     fn test_check_join_split_public_inputs() {
         parent_account!(mut storage, StorageAccount);
+        parent_account!(root_history_account, RootHistoryAccount);
+        let root_history = RootHistoryParameters::default();
         parent_account!(n_account, NullifierAccount);
 
         let commitments_count = 1000;
@@ -3761,8 +4335,11 @@ mod tests {
                 check_join_split_public_inputs(
                     &public_inputs,
                     &storage,
+                    &root_history_account,
+                    &root_history,
                     [&n_account, &n_account],
-                    &[0, 1]
+                    &[0, 1],
+                    0
                 ),
                 Err(err.into())
             );
@@ -3784,8 +4361,11 @@ mod tests {
                     ];
                 }),
                 &storage,
+                &root_history_account,
+                &root_history,
                 [&n_account, &n_account],
-                &[0, 0]
+                &[0, 0],
+                0
             ),
             Err(ElusivError::InvalidInstructionData.into())
         );
@@ -3795,8 +4375,11 @@ mod tests {
             check_join_split_public_inputs(
                 &valid_inputs,
                 &storage,
+                &root_history_account,
+                &root_history,
                 [&n_account, &n_account],
-                &[0, 1]
+                &[0, 1],
+                0
             ),
             Ok(())
         );
@@ -3829,8 +4412,11 @@ mod tests {
                 check_join_split_public_inputs(
                     &public_inputs,
                     &storage,
+                    &root_history_account,
+                    &root_history,
                     [&n_account, &n_account],
-                    &[0, 1]
+                    &[0, 1],
+                    0
                 ),
                 Ok(())
             );
@@ -3859,8 +4445,11 @@ mod tests {
                         RawU256::new(u256_from_str_skip_mr("1"));
                 }),
                 &storage,
+                &root_history_account,
+                &root_history,
                 [&n_account, &n_account],
-                &[0, 1]
+                &[0, 1],
+                0
             ),
             Err(ElusivError::CouldNotInsertNullifier.into())
         );
@@ -8,8 +8,8 @@ use solana_program::program_pack::Pack;
 use solana_program::pubkey::Pubkey;
 use solana_program::sysvar::instructions;
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError, rent::Rent,
-    sysvar::Sysvar,
+    account_info::AccountInfo, clock::Clock, entrypoint::ProgramResult,
+    program_error::ProgramError, rent::Rent, sysvar::Sysvar,
 };
 use spl_associated_token_account::get_associated_token_address;
 
@@ -20,6 +20,19 @@ pub fn nop() -> solana_program::entrypoint::ProgramResult {
     Ok(())
 }
 
+/// Returns the current slot
+///
+/// # Note
+///
+/// The clock sysvar is not available in unit tests, so we use slot zero there.
+pub fn current_slot() -> Result<u64, ProgramError> {
+    if cfg!(feature = "test-unit") {
+        return Ok(0);
+    }
+
+    Ok(Clock::get()?.slot)
+}
+
 pub trait InstructionsSysvar {
     fn current_index(&self) -> Result<u16, ProgramError>;
     fn instruction_at_index(&self, index: usize) -> Result<Instruction, ProgramError>;
@@ -1,5 +1,7 @@
 use crate::{
-    error::ElusivError, processor::setup_child_account, proof::vkey::VerifyingKey,
+    error::ElusivError,
+    processor::setup_child_account,
+    proof::vkey::{VerifyingKey, VerifyingKeyLayout},
     state::vkey::VKeyAccount,
 };
 use borsh::{BorshDeserialize, BorshSerialize};
@@ -31,6 +33,7 @@ pub fn create_vkey_account<'a>(
     vkey_id: u32,
     public_inputs_count: u32,
     authority: ElusivOption<Pubkey>,
+    layout: VerifyingKeyLayout,
 ) -> ProgramResult {
     guard!(
         vkey_id < MAX_NUMBER_OF_VKEYS,
@@ -42,6 +45,7 @@ pub fn create_vkey_account<'a>(
     pda_account!(mut vkey_account, VKeyAccount, vkey_account);
     vkey_account.set_authority(&authority);
     vkey_account.set_public_inputs_count(&public_inputs_count);
+    vkey_account.set_layout(&layout);
 
     Ok(())
 }
@@ -62,7 +66,8 @@ pub fn create_new_vkey_version(
 
     let public_inputs_count = vkey_account.get_public_inputs_count() as usize;
     let binary_data_account_size =
-        VerifyingKey::source_size(public_inputs_count) + ChildAccountConfig::SIZE;
+        VerifyingKey::source_size(public_inputs_count, vkey_account.get_layout())
+            + ChildAccountConfig::SIZE;
 
     setup_child_account(
         vkey_account,
@@ -86,7 +91,7 @@ pub fn set_vkey_data(
     verify_vkey_modification(signer, vkey_account)?;
 
     let public_inputs_count = vkey_account.get_public_inputs_count();
-    let len = VerifyingKey::source_size(public_inputs_count as usize);
+    let len = VerifyingKey::source_size(public_inputs_count as usize, vkey_account.get_layout());
     let start = data_position as usize * VKEY_ACCOUNT_DATA_PACKET_SIZE;
     let end = start + VKEY_ACCOUNT_DATA_PACKET_SIZE;
     let cutoff = if end > len { end - len } else { 0 };
@@ -210,7 +215,8 @@ mod test {
 
         let public_inputs_count = vkey_account.get_public_inputs_count() as usize;
         let binary_data_account_size =
-            VerifyingKey::source_size(public_inputs_count) + ChildAccountConfig::SIZE;
+            VerifyingKey::source_size(public_inputs_count, TestVKey::LAYOUT)
+                + ChildAccountConfig::SIZE;
 
         test_account_info!(valid_vkey_binary_data_account, binary_data_account_size);
         test_account_info!(
@@ -285,7 +291,7 @@ mod test {
             .unwrap();
 
         let positions = div_ceiling_usize(
-            VerifyingKey::source_size(TestVKey::public_inputs_count()),
+            VerifyingKey::source_size(TestVKey::public_inputs_count(), TestVKey::LAYOUT),
             VKEY_ACCOUNT_DATA_PACKET_SIZE,
         );
         for i in 0..positions {
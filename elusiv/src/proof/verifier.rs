@@ -7,26 +7,30 @@
 #![allow(clippy::too_many_arguments)]
 #![allow(clippy::assign_op_pattern)]
 
-use super::vkey::VerifyingKey;
+use super::vkey::{VerifyingKey, VerifyingKeyLayout};
 use crate::bytes::{usize_as_u32_safe, usize_as_u8_safe};
 use crate::error::ElusivError::{
     self, ComputationIsAlreadyFinished, CouldNotProcessProof, InvalidAccountState,
     PartialComputationError,
 };
 use crate::error::ElusivResult;
-use crate::fields::{G2HomProjective, Wrap, G1A, G2A};
+use crate::fields::{fr_to_u256_le_repr, u256_to_big_uint, G2HomProjective, Wrap, G1A, G2A};
+use crate::macros::profile_compute_units;
 use crate::processor::COMPUTE_VERIFICATION_IX_COUNT;
-use crate::state::proof::{RAMFq, VerificationAccount, VerificationState};
-use crate::types::U256;
+use crate::state::proof::{BatchVerificationAccount, VerificationAccount, VerificationState};
+use crate::types::{LazyField, U256};
 use ark_bn254::{
-    Fq, Fq12, Fq12Parameters, Fq2, Fq6, Fq6Parameters, G1Affine, G1Projective, G2Affine, Parameters,
+    Fq, Fq12, Fq12Parameters, Fq2, Fq6, Fq6Parameters, Fr, G1Affine, G1Projective, G2Affine,
+    Parameters,
 };
 use ark_ec::models::bn::BnParameters;
 use ark_ec::ProjectiveCurve;
 use ark_ff::fields::models::{
     fp12_2over3over2::Fp12ParamsWrapper, fp6_3over2::Fp6ParamsWrapper, QuadExtParameters,
 };
-use ark_ff::{biginteger::BigInteger256, field_new, CubicExtParameters, Field, One, Zero};
+use ark_ff::{
+    biginteger::BigInteger256, field_new, CubicExtParameters, Field, One, PrimeField, Zero,
+};
 use borsh::{BorshDeserialize, BorshSerialize};
 use elusiv_computation::{PartialComputation, RAM};
 use elusiv_derive::BorshSerDeSized;
@@ -40,6 +44,9 @@ pub enum VerificationStep {
     PublicInputPreparation,
     CombinedMillerLoop,
     FinalExponentiation,
+
+    /// The Miller loop is computed by a [`crate::state::proof::BatchVerificationAccount`]
+    Batched,
 }
 
 /// Requires `verification_account.prepare_inputs_instructions_count + COMBINED_MILLER_LOOP_IXS + FINAL_EXPONENTIATION_IXS` calls to verify a valid proof
@@ -82,6 +89,10 @@ pub fn verify_partial(
             verification_account.serialize_rams().unwrap();
             return v;
         }
+        VerificationStep::Batched => {
+            // The batch account performs the computation
+            return Err(InvalidAccountState);
+        }
     }
 
     Ok(None)
@@ -97,7 +108,9 @@ pub fn prepare_public_inputs(
 
     let result = prepare_public_inputs_partial(round, rounds as usize, verification_account, vkey);
 
-    if round + rounds as usize == prepare_public_inputs_rounds(vkey.public_inputs_count) {
+    if round + rounds as usize
+        == prepare_public_inputs_rounds(vkey.public_inputs_count, vkey.layout)
+    {
         let prepared_inputs = result.ok_or(CouldNotProcessProof)?;
 
         verification_account
@@ -134,6 +147,7 @@ pub fn combined_miller_loop(
 
     let mut result = None;
     for round in round..round + rounds {
+        profile_compute_units!();
         result = combined_miller_loop_partial(
             round,
             verification_account,
@@ -147,6 +161,7 @@ pub fn combined_miller_loop(
             &mut alt_b,
         )?;
     }
+    profile_compute_units!();
 
     verification_account.set_coeff_index(&usize_as_u8_safe(coeff_index));
 
@@ -187,8 +202,10 @@ pub fn final_exponentiation(
 
     let mut result = None;
     for round in round..round + rounds {
+        profile_compute_units!();
         result = final_exponentiation_partial(round, verification_account, &f)?;
     }
+    profile_compute_units!();
 
     verification_account.set_round(&usize_as_u32_safe(round + rounds));
     verification_account.set_instruction(&(instruction as u32 + 1));
@@ -212,11 +229,16 @@ macro_rules! read_g1_p {
     };
 }
 
-const PREPARE_PUBLIC_INPUTS_ROUNDS: usize = 33;
-const fn prepare_public_inputs_rounds(public_inputs_count: usize) -> usize {
-    PREPARE_PUBLIC_INPUTS_ROUNDS * public_inputs_count
+const fn prepare_public_inputs_rounds(
+    public_inputs_count: usize,
+    layout: VerifyingKeyLayout,
+) -> usize {
+    layout.input_preparation_rounds() * public_inputs_count
 }
 
+/// The accumulator and the sum of the already prepared inputs (two projective points)
+pub const PREPARE_PUBLIC_INPUTS_RAM_FQ_SIZE: usize = 2 * 3;
+
 /// Public input preparation
 ///
 /// # Notes
@@ -224,8 +246,8 @@ const fn prepare_public_inputs_rounds(public_inputs_count: usize) -> usize {
 /// - `prepared_inputs = \sum_{i = 0}ˆ{N} input_{i} gamma_abc_g1_{i}`
 /// - reference implementation: https://github.com/arkworks-rs/groth16/blob/765817f77a6e14964c6f264d565b18676b11bd59/src/verifier.rs#L22
 /// - N public inputs (elements of the scalar field) in non-reduced form
-/// - the total rounds required for preparation of all inputs is `PREPARE_PUBLIC_INPUTS_ROUNDS` * N
-/// - this partial computation is different from the rest, in that it's cost is dependent on the public inputs count and bits
+/// - the total rounds required for preparation of all inputs is `layout.input_preparation_rounds()` * N
+/// - this partial computation is different from the rest, in that it's cost is dependent on the public inputs count, bits and the [`VerifyingKeyLayout`]
 /// - for `prepare_public_inputs` we use 1 instruction with 1.4m compute units
 fn prepare_public_inputs_partial(
     round: usize,
@@ -233,18 +255,20 @@ fn prepare_public_inputs_partial(
     storage: &mut VerificationAccount,
     vkey: &VerifyingKey,
 ) -> Option<G1Affine> {
+    let input_rounds = vkey.layout.input_preparation_rounds();
     let mut acc: G1Projective = read_g1_p!(storage.ram_fq, 3);
-    let mut input_index = round / PREPARE_PUBLIC_INPUTS_ROUNDS;
+    let mut input_index = round / input_rounds;
     let mut public_input = storage.get_public_input(input_index).skip_mr();
 
     for round in round..round + rounds {
-        let round = round % PREPARE_PUBLIC_INPUTS_ROUNDS;
+        let round = round % input_rounds;
         if round == 0 {
             acc = G1Projective::zero();
         }
 
-        if round < PREPARE_PUBLIC_INPUTS_ROUNDS - 1 {
-            let gamma_abc = vkey.gamma_abc(input_index, round, public_input[round]);
+        if round < input_rounds - 1 {
+            let window = vkey.layout.window(&public_input, round);
+            let gamma_abc = vkey.gamma_abc(input_index, round, window);
             acc.add_assign_mixed(&gamma_abc);
         } else {
             // Adding
@@ -291,8 +315,8 @@ pub fn precomputed_input_preparation(
 
         let mut acc = G1Projective::zero();
 
-        for (j, window) in public_input.iter().enumerate() {
-            let gamma_abc = vkey.gamma_abc(i, j, *window);
+        for j in 0..vkey.layout.windows_count() {
+            let gamma_abc = vkey.gamma_abc(i, j, vkey.layout.window(public_input, j));
             acc.add_assign_mixed(&gamma_abc);
         }
 
@@ -303,12 +327,18 @@ pub fn precomputed_input_preparation(
 
 const ADD_MIXED_COST: u16 = 22;
 const ADD_COST: u16 = 30;
+const DOUBLE_COST: u16 = 16;
 const MAX_CUS: u16 = 1_330; // 1_400_000 / 1000 minus padding
 
 /// Returns the instructions (and their rounds) required for a specific public-input-bound input preparation
+///
+/// # Notes
+///
+/// The number of rounds (and therefore instructions) depends on the [`VerifyingKeyLayout`] of the vkey.
 pub fn prepare_public_inputs_instructions(
     public_inputs: &[U256],
     public_inputs_count: usize,
+    layout: VerifyingKeyLayout,
 ) -> Vec<u32> {
     assert!(public_inputs.len() == public_inputs_count);
 
@@ -317,16 +347,17 @@ pub fn prepare_public_inputs_instructions(
     let mut total_rounds = 0;
     let mut rounds = 0;
     let mut compute_units = 0;
+    let windows_count = layout.windows_count();
 
     for public_input in public_inputs.iter() {
-        for b in 0..33 {
-            let cus = if b == 32 {
+        for b in 0..layout.input_preparation_rounds() {
+            let cus = if b == windows_count {
                 if *public_input == [0; 32] {
                     0
                 } else {
                     ADD_COST
                 }
-            } else if public_input[b] == 0 {
+            } else if layout.window(public_input, b) == 0 {
                 0
             } else {
                 ADD_MIXED_COST
@@ -353,12 +384,499 @@ pub fn prepare_public_inputs_instructions(
     // Redundant check
     assert_eq!(
         total_rounds,
-        prepare_public_inputs_rounds(public_inputs_count)
+        prepare_public_inputs_rounds(public_inputs_count, layout)
     );
 
     instructions
 }
 
+/// The points of a member proof that are scaled in a batch verification (`A`, the prepared inputs and `C`)
+const BATCH_SCALED_POINTS_COUNT: usize = 3;
+
+/// The batch accumulator, the aggregated prepared inputs and the aggregated `C` (three projective points)
+pub const BATCH_ACCUMULATION_RAM_FQ_SIZE: usize = 3 * 3;
+
+/// The number of double-and-add rounds performed in a single batch accumulation instruction
+pub const BATCH_SCALAR_MULTIPLICATION_ROUNDS_PER_IX: usize =
+    (MAX_CUS / (DOUBLE_COST + ADD_MIXED_COST)) as usize;
+
+/// The number of bits of a little-endian scalar
+fn scalar_bits(scalar: &U256) -> usize {
+    for i in (0..32).rev() {
+        if scalar[i] != 0 {
+            return i * 8 + 8 - scalar[i].leading_zeros() as usize;
+        }
+    }
+    0
+}
+
+/// Returns the number of instructions required for accumulating a batch member with a specific scalar
+pub fn batch_accumulation_instructions_count(scalar: &U256) -> usize {
+    let rounds = BATCH_SCALED_POINTS_COUNT * scalar_bits(scalar);
+    rounds / BATCH_SCALAR_MULTIPLICATION_ROUNDS_PER_IX
+        + usize::from(!rounds.is_multiple_of(BATCH_SCALAR_MULTIPLICATION_ROUNDS_PER_IX))
+}
+
+/// Computes the random scalar (non-mr-form) of the member `member_index` of a batch verification
+///
+/// # Notes
+///
+/// - we verify `k` proofs by checking `prod_i e(r_i A_i, B_i) e(sum_i r_i P_i, -gamma) e(sum_i r_i C_i, -delta) = e(alpha, beta)^{sum_i r_i}`
+/// - the scalars `r_0, .., r_{k-2}` are 128 bit values derived from the `transcript` of all member proofs
+/// - the last scalar is chosen s.t. `sum_i r_i = 1`, which allows us to use the unmodified final check `e(alpha, beta)`
+/// - soundness: since the scalars form a random affine combination, an invalid member is accepted with negligible probability
+pub fn batch_verification_scalar(
+    transcript: &U256,
+    member_index: usize,
+    members_count: usize,
+) -> U256 {
+    fn random_scalar(transcript: &U256, member_index: usize) -> U256 {
+        let hash = solana_program::hash::hashv(&[
+            &transcript[..],
+            &usize_as_u32_safe(member_index).to_le_bytes(),
+        ])
+        .to_bytes();
+
+        let mut scalar = [0; 32];
+        scalar[..16].copy_from_slice(&hash[..16]);
+
+        // Zero-scalars would remove the proof from the batch
+        if scalar == [0; 32] {
+            scalar[0] = 1;
+        }
+        scalar
+    }
+
+    if member_index + 1 < members_count {
+        return random_scalar(transcript, member_index);
+    }
+
+    let mut sum = Fr::zero();
+    for i in 0..member_index {
+        sum += Fr::from_repr(u256_to_big_uint(&random_scalar(transcript, i))).unwrap();
+    }
+    fr_to_u256_le_repr(&(Fr::one() - sum))
+}
+
+/// Scales `A`, the prepared inputs and `C` of the next batch member by its random scalar
+///
+/// # Notes
+///
+/// - members are accumulated in the order of their member index
+/// - MSB-first double-and-add, one round per scalar bit and point
+/// - the scaled `A` is stored per member, the scaled prepared inputs and `C` are aggregated over all members
+/// - the member's [`VerificationAccount`] is not modified, which allows for evicting members from the batch
+/// - after the last member is accumulated, the batch Miller loop is set up
+pub fn batch_accumulation(
+    batch_account: &mut BatchVerificationAccount,
+    a: &G1Affine,
+    b: &G2A,
+    prepared_inputs: &G1Affine,
+    c: &G1Affine,
+) -> ElusivResult {
+    let members_count = batch_account.get_members_count() as usize;
+    let member_index = batch_account.get_accumulated_count() as usize;
+    guard!(member_index < members_count, ComputationIsAlreadyFinished);
+
+    let scalar =
+        batch_verification_scalar(&batch_account.get_transcript(), member_index, members_count);
+    let bits = scalar_bits(&scalar);
+    guard!(bits > 0, InvalidAccountState);
+
+    let total_rounds = BATCH_SCALED_POINTS_COUNT * bits;
+    let round = batch_account.get_accumulation_round() as usize;
+    guard!(round < total_rounds, ComputationIsAlreadyFinished);
+    let rounds = std::cmp::min(
+        BATCH_SCALAR_MULTIPLICATION_ROUNDS_PER_IX,
+        total_rounds - round,
+    );
+
+    if round == 0 {
+        if member_index == 0 {
+            write_g1_projective(&mut batch_account.ram_fq, &G1Projective::zero(), 3);
+            write_g1_projective(&mut batch_account.ram_fq, &G1Projective::zero(), 6);
+        }
+        batch_account.b.set(member_index, *b);
+    }
+
+    let mut acc: G1Projective = read_g1_p!(batch_account.ram_fq, 0);
+
+    for round in round..round + rounds {
+        let point_index = round / bits;
+        let bit = bits - 1 - round % bits;
+
+        if round % bits == 0 {
+            acc = G1Projective::zero();
+        }
+
+        acc.double_in_place();
+
+        if (scalar[bit / 8] >> (bit % 8)) & 1 == 1 {
+            let point = match point_index {
+                0 => a,
+                1 => prepared_inputs,
+                _ => c,
+            };
+            acc.add_assign_mixed(point);
+        }
+
+        if bit == 0 {
+            match point_index {
+                0 => batch_account.a.set(member_index, G1A(acc.into_affine())),
+                1 => {
+                    let sum: G1Projective = read_g1_p!(batch_account.ram_fq, 3);
+                    write_g1_projective(&mut batch_account.ram_fq, &(sum + acc), 3);
+                }
+                _ => {
+                    let sum: G1Projective = read_g1_p!(batch_account.ram_fq, 6);
+                    write_g1_projective(&mut batch_account.ram_fq, &(sum + acc), 6);
+                }
+            }
+        }
+    }
+
+    write_g1_projective(&mut batch_account.ram_fq, &acc, 0);
+
+    if round + rounds == total_rounds {
+        batch_account.set_accumulation_round(&0);
+        batch_account.set_accumulated_count(&usize_as_u32_safe(member_index + 1));
+
+        if member_index + 1 == members_count {
+            let prepared_inputs: G1Projective = read_g1_p!(batch_account.ram_fq, 3);
+            let c: G1Projective = read_g1_p!(batch_account.ram_fq, 6);
+            batch_account
+                .prepared_inputs
+                .set(G1A(prepared_inputs.into_affine()));
+            batch_account.c.set(G1A(c.into_affine()));
+
+            for i in 0..members_count {
+                let b = batch_account.b.get(i).0;
+                batch_account.r.set_no_serialization(
+                    i,
+                    G2HomProjective {
+                        x: b.x,
+                        y: b.y,
+                        z: Fq2::one(),
+                    },
+                );
+            }
+            batch_account.r.serialize();
+
+            batch_account.f.set(Wrap(Fq12::one()));
+            batch_account.set_round(&0);
+            batch_account.set_coeff_index(&0);
+        }
+    } else {
+        batch_account.set_accumulation_round(&usize_as_u32_safe(round + rounds));
+    }
+
+    batch_account.ram_fq.serialize().unwrap();
+
+    Ok(())
+}
+
+/// A single round of the batch Miller loop
+#[derive(Debug, PartialEq, Eq)]
+enum BatchMillerLoopRound {
+    /// Squaring of `f`
+    Square,
+
+    /// Doubling step and line evaluation of a member's `(A, B)` pair
+    Doubling(usize),
+
+    /// Addition step (with `B` or `-B`) and line evaluation of a member's `(A, B)` pair
+    Addition { member: usize, negated: bool },
+
+    /// Line evaluations of the aggregated prepared inputs and `C` with the precomputed coefficients of `-gamma` and `-delta`
+    FixedEll,
+
+    /// One of the two final addition steps (with the Frobenius-twisted `B`) and line evaluation of a member's `(A, B)` pair
+    FrobeniusAddition { member: usize, second: bool },
+}
+
+/// Returns the round `round` of a batch Miller loop with `members_count` members
+///
+/// # Notes
+///
+/// - the rounds follow the multi-pairing Miller loop of arkworks, with the coefficients of all `B`s generated on the fly
+/// - reference implementation: https://github.com/arkworks-rs/algebra/blob/6ea310ef09f8b7510ce947490919ea6229bbecd6/ec/src/models/bn/mod.rs#L99
+fn batch_miller_loop_round(members_count: usize, round: usize) -> Option<BatchMillerLoopRound> {
+    let ate_loop_count = Parameters::ATE_LOOP_COUNT;
+    let mut offset = round;
+
+    for i in (1..ate_loop_count.len()).rev() {
+        if i != ate_loop_count.len() - 1 {
+            if offset == 0 {
+                return Some(BatchMillerLoopRound::Square);
+            }
+            offset -= 1;
+        }
+
+        if offset < members_count {
+            return Some(BatchMillerLoopRound::Doubling(offset));
+        }
+        offset -= members_count;
+
+        if offset == 0 {
+            return Some(BatchMillerLoopRound::FixedEll);
+        }
+        offset -= 1;
+
+        let bit = ate_loop_count[i - 1];
+        if bit != 0 {
+            if offset < members_count {
+                return Some(BatchMillerLoopRound::Addition {
+                    member: offset,
+                    negated: bit < 0,
+                });
+            }
+            offset -= members_count;
+
+            if offset == 0 {
+                return Some(BatchMillerLoopRound::FixedEll);
+            }
+            offset -= 1;
+        }
+    }
+
+    for second in [false, true] {
+        if offset < members_count {
+            return Some(BatchMillerLoopRound::FrobeniusAddition {
+                member: offset,
+                second,
+            });
+        }
+        offset -= members_count;
+
+        if offset == 0 {
+            return Some(BatchMillerLoopRound::FixedEll);
+        }
+        offset -= 1;
+    }
+
+    None
+}
+
+const SQUARE_COST: u16 = 88;
+const DOUBLING_STEP_COST: u16 = 68;
+const ADDITION_STEP_COST: u16 = 85;
+const MUL_BY_CHARACTERISTICS_COST: u16 = 24;
+const ELL_COST: u16 = 130;
+
+/// Deserialization and serialization of the batch account values
+const BATCH_MILLER_LOOP_BASE_COST: u16 = 60;
+
+fn batch_miller_loop_round_cost(round: &BatchMillerLoopRound) -> u16 {
+    match round {
+        BatchMillerLoopRound::Square => SQUARE_COST,
+        BatchMillerLoopRound::Doubling(_) => DOUBLING_STEP_COST + ELL_COST,
+        BatchMillerLoopRound::Addition { .. } => ADDITION_STEP_COST + ELL_COST,
+        BatchMillerLoopRound::FixedEll => 2 * ELL_COST,
+        BatchMillerLoopRound::FrobeniusAddition { second, .. } => {
+            (1 + u16::from(*second)) * MUL_BY_CHARACTERISTICS_COST + ADDITION_STEP_COST + ELL_COST
+        }
+    }
+}
+
+/// Returns the number of rounds performed by the batch Miller loop instruction starting at `round`
+fn batch_miller_loop_instruction_rounds(members_count: usize, round: usize) -> usize {
+    let mut rounds = 0;
+    let mut compute_units = BATCH_MILLER_LOOP_BASE_COST;
+
+    while let Some(r) = batch_miller_loop_round(members_count, round + rounds) {
+        let cus = batch_miller_loop_round_cost(&r);
+        if compute_units + cus > MAX_CUS {
+            break;
+        }
+
+        rounds += 1;
+        compute_units += cus;
+    }
+
+    rounds
+}
+
+/// Returns the number of instructions required for the batch Miller loop of `members_count` members
+pub fn batch_miller_loop_instructions_count(members_count: usize) -> usize {
+    let mut instructions = 0;
+    let mut round = 0;
+
+    loop {
+        let rounds = batch_miller_loop_instruction_rounds(members_count, round);
+        if rounds == 0 {
+            return instructions;
+        }
+
+        instructions += 1;
+        round += rounds;
+    }
+}
+
+/// Computes a single multi-pairing Miller loop over the pairs of all batch members
+///
+/// # Notes
+///
+/// - the pairs are `(r_i A_i, B_i)` for all members and `(sum_i r_i P_i, -gamma)`, `(sum_i r_i C_i, -delta)` (using the precomputed vkey coefficients)
+/// - each instruction performs as many rounds as fit into the compute budget (see [`batch_miller_loop_instructions_count`])
+/// - returns the Miller loop result `f` after the last round
+pub fn batch_miller_loop(
+    batch_account: &mut BatchVerificationAccount,
+    vkey: &VerifyingKey,
+) -> Result<Option<Fq12>, ElusivError> {
+    let members_count = batch_account.get_members_count() as usize;
+    let round = batch_account.get_round() as usize;
+    let rounds = batch_miller_loop_instruction_rounds(members_count, round);
+    guard!(rounds > 0, ComputationIsAlreadyFinished);
+
+    let mut f = batch_account.f.get().0;
+    let mut coeff_index = batch_account.get_coeff_index() as usize;
+    let prepared_inputs = batch_account.prepared_inputs.get().0;
+    let c = batch_account.c.get().0;
+
+    for round in round..round + rounds {
+        profile_compute_units!();
+        match batch_miller_loop_round(members_count, round).ok_or(PartialComputationError)? {
+            BatchMillerLoopRound::Square => {
+                f.square_in_place();
+            }
+            BatchMillerLoopRound::Doubling(member) => {
+                let mut r = batch_account.r.get(member);
+                let coeffs = g2_doubling_step(&mut r);
+                batch_account.r.set_no_serialization(member, r);
+
+                member_ell(batch_account, &mut f, &coeffs, member);
+            }
+            BatchMillerLoopRound::Addition { member, negated } => {
+                let b = batch_account.b.get(member).0;
+                let q = if negated { b.neg() } else { b };
+
+                let mut r = batch_account.r.get(member);
+                let coeffs = g2_addition_step(&mut r, &q);
+                batch_account.r.set_no_serialization(member, r);
+
+                member_ell(batch_account, &mut f, &coeffs, member);
+            }
+            BatchMillerLoopRound::FixedEll => {
+                ell(
+                    &mut f,
+                    &(
+                        vkey.gamma_g2_neg_pc(coeff_index, 0),
+                        vkey.gamma_g2_neg_pc(coeff_index, 1),
+                        vkey.gamma_g2_neg_pc(coeff_index, 2),
+                    ),
+                    &prepared_inputs,
+                );
+                ell(
+                    &mut f,
+                    &(
+                        vkey.delta_g2_neg_pc(coeff_index, 0),
+                        vkey.delta_g2_neg_pc(coeff_index, 1),
+                        vkey.delta_g2_neg_pc(coeff_index, 2),
+                    ),
+                    &c,
+                );
+                coeff_index += 1;
+            }
+            BatchMillerLoopRound::FrobeniusAddition { member, second } => {
+                let mut q = g2_mul_by_characteristics(&batch_account.b.get(member).0);
+                if second {
+                    q = g2_mul_by_characteristics(&q);
+                    q.y = q.y.neg();
+                }
+
+                let mut r = batch_account.r.get(member);
+                let coeffs = g2_addition_step(&mut r, &q);
+                batch_account.r.set_no_serialization(member, r);
+
+                member_ell(batch_account, &mut f, &coeffs, member);
+            }
+        }
+    }
+    profile_compute_units!();
+
+    batch_account.r.serialize();
+    batch_account.f.set(Wrap(f));
+    batch_account.set_coeff_index(&usize_as_u32_safe(coeff_index));
+    batch_account.set_round(&usize_as_u32_safe(round + rounds));
+
+    if batch_miller_loop_round(members_count, round + rounds).is_none() {
+        return Ok(Some(f));
+    }
+
+    Ok(None)
+}
+
+/// Line evaluation of a member's `(A, B)` pair (pairs with a zero point are skipped)
+fn member_ell(
+    batch_account: &mut BatchVerificationAccount,
+    f: &mut Fq12,
+    coeffs: &Coefficients,
+    member: usize,
+) {
+    if batch_account.b.get(member).0.is_zero() {
+        return;
+    }
+
+    ell(f, coeffs, &batch_account.a.get(member).0);
+}
+
+// https://github.com/arkworks-rs/algebra/blob/6ea310ef09f8b7510ce947490919ea6229bbecd6/ec/src/models/bn/mod.rs#L59
+fn ell(f: &mut Fq12, coeffs: &Coefficients, p: &G1Affine) {
+    if p.is_zero() {
+        return;
+    }
+
+    let c0 = mul_by_fp(&coeffs.0, p.y);
+    let c1 = mul_by_fp(&coeffs.1, p.x);
+    f.mul_by_034(&c0, &c1, &coeffs.2);
+}
+
+// https://github.com/arkworks-rs/algebra/blob/6ea310ef09f8b7510ce947490919ea6229bbecd6/ec/src/models/bn/g2.rs#L139
+fn g2_doubling_step(r: &mut G2HomProjective) -> Coefficients {
+    let a = mul_by_fp(&(r.x * r.y), TWO_INV);
+    let b = r.y.square();
+    let c = r.z.square();
+    let e = COEFF_B * (c.double() + c);
+    let f = e.double() + e;
+    let g = mul_by_fp(&(b + f), TWO_INV);
+    let h = (r.y + r.z).square() - (b + c);
+    let i = e - b;
+    let j = r.x.square();
+    let e_square = e.square();
+
+    r.x = a * (b - f);
+    r.y = g.square() - (e_square.double() + e_square);
+    r.z = b * h;
+
+    new_coeffs(h.neg(), j.double() + j, i)
+}
+
+// https://github.com/arkworks-rs/algebra/blob/6ea310ef09f8b7510ce947490919ea6229bbecd6/ec/src/models/bn/g2.rs#L168
+fn g2_addition_step(r: &mut G2HomProjective, q: &G2Affine) -> Coefficients {
+    let theta = r.y - (q.y * r.z);
+    let lambda = r.x - (q.x * r.z);
+    let c = theta.square();
+    let d = lambda.square();
+    let e = lambda * d;
+    let f = r.z * c;
+    let g = r.x * d;
+    let h = e + f - g.double();
+    let j = theta * q.x - (lambda * q.y);
+
+    r.x = lambda * h;
+    r.y = theta * (g - h) - (e * r.y);
+    r.z = r.z * e;
+
+    new_coeffs(lambda, theta.neg(), j)
+}
+
+// https://github.com/arkworks-rs/algebra/blob/6ea310ef09f8b7510ce947490919ea6229bbecd6/ec/src/models/bn/g2.rs#L127
+fn g2_mul_by_characteristics(r: &G2Affine) -> G2Affine {
+    let x = frobenius_map_fq2_one(r.x) * TWIST_MUL_BY_Q_X;
+    let y = frobenius_map_fq2_one(r.y) * TWIST_MUL_BY_Q_Y;
+    G2Affine::new(x, y, r.infinity)
+}
+
 #[cfg(test)]
 const_assert_eq!(ADDITION_STEP_ROUNDS_COUNT, 2);
 #[cfg(test)]
@@ -798,7 +1316,7 @@ elusiv_computations!(
     }
 );
 
-fn write_g1_projective(ram: &mut RAMFq, g1p: &G1Projective, offset: usize) {
+fn write_g1_projective(ram: &mut impl RAM<Fq>, g1p: &G1Projective, offset: usize) {
     ram.write(g1p.x, offset);
     ram.write(g1p.y, offset + 1);
     ram.write(g1p.z, offset + 2);
@@ -953,6 +1471,123 @@ pub fn proof_from_str_projective(
     }
 }
 
+/// Inputs and assertions for the partial computation tests generated by `elusiv_computations`
+#[cfg(test)]
+mod partial_computation_testing {
+    use super::*;
+    use crate::bytes::BorshSerDeSized;
+    use crate::macros::zero_program_account;
+    use crate::proof::vkey::{TestVKey, VerifyingKeyInfo};
+    use crate::state::proof::LazyRAM;
+    use ark_ec::short_weierstrass_jacobian::{GroupAffine, GroupProjective};
+    use ark_ec::SWModelParameters;
+    use ark_ff::UniformRand;
+    use rand::{rngs::StdRng, Rng};
+    use std::fmt::Debug;
+
+    pub const PARTIAL_COMPUTATION_TEST_ITERATIONS: usize = 8;
+
+    pub trait PartialComputationInput {
+        fn random(rng: &mut StdRng) -> Self;
+    }
+
+    impl PartialComputationInput for Fq2 {
+        fn random(rng: &mut StdRng) -> Self {
+            Fq2::rand(rng)
+        }
+    }
+
+    impl PartialComputationInput for Fq12 {
+        fn random(rng: &mut StdRng) -> Self {
+            Fq12::rand(rng)
+        }
+    }
+
+    impl<P: SWModelParameters> PartialComputationInput for GroupAffine<P> {
+        /// Every fourth point is zero (skipping the evaluations on it)
+        fn random(rng: &mut StdRng) -> Self {
+            if rng.gen_ratio(1, 4) {
+                GroupAffine::zero()
+            } else {
+                GroupProjective::<P>::rand(rng).into_affine()
+            }
+        }
+    }
+
+    impl PartialComputationInput for G2A {
+        fn random(rng: &mut StdRng) -> Self {
+            G2A(G2Affine::random(rng))
+        }
+    }
+
+    impl PartialComputationInput for G2HomProjective {
+        fn random(rng: &mut StdRng) -> Self {
+            G2HomProjective {
+                x: Fq2::rand(rng),
+                y: Fq2::rand(rng),
+                z: Fq2::rand(rng),
+            }
+        }
+    }
+
+    impl PartialComputationInput for usize {
+        /// Coefficient indices (a miller loop has to start at the first coefficient)
+        fn random(_rng: &mut StdRng) -> Self {
+            0
+        }
+    }
+
+    impl PartialComputationInput for VerifyingKey<'static> {
+        fn random(_rng: &mut StdRng) -> Self {
+            let source = Box::leak(TestVKey::verifying_key_source().into_boxed_slice());
+            VerifyingKey::new(source, TestVKey::public_inputs_count(), TestVKey::LAYOUT).unwrap()
+        }
+    }
+
+    /// Runs all rounds of a partial computation and compares the result (and the mutated input) with its monolithic version
+    /// - additionally checks that the partial computation stays in the RAM bounds and releases all frames
+    pub fn assert_partial_computation<I, T>(
+        rounds_count: usize,
+        input: &I,
+        partial: impl Fn(usize, &mut VerificationAccount, &mut I) -> Result<Option<T>, ElusivError>,
+        monolithic: impl Fn(&mut VerificationAccount, &mut I) -> Result<T, ElusivError>,
+    ) where
+        I: Clone + PartialEq + Debug,
+        T: PartialEq + Debug,
+    {
+        zero_program_account!(mut storage, VerificationAccount);
+        let mut partial_input = input.clone();
+        let mut result = None;
+        for round in 0..rounds_count {
+            assert!(result.is_none(), "Result returned before round {}", round);
+            result = partial(round, &mut storage, &mut partial_input).unwrap();
+        }
+
+        assert_ram_bounds(&mut storage.ram_fq);
+        assert_ram_bounds(&mut storage.ram_fq2);
+        assert_ram_bounds(&mut storage.ram_fq6);
+        assert_ram_bounds(&mut storage.ram_fq12);
+
+        zero_program_account!(mut storage, VerificationAccount);
+        let mut monolithic_input = input.clone();
+        let expected = monolithic(&mut storage, &mut monolithic_input).unwrap();
+
+        assert_eq!(result.unwrap(), expected);
+        assert_eq!(partial_input, monolithic_input);
+    }
+
+    fn assert_ram_bounds<N: Clone + Copy, const SIZE: usize>(ram: &mut LazyRAM<N, SIZE>)
+    where
+        Wrap<N>: BorshSerDeSized,
+    {
+        assert_eq!(ram.get_frame(), 0);
+        assert!(ram.usage() <= SIZE);
+    }
+}
+
+#[cfg(test)]
+use partial_computation_testing::*;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -961,23 +1596,30 @@ mod tests {
     use crate::proof::test_proofs::{invalid_proofs, valid_proofs};
     use crate::proof::vkey::{TestVKey, VerifyingKeyInfo};
     use crate::state::metadata::CommitmentMetadata;
+    use crate::state::proof::MAX_BATCH_VERIFICATION_MEMBERS;
     use crate::state::storage::empty_root_raw;
     use crate::types::{
         InputCommitment, JoinSplitPublicInputs, OptionalFee, PublicInputs, RawU256,
         SendPublicInputs,
     };
-    use ark_bn254::{Bn254, Fr};
+    use ark_bn254::{Bn254, Fr, G2Projective};
     use ark_ec::bn::G2Prepared;
     use ark_ec::models::bn::BnParameters;
-    use ark_ec::PairingEngine;
+    use ark_ec::{AffineCurve, PairingEngine};
+    use ark_ff::UniformRand;
     use ark_groth16::prepare_inputs;
+    use elusiv_computation::{
+        compute_unit_instructions, COMPUTE_UNIT_PADDING, MAX_COMPUTE_UNIT_LIMIT,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
     use solana_program::native_token::LAMPORTS_PER_SOL;
     use std::str::FromStr;
 
-    fn setup_storage_account<VKey: VerifyingKeyInfo>(
+    fn setup_storage_account(
         storage: &mut VerificationAccount,
         proof: Proof,
         public_inputs: &[U256],
+        vkey: &VerifyingKey,
     ) {
         storage.a.set(proof.a);
         storage.b.set(proof.b);
@@ -988,8 +1630,11 @@ mod tests {
             storage.set_public_input(i, &RawU256::new(public_input));
         }
 
-        let instructions =
-            prepare_public_inputs_instructions(public_inputs, VKey::public_inputs_count());
+        let instructions = prepare_public_inputs_instructions(
+            public_inputs,
+            vkey.public_inputs_count,
+            vkey.layout,
+        );
         storage
             .setup_public_inputs_instructions(&instructions)
             .unwrap();
@@ -1038,7 +1683,8 @@ mod tests {
     macro_rules! vkey {
         ($id: ident, $vkey: ident) => {
             let source = $vkey::verifying_key_source();
-            let $id = VerifyingKey::new(&source, $vkey::public_inputs_count()).unwrap();
+            let $id =
+                VerifyingKey::new(&source, $vkey::public_inputs_count(), $vkey::LAYOUT).unwrap();
         };
     }
 
@@ -1081,7 +1727,7 @@ mod tests {
 
         let result = prepare_public_inputs_partial(
             0,
-            prepare_public_inputs_rounds(TestVKey::public_inputs_count()),
+            prepare_public_inputs_rounds(TestVKey::public_inputs_count(), TestVKey::LAYOUT),
             &mut storage,
             &vkey,
         )
@@ -1097,7 +1743,7 @@ mod tests {
         // Second version
         zero_program_account!(mut storage, VerificationAccount);
         let public_inputs = valid_proofs()[0].public_inputs.clone();
-        setup_storage_account::<TestVKey>(&mut storage, valid_proofs()[0].proof, &public_inputs);
+        setup_storage_account(&mut storage, valid_proofs()[0].proof, &public_inputs, &vkey);
 
         for i in 0..storage.get_prepare_inputs_instructions_count() {
             let round = storage.get_round();
@@ -1450,6 +2096,101 @@ mod tests {
         assert_eq!(storage.f.get().0, expected);
     }
 
+    #[test]
+    fn test_ram_sizes() {
+        vkey!(vkey, TestVKey);
+        let mut rng = StdRng::seed_from_u64(9);
+        let a = G1Projective::rand(&mut rng).into_affine();
+        let b = G2Projective::rand(&mut rng).into_affine();
+        let c = G1Projective::rand(&mut rng).into_affine();
+        let prepared_inputs = G1Projective::rand(&mut rng).into_affine();
+        let mut r = G2HomProjective::random(&mut rng);
+        let mut j = 0;
+        let mut alt_b = G2A(b);
+
+        // The RAM sizes computed by the interpreter are exactly the peak usages
+        zero_program_account!(mut storage, VerificationAccount);
+        let mut f = None;
+        for round in 0..COMBINED_MILLER_LOOP_ROUNDS_COUNT {
+            f = combined_miller_loop_partial(
+                round,
+                &mut storage,
+                &vkey,
+                &a,
+                &b,
+                &c,
+                &prepared_inputs,
+                &mut r,
+                &mut j,
+                &mut alt_b,
+            )
+            .unwrap();
+        }
+        assert_eq!(storage.ram_fq2.usage(), COMBINED_MILLER_LOOP_RAM_FQ2_SIZE);
+        assert_eq!(storage.ram_fq6.usage(), COMBINED_MILLER_LOOP_RAM_FQ6_SIZE);
+        assert_eq!(storage.ram_fq12.usage(), COMBINED_MILLER_LOOP_RAM_FQ12_SIZE);
+
+        let f = f.unwrap();
+        zero_program_account!(mut storage, VerificationAccount);
+        for round in 0..FINAL_EXPONENTIATION_ROUNDS_COUNT {
+            final_exponentiation_partial(round, &mut storage, &f).unwrap();
+        }
+        assert_eq!(storage.ram_fq2.usage(), 0);
+        assert_eq!(storage.ram_fq6.usage(), FINAL_EXPONENTIATION_RAM_FQ6_SIZE);
+        assert_eq!(storage.ram_fq12.usage(), FINAL_EXPONENTIATION_RAM_FQ12_SIZE);
+    }
+
+    fn greedy_tx_count(round_compute_units: &[u32], compute_budget_per_ix: u32) -> usize {
+        let ixs_per_tx = (MAX_COMPUTE_UNIT_LIMIT / compute_budget_per_ix) as usize;
+        let ixs = compute_unit_instructions(round_compute_units.to_vec(), compute_budget_per_ix);
+        let txs = ixs.len() / ixs_per_tx;
+        if txs * ixs_per_tx < ixs.len() {
+            txs + 1
+        } else {
+            txs
+        }
+    }
+
+    /// Asserts that each single instruction stays within its compute budget (minus the padding)
+    fn assert_instruction_compute_units<const N: usize, P: PartialComputation<N>>() {
+        let mut round = 0;
+        for (i, &rounds) in P::INSTRUCTION_ROUNDS.iter().enumerate() {
+            let compute_units: u32 = P::ROUND_COMPUTE_UNITS[round..round + rounds as usize]
+                .iter()
+                .sum();
+            assert!(
+                compute_units <= P::COMPUTE_BUDGET_PER_IX - COMPUTE_UNIT_PADDING,
+                "Instruction {} requires {} compute units",
+                i,
+                compute_units
+            );
+            round += rounds as usize;
+        }
+        assert_eq!(round, P::TOTAL_ROUNDS as usize);
+    }
+
+    #[test]
+    fn test_instruction_packing() {
+        assert_instruction_compute_units::<COMBINED_MILLER_LOOP_IXS, CombinedMillerLoop>();
+        assert_instruction_compute_units::<FINAL_EXPONENTIATION_IXS, FinalExponentiation>();
+
+        // With the instructions bounded by their compute budget, the greedy packing is already optimal
+        assert_eq!(
+            greedy_tx_count(
+                CombinedMillerLoop::ROUND_COMPUTE_UNITS,
+                CombinedMillerLoop::COMPUTE_BUDGET_PER_IX
+            ),
+            CombinedMillerLoop::TX_COUNT
+        );
+        assert_eq!(
+            greedy_tx_count(
+                FinalExponentiation::ROUND_COMPUTE_UNITS,
+                FinalExponentiation::COMPUTE_BUDGET_PER_IX
+            ),
+            FinalExponentiation::TX_COUNT
+        );
+    }
+
     #[test]
     fn test_public_inputs_preparation_costs() {
         let public_inputs = SendPublicInputs {
@@ -1478,30 +2219,93 @@ mod tests {
             solana_pay_transfer: false,
         };
         let p = public_inputs.public_signals_skip_mr();
-        let v = prepare_public_inputs_instructions(&p, TestVKey::public_inputs_count());
+        let v = prepare_public_inputs_instructions(
+            &p,
+            TestVKey::public_inputs_count(),
+            VerifyingKeyLayout::Window8,
+        );
         assert_eq!(v.len(), 3);
+
+        // Twice the number of windows results in at most twice the number of instructions
+        let v4 = prepare_public_inputs_instructions(
+            &p,
+            TestVKey::public_inputs_count(),
+            VerifyingKeyLayout::Window4,
+        );
+        assert!(v4.len() >= v.len());
+        assert!(v4.len() <= 2 * v.len());
     }
 
     #[test]
     fn test_prepare_public_inputs_instructions() {
-        let expected = prepare_public_inputs_rounds(TestVKey::public_inputs_count()) as u32;
+        for layout in [VerifyingKeyLayout::Window8, VerifyingKeyLayout::Window4] {
+            let expected =
+                prepare_public_inputs_rounds(TestVKey::public_inputs_count(), layout) as u32;
+
+            assert_eq!(
+                prepare_public_inputs_instructions(
+                    &vec![[0; 32]; TestVKey::public_inputs_count()],
+                    TestVKey::public_inputs_count(),
+                    layout,
+                ),
+                vec![expected]
+            );
+        }
+    }
+
+    /// Documents the rent-versus-compute trade-off between the [`VerifyingKeyLayout`]s
+    ///
+    /// - `Window8`: ~7.5 MB vkey account, at most 8 input preparation txs
+    /// - `Window4`: ~0.9 MB vkey account (more than 8 times less rent), at most 16 input preparation txs
+    #[test]
+    fn test_vkey_layout_rent_compute_trade_off() {
+        use solana_program::rent::Rent;
+
+        let public_inputs_count = TestVKey::public_inputs_count();
+        let worst_case_inputs = vec![[0xff; 32]; public_inputs_count];
+
+        let size8 = VerifyingKey::source_size(public_inputs_count, VerifyingKeyLayout::Window8);
+        let size4 = VerifyingKey::source_size(public_inputs_count, VerifyingKeyLayout::Window4);
+
+        let rent8 = Rent::default().minimum_balance(size8);
+        let rent4 = Rent::default().minimum_balance(size4);
+
+        let txs8 = prepare_public_inputs_instructions(
+            &worst_case_inputs,
+            public_inputs_count,
+            VerifyingKeyLayout::Window8,
+        )
+        .len();
+        let txs4 = prepare_public_inputs_instructions(
+            &worst_case_inputs,
+            public_inputs_count,
+            VerifyingKeyLayout::Window4,
+        )
+        .len();
 
+        // Rent
+        assert_eq!(size8, 7_461_445);
+        assert_eq!(size4, 909_445);
+        assert!(rent4 * 8 < rent8);
+
+        // Compute (each input preparation tx requires a full compute budget)
+        assert_eq!(txs8, 8);
+        assert_eq!(txs4, 16);
+
+        // Total public input preparation rounds
         assert_eq!(
-            prepare_public_inputs_instructions(
-                &vec![[0; 32]; TestVKey::public_inputs_count()],
-                TestVKey::public_inputs_count()
-            ),
-            vec![expected]
+            prepare_public_inputs_rounds(public_inputs_count, VerifyingKeyLayout::Window8),
+            33 * 14
+        );
+        assert_eq!(
+            prepare_public_inputs_rounds(public_inputs_count, VerifyingKeyLayout::Window4),
+            65 * 14
         );
     }
 
-    fn full_verification<VKey: VerifyingKeyInfo>(
-        proof: Proof,
-        public_inputs: &[U256],
-        vkey: &VerifyingKey,
-    ) -> bool {
+    fn full_verification(proof: Proof, public_inputs: &[U256], vkey: &VerifyingKey) -> bool {
         zero_program_account!(mut storage, VerificationAccount);
-        setup_storage_account::<VKey>(&mut storage, proof, public_inputs);
+        setup_storage_account(&mut storage, proof, public_inputs, vkey);
         let instruction_count = storage.get_prepare_inputs_instructions_count() as usize
             + COMBINED_MILLER_LOOP_IXS
             + FINAL_EXPONENTIATION_IXS;
@@ -1519,19 +2323,31 @@ mod tests {
         vkey!(vkey, TestVKey);
 
         for p in valid_proofs() {
-            assert!(full_verification::<TestVKey>(
-                p.proof,
-                &p.public_inputs,
-                &vkey
-            ));
+            assert!(full_verification(p.proof, &p.public_inputs, &vkey));
         }
 
         for p in invalid_proofs() {
-            assert!(!full_verification::<TestVKey>(
-                p.proof,
-                &p.public_inputs,
-                &vkey
-            ));
+            assert!(!full_verification(p.proof, &p.public_inputs, &vkey));
+        }
+    }
+
+    #[test]
+    fn test_verify_proofs_window4() {
+        vkey!(vkey8, TestVKey);
+        let source = vkey8.source_with_layout(VerifyingKeyLayout::Window4);
+        let vkey = VerifyingKey::new(
+            &source,
+            TestVKey::public_inputs_count(),
+            VerifyingKeyLayout::Window4,
+        )
+        .unwrap();
+
+        for p in valid_proofs() {
+            assert!(full_verification(p.proof, &p.public_inputs, &vkey));
+        }
+
+        for p in invalid_proofs() {
+            assert!(!full_verification(p.proof, &p.public_inputs, &vkey));
         }
     }
 
@@ -1539,14 +2355,13 @@ mod tests {
     fn test_verify_partial_too_many_calls() {
         let proof = valid_proofs()[0].proof;
         let public_inputs = valid_proofs()[0].public_inputs.clone();
+        vkey!(vkey, TestVKey);
         zero_program_account!(mut storage, VerificationAccount);
-        setup_storage_account::<TestVKey>(&mut storage, proof, &public_inputs);
+        setup_storage_account(&mut storage, proof, &public_inputs, &vkey);
         let instruction_count = storage.get_prepare_inputs_instructions_count() as usize
             + COMBINED_MILLER_LOOP_IXS
             + FINAL_EXPONENTIATION_IXS;
 
-        vkey!(vkey, TestVKey);
-
         for _ in 0..instruction_count {
             verify_partial(&mut storage, &vkey, COMPUTE_VERIFICATION_IX_COUNT - 1).unwrap();
         }
@@ -1558,6 +2373,264 @@ mod tests {
         );
     }
 
+    fn batch_verification(proofs: &[(Proof, Vec<U256>)], vkey: &VerifyingKey) -> bool {
+        let transcript = [7; 32];
+        zero_program_account!(mut batch_account, BatchVerificationAccount);
+        batch_account.set_members_count(&usize_as_u32_safe(proofs.len()));
+        batch_account.set_transcript(&transcript);
+
+        for (i, (proof, public_inputs)) in proofs.iter().enumerate() {
+            zero_program_account!(mut storage, VerificationAccount);
+            setup_storage_account(&mut storage, *proof, public_inputs, vkey);
+
+            for _ in 0..storage.get_prepare_inputs_instructions_count() {
+                verify_partial(&mut storage, vkey, COMPUTE_VERIFICATION_IX_COUNT - 1).unwrap();
+            }
+            let prepared_inputs = storage.prepared_inputs.get().0;
+
+            let scalar = batch_verification_scalar(&transcript, i, proofs.len());
+            for _ in 0..batch_accumulation_instructions_count(&scalar) {
+                batch_accumulation(
+                    &mut batch_account,
+                    &proof.a.0,
+                    &proof.b,
+                    &prepared_inputs,
+                    &proof.c.0,
+                )
+                .unwrap();
+            }
+            assert_eq!(batch_account.get_accumulated_count() as usize, i + 1);
+        }
+
+        let mut f = None;
+        for _ in 0..batch_miller_loop_instructions_count(proofs.len()) {
+            f = batch_miller_loop(&mut batch_account, vkey).unwrap();
+        }
+        assert_eq!(
+            batch_miller_loop(&mut batch_account, vkey),
+            Err(ComputationIsAlreadyFinished)
+        );
+
+        // The leader performs the final exponentiation
+        zero_program_account!(mut leader, VerificationAccount);
+        leader.f.set(Wrap(f.unwrap()));
+        leader.set_step(&VerificationStep::FinalExponentiation);
+
+        let mut result = None;
+        for _ in 0..FINAL_EXPONENTIATION_IXS {
+            result = verify_partial(&mut leader, vkey, COMPUTE_VERIFICATION_IX_COUNT - 1).unwrap();
+        }
+        result.unwrap()
+    }
+
+    #[test]
+    fn test_batch_verification() {
+        vkey!(vkey, TestVKey);
+
+        let valid: Vec<_> = valid_proofs()
+            .iter()
+            .map(|p| (p.proof, p.public_inputs.clone()))
+            .collect();
+        assert!(batch_verification(&valid[..2], &vkey));
+        assert!(batch_verification(
+            &[valid[0].clone(), valid[1].clone(), valid[0].clone()],
+            &vkey
+        ));
+
+        let invalid_proofs = invalid_proofs();
+        let invalid = &invalid_proofs[0];
+        assert!(!batch_verification(
+            &[
+                valid[0].clone(),
+                (invalid.proof, invalid.public_inputs.clone())
+            ],
+            &vkey
+        ));
+    }
+
+    #[test]
+    fn test_batch_verification_scalar() {
+        let transcript = [1; 32];
+
+        for members_count in 2..=4 {
+            let mut sum = Fr::zero();
+            for i in 0..members_count {
+                let scalar = batch_verification_scalar(&transcript, i, members_count);
+                assert!(scalar != [0; 32]);
+
+                if i < members_count - 1 {
+                    assert_eq!(scalar[16..], [0; 16]);
+                }
+
+                sum += Fr::from_repr(u256_to_big_uint(&scalar)).unwrap();
+            }
+            assert_eq!(sum, Fr::one());
+        }
+
+        // Scalars depend on the transcript
+        assert_ne!(
+            batch_verification_scalar(&[1; 32], 0, 2),
+            batch_verification_scalar(&[2; 32], 0, 2)
+        );
+    }
+
+    #[test]
+    fn test_batch_accumulation() {
+        let proofs = valid_proofs();
+        let transcript = [3; 32];
+
+        zero_program_account!(mut batch_account, BatchVerificationAccount);
+        batch_account.set_members_count(&2);
+        batch_account.set_transcript(&transcript);
+
+        let mut prepared_inputs_sum = G1Projective::zero();
+        let mut c_sum = G1Projective::zero();
+
+        for i in 0..2 {
+            let proof = proofs[i].proof;
+            let prepared_inputs = proofs[1 - i].proof.a.0;
+
+            let scalar = batch_verification_scalar(&transcript, i, 2);
+            let r = Fr::from_repr(u256_to_big_uint(&scalar)).unwrap();
+            for _ in 0..batch_accumulation_instructions_count(&scalar) {
+                batch_accumulation(
+                    &mut batch_account,
+                    &proof.a.0,
+                    &proof.b,
+                    &prepared_inputs,
+                    &proof.c.0,
+                )
+                .unwrap();
+            }
+
+            assert_eq!(batch_account.a.get(i).0, proof.a.0.mul(r).into_affine());
+            assert_eq!(batch_account.b.get(i), proof.b);
+
+            prepared_inputs_sum += prepared_inputs.mul(r);
+            c_sum += proof.c.0.mul(r);
+        }
+
+        assert_eq!(
+            batch_account.prepared_inputs.get().0,
+            prepared_inputs_sum.into_affine()
+        );
+        assert_eq!(batch_account.c.get().0, c_sum.into_affine());
+        assert_eq!(batch_account.f.get().0, Fq12::one());
+        assert_eq!(
+            batch_account.r.get(1),
+            G2HomProjective {
+                x: proofs[1].proof.b.0.x,
+                y: proofs[1].proof.b.0.y,
+                z: Fq2::one()
+            }
+        );
+
+        // All members accumulated
+        assert_eq!(
+            batch_accumulation(
+                &mut batch_account,
+                &proofs[0].proof.a.0,
+                &proofs[0].proof.b,
+                &proofs[0].proof.c.0,
+                &proofs[0].proof.c.0,
+            ),
+            Err(ComputationIsAlreadyFinished)
+        );
+    }
+
+    #[test]
+    fn test_batch_miller_loop() {
+        vkey!(vkey, TestVKey);
+        let pvk = TestVKey::arkworks_pvk();
+        let proofs = valid_proofs();
+        let transcript = [5; 32];
+
+        for members_count in 2..=4 {
+            zero_program_account!(mut batch_account, BatchVerificationAccount);
+            batch_account.set_members_count(&usize_as_u32_safe(members_count));
+            batch_account.set_transcript(&transcript);
+
+            let mut pairs = Vec::new();
+            let mut prepared_inputs_sum = G1Projective::zero();
+            let mut c_sum = G1Projective::zero();
+
+            for i in 0..members_count {
+                let proof = proofs[i % proofs.len()].proof;
+                let prepared_inputs = proof.c.0.mul(Fr::from(i as u64 + 2)).into_affine();
+
+                let scalar = batch_verification_scalar(&transcript, i, members_count);
+                let r = Fr::from_repr(u256_to_big_uint(&scalar)).unwrap();
+                for _ in 0..batch_accumulation_instructions_count(&scalar) {
+                    batch_accumulation(
+                        &mut batch_account,
+                        &proof.a.0,
+                        &proof.b,
+                        &prepared_inputs,
+                        &proof.c.0,
+                    )
+                    .unwrap();
+                }
+
+                let b: G2Prepared<Parameters> = proof.b.0.into();
+                pairs.push((proof.a.0.mul(r).into_affine().into(), b));
+                prepared_inputs_sum += prepared_inputs.mul(r);
+                c_sum += proof.c.0.mul(r);
+            }
+
+            pairs.push((
+                prepared_inputs_sum.into_affine().into(),
+                pvk.gamma_g2_neg_pc.clone(),
+            ));
+            pairs.push((c_sum.into_affine().into(), pvk.delta_g2_neg_pc.clone()));
+
+            let instructions = batch_miller_loop_instructions_count(members_count);
+            let mut result = None;
+            for i in 0..instructions {
+                result = batch_miller_loop(&mut batch_account, &vkey).unwrap();
+                assert_eq!(result.is_some(), i == instructions - 1);
+            }
+
+            assert_eq!(batch_account.get_coeff_index(), 91);
+            assert_eq!(result.unwrap(), Bn254::miller_loop(pairs.iter()));
+        }
+    }
+
+    #[test]
+    fn test_batch_miller_loop_instructions() {
+        for members_count in 2..=MAX_BATCH_VERIFICATION_MEMBERS {
+            let mut round = 0;
+            while let Some(r) = batch_miller_loop_round(members_count, round) {
+                // A single round never exceeds the compute budget of an instruction
+                assert!(BATCH_MILLER_LOOP_BASE_COST + batch_miller_loop_round_cost(&r) <= MAX_CUS);
+                round += 1;
+            }
+
+            let mut total_rounds = 0;
+            for _ in 0..batch_miller_loop_instructions_count(members_count) {
+                let rounds = batch_miller_loop_instruction_rounds(members_count, total_rounds);
+                let compute_units: u16 = (total_rounds..total_rounds + rounds)
+                    .map(|round| {
+                        batch_miller_loop_round_cost(
+                            &batch_miller_loop_round(members_count, round).unwrap(),
+                        )
+                    })
+                    .sum();
+                assert!(BATCH_MILLER_LOOP_BASE_COST + compute_units <= MAX_CUS);
+
+                total_rounds += rounds;
+            }
+            assert_eq!(total_rounds, round);
+        }
+
+        // A batch of `k` members requires less instructions than `k` individual Miller loops
+        for members_count in 2..=MAX_BATCH_VERIFICATION_MEMBERS {
+            assert!(
+                batch_miller_loop_instructions_count(members_count)
+                    < members_count * CombinedMillerLoop::TX_COUNT
+            );
+        }
+    }
+
     // https://github.com/arkworks-rs/algebra/blob/6ea310ef09f8b7510ce947490919ea6229bbecd6/ec/src/models/bn/mod.rs#L59
     fn reference_ell(f: Fq12, coeffs: (Fq2, Fq2, Fq2), p: G1Affine) -> Fq12 {
         let mut c0: Fq2 = coeffs.0;
@@ -1,14 +1,19 @@
 use crate::fields::{Wrap, G1A, G2A};
+use crate::types::U256;
 use ark_bn254::{Fq12, Fq2, G1Affine, G1Projective};
 use ark_ec::AffineCurve;
 use ark_ff::Zero;
-use borsh::BorshDeserialize;
+use borsh::{BorshDeserialize, BorshSerialize};
+use elusiv_derive::BorshSerDeSized;
 use elusiv_types::BorshSerDeSized;
 
 pub trait VerifyingKeyInfo {
     const VKEY_ID: u32;
     const PUBLIC_INPUTS_COUNT: u32;
 
+    /// The [`VerifyingKeyLayout`] of the deployed verifying key
+    const LAYOUT: VerifyingKeyLayout = VerifyingKeyLayout::Window8;
+
     #[cfg(feature = "elusiv-client")]
     const DIRECTORY: &'static str;
 
@@ -72,10 +77,67 @@ verification_key_info!(MigrateUnaryVKey, 1, 7, "migrate_unary");
 #[cfg(test)]
 verification_key_info!(TestVKey, 2, 14, "test");
 
+/// The window layout of the precomputed `gamma_abc` values in a [`VerifyingKey`]
+///
+/// # Notes
+///
+/// Smaller windows require less precomputed points (-> less rent for the vkey account) but more point additions (-> more compute units) during the public input preparation.
+#[derive(
+    BorshDeserialize, BorshSerialize, BorshSerDeSized, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+pub enum VerifyingKeyLayout {
+    /// 32 windows of 8 bits per public input (255 points per window)
+    #[default]
+    Window8,
+
+    /// 64 windows of 4 bits per public input (15 points per window)
+    Window4,
+}
+
+impl VerifyingKeyLayout {
+    pub const fn window_bits(&self) -> usize {
+        match self {
+            VerifyingKeyLayout::Window8 => 8,
+            VerifyingKeyLayout::Window4 => 4,
+        }
+    }
+
+    /// The number of windows per public input
+    pub const fn windows_count(&self) -> usize {
+        256 / self.window_bits()
+    }
+
+    /// The number of precomputed (non-zero) points per window
+    pub const fn window_size(&self) -> usize {
+        (1 << self.window_bits()) - 1
+    }
+
+    /// The number of rounds required for preparing a single public input (one round per window and one for the final addition)
+    pub const fn input_preparation_rounds(&self) -> usize {
+        self.windows_count() + 1
+    }
+
+    /// Returns the value of the window `window_index` of a little-endian `public_input`
+    pub fn window(&self, public_input: &U256, window_index: usize) -> u8 {
+        match self {
+            VerifyingKeyLayout::Window8 => public_input[window_index],
+            VerifyingKeyLayout::Window4 => {
+                let byte = public_input[window_index / 2];
+                if window_index.is_multiple_of(2) {
+                    byte & 0x0f
+                } else {
+                    byte >> 4
+                }
+            }
+        }
+    }
+}
+
 /// A Groth16 verifying key with precomputed values
 pub struct VerifyingKey<'a> {
     source: &'a [u8],
     pub public_inputs_count: usize,
+    pub layout: VerifyingKeyLayout,
     gamma_abc_size: usize,
 }
 
@@ -87,7 +149,7 @@ impl<'a> VerifyingKey<'a> {
     /// ```
     /// alpha_beta: Fq12,
     /// gamma_abc_base: G1Affine,
-    /// gamma_abc: [[[G1Affine; 255]; 32]; public_inputs_count],
+    /// gamma_abc: [[[G1Affine; layout.window_size()]; layout.windows_count()]; public_inputs_count],
     /// gamma_neg: [(Fq2, Fq2, Fq2); 91],
     /// delta_neg: [(Fq2, Fq2, Fq2); 91],
     ///
@@ -96,29 +158,34 @@ impl<'a> VerifyingKey<'a> {
     /// gamma: G2Affine,
     /// delta: G2Affine,
     /// ```
-    pub fn new(source: &'a [u8], public_inputs_count: usize) -> Option<Self> {
-        assert_eq!(source.len(), Self::source_size(public_inputs_count));
-        if source.len() != Self::source_size(public_inputs_count) {
+    pub fn new(
+        source: &'a [u8],
+        public_inputs_count: usize,
+        layout: VerifyingKeyLayout,
+    ) -> Option<Self> {
+        assert_eq!(source.len(), Self::source_size(public_inputs_count, layout));
+        if source.len() != Self::source_size(public_inputs_count, layout) {
             return None;
         }
 
         Some(Self {
             source,
             public_inputs_count,
-            gamma_abc_size: Self::gamma_abc_size(public_inputs_count),
+            layout,
+            gamma_abc_size: Self::gamma_abc_size(public_inputs_count, layout),
         })
     }
 
     const COEFFS_ARRAY_SIZE: usize = 91 * 3 * Wrap::<Fq2>::SIZE;
 
-    const fn gamma_abc_size(public_inputs_count: usize) -> usize {
-        public_inputs_count * 32 * 255 * G1A::SIZE
+    const fn gamma_abc_size(public_inputs_count: usize, layout: VerifyingKeyLayout) -> usize {
+        public_inputs_count * layout.windows_count() * layout.window_size() * G1A::SIZE
     }
 
-    pub const fn source_size(public_inputs_count: usize) -> usize {
+    pub const fn source_size(public_inputs_count: usize, layout: VerifyingKeyLayout) -> usize {
         Wrap::<Fq12>::SIZE
             + G1A::SIZE
-            + Self::gamma_abc_size(public_inputs_count)
+            + Self::gamma_abc_size(public_inputs_count, layout)
             + 2 * Self::COEFFS_ARRAY_SIZE
             + G1A::SIZE
             + 3 * G2A::SIZE
@@ -142,7 +209,11 @@ impl<'a> VerifyingKey<'a> {
 
         let offset = Wrap::<Fq12>::SIZE
             + G1A::SIZE
-            + ((public_input * 32 + window_index) * 255 + window as usize - 1) * G1A::SIZE;
+            + ((public_input * self.layout.windows_count() + window_index)
+                * self.layout.window_size()
+                + window as usize
+                - 1)
+                * G1A::SIZE;
         let slice = &self.source[offset..offset + G1A::SIZE];
         G1A::try_from_slice(slice).unwrap().0
     }
@@ -208,6 +279,46 @@ impl<'a> VerifyingKey<'a> {
         let slice = &self.source[offset..offset + G2A::SIZE];
         G2A::try_from_slice(slice).unwrap().0
     }
+
+    /// Returns `scalar * gamma_abc_g1[public_input + 1]` for a little-endian `scalar`
+    #[cfg(feature = "elusiv-client")]
+    pub fn gamma_abc_mul(&self, public_input: usize, scalar: &U256) -> G1Projective {
+        use ark_ec::ProjectiveCurve;
+
+        let mut acc = G1Projective::zero();
+        for window_index in 0..self.layout.windows_count() {
+            let window = self.layout.window(scalar, window_index);
+            acc.add_assign_mixed(&self.gamma_abc(public_input, window_index, window));
+        }
+        acc
+    }
+
+    /// Converts the source of this [`VerifyingKey`] into a source with a different [`VerifyingKeyLayout`]
+    #[cfg(feature = "elusiv-client")]
+    pub fn source_with_layout(&self, layout: VerifyingKeyLayout) -> Vec<u8> {
+        use ark_ec::ProjectiveCurve;
+
+        let gamma_abc_offset = Wrap::<Fq12>::SIZE + G1A::SIZE;
+        let mut source = Vec::with_capacity(Self::source_size(self.public_inputs_count, layout));
+        source.extend_from_slice(&self.source[..gamma_abc_offset]);
+
+        for public_input in 0..self.public_inputs_count {
+            for window_index in 0..layout.windows_count() {
+                let bit_offset = window_index * layout.window_bits();
+
+                for window in 1..=layout.window_size() {
+                    let mut scalar = [0; 32];
+                    scalar[bit_offset / 8] = (window << (bit_offset % 8)) as u8;
+
+                    let point = self.gamma_abc_mul(public_input, &scalar).into_affine();
+                    source.extend(G1A(point).try_to_vec().unwrap());
+                }
+            }
+        }
+
+        source.extend_from_slice(&self.source[gamma_abc_offset + self.gamma_abc_size..]);
+        source
+    }
 }
 
 /// Groth16 verifying key used for testing purposes
@@ -286,7 +397,7 @@ mod test {
 
     fn test_vkey<VKey: VerifyingKeyInfo>() {
         let source = VKey::verifying_key_source();
-        let vkey = VerifyingKey::new(&source, VKey::public_inputs_count()).unwrap();
+        let vkey = VerifyingKey::new(&source, VKey::public_inputs_count(), VKey::LAYOUT).unwrap();
         let pvk = VKey::arkworks_pvk();
 
         assert_eq!(vkey.alpha(), pvk.vk.alpha_g1);
@@ -326,6 +437,73 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_window4_layout() {
+        let source = TestVKey::verifying_key_source();
+        let vkey = VerifyingKey::new(
+            &source,
+            TestVKey::public_inputs_count(),
+            VerifyingKeyLayout::Window8,
+        )
+        .unwrap();
+        let pvk = TestVKey::arkworks_pvk();
+
+        let source = vkey.source_with_layout(VerifyingKeyLayout::Window4);
+        assert_eq!(
+            source.len(),
+            VerifyingKey::source_size(TestVKey::public_inputs_count(), VerifyingKeyLayout::Window4)
+        );
+
+        let vkey = VerifyingKey::new(
+            &source,
+            TestVKey::public_inputs_count(),
+            VerifyingKeyLayout::Window4,
+        )
+        .unwrap();
+
+        assert_eq!(vkey.alpha(), pvk.vk.alpha_g1);
+        assert_eq!(vkey.delta(), pvk.vk.delta_g2);
+        assert_eq!(vkey.alpha_beta(), pvk.alpha_g1_beta_g2);
+        assert_eq!(vkey.gamma_abc_base(), pvk.vk.gamma_abc_g1[0]);
+
+        for i in 0..TestVKey::public_inputs_count() {
+            let one = pvk.vk.gamma_abc_g1[i + 1];
+            assert_eq!(vkey.gamma_abc(i, 0, 1), one);
+
+            for j in 0..64 {
+                for k in 1..=15 {
+                    let mut scalar = [0u8; 32];
+                    scalar[j / 2] = if j % 2 == 0 { k } else { k << 4 };
+                    let s = u256_to_big_uint(&scalar);
+                    assert_eq!(vkey.gamma_abc(i, j, k), one.mul(s));
+                }
+            }
+        }
+
+        for (i, coeffs) in pvk.gamma_g2_neg_pc.ell_coeffs.iter().enumerate() {
+            assert_eq!(vkey.gamma_g2_neg_pc(i, 0), coeffs.0);
+            assert_eq!(
+                vkey.delta_g2_neg_pc(i, 2),
+                pvk.delta_g2_neg_pc.ell_coeffs[i].2
+            );
+        }
+    }
+
+    #[test]
+    fn test_layout_window() {
+        let mut public_input = [0; 32];
+        public_input[0] = 0xab;
+        public_input[31] = 0xcd;
+
+        assert_eq!(VerifyingKeyLayout::Window8.window(&public_input, 0), 0xab);
+        assert_eq!(VerifyingKeyLayout::Window8.window(&public_input, 31), 0xcd);
+
+        assert_eq!(VerifyingKeyLayout::Window4.window(&public_input, 0), 0xb);
+        assert_eq!(VerifyingKeyLayout::Window4.window(&public_input, 1), 0xa);
+        assert_eq!(VerifyingKeyLayout::Window4.window(&public_input, 62), 0xd);
+        assert_eq!(VerifyingKeyLayout::Window4.window(&public_input, 63), 0xc);
+    }
+
     #[test]
     fn test_send_quadra_vkey() {
         test_vkey::<SendQuadraVKey>()
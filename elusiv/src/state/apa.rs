@@ -0,0 +1,444 @@
+//! Read-only views of the APA accounts owned by the elusiv-warden-network program
+
+use crate::error::ElusivError;
+use crate::macros::guard;
+use crate::token::TokenID;
+use borsh::BorshDeserialize;
+use elusiv_types::{accounts::PDAAccountData, BorshSerDeSized, ElusivOption};
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+pub const WARDEN_NETWORK_PROGRAM_ID: Pubkey = crate::macros::program_id!(elusiv - warden - network);
+
+const APA_TARGET_MAP_SEED: &[u8] = b"ApaTargetMap";
+const APA_PROPOSAL_SEED: &[u8] = b"ApaProposal";
+
+/// Mirrors `elusiv_warden_network::apa::ApaLevel`
+#[repr(u8)]
+#[derive(BorshDeserialize, PartialEq, Clone, Copy)]
+#[cfg_attr(any(test, feature = "elusiv-client"), derive(Debug))]
+pub enum ApaLevel {
+    Flag1,
+    Flag2,
+    Outcast,
+}
+
+/// The leading fields of `elusiv_warden_network::apa::ApaProposal`
+///
+/// # Note
+///
+/// The trailing reason is not required for enforcing restrictions and is never deserialized.
+#[derive(BorshDeserialize)]
+#[cfg_attr(any(test, feature = "elusiv-client"), derive(Debug))]
+pub struct ApaProposalHeader {
+    pub proponent: Pubkey,
+    pub proponent_role: u8,
+    pub timestamp: u64,
+    pub target: Pubkey,
+    pub level: ApaLevel,
+    pub token_constraint: ElusivOption<TokenID>,
+}
+
+impl ApaProposalHeader {
+    /// Returns true if the proposal prohibits `target` from using the token with `token_id`
+    pub fn restricts(&self, token_id: TokenID) -> bool {
+        if self.level != ApaLevel::Outcast {
+            return false;
+        }
+
+        match self.token_constraint.option() {
+            Some(constraint) => constraint == token_id,
+            None => true,
+        }
+    }
+}
+
+/// The PDA of the `ApaTargetMapAccount` associated with `target`
+pub fn apa_target_map_account(target: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[APA_TARGET_MAP_SEED, &target.to_bytes()],
+        &WARDEN_NETWORK_PROGRAM_ID,
+    )
+}
+
+/// The PDA of the `ApaProposalAccount` with the id `proposal_id`
+pub fn apa_proposal_account(proposal_id: u32) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[APA_PROPOSAL_SEED, &proposal_id.to_le_bytes()],
+        &WARDEN_NETWORK_PROGRAM_ID,
+    )
+}
+
+/// Returns the [`ApaProposalHeader`] associated with `target` (if one exists)
+///
+/// # Notes
+///
+/// - `target_map_account` always has to be the `ApaTargetMapAccount` PDA of `target`.
+/// - If the target map account has not been opened by the warden network, the target is unrestricted.
+/// - `proposal_account` is only accessed if the target map references a proposal.
+pub fn get_apa_proposal(
+    target: &Pubkey,
+    target_map_account: &AccountInfo,
+    proposal_account: &AccountInfo,
+) -> Result<Option<ApaProposalHeader>, ProgramError> {
+    guard!(
+        *target_map_account.key == apa_target_map_account(target).0,
+        ElusivError::InvalidAccount
+    );
+
+    if *target_map_account.owner != WARDEN_NETWORK_PROGRAM_ID || target_map_account.data_is_empty()
+    {
+        return Ok(None);
+    }
+
+    let proposal_id = {
+        let data = &target_map_account.data.borrow()[..];
+        guard!(
+            data.len() >= PDAAccountData::SIZE,
+            ElusivError::InvalidAccount
+        );
+        let proposal_id = ElusivOption::<u32>::deserialize(&mut &data[PDAAccountData::SIZE..])?;
+
+        match proposal_id.option() {
+            Some(proposal_id) => proposal_id,
+            None => return Ok(None),
+        }
+    };
+
+    guard!(
+        *proposal_account.owner == WARDEN_NETWORK_PROGRAM_ID,
+        ElusivError::InvalidAccount
+    );
+
+    let data = &proposal_account.data.borrow()[..];
+    guard!(
+        data.len() >= PDAAccountData::SIZE,
+        ElusivError::InvalidAccount
+    );
+    let bump = data[0];
+    let pubkey = Pubkey::create_program_address(
+        &[APA_PROPOSAL_SEED, &proposal_id.to_le_bytes(), &[bump]],
+        &WARDEN_NETWORK_PROGRAM_ID,
+    )
+    .or(Err(ElusivError::InvalidAccount))?;
+    guard!(*proposal_account.key == pubkey, ElusivError::InvalidAccount);
+
+    let proposal = ApaProposalHeader::deserialize(&mut &data[PDAAccountData::SIZE..])?;
+    guard!(proposal.target == *target, ElusivError::InvalidAccount);
+
+    Ok(Some(proposal))
+}
+
+/// Refuses any `target` that the warden network has declared an outcast for the token with `token_id`
+pub fn enforce_apa_restrictions(
+    target: &Pubkey,
+    token_id: TokenID,
+    target_map_account: &AccountInfo,
+    proposal_account: &AccountInfo,
+) -> ProgramResult {
+    if let Some(proposal) = get_apa_proposal(target, target_map_account, proposal_account)? {
+        guard!(!proposal.restricts(token_id), ElusivError::ApaRestricted);
+    }
+
+    Ok(())
+}
+
+/// Splits `accounts` into the leading accounts and the trailing APA accounts of `target`
+///
+/// # Note
+///
+/// The APA accounts start with the required `ApaTargetMapAccount` of `target` (see [`enforce_trailing_apa_restrictions`]).
+pub fn split_apa_accounts<'a, 'b, 'c>(
+    target: &Pubkey,
+    accounts: &'a [&'b AccountInfo<'c>],
+) -> Result<(&'a [&'b AccountInfo<'c>], &'a [&'b AccountInfo<'c>]), ProgramError> {
+    let target_map = apa_target_map_account(target).0;
+    let position = accounts
+        .iter()
+        .position(|account| *account.key == target_map)
+        .ok_or(ElusivError::InvalidAccount)?;
+
+    Ok(accounts.split_at(position))
+}
+
+/// Enforces the APA restrictions of `target` with the trailing `apa_accounts`
+///
+/// # Notes
+///
+/// - `apa_accounts` are either the `ApaTargetMapAccount` of `target` or the `ApaTargetMapAccount` followed by the `ApaProposalAccount`.
+/// - The `ApaTargetMapAccount` is always required (an uninitialized target map leaves `target` unrestricted).
+/// - If the target map references a proposal, the `ApaProposalAccount` is required.
+pub fn enforce_trailing_apa_restrictions(
+    target: &Pubkey,
+    token_id: TokenID,
+    apa_accounts: &[&AccountInfo],
+) -> ProgramResult {
+    match apa_accounts {
+        [target_map_account] => {
+            enforce_apa_restrictions(target, token_id, target_map_account, target_map_account)
+        }
+        [target_map_account, proposal_account] => {
+            enforce_apa_restrictions(target, token_id, target_map_account, proposal_account)
+        }
+        _ => Err(ElusivError::InvalidAccount.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macros::account_info;
+    use borsh::BorshSerialize;
+
+    fn target_map_data(bump: u8, proposal_id: Option<u32>) -> Vec<u8> {
+        let mut data = vec![bump, 0];
+        ElusivOption::from(proposal_id)
+            .serialize(&mut data)
+            .unwrap();
+        data
+    }
+
+    fn proposal_data(
+        bump: u8,
+        target: Pubkey,
+        level: ApaLevel,
+        token_constraint: Option<TokenID>,
+    ) -> Vec<u8> {
+        let mut data = vec![bump, 0];
+        data.extend(Pubkey::new_unique().to_bytes());
+        data.push(0);
+        data.extend(0u64.to_le_bytes());
+        data.extend(target.to_bytes());
+        data.push(level as u8);
+        ElusivOption::from(token_constraint)
+            .serialize(&mut data)
+            .unwrap();
+        data.extend(vec![0; 8 + 512]);
+        data
+    }
+
+    #[test]
+    fn test_apa_proposal_restricts() {
+        let proposal = |level, token_constraint: Option<TokenID>| ApaProposalHeader {
+            proponent: Pubkey::new_unique(),
+            proponent_role: 0,
+            timestamp: 0,
+            target: Pubkey::new_unique(),
+            level,
+            token_constraint: token_constraint.into(),
+        };
+
+        assert!(!proposal(ApaLevel::Flag1, None).restricts(0));
+        assert!(!proposal(ApaLevel::Flag2, None).restricts(0));
+        assert!(proposal(ApaLevel::Outcast, None).restricts(0));
+        assert!(proposal(ApaLevel::Outcast, None).restricts(1));
+        assert!(proposal(ApaLevel::Outcast, Some(1)).restricts(1));
+        assert!(!proposal(ApaLevel::Outcast, Some(1)).restricts(0));
+    }
+
+    #[test]
+    fn test_enforce_apa_restrictions_without_target_map() {
+        let target = Pubkey::new_unique();
+        let (map_pubkey, _) = apa_target_map_account(&target);
+
+        // Uninitialized target map
+        account_info!(
+            map,
+            map_pubkey,
+            vec![],
+            solana_program::system_program::id(),
+            false
+        );
+        enforce_apa_restrictions(&target, 0, &map, &map).unwrap();
+
+        // Invalid target map PDA
+        account_info!(
+            map,
+            Pubkey::new_unique(),
+            vec![],
+            solana_program::system_program::id(),
+            false
+        );
+        assert_eq!(
+            enforce_apa_restrictions(&target, 0, &map, &map),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        // Target map without a proposal
+        account_info!(
+            map,
+            map_pubkey,
+            target_map_data(0, None),
+            WARDEN_NETWORK_PROGRAM_ID,
+            false
+        );
+        enforce_apa_restrictions(&target, 0, &map, &map).unwrap();
+    }
+
+    #[test]
+    fn test_enforce_apa_restrictions() {
+        let target = Pubkey::new_unique();
+        let (map_pubkey, map_bump) = apa_target_map_account(&target);
+        let (proposal_pubkey, proposal_bump) = apa_proposal_account(3);
+        account_info!(
+            map,
+            map_pubkey,
+            target_map_data(map_bump, Some(3)),
+            WARDEN_NETWORK_PROGRAM_ID,
+            false
+        );
+
+        // Outcast
+        account_info!(
+            proposal,
+            proposal_pubkey,
+            proposal_data(proposal_bump, target, ApaLevel::Outcast, None),
+            WARDEN_NETWORK_PROGRAM_ID,
+            false
+        );
+        assert_eq!(
+            enforce_apa_restrictions(&target, 0, &map, &proposal),
+            Err(ElusivError::ApaRestricted.into())
+        );
+
+        // Token constraint
+        account_info!(
+            proposal,
+            proposal_pubkey,
+            proposal_data(proposal_bump, target, ApaLevel::Outcast, Some(1)),
+            WARDEN_NETWORK_PROGRAM_ID,
+            false
+        );
+        enforce_apa_restrictions(&target, 0, &map, &proposal).unwrap();
+        assert_eq!(
+            enforce_apa_restrictions(&target, 1, &map, &proposal),
+            Err(ElusivError::ApaRestricted.into())
+        );
+
+        // Flagged targets are not restricted
+        account_info!(
+            proposal,
+            proposal_pubkey,
+            proposal_data(proposal_bump, target, ApaLevel::Flag2, None),
+            WARDEN_NETWORK_PROGRAM_ID,
+            false
+        );
+        enforce_apa_restrictions(&target, 0, &map, &proposal).unwrap();
+
+        // Proposal of a different target
+        account_info!(
+            proposal,
+            proposal_pubkey,
+            proposal_data(proposal_bump, Pubkey::new_unique(), ApaLevel::Outcast, None),
+            WARDEN_NETWORK_PROGRAM_ID,
+            false
+        );
+        assert_eq!(
+            enforce_apa_restrictions(&target, 0, &map, &proposal),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        // Invalid proposal PDA
+        account_info!(
+            proposal,
+            apa_proposal_account(4).0,
+            proposal_data(proposal_bump, target, ApaLevel::Outcast, None),
+            WARDEN_NETWORK_PROGRAM_ID,
+            false
+        );
+        assert_eq!(
+            enforce_apa_restrictions(&target, 0, &map, &proposal),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        // Proposal not owned by the warden network
+        account_info!(
+            proposal,
+            proposal_pubkey,
+            proposal_data(proposal_bump, target, ApaLevel::Outcast, None),
+            crate::id(),
+            false
+        );
+        assert_eq!(
+            enforce_apa_restrictions(&target, 0, &map, &proposal),
+            Err(ElusivError::InvalidAccount.into())
+        );
+    }
+
+    #[test]
+    fn test_trailing_apa_accounts() {
+        let target = Pubkey::new_unique();
+        let (map_pubkey, map_bump) = apa_target_map_account(&target);
+        let (proposal_pubkey, proposal_bump) = apa_proposal_account(3);
+        account_info!(
+            map,
+            map_pubkey,
+            target_map_data(map_bump, Some(3)),
+            WARDEN_NETWORK_PROGRAM_ID,
+            false
+        );
+        account_info!(
+            proposal,
+            proposal_pubkey,
+            proposal_data(proposal_bump, target, ApaLevel::Outcast, None),
+            WARDEN_NETWORK_PROGRAM_ID,
+            false
+        );
+        account_info!(reference, Pubkey::new_unique());
+
+        // Omitted APA accounts
+        let accounts = [&reference, &reference];
+        assert_eq!(
+            split_apa_accounts(&target, &accounts).unwrap_err(),
+            ElusivError::InvalidAccount.into()
+        );
+        assert_eq!(
+            enforce_trailing_apa_restrictions(&target, 0, &[]),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        // Uninitialized target map
+        account_info!(
+            uninitialized_map,
+            map_pubkey,
+            vec![],
+            solana_program::system_program::id(),
+            false
+        );
+        let accounts = [&reference, &uninitialized_map];
+        let (leading, apa_accounts) = split_apa_accounts(&target, &accounts).unwrap();
+        assert_eq!(leading.len(), 1);
+        enforce_trailing_apa_restrictions(&target, 0, apa_accounts).unwrap();
+
+        // Trailing APA accounts
+        let accounts = [&reference, &map, &proposal];
+        let (leading, apa_accounts) = split_apa_accounts(&target, &accounts).unwrap();
+        assert_eq!(leading.len(), 1);
+        assert_eq!(*leading[0].key, *reference.key);
+        assert_eq!(apa_accounts.len(), 2);
+        assert_eq!(
+            enforce_trailing_apa_restrictions(&target, 0, apa_accounts),
+            Err(ElusivError::ApaRestricted.into())
+        );
+
+        // Missing proposal account
+        assert_eq!(
+            enforce_trailing_apa_restrictions(&target, 0, &[&map]),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        // Too many APA accounts
+        assert_eq!(
+            enforce_trailing_apa_restrictions(&target, 0, &[&map, &proposal, &proposal]),
+            Err(ElusivError::InvalidAccount.into())
+        );
+
+        // Target map of a different target
+        assert_eq!(
+            enforce_trailing_apa_restrictions(&Pubkey::new_unique(), 0, &[&map, &proposal]),
+            Err(ElusivError::InvalidAccount.into())
+        );
+    }
+}
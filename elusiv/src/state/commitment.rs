@@ -2,14 +2,16 @@ use super::metadata::CommitmentMetadata;
 use super::queue::{queue_account, RingQueue};
 use crate::buffer::buffer_account;
 use crate::bytes::usize_as_u32_safe;
-use crate::commitment::poseidon_hash::BinarySpongeHashingState;
-use crate::commitment::{commitments_per_batch, MAX_HT_SIZE, MT_HEIGHT};
+use crate::commitment::poseidon_hash::{BinarySpongeHashingState, TOTAL_POSEIDON_ROUNDS};
+use crate::commitment::{
+    commitments_per_batch, MAX_COMMITMENT_BATCHING_RATE, MAX_HT_SIZE, MT_HEIGHT,
+};
 use crate::error::ElusivError;
 use crate::fields::{fr_to_u256_le, u256_to_fr_skip_mr};
 use crate::macros::{elusiv_account, guard, two_pow};
 use crate::processor::{BaseCommitmentHashRequest, CommitmentHashRequest};
 use crate::state::program_account::PDAAccountData;
-use crate::state::storage::{StorageAccount, HISTORY_ARRAY_SIZE};
+use crate::state::storage::{RootHistoryAccount, StorageAccount};
 use crate::types::U256;
 use ark_bn254::Fr;
 use ark_ff::{BigInteger256, PrimeField};
@@ -69,7 +71,25 @@ impl<'a> BaseCommitmentHashingAccount<'a> {
     }
 }
 
+/// Number of [`CommitmentHashingAccount`]s that can hash batches concurrently
+pub const COMMITMENT_HASHING_ACCOUNTS_COUNT: u32 = 4;
+
+/// The [`CommitmentHashingAccount`] with index zero is the original single instance account (without a PDA-offset)
+pub const fn commitment_hashing_account_offset(hashing_account_index: u32) -> Option<u32> {
+    if hashing_account_index == 0 {
+        None
+    } else {
+        Some(hashing_account_index)
+    }
+}
+
 /// Account used for computing the hashes of a MT
+///
+/// # Notes
+///
+/// - multiple accounts hash disjoint batches concurrently
+/// - the HT hashes of a batch are independent of the MT, so they can be computed directly after claiming the batch
+/// - the hashes above the HT require the siblings, which are only available once all previous batches are inserted
 #[elusiv_account(partial_computation: true, eager_type: true)]
 pub struct CommitmentHashingAccount {
     #[no_getter]
@@ -98,24 +118,67 @@ pub struct CommitmentHashingAccount {
 }
 
 impl<'a> CommitmentHashingAccount<'a> {
-    /// Called before reset, sets the siblings
+    /// Sets the siblings of the batch, either before or after the batch is claimed
+    ///
+    /// # Note
+    ///
+    /// For an already claimed batch, `ordering` has to match the claimed ordering.
     pub fn setup(&mut self, ordering: u32, siblings: &[U256]) -> Result<(), ProgramError> {
-        guard!(!self.get_is_active(), ElusivError::InvalidAccountState);
+        if self.get_is_active() {
+            guard!(
+                !self.get_setup() && self.get_ordering() == ordering,
+                ElusivError::InvalidAccountState
+            );
+        } else {
+            self.set_instruction(&0);
+            self.set_round(&0);
+            self.set_ordering(&ordering);
+            self.set_finalization_ix(&0);
+        }
 
         self.set_setup(&true);
-        self.set_instruction(&0);
-        self.set_round(&0);
-        self.set_ordering(&ordering);
-        self.set_finalization_ix(&0);
 
         for (i, sibling) in siblings.iter().enumerate() {
             self.set_siblings(i, sibling);
         }
 
+        // Without a HT, the first hash already requires the siblings
+        if self.get_is_active() && self.get_batching_rate() == 0 {
+            self.set_state(&self.next_hashing_state(0));
+        }
+
         Ok(())
     }
 
-    /// Called after setup, sets the commitments and batching rate
+    /// Verifies that the batch at `ordering` can be claimed (without modifying the account)
+    pub fn can_claim(&self, ordering: u32) -> Result<(), ProgramError> {
+        guard!(!self.get_is_active(), ElusivError::InvalidAccountState);
+
+        if self.get_setup() {
+            guard!(
+                self.get_ordering() == ordering,
+                ElusivError::InvalidAccountState
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Claims the batch at `ordering` in the active MT (siblings can be setup afterwards)
+    pub fn claim(&mut self, ordering: u32) -> Result<(), ProgramError> {
+        self.can_claim(ordering)?;
+
+        if !self.get_setup() {
+            self.set_instruction(&0);
+            self.set_round(&0);
+            self.set_ordering(&ordering);
+            self.set_finalization_ix(&0);
+        }
+
+        Ok(())
+    }
+
+    /// Called after claiming, sets the commitments and batching rate
     pub fn reset(
         &mut self,
         batching_rate: u32,
@@ -123,7 +186,6 @@ impl<'a> CommitmentHashingAccount<'a> {
         commitments: &[U256],
     ) -> Result<(), ProgramError> {
         guard!(!self.get_is_active(), ElusivError::InvalidAccountState);
-        guard!(self.get_setup(), ElusivError::InvalidAccountState);
 
         self.set_is_active(&true);
         self.set_fee_version(&fee_version);
@@ -134,11 +196,20 @@ impl<'a> CommitmentHashingAccount<'a> {
             self.set_hash_tree(i, commitment);
         }
 
-        self.set_state(&self.next_hashing_state(0));
+        // Without siblings only the HT hashes can be computed
+        if self.get_setup() || batching_rate > 0 {
+            self.set_state(&self.next_hashing_state(0));
+        }
 
         Ok(())
     }
 
+    /// Returns the first round that requires the siblings (the round that prepares the first hash above the HT)
+    pub fn first_path_round(&self) -> u32 {
+        let sub_tree_size = two_pow!(self.get_batching_rate()) - 1;
+        usize_as_u32_safe(sub_tree_size) * TOTAL_POSEIDON_ROUNDS
+    }
+
     /// Returns the initial state for the next hash
     /// - hashing order:
     ///     1. commitment sibling hashes on MT-layer `n`: h(c0, c1), h(c2, c3), ..
@@ -213,7 +284,15 @@ impl<'a> CommitmentHashingAccount<'a> {
     }
 
     /// Updates the active MT with all finished hashes and commitments
-    pub fn update_mt(&self, storage_account: &mut StorageAccount, finalization_ix: u32) {
+    ///
+    /// `slot` is stored alongside the new root in the `root_history_account`
+    pub fn update_mt(
+        &self,
+        storage_account: &mut StorageAccount,
+        root_history_account: &mut RootHistoryAccount,
+        finalization_ix: u32,
+        slot: u64,
+    ) {
         let batching_rate = self.get_batching_rate();
         let ordering = self.get_ordering();
 
@@ -258,18 +337,19 @@ impl<'a> CommitmentHashingAccount<'a> {
                 &(ordering + usize_as_u32_safe(commitments_per_batch(batching_rate))),
             );
 
-            // This inserts the new root into the `active_mt_root_history`
-            storage_account.set_active_mt_root_history(
-                ordering as usize % HISTORY_ARRAY_SIZE,
-                &storage_account.get_root().unwrap(),
-            );
-            storage_account.set_mt_roots_count(&(storage_account.get_mt_roots_count() + 1));
+            // This inserts the new root into the `root_history_account`
+            storage_account
+                .push_root_to_history(root_history_account, slot)
+                .unwrap();
         }
     }
 }
 
 pub const COMMITMENT_BUFFER_LEN: u32 = 128;
 
+/// The number of slots (~ one hour) after which a commitment in the [`CommitmentBufferAccount`] no longer counts as a duplicate
+pub const COMMITMENT_BUFFER_EXPIRY_SLOTS: u64 = 9_000;
+
 buffer_account!(
     BaseCommitmentBufferAccount,
     U256,
@@ -299,9 +379,14 @@ impl<'a, 'b> CommitmentQueue<'a, 'b> {
         let mut highest_batching_rate = 0;
         let mut commitment_count: usize = u32::MAX as usize;
         let mut fee_version = None;
+        let mut queue = self.iter();
 
         while requests.len() < commitment_count {
-            let request = self.view(requests.len())?;
+            let request = match queue.next() {
+                Some(request) => request,
+                None if requests.is_empty() => return Err(ElusivError::QueueIsEmpty.into()),
+                None => return Err(ElusivError::InvalidQueueAccess.into()),
+            };
 
             highest_batching_rate = std::cmp::max(highest_batching_rate, request.min_batching_rate);
             commitment_count = commitments_per_batch(highest_batching_rate);
@@ -321,6 +406,44 @@ impl<'a, 'b> CommitmentQueue<'a, 'b> {
 
         Ok((requests, highest_batching_rate))
     }
+
+    /// Returns the batch with the largest batching rate (up to `MAX_COMMITMENT_BATCHING_RATE`), for which all commitments are already in the queue
+    ///
+    /// # Note
+    ///
+    /// - A batching rate is only used if it is allowed by the `min_batching_rate` of every request in the batch.
+    /// - Falls back to [`CommitmentQueue::next_batch`] if there is no such batch.
+    pub fn next_adaptive_batch(&self) -> Result<(Vec<CommitmentHashRequest>, u32), ProgramError> {
+        let len = self.len() as usize;
+
+        for batching_rate in (0..=usize_as_u32_safe(MAX_COMMITMENT_BATCHING_RATE)).rev() {
+            let commitment_count = commitments_per_batch(batching_rate);
+            if commitment_count > len {
+                continue;
+            }
+
+            let mut requests: Vec<CommitmentHashRequest> = Vec::with_capacity(commitment_count);
+            for request in self.iter().take(commitment_count) {
+                if request.min_batching_rate > batching_rate {
+                    break;
+                }
+
+                if let Some(first) = requests.first() {
+                    if first.fee_version != request.fee_version {
+                        break;
+                    }
+                }
+
+                requests.push(request);
+            }
+
+            if requests.len() == commitment_count {
+                return Ok((requests, batching_rate));
+            }
+        }
+
+        self.next_batch()
+    }
 }
 
 #[cfg(test)]
@@ -343,6 +466,7 @@ pub fn base_commitment_request(
         token_id,
         fee_version,
         min_batching_rate,
+        metadata_hash: [0; 32],
     }
 }
 
@@ -354,6 +478,7 @@ mod tests {
     };
     use crate::fields::{u64_to_scalar, u64_to_scalar_skip_mr, u64_to_u256_skip_mr};
     use crate::macros::{parent_account, zero_program_account};
+    use crate::state::governor::RootHistoryParameters;
     use crate::state::queue::Queue;
     use crate::types::RawU256;
     use ark_bn254::Fr;
@@ -505,6 +630,7 @@ mod tests {
     fn test_update_mt() {
         zero_program_account!(mut account, CommitmentHashingAccount);
         parent_account!(mut storage_account, StorageAccount);
+        parent_account!(mut root_history_account, RootHistoryAccount);
 
         let batching_rates: Vec<u32> = (0..MAX_COMMITMENT_BATCHING_RATE as u32).collect();
         let mut previous_commitments_count = 0;
@@ -531,7 +657,7 @@ mod tests {
 
             // Update
             for i in 0..=batching_rate {
-                account.update_mt(&mut storage_account, i);
+                account.update_mt(&mut storage_account, &mut root_history_account, i, 0);
             }
 
             // Check commitments
@@ -569,6 +695,13 @@ mod tests {
                 ordering + commitments_count as u32
             );
             assert_eq!(storage_account.get_mt_roots_count(), i as u32 + 1);
+            assert_eq!(root_history_account.get_roots_count(), i as u32 + 1);
+            assert!(root_history_account.contains_root(
+                &storage_account.get_root().unwrap(),
+                0,
+                &RootHistoryParameters::default(),
+                0
+            ));
 
             previous_commitments_count += commitments_count;
         }
@@ -586,6 +719,7 @@ mod tests {
             commitment: RawU256::new([2; 32]),
             fee_version: 444,
             min_batching_rate: 555,
+            metadata_hash: [7; 32],
         };
         let fee_payer = [6; 32];
 
@@ -718,4 +852,71 @@ mod tests {
         .unwrap();
         assert_eq!(q.next_batch(), Err(ElusivError::InvalidFeeVersion.into()));
     }
+
+    #[test]
+    fn test_commitment_queue_next_adaptive_batch() {
+        let mut data = vec![0; <CommitmentQueueAccount as elusiv_types::SizedAccount>::SIZE];
+        let mut q = CommitmentQueueAccount::new(&mut data).unwrap();
+        let mut q = CommitmentQueue::new(&mut q);
+
+        let request = |i: u64, fee_version: u32, min_batching_rate: u32| CommitmentHashRequest {
+            commitment: fr_to_u256_le(&u64_to_scalar(i)),
+            fee_version,
+            min_batching_rate,
+        };
+
+        // Empty queue
+        assert_eq!(
+            q.next_adaptive_batch(),
+            Err(ElusivError::QueueIsEmpty.into())
+        );
+
+        // The largest complete batch is used
+        for i in 0..7 {
+            q.enqueue(request(i, 0, 0)).unwrap();
+        }
+        let (batch, batching_rate) = q.next_adaptive_batch().unwrap();
+        assert_eq!(batching_rate, 2);
+        for (i, c) in batch.iter().enumerate() {
+            assert_eq!(c.commitment, fr_to_u256_le(&u64_to_scalar(i as u64)));
+        }
+
+        // The batching rate is capped at `MAX_COMMITMENT_BATCHING_RATE`
+        q.clear();
+        let max_count = commitments_per_batch(MAX_COMMITMENT_BATCHING_RATE as u32);
+        for i in 0..max_count + 1 {
+            q.enqueue(request(i as u64, 0, 0)).unwrap();
+        }
+        let (batch, batching_rate) = q.next_adaptive_batch().unwrap();
+        assert_eq!(batching_rate as usize, MAX_COMMITMENT_BATCHING_RATE);
+        assert_eq!(batch.len(), max_count);
+
+        // A higher `min_batching_rate` inside of a smaller batch prevents the smaller batch
+        q.clear();
+        q.enqueue(request(0, 0, 0)).unwrap();
+        q.enqueue(request(1, 0, 1)).unwrap();
+        q.enqueue(request(2, 0, 0)).unwrap();
+        let (batch, batching_rate) = q.next_adaptive_batch().unwrap();
+        assert_eq!(batching_rate, 1);
+        assert_eq!(batch.len(), 2);
+
+        // A `min_batching_rate` that is not yet satisfiable falls back to `next_batch`
+        q.clear();
+        q.enqueue(request(0, 0, 2)).unwrap();
+        q.enqueue(request(1, 0, 0)).unwrap();
+        assert_eq!(
+            q.next_adaptive_batch(),
+            Err(ElusivError::InvalidQueueAccess.into())
+        );
+
+        // Mismatching fee versions limit the batch size
+        q.clear();
+        q.enqueue(request(0, 0, 0)).unwrap();
+        q.enqueue(request(1, 0, 0)).unwrap();
+        q.enqueue(request(2, 1, 0)).unwrap();
+        q.enqueue(request(3, 1, 0)).unwrap();
+        let (batch, batching_rate) = q.next_adaptive_batch().unwrap();
+        assert_eq!(batching_rate, 1);
+        assert_eq!(batch.len(), 2);
+    }
 }
@@ -134,6 +134,26 @@ impl ProgramFee {
         ))
     }
 
+    /// The difference between the commitment hash fees paid by the requests in a batch and the actual cost of hashing the batch
+    ///
+    /// # Note
+    ///
+    /// The `batching_rate` is at least each request's `min_batching_rate`, so the actual cost per commitment can only be lower.
+    pub fn commitment_hash_computation_fee_surplus(
+        &self,
+        min_batching_rates: &[u32],
+        batching_rate: u32,
+    ) -> Lamports {
+        let paid: u64 = min_batching_rates
+            .iter()
+            .map(|&min_batching_rate| self.commitment_hash_computation_fee(min_batching_rate).0)
+            .sum();
+        let cost = commitment_hash_computation_instructions(batching_rate).len() as u64
+            * self.hash_tx_compensation().0;
+
+        Lamports(paid.saturating_sub(cost))
+    }
+
     pub fn proof_verification_computation_fee(
         &self,
         input_preparation_tx_count: usize,
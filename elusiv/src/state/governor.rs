@@ -1,7 +1,27 @@
-use super::{fee::ProgramFee, program_account::PDAAccountData};
-use crate::macros::elusiv_account;
+use super::{
+    fee::ProgramFee,
+    program_account::{MigratableAccount, PDAAccountData, SizedAccount},
+};
+use crate::bytes::BorshSerDeSized;
+use crate::error::ElusivError;
+use crate::macros::{elusiv_account, guard};
+use crate::state::storage::{DEFAULT_ROOT_HISTORY_DEPTH, DEFAULT_ROOT_HISTORY_SLOT_WINDOW};
+use crate::token::{TokenID, SPL_TOKEN_COUNT};
+use borsh::{BorshDeserialize, BorshSerialize};
+use elusiv_derive::BorshSerDeSized;
+use elusiv_types::{PDAAccount, TokenError};
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+};
 
-#[elusiv_account(eager_type: true)]
+/// # Versions
+///
+/// - `0`: trailing (unused) `program_version: u32`
+/// - `1`: removed `program_version`
+/// - `2`: added `rate_limits`
+/// - `3`: added `root_history`
+/// - `4`: moved the rate limit windows into the [`RateLimitAccount`]s
+#[elusiv_account(eager_type: true, version: 4)]
 pub struct GovernorAccount {
     #[no_getter]
     #[no_setter]
@@ -16,7 +36,271 @@ pub struct GovernorAccount {
     /// The number of commitments in a MT-root hashing batch
     pub commitment_batching_rate: u32,
 
-    program_version: u32,
+    /// Caps on the deposited and withdrawn amounts per token
+    pub rate_limits: RateLimits,
+
+    /// The previous roots of the active MT accepted for proofs
+    pub root_history: RootHistoryParameters,
+}
+
+/// The size of the `rate_limits` (including `window_start` and the volumes of all tokens) before version `4`
+const LEGACY_RATE_LIMITS_SIZE: usize = RateLimits::SIZE + 8 + 2 * 8 * TOKENS_COUNT;
+
+impl<'a> MigratableAccount for GovernorAccount<'a> {
+    fn size_at_version(version: u8) -> Option<usize> {
+        match version {
+            0 => Some(Self::SIZE - RootHistoryParameters::SIZE - RateLimits::SIZE + 4),
+            1 => Some(Self::SIZE - RootHistoryParameters::SIZE - RateLimits::SIZE),
+            2 => Some(
+                Self::SIZE - RootHistoryParameters::SIZE - RateLimits::SIZE
+                    + LEGACY_RATE_LIMITS_SIZE,
+            ),
+            3 => Some(Self::SIZE - RateLimits::SIZE + LEGACY_RATE_LIMITS_SIZE),
+            4 => Some(Self::SIZE),
+            _ => None,
+        }
+    }
+
+    fn migrate_from(version: u8, data: &mut [u8]) -> ProgramResult {
+        let rate_limits_offset = Self::SIZE - RootHistoryParameters::SIZE - RateLimits::SIZE;
+
+        match version {
+            // The trailing `program_version` is truncated
+            0 => Ok(()),
+
+            // Zeroed `rate_limits` are disabled
+            1 => Ok(()),
+
+            2 => {
+                let offset = Self::size_at_version(3).unwrap() - RootHistoryParameters::SIZE;
+                let mut slice = &mut data[offset..];
+                RootHistoryParameters::default().serialize(&mut slice)?;
+                Ok(())
+            }
+
+            // `window_start` and the volumes are dropped (the windows restart in the `RateLimitAccount`s)
+            3 => {
+                let caps_offset = rate_limits_offset + 8;
+                let legacy_caps_offset = caps_offset + 8;
+                data.copy_within(
+                    legacy_caps_offset..legacy_caps_offset + 2 * 8 * TOKENS_COUNT,
+                    caps_offset,
+                );
+
+                let legacy_root_history_offset = rate_limits_offset + LEGACY_RATE_LIMITS_SIZE;
+                data.copy_within(
+                    legacy_root_history_offset
+                        ..legacy_root_history_offset + RootHistoryParameters::SIZE,
+                    rate_limits_offset + RateLimits::SIZE,
+                );
+                Ok(())
+            }
+
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+}
+
+/// Configures which previous roots of the active MT are accepted (see [`crate::state::storage::RootHistoryAccount`])
+#[derive(BorshDeserialize, BorshSerialize, BorshSerDeSized, PartialEq, Eq, Clone)]
+#[cfg_attr(any(test, feature = "elusiv-client"), derive(Debug))]
+pub struct RootHistoryParameters {
+    /// The number of previous roots (at most [`crate::state::storage::MAX_ROOT_HISTORY_DEPTH`])
+    pub depth: u32,
+
+    /// The number of slots after its insertion during which a previous root is accepted
+    pub slot_window: u64,
+}
+
+impl Default for RootHistoryParameters {
+    fn default() -> Self {
+        Self {
+            depth: DEFAULT_ROOT_HISTORY_DEPTH,
+            slot_window: DEFAULT_ROOT_HISTORY_SLOT_WINDOW,
+        }
+    }
+}
+
+const TOKENS_COUNT: usize = SPL_TOKEN_COUNT + 1;
+
+/// Per-token caps on the amounts deposited into and withdrawn from the pool in windows of `window_slots` slots
+///
+/// # Note
+///
+/// The windows are tracked per token by the [`RateLimitAccount`]s.
+#[derive(BorshDeserialize, BorshSerialize, BorshSerDeSized, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(any(test, feature = "elusiv-client"), derive(Debug))]
+pub struct RateLimits {
+    /// The length of a window in slots (zero disables all rate limits)
+    pub window_slots: u64,
+
+    /// The maximum amount that can be deposited per window (zero disables the cap of a token)
+    pub deposit_caps: [u64; TOKENS_COUNT],
+
+    /// The maximum amount that can be withdrawn per window (zero disables the cap of a token)
+    pub withdrawal_caps: [u64; TOKENS_COUNT],
+}
+
+impl RateLimits {
+    /// Returns true if deposits of `token_id` are capped (requiring its [`RateLimitAccount`])
+    pub fn is_deposit_limited(&self, token_id: TokenID) -> bool {
+        self.window_slots > 0 && self.deposit_caps[token_id as usize] > 0
+    }
+
+    /// Returns true if withdrawals of `token_id` are capped (requiring its [`RateLimitAccount`])
+    pub fn is_withdrawal_limited(&self, token_id: TokenID) -> bool {
+        self.window_slots > 0 && self.withdrawal_caps[token_id as usize] > 0
+    }
+}
+
+/// The current rate limit window of a single token
+#[derive(BorshDeserialize, BorshSerialize, BorshSerDeSized, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(any(test, feature = "elusiv-client"), derive(Debug))]
+pub struct RateLimitWindow {
+    /// The `window_slots` of the [`RateLimits`] the window has been started with
+    pub window_slots: u64,
+
+    /// The first slot of the window
+    pub window_start: u64,
+
+    /// The amount deposited in the window
+    pub deposit_volume: u64,
+
+    /// The amount withdrawn in the window
+    pub withdrawal_volume: u64,
+}
+
+impl RateLimitWindow {
+    /// Starts a new window (and resets the volumes), if the window has ended at `slot`
+    ///
+    /// # Note
+    ///
+    /// Changing `window_slots` starts a new window at `slot`.
+    fn update(&mut self, window_slots: u64, slot: u64) {
+        if self.window_slots != window_slots {
+            *self = Self {
+                window_slots,
+                window_start: slot,
+                ..Default::default()
+            };
+            return;
+        }
+
+        if slot < self.window_start.saturating_add(window_slots) {
+            return;
+        }
+
+        self.window_start = slot - (slot - self.window_start) % window_slots;
+        self.deposit_volume = 0;
+        self.withdrawal_volume = 0;
+    }
+
+    /// Returns the amount of `token_id` that can still be withdrawn in the window containing `slot`
+    pub fn remaining_withdrawal(
+        &mut self,
+        rate_limits: &RateLimits,
+        token_id: TokenID,
+        slot: u64,
+    ) -> u64 {
+        if !rate_limits.is_withdrawal_limited(token_id) {
+            return u64::MAX;
+        }
+
+        self.update(rate_limits.window_slots, slot);
+        rate_limits.withdrawal_caps[token_id as usize].saturating_sub(self.withdrawal_volume)
+    }
+
+    /// Adds a deposit of `amount` to the window containing `slot`
+    pub fn add_deposit(
+        &mut self,
+        rate_limits: &RateLimits,
+        token_id: TokenID,
+        amount: u64,
+        slot: u64,
+    ) -> ProgramResult {
+        if !rate_limits.is_deposit_limited(token_id) {
+            return Ok(());
+        }
+
+        self.update(rate_limits.window_slots, slot);
+        guard!(
+            amount
+                <= rate_limits.deposit_caps[token_id as usize].saturating_sub(self.deposit_volume),
+            ElusivError::RateLimitExceeded
+        );
+        self.deposit_volume = self
+            .deposit_volume
+            .checked_add(amount)
+            .ok_or(TokenError::Overflow)?;
+
+        Ok(())
+    }
+
+    /// Adds a withdrawal of `amount` to the window containing `slot`
+    pub fn add_withdrawal(
+        &mut self,
+        rate_limits: &RateLimits,
+        token_id: TokenID,
+        amount: u64,
+        slot: u64,
+    ) -> ProgramResult {
+        if !rate_limits.is_withdrawal_limited(token_id) {
+            return Ok(());
+        }
+
+        guard!(
+            amount <= self.remaining_withdrawal(rate_limits, token_id, slot),
+            ElusivError::RateLimitExceeded
+        );
+        self.withdrawal_volume = self
+            .withdrawal_volume
+            .checked_add(amount)
+            .ok_or(TokenError::Overflow)?;
+
+        Ok(())
+    }
+}
+
+/// Tracks the [`RateLimitWindow`] of the token with the `token_id` used as `pda_offset`
+///
+/// # Note
+///
+/// Only supplied to (and locked by) deposits and withdrawals of a token with a configured cap.
+#[elusiv_account(eager_type: true)]
+pub struct RateLimitAccount {
+    #[no_getter]
+    #[no_setter]
+    pda_data: PDAAccountData,
+
+    pub window: RateLimitWindow,
+}
+
+/// Splits the optional leading [`RateLimitAccount`] of `token_id` off `accounts`
+///
+/// # Notes
+///
+/// - The [`RateLimitAccount`] is required if `is_limited`.
+/// - Without a cap, a supplied [`RateLimitAccount`] is skipped.
+pub fn split_rate_limit_account<'a, 'b, 'c>(
+    token_id: TokenID,
+    is_limited: bool,
+    accounts: &'a [&'b AccountInfo<'c>],
+) -> Result<(Option<&'b AccountInfo<'c>>, &'a [&'b AccountInfo<'c>]), ProgramError> {
+    match accounts.split_first() {
+        Some((account, remaining)) if is_rate_limit_account(account, token_id) => {
+            Ok((Some(account), remaining))
+        }
+        _ => {
+            guard!(!is_limited, ElusivError::InvalidAccount);
+            Ok((None, accounts))
+        }
+    }
+}
+
+fn is_rate_limit_account(account: &AccountInfo, token_id: TokenID) -> bool {
+    *account.owner == crate::ID
+        && account.data_len() == RateLimitAccount::SIZE
+        && RateLimitAccount::verify_account(account, Some(token_id.into())).is_ok()
 }
 
 #[elusiv_account(eager_type: true)]
@@ -32,3 +316,252 @@ pub struct FeeCollectorAccount {
     #[no_setter]
     pda_data: PDAAccountData,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macros::{account_info, program_account_info};
+    use elusiv_types::{ProgramAccount, VersionedAccount};
+    use solana_program::pubkey::Pubkey;
+
+    fn rate_limits_config(
+        window_slots: u64,
+        token_id: TokenID,
+        deposit_cap: u64,
+        withdrawal_cap: u64,
+    ) -> RateLimits {
+        let mut rate_limits = RateLimits {
+            window_slots,
+            ..Default::default()
+        };
+        rate_limits.deposit_caps[token_id as usize] = deposit_cap;
+        rate_limits.withdrawal_caps[token_id as usize] = withdrawal_cap;
+        rate_limits
+    }
+
+    #[test]
+    fn test_rate_limits_disabled() {
+        let mut window = RateLimitWindow::default();
+
+        // Disabled window
+        let rate_limits = rate_limits_config(0, 0, 1, 1);
+        assert!(!rate_limits.is_deposit_limited(0));
+        assert!(!rate_limits.is_withdrawal_limited(0));
+        assert_eq!(window.remaining_withdrawal(&rate_limits, 0, 0), u64::MAX);
+        window.add_deposit(&rate_limits, 0, u64::MAX, 0).unwrap();
+        window.add_withdrawal(&rate_limits, 0, u64::MAX, 0).unwrap();
+
+        // Uncapped token
+        let rate_limits = rate_limits_config(10, 1, 1, 1);
+        assert!(!rate_limits.is_deposit_limited(0));
+        assert!(rate_limits.is_deposit_limited(1));
+        window.add_deposit(&rate_limits, 0, u64::MAX, 0).unwrap();
+        window.add_withdrawal(&rate_limits, 0, u64::MAX, 0).unwrap();
+
+        assert_eq!(window, RateLimitWindow::default());
+    }
+
+    #[test]
+    fn test_rate_limits_deposit() {
+        let rate_limits = rate_limits_config(10, 1, 100, 0);
+        let mut window = RateLimitWindow::default();
+
+        window.add_deposit(&rate_limits, 1, 60, 0).unwrap();
+        window.add_deposit(&rate_limits, 1, 40, 9).unwrap();
+        assert_eq!(
+            window.add_deposit(&rate_limits, 1, 1, 9),
+            Err(ElusivError::RateLimitExceeded.into())
+        );
+
+        // Next window
+        window.add_deposit(&rate_limits, 1, 100, 10).unwrap();
+        assert_eq!(window.window_start, 10);
+        assert_eq!(window.deposit_volume, 100);
+    }
+
+    #[test]
+    fn test_rate_limits_withdrawal() {
+        let rate_limits = rate_limits_config(10, 0, 0, 100);
+        let mut window = RateLimitWindow {
+            window_slots: 10,
+            window_start: 5,
+            ..Default::default()
+        };
+
+        assert_eq!(window.remaining_withdrawal(&rate_limits, 0, 5), 100);
+        window.add_withdrawal(&rate_limits, 0, 70, 5).unwrap();
+        assert_eq!(window.remaining_withdrawal(&rate_limits, 0, 14), 30);
+        assert_eq!(
+            window.add_withdrawal(&rate_limits, 0, 31, 14),
+            Err(ElusivError::RateLimitExceeded.into())
+        );
+
+        // Skipped windows are aligned to `window_start`
+        assert_eq!(window.remaining_withdrawal(&rate_limits, 0, 37), 100);
+        assert_eq!(window.window_start, 35);
+        assert_eq!(window.withdrawal_volume, 0);
+    }
+
+    #[test]
+    fn test_rate_limits_window_slots_changed() {
+        let mut window = RateLimitWindow {
+            window_slots: 10,
+            window_start: 0,
+            deposit_volume: 1,
+            withdrawal_volume: 50,
+        };
+
+        // A new window starts at the current slot
+        let rate_limits = rate_limits_config(20, 0, 0, 100);
+        assert_eq!(window.remaining_withdrawal(&rate_limits, 0, 3), 100);
+        assert_eq!(
+            window,
+            RateLimitWindow {
+                window_slots: 20,
+                window_start: 3,
+                deposit_volume: 0,
+                withdrawal_volume: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_rate_limits_overflow() {
+        let rate_limits = rate_limits_config(10, 0, u64::MAX, u64::MAX);
+        let mut window = RateLimitWindow {
+            window_slots: 10,
+            deposit_volume: 1,
+            withdrawal_volume: 1,
+            ..Default::default()
+        };
+
+        // The cap guard is saturating, the volumes are checked
+        assert_eq!(
+            window.add_deposit(&rate_limits, 0, u64::MAX, 0),
+            Err(ElusivError::RateLimitExceeded.into())
+        );
+        window.deposit_volume = 0;
+        window.add_deposit(&rate_limits, 0, u64::MAX, 0).unwrap();
+        assert_eq!(
+            window.add_deposit(&rate_limits, 0, 1, 0),
+            Err(ElusivError::RateLimitExceeded.into())
+        );
+
+        window.withdrawal_volume = 0;
+        window.add_withdrawal(&rate_limits, 0, u64::MAX, 0).unwrap();
+        assert_eq!(window.remaining_withdrawal(&rate_limits, 0, 0), 0);
+    }
+
+    #[test]
+    fn test_split_rate_limit_account() {
+        program_account_info!(rate_limit_account, RateLimitAccount, Some(1));
+        program_account_info!(other_token_account, RateLimitAccount, Some(0));
+        let pk = *rate_limit_account.key;
+        account_info!(
+            foreign_account,
+            pk,
+            vec![0; RateLimitAccount::SIZE],
+            Pubkey::new_unique(),
+            false
+        );
+        account_info!(account, Pubkey::new_unique());
+
+        // Required
+        let accounts = [&rate_limit_account, &account];
+        let (rate_limit, remaining) = split_rate_limit_account(1, true, &accounts).unwrap();
+        assert_eq!(rate_limit.unwrap().key, &pk);
+        assert_eq!(remaining.len(), 1);
+
+        for accounts in [
+            &[][..],
+            &[&account],
+            &[&other_token_account],
+            &[&foreign_account],
+            &[&account, &rate_limit_account],
+        ] {
+            assert_eq!(
+                split_rate_limit_account(1, true, accounts).unwrap_err(),
+                ElusivError::InvalidAccount.into()
+            );
+        }
+
+        // Optional
+        let accounts = [&rate_limit_account];
+        let (rate_limit, remaining) = split_rate_limit_account(1, false, &accounts).unwrap();
+        assert!(rate_limit.is_some());
+        assert!(remaining.is_empty());
+
+        let accounts = [&account];
+        let (rate_limit, remaining) = split_rate_limit_account(1, false, &accounts).unwrap();
+        assert!(rate_limit.is_none());
+        assert_eq!(remaining.len(), 1);
+    }
+
+    #[test]
+    fn test_governor_account_sizes() {
+        assert_eq!(
+            GovernorAccount::size_at_version(3).unwrap() - LEGACY_RATE_LIMITS_SIZE
+                + RateLimits::SIZE,
+            GovernorAccount::SIZE
+        );
+        assert_eq!(
+            GovernorAccount::size_at_version(2).unwrap() + RootHistoryParameters::SIZE,
+            GovernorAccount::size_at_version(3).unwrap()
+        );
+        assert_eq!(
+            GovernorAccount::size_at_version(1).unwrap() + LEGACY_RATE_LIMITS_SIZE,
+            GovernorAccount::size_at_version(2).unwrap()
+        );
+        assert_eq!(
+            GovernorAccount::size_at_version(0).unwrap(),
+            GovernorAccount::size_at_version(1).unwrap() + 4
+        );
+        assert_eq!(GovernorAccount::size_at_version(5), None);
+    }
+
+    #[test]
+    fn test_governor_account_migrate_root_history() {
+        let mut data = vec![0; GovernorAccount::size_at_version(3).unwrap()];
+        GovernorAccount::migrate_from(2, &mut data).unwrap();
+
+        let offset = data.len() - RootHistoryParameters::SIZE;
+        assert_eq!(
+            RootHistoryParameters::try_from_slice(&data[offset..]).unwrap(),
+            RootHistoryParameters::default()
+        );
+    }
+
+    #[test]
+    fn test_governor_account_migrate_rate_limits() {
+        let root_history = RootHistoryParameters {
+            depth: 3,
+            slot_window: 4,
+        };
+
+        // Version 3 layout
+        let mut data = vec![0; GovernorAccount::size_at_version(3).unwrap()];
+        let mut offset = GovernorAccount::size_at_version(1).unwrap();
+        let mut legacy_rate_limits = vec![100, 5];
+        legacy_rate_limits.extend((0..TOKENS_COUNT).map(|i| i as u64 + 1));
+        legacy_rate_limits.extend((0..TOKENS_COUNT).map(|i| i as u64 + 10));
+        legacy_rate_limits.extend([u64::MAX; 2 * TOKENS_COUNT]);
+        for value in legacy_rate_limits {
+            data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            offset += 8;
+        }
+        data[offset..].copy_from_slice(&root_history.try_to_vec().unwrap());
+
+        GovernorAccount::migrate_from(3, &mut data).unwrap();
+        data.truncate(GovernorAccount::SIZE);
+        data[PDAAccountData::VERSION_OFFSET] = GovernorAccount::VERSION;
+
+        let governor = GovernorAccount::new(&mut data).unwrap();
+        let rate_limits = governor.get_rate_limits();
+        assert_eq!(rate_limits.window_slots, 100);
+        for i in 0..TOKENS_COUNT {
+            assert_eq!(rate_limits.deposit_caps[i], i as u64 + 1);
+            assert_eq!(rate_limits.withdrawal_caps[i], i as u64 + 10);
+        }
+        assert_eq!(governor.get_root_history(), root_history);
+    }
+}
@@ -1,10 +1,17 @@
 use super::{commitment::COMMITMENT_QUEUE_LEN, queue::queue_account};
+#[cfg(feature = "elusiv-client")]
+use super::{queue::RingQueue, storage::StorageAccount};
 use crate::commitment::MT_HEIGHT;
+use crate::error::ElusivError;
+use crate::macros::guard;
+#[cfg(feature = "elusiv-client")]
+use crate::processor::CommitmentHashRequest;
+use crate::types::U256;
 use elusiv_proc_macros::elusiv_account;
 use elusiv_types::{
     accounts::PDAAccountData, BorshSerDeSized, ChildAccount, ElusivOption, ParentAccount,
 };
-use elusiv_utils::two_pow;
+use elusiv_utils::{two_pow, MATH_ERR};
 use solana_program::{entrypoint::ProgramResult, pubkey::Pubkey};
 
 pub type CommitmentMetadata = [u8; 17];
@@ -16,18 +23,41 @@ queue_account!(
     CommitmentMetadata,
 );
 
-const VALUES_PER_METADATA_CHILD_ACCOUNT: usize = two_pow!(16);
-const ACCOUNTS_COUNT: usize = two_pow!(MT_HEIGHT as u32) / VALUES_PER_METADATA_CHILD_ACCOUNT;
+const METADATA_COUNT: usize = two_pow!(MT_HEIGHT as u32);
+const MAX_VALUES_PER_METADATA_CHILD_ACCOUNT: usize = two_pow!(16);
+const VALUES_PER_METADATA_CHILD_ACCOUNT: usize =
+    if METADATA_COUNT < MAX_VALUES_PER_METADATA_CHILD_ACCOUNT {
+        METADATA_COUNT
+    } else {
+        MAX_VALUES_PER_METADATA_CHILD_ACCOUNT
+    };
+const ACCOUNTS_COUNT: usize = METADATA_COUNT / VALUES_PER_METADATA_CHILD_ACCOUNT;
 
-#[cfg(test)]
+#[cfg(all(test, not(feature = "small-mt")))]
 const_assert_eq!(ACCOUNTS_COUNT, 16);
 
+#[cfg(all(test, feature = "small-mt"))]
+const_assert_eq!(ACCOUNTS_COUNT, 1);
+
 pub struct MetadataChildAccount;
 
 impl ChildAccount for MetadataChildAccount {
     const INNER_SIZE: usize = VALUES_PER_METADATA_CHILD_ACCOUNT * CommitmentMetadata::SIZE;
 }
 
+/// A [`CommitmentMetadata`] together with its commitment
+#[cfg(feature = "elusiv-client")]
+#[derive(Debug, PartialEq, Clone)]
+pub struct CommitmentMetadataEntry {
+    /// Index of the commitment in the active MT
+    pub commitment_index: usize,
+
+    /// `None` if the commitment is currently being hashed (neither inserted into the [`StorageAccount`] nor queued)
+    pub commitment: Option<U256>,
+
+    pub metadata: CommitmentMetadata,
+}
+
 #[elusiv_account(parent_account: { child_account_count: ACCOUNTS_COUNT, child_account: MetadataChildAccount }, eager_type: true)]
 pub struct MetadataAccount {
     #[no_getter]
@@ -39,6 +69,27 @@ pub struct MetadataAccount {
 }
 
 impl<'a, 'b, 't> MetadataAccount<'a, 'b, 't> {
+    /// Verifies that the next `count` [`CommitmentMetadata`] can be added (without modifying the account)
+    pub fn can_add_commitment_metadata(&self, count: usize) -> ProgramResult {
+        let metadata_index = self.get_next_metadata_ptr() as usize;
+        guard!(
+            metadata_index + count <= METADATA_COUNT,
+            ElusivError::NoRoomForCommitment
+        );
+
+        if count == 0 {
+            return Ok(());
+        }
+
+        let (first_child_index, _) = Self::child_account_and_local_index(metadata_index);
+        let (last_child_index, _) = Self::child_account_and_local_index(metadata_index + count - 1);
+        for child_index in first_child_index..=last_child_index {
+            self.execute_on_child_account(child_index, |_| ())?;
+        }
+
+        Ok(())
+    }
+
     pub fn add_commitment_metadata(&mut self, metadata: &CommitmentMetadata) -> ProgramResult {
         let metadata_index = self.get_next_metadata_ptr() as usize;
         let (child_index, index) = Self::child_account_and_local_index(metadata_index);
@@ -59,8 +110,6 @@ impl<'a, 'b, 't> MetadataAccount<'a, 'b, 't> {
         &self,
         index: usize,
     ) -> Result<CommitmentMetadata, solana_program::program_error::ProgramError> {
-        use crate::error::ElusivError;
-
         let metadata_index = self.get_next_metadata_ptr() as usize;
         crate::macros::guard!(index < metadata_index, ElusivError::MissingValue);
 
@@ -74,6 +123,92 @@ impl<'a, 'b, 't> MetadataAccount<'a, 'b, 't> {
         })
     }
 
+    /// Scans the metadata of the commitments in `range` and returns all entries accepted by `predicate`
+    ///
+    /// # Note
+    ///
+    /// - `predicate` is intended to be a trial-decryption of the metadata.
+    /// - Metadata that is still pending in the `metadata_queue` is included (following the stored metadata).
+    /// - Pending metadata is paired with the commitment at the same position in the `commitment_queue`.
+    #[cfg(feature = "elusiv-client")]
+    pub fn scan_commitment_metadata<Q, C, F>(
+        &self,
+        storage_account: &StorageAccount,
+        metadata_queue: &Q,
+        commitment_queue: &C,
+        range: std::ops::Range<usize>,
+        mut predicate: F,
+    ) -> Result<Vec<CommitmentMetadataEntry>, solana_program::program_error::ProgramError>
+    where
+        Q: RingQueue<N = CommitmentMetadata>,
+        C: RingQueue<N = CommitmentHashRequest>,
+        F: FnMut(&CommitmentMetadataEntry) -> bool,
+    {
+        let stored_count = self.get_next_metadata_ptr() as usize;
+        let pending_count = metadata_queue.len() as usize;
+        let end = std::cmp::min(range.end, stored_count + pending_count);
+        let stored_end = std::cmp::min(end, stored_count);
+
+        let mut metadata = Vec::new();
+        let mut index = range.start;
+
+        // Stored metadata is read in chunks, each chunk being contained in a single child-account
+        while index < stored_end {
+            let (child_index, local_index) = Self::child_account_and_local_index(index);
+            let chunk_end = std::cmp::min(
+                stored_end,
+                (child_index + 1) * VALUES_PER_METADATA_CHILD_ACCOUNT,
+            );
+            let chunk_len = chunk_end - index;
+
+            self.execute_on_child_account(child_index, |data| {
+                let offset = local_index * CommitmentMetadata::SIZE;
+                let chunk = &data[offset..offset + chunk_len * CommitmentMetadata::SIZE];
+                metadata.extend(
+                    chunk
+                        .chunks(CommitmentMetadata::SIZE)
+                        .map(|m| CommitmentMetadata::try_from(m).unwrap()),
+                );
+            })?;
+
+            index = chunk_end;
+        }
+
+        while index < end {
+            metadata.push(metadata_queue.view(index - stored_count)?);
+            index += 1;
+        }
+
+        let inserted_count = storage_account.get_next_commitment_ptr() as usize;
+        let mut entries = Vec::new();
+        for (i, metadata) in metadata.into_iter().enumerate() {
+            let commitment_index = range.start + i;
+            let commitment = if commitment_index < inserted_count {
+                Some(storage_account.get_node(commitment_index, MT_HEIGHT)?)
+            } else if commitment_index >= stored_count {
+                Some(
+                    commitment_queue
+                        .view(commitment_index - stored_count)?
+                        .commitment,
+                )
+            } else {
+                None
+            };
+
+            let entry = CommitmentMetadataEntry {
+                commitment_index,
+                commitment,
+                metadata,
+            };
+
+            if predicate(&entry) {
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+
     fn child_account_and_local_index(metadata_index: usize) -> (usize, usize) {
         let child_index = metadata_index / VALUES_PER_METADATA_CHILD_ACCOUNT;
         let index = metadata_index % VALUES_PER_METADATA_CHILD_ACCOUNT;
@@ -82,10 +217,126 @@ impl<'a, 'b, 't> MetadataAccount<'a, 'b, 't> {
     }
 }
 
+/// Maximum length of the encrypted metadata that can be attached to a single commitment
+pub const MAX_ENCRYPTED_METADATA_LEN: usize = 256;
+
+/// Commitment, length prefix and the (zero-padded) encrypted metadata
+const ENCRYPTED_METADATA_ENTRY_SIZE: usize = U256::SIZE + 2 + MAX_ENCRYPTED_METADATA_LEN;
+
+/// Number of the latest encrypted metadata entries available for scanning
+pub const ENCRYPTED_METADATA_COUNT: usize = two_pow!(15);
+const ENTRIES_PER_ENCRYPTED_METADATA_CHILD_ACCOUNT: usize = two_pow!(12);
+const ENCRYPTED_METADATA_ACCOUNTS_COUNT: usize =
+    ENCRYPTED_METADATA_COUNT / ENTRIES_PER_ENCRYPTED_METADATA_CHILD_ACCOUNT;
+
+#[cfg(test)]
+const_assert_eq!(ENCRYPTED_METADATA_ACCOUNTS_COUNT, 8);
+
+pub struct EncryptedMetadataChildAccount;
+
+impl ChildAccount for EncryptedMetadataChildAccount {
+    const INNER_SIZE: usize =
+        ENTRIES_PER_ENCRYPTED_METADATA_CHILD_ACCOUNT * ENCRYPTED_METADATA_ENTRY_SIZE;
+}
+
+/// Stores the variable-length encrypted metadata of commitments, which allows recipients to discover incoming funds by scanning on-chain
+///
+/// # Note
+///
+/// - The entries are stored in a ring, so only the latest [`ENCRYPTED_METADATA_COUNT`] entries are available.
+/// - For sends the encrypted metadata is bound to the `hashed_inputs`, for deposits to the [`crate::processor::BaseCommitmentHashRequest`].
+#[elusiv_account(parent_account: { child_account_count: ENCRYPTED_METADATA_ACCOUNTS_COUNT, child_account: EncryptedMetadataChildAccount }, eager_type: true)]
+pub struct EncryptedMetadataAccount {
+    #[no_getter]
+    #[no_setter]
+    pda_data: PDAAccountData,
+    pubkeys: [ElusivOption<Pubkey>; ENCRYPTED_METADATA_ACCOUNTS_COUNT],
+
+    /// The amount of all entries ever added
+    pub entries_count: u32,
+}
+
+impl<'a, 'b, 't> EncryptedMetadataAccount<'a, 'b, 't> {
+    pub fn add_encrypted_metadata(
+        &mut self,
+        commitment: &U256,
+        encrypted_metadata: &[u8],
+    ) -> ProgramResult {
+        let len = encrypted_metadata.len();
+        guard!(
+            len <= MAX_ENCRYPTED_METADATA_LEN,
+            ElusivError::EncryptedMetadataTooLong
+        );
+
+        let entries_count = self.get_entries_count();
+        let (child_index, index) = Self::child_account_and_local_index(entries_count as usize);
+
+        self.execute_on_child_account_mut(child_index, |data| {
+            let offset = index * ENCRYPTED_METADATA_ENTRY_SIZE;
+            let entry = &mut data[offset..offset + ENCRYPTED_METADATA_ENTRY_SIZE];
+            entry[..U256::SIZE].copy_from_slice(commitment);
+            entry[U256::SIZE..U256::SIZE + 2].copy_from_slice(&(len as u16).to_le_bytes());
+
+            let encrypted_metadata_slice = &mut entry[U256::SIZE + 2..];
+            encrypted_metadata_slice[..len].copy_from_slice(encrypted_metadata);
+            encrypted_metadata_slice[len..].fill(0);
+        })?;
+
+        self.set_entries_count(&(entries_count.checked_add(1).ok_or(MATH_ERR)?));
+
+        Ok(())
+    }
+
+    /// Returns the index of the child-account the next entry is added to
+    #[cfg(feature = "elusiv-client")]
+    pub fn next_entry_child_index(&self) -> usize {
+        Self::child_account_and_local_index(self.get_entries_count() as usize).0
+    }
+
+    /// Returns the commitment and its encrypted metadata of the entry with the index `entry_index`
+    #[cfg(feature = "elusiv-client")]
+    pub fn get_encrypted_metadata(
+        &self,
+        entry_index: usize,
+    ) -> Result<(U256, Vec<u8>), solana_program::program_error::ProgramError> {
+        let entries_count = self.get_entries_count() as usize;
+        guard!(entry_index < entries_count, ElusivError::MissingValue);
+        guard!(
+            entry_index + ENCRYPTED_METADATA_COUNT >= entries_count,
+            ElusivError::MissingValue
+        );
+
+        let (child_index, index) = Self::child_account_and_local_index(entry_index);
+
+        self.execute_on_child_account(child_index, |data| {
+            let offset = index * ENCRYPTED_METADATA_ENTRY_SIZE;
+            let entry = &data[offset..offset + ENCRYPTED_METADATA_ENTRY_SIZE];
+            let commitment: U256 = entry[..U256::SIZE].try_into().unwrap();
+            let len =
+                u16::from_le_bytes(entry[U256::SIZE..U256::SIZE + 2].try_into().unwrap()) as usize;
+
+            (
+                commitment,
+                entry[U256::SIZE + 2..U256::SIZE + 2 + len].to_vec(),
+            )
+        })
+    }
+
+    fn child_account_and_local_index(entry_index: usize) -> (usize, usize) {
+        let entry_index = entry_index % ENCRYPTED_METADATA_COUNT;
+        let child_index = entry_index / ENTRIES_PER_ENCRYPTED_METADATA_CHILD_ACCOUNT;
+        let index = entry_index % ENTRIES_PER_ENCRYPTED_METADATA_CHILD_ACCOUNT;
+
+        (child_index, index)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::macros::parent_account;
+    use crate::macros::{parent_account, zero_program_account};
+    use crate::state::commitment::{CommitmentQueue, CommitmentQueueAccount};
+    use crate::state::queue::{Queue, RingQueue};
 
     fn usize_to_metadata(u: usize) -> CommitmentMetadata {
         let mut metadata = [0; CommitmentMetadata::SIZE];
@@ -112,4 +363,176 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn test_add_encrypted_metadata() {
+        parent_account!(mut encrypted_metadata_account, EncryptedMetadataAccount);
+
+        assert_eq!(
+            encrypted_metadata_account.get_encrypted_metadata(0),
+            Err(ElusivError::MissingValue.into())
+        );
+
+        for i in 0..=MAX_ENCRYPTED_METADATA_LEN {
+            encrypted_metadata_account
+                .add_encrypted_metadata(&[i as u8; 32], &vec![i as u8; i])
+                .unwrap();
+        }
+
+        assert_eq!(
+            encrypted_metadata_account.get_entries_count() as usize,
+            MAX_ENCRYPTED_METADATA_LEN + 1
+        );
+        for i in 0..=MAX_ENCRYPTED_METADATA_LEN {
+            assert_eq!(
+                encrypted_metadata_account
+                    .get_encrypted_metadata(i)
+                    .unwrap(),
+                ([i as u8; 32], vec![i as u8; i])
+            );
+        }
+
+        // Exceeding the maximum length
+        assert_eq!(
+            encrypted_metadata_account
+                .add_encrypted_metadata(&[0; 32], &[0; MAX_ENCRYPTED_METADATA_LEN + 1]),
+            Err(ElusivError::EncryptedMetadataTooLong.into())
+        );
+    }
+
+    #[test]
+    fn test_add_encrypted_metadata_overwrites_oldest_entry() {
+        parent_account!(mut encrypted_metadata_account, EncryptedMetadataAccount);
+
+        encrypted_metadata_account.set_entries_count(&(ENCRYPTED_METADATA_COUNT as u32 - 1));
+        assert_eq!(
+            encrypted_metadata_account.next_entry_child_index(),
+            ENCRYPTED_METADATA_ACCOUNTS_COUNT - 1
+        );
+        encrypted_metadata_account
+            .add_encrypted_metadata(&[1; 32], &[1, 2, 3])
+            .unwrap();
+        assert_eq!(encrypted_metadata_account.next_entry_child_index(), 0);
+        encrypted_metadata_account
+            .add_encrypted_metadata(&[2; 32], &[4])
+            .unwrap();
+
+        assert_eq!(
+            encrypted_metadata_account.get_encrypted_metadata(ENCRYPTED_METADATA_COUNT - 1),
+            Ok(([1; 32], vec![1, 2, 3]))
+        );
+        assert_eq!(
+            encrypted_metadata_account.get_encrypted_metadata(ENCRYPTED_METADATA_COUNT),
+            Ok(([2; 32], vec![4]))
+        );
+
+        // The first entry has been overwritten
+        assert_eq!(
+            encrypted_metadata_account.get_encrypted_metadata(0),
+            Err(ElusivError::MissingValue.into())
+        );
+    }
+
+    #[test]
+    fn test_scan_commitment_metadata() {
+        parent_account!(mut metadata_account, MetadataAccount);
+        parent_account!(mut storage_account, StorageAccount);
+        zero_program_account!(mut metadata_queue, MetadataQueueAccount);
+        let mut metadata_queue = MetadataQueue::new(&mut metadata_queue);
+        zero_program_account!(mut commitment_queue, CommitmentQueueAccount);
+        let mut commitment_queue = CommitmentQueue::new(&mut commitment_queue);
+
+        // The stored metadata spans a child-account boundary (if there are multiple child-accounts)
+        let start = if ACCOUNTS_COUNT > 1 {
+            VALUES_PER_METADATA_CHILD_ACCOUNT - 2
+        } else {
+            METADATA_COUNT - 8
+        };
+        metadata_account.set_next_metadata_ptr(&(start as u32));
+        for i in start..start + 4 {
+            metadata_account
+                .add_commitment_metadata(&usize_to_metadata(i))
+                .unwrap();
+        }
+        for i in start + 4..start + 6 {
+            metadata_queue.enqueue(usize_to_metadata(i)).unwrap();
+            commitment_queue
+                .enqueue(CommitmentHashRequest {
+                    commitment: [i as u8; 32],
+                    fee_version: 0,
+                    min_batching_rate: 0,
+                })
+                .unwrap();
+        }
+
+        // Only the first two commitments are inserted
+        for i in start..start + 2 {
+            storage_account
+                .set_node(&[i as u8; 32], i, MT_HEIGHT)
+                .unwrap();
+        }
+        storage_account.set_next_commitment_ptr(&(start as u32 + 2));
+
+        let all = metadata_account
+            .scan_commitment_metadata(
+                &storage_account,
+                &metadata_queue,
+                &commitment_queue,
+                start..usize::MAX,
+                |_| true,
+            )
+            .unwrap();
+        assert_eq!(all.len(), 6);
+        for (i, entry) in all.iter().enumerate() {
+            let commitment_index = start + i;
+            assert_eq!(entry.commitment_index, commitment_index);
+            assert_eq!(entry.metadata, usize_to_metadata(commitment_index));
+
+            // Inserted and queued commitments are known, the commitments currently being hashed are not
+            assert_eq!(
+                entry.commitment,
+                if !(2..4).contains(&i) {
+                    Some([commitment_index as u8; 32])
+                } else {
+                    None
+                }
+            );
+        }
+
+        // Sub-range starting inside the stored metadata and ending inside the queue
+        let entries = metadata_account
+            .scan_commitment_metadata(
+                &storage_account,
+                &metadata_queue,
+                &commitment_queue,
+                start + 1..start + 5,
+                |_| true,
+            )
+            .unwrap();
+        assert_eq!(entries, all[1..5]);
+
+        // Predicate
+        let entries = metadata_account
+            .scan_commitment_metadata(
+                &storage_account,
+                &metadata_queue,
+                &commitment_queue,
+                start..start + 6,
+                |e| e.metadata == usize_to_metadata(start + 3),
+            )
+            .unwrap();
+        assert_eq!(entries, vec![all[3].clone()]);
+
+        // Empty range
+        assert!(metadata_account
+            .scan_commitment_metadata(
+                &storage_account,
+                &metadata_queue,
+                &commitment_queue,
+                start + 6..start + 10,
+                |_| true
+            )
+            .unwrap()
+            .is_empty());
+    }
 }
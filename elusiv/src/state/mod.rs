@@ -1,3 +1,4 @@
+pub mod apa;
 pub mod commitment;
 pub mod fee;
 pub mod governor;
@@ -16,12 +16,20 @@ const NULLIFIERS_COUNT: usize = two_pow!(MT_HEIGHT);
 /// We store nullifiers with the `NullifierMap` data structure for efficient searching and later N-SMT construction
 pub type NullifierMap<'a> = ElusivSet<'a, OrdU256, NULLIFIERS_PER_ACCOUNT>;
 
-pub const NULLIFIERS_PER_ACCOUNT: usize = two_pow!(16);
+const MAX_NULLIFIERS_PER_ACCOUNT: usize = two_pow!(16);
+pub const NULLIFIERS_PER_ACCOUNT: usize = if NULLIFIERS_COUNT < MAX_NULLIFIERS_PER_ACCOUNT {
+    NULLIFIERS_COUNT
+} else {
+    MAX_NULLIFIERS_PER_ACCOUNT
+};
 const ACCOUNTS_COUNT: usize = div_ceiling_usize(NULLIFIERS_COUNT, NULLIFIERS_PER_ACCOUNT);
 
-#[cfg(test)]
+#[cfg(all(test, not(feature = "small-mt")))]
 const_assert_eq!(ACCOUNTS_COUNT, 16);
 
+#[cfg(all(test, feature = "small-mt"))]
+const_assert_eq!(ACCOUNTS_COUNT, 1);
+
 pub struct NullifierChildAccount;
 
 impl ChildAccount for NullifierChildAccount {
@@ -61,6 +69,20 @@ pub struct ArchivedNullifierAccount {
     nullifier_root: U256,
 }
 
+/// The neighbouring nullifier-hashes around a nullifier-hash that is not contained in a [`NullifierAccount`]
+#[derive(PartialEq, Clone)]
+#[cfg_attr(any(test, feature = "elusiv-client"), derive(Debug))]
+pub struct NullifierNeighbours {
+    /// Index of the child account the nullifier-hash would be inserted into
+    pub child_index: usize,
+
+    /// The largest nullifier-hash smaller than the queried one
+    pub predecessor: Option<U256>,
+
+    /// The smallest nullifier-hash larger than the queried one
+    pub successor: Option<U256>,
+}
+
 impl<'a, 'b, 'c> NullifierAccount<'a, 'b, 'c> {
     pub fn can_insert_nullifier_hash(&self, nullifier_hash: U256) -> Result<bool, ProgramError> {
         let count = self.get_nullifier_hash_count();
@@ -88,6 +110,62 @@ impl<'a, 'b, 'c> NullifierAccount<'a, 'b, 'c> {
         Ok(!contains)
     }
 
+    /// Returns the neighbouring nullifier-hashes of a nullifier-hash (across all child accounts)
+    ///
+    /// # Return
+    ///
+    /// Returns [`None`] if the nullifier-hash is already contained (or is a moved value).
+    pub fn nullifier_hash_neighbours(
+        &self,
+        nullifier_hash: &U256,
+    ) -> Result<Option<NullifierNeighbours>, ProgramError> {
+        let child_index = std::cmp::min(
+            self.find_child_account_index(nullifier_hash),
+            ACCOUNTS_COUNT - 1,
+        );
+        let nullifier_hash = OrdU256(*nullifier_hash);
+
+        let moved_values = self.get_all_moved_values();
+        if moved_values
+            .iter()
+            .any(|(value, _)| *value == nullifier_hash)
+        {
+            return Ok(None);
+        }
+
+        let neighbours = self.execute_on_child_account_mut(child_index, |data| {
+            let mut map = NullifierMap::new(data);
+            map.neighbours(&nullifier_hash).ok()
+        })?;
+        let neighbours = match neighbours {
+            Some(neighbours) => neighbours,
+            None => return Ok(None),
+        };
+
+        // The predecessor of a map's min value is the max value of the previous map
+        let mut predecessor = match neighbours.predecessor {
+            Some(predecessor) => Some(predecessor),
+            None if child_index > 0 => self.get_max_values(child_index - 1).option().map(OrdU256),
+            None => None,
+        };
+        let mut successor = neighbours.successor;
+
+        // Moved values are located between two maps
+        for (value, _) in moved_values {
+            if value < nullifier_hash && predecessor.is_none_or(|p| p < value) {
+                predecessor = Some(value);
+            } else if value > nullifier_hash && successor.is_none_or(|s| value < s) {
+                successor = Some(value);
+            }
+        }
+
+        Ok(Some(NullifierNeighbours {
+            child_index,
+            predecessor: predecessor.map(|p| p.0),
+            successor: successor.map(|s| s.0),
+        }))
+    }
+
     pub fn try_insert_nullifier_hash(&mut self, nullifier_hash: U256) -> ProgramResult {
         let count = self.get_nullifier_hash_count();
         guard!(
@@ -258,6 +336,51 @@ impl<'a, 'b, 'c> NullifierAccount<'a, 'b, 'c> {
     }
 }
 
+/// Checks that `neighbours` are consistent with the absence of `nullifier_hash` in a [`NullifierAccount`] with the provided `max_values`
+///
+/// # Note
+///
+/// The neighbours themselves need to be verified against the child account data (or a N-SMT root).
+#[cfg(feature = "elusiv-client")]
+pub fn verify_nullifier_hash_absence(
+    nullifier_hash: &U256,
+    neighbours: &NullifierNeighbours,
+    max_values: &[Option<U256>],
+) -> bool {
+    let nullifier_hash = OrdU256(*nullifier_hash);
+    let child_index = neighbours.child_index;
+    let predecessor = neighbours.predecessor.map(OrdU256);
+    let successor = neighbours.successor.map(OrdU256);
+
+    if child_index >= max_values.len() {
+        return false;
+    }
+
+    if predecessor.is_some_and(|p| p >= nullifier_hash)
+        || successor.is_some_and(|s| s <= nullifier_hash)
+    {
+        return false;
+    }
+
+    // All previous maps only contain smaller values
+    if let Some(max) = max_values[..child_index].iter().rev().flatten().next() {
+        if OrdU256(*max) >= nullifier_hash || predecessor.is_none_or(|p| p < OrdU256(*max)) {
+            return false;
+        }
+    }
+
+    // The successor can only be missing if no larger value exists
+    match max_values[child_index] {
+        Some(max) if nullifier_hash < OrdU256(max) => successor.is_some(),
+        Some(max) => {
+            OrdU256(max) < nullifier_hash
+                && max_values[child_index + 1..].iter().all(|v| v.is_none())
+                && predecessor.is_some_and(|p| p >= OrdU256(max))
+        }
+        None => max_values[child_index + 1..].iter().all(|v| v.is_none()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -450,6 +573,128 @@ mod tests {
         assert_eq!(nullifier_account.find_child_account_index(&[0; 32]), 0);
     }
 
+    #[test]
+    fn test_nullifier_hash_neighbours() {
+        parent_account!(mut nullifier_account, NullifierAccount);
+        let count = NULLIFIERS_PER_ACCOUNT as u64;
+
+        macro_rules! max_values {
+            () => {
+                (0..ACCOUNTS_COUNT)
+                    .map(|i| nullifier_account.get_max_values(i).option())
+                    .collect::<Vec<_>>()
+            };
+        }
+
+        // Empty account
+        let a = u64_to_u256_skip_mr(1);
+        let neighbours = nullifier_account
+            .nullifier_hash_neighbours(&a)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            neighbours,
+            NullifierNeighbours {
+                child_index: 0,
+                predecessor: None,
+                successor: None
+            }
+        );
+        assert!(verify_nullifier_hash_absence(
+            &a,
+            &neighbours,
+            &max_values!()
+        ));
+
+        for i in 0..count {
+            nullifier_account
+                .try_insert_nullifier_hash(u64_to_u256_skip_mr(i * 2))
+                .unwrap();
+        }
+
+        // Contained value
+        assert!(nullifier_account
+            .nullifier_hash_neighbours(&u64_to_u256_skip_mr(2))
+            .unwrap()
+            .is_none());
+
+        // Value inside the first map
+        let a = u64_to_u256_skip_mr(3);
+        let neighbours = nullifier_account
+            .nullifier_hash_neighbours(&a)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            neighbours,
+            NullifierNeighbours {
+                child_index: 0,
+                predecessor: Some(u64_to_u256_skip_mr(2)),
+                successor: Some(u64_to_u256_skip_mr(4))
+            }
+        );
+        assert!(verify_nullifier_hash_absence(
+            &a,
+            &neighbours,
+            &max_values!()
+        ));
+
+        // Invalid neighbours
+        let mut invalid = neighbours.clone();
+        invalid.successor = None;
+        assert!(!verify_nullifier_hash_absence(&a, &invalid, &max_values!()));
+
+        let mut invalid = neighbours.clone();
+        invalid.child_index = 1;
+        assert!(!verify_nullifier_hash_absence(&a, &invalid, &max_values!()));
+
+        let mut invalid = neighbours;
+        invalid.predecessor = Some(a);
+        assert!(!verify_nullifier_hash_absence(&a, &invalid, &max_values!()));
+
+        // Value larger than all values (predecessor is the max of the first map)
+        let a = u64_to_u256_skip_mr(count * 2);
+        let neighbours = nullifier_account
+            .nullifier_hash_neighbours(&a)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            neighbours,
+            NullifierNeighbours {
+                child_index: 1,
+                predecessor: Some(u64_to_u256_skip_mr(count * 2 - 2)),
+                successor: None
+            }
+        );
+        assert!(verify_nullifier_hash_absence(
+            &a,
+            &neighbours,
+            &max_values!()
+        ));
+
+        let mut invalid = neighbours;
+        invalid.predecessor = None;
+        assert!(!verify_nullifier_hash_absence(&a, &invalid, &max_values!()));
+
+        // Moved values are taken into account
+        let moved_value = u64_to_u256_skip_mr(count * 2 + 1);
+        nullifier_account.set_all_moved_values(&[(OrdU256(moved_value), 1)]);
+        assert!(nullifier_account
+            .nullifier_hash_neighbours(&moved_value)
+            .unwrap()
+            .is_none());
+
+        let neighbours = nullifier_account
+            .nullifier_hash_neighbours(&a)
+            .unwrap()
+            .unwrap();
+        assert_eq!(neighbours.successor, Some(moved_value));
+        assert!(verify_nullifier_hash_absence(
+            &a,
+            &neighbours,
+            &max_values!()
+        ));
+    }
+
     #[test]
     fn test_set_all_moved_values() {
         parent_account!(mut nullifier_account, NullifierAccount);
@@ -79,7 +79,11 @@ mod tests {
     }
 
     impl<'a, 'b, 't> SizedAccount for TestParentAccount<'a, 'b, 't> {
-        const SIZE: usize = 0;
+        const SIZE: usize = PDAAccountData::SIZE;
+    }
+
+    impl<'a, 'b, 't> VersionedAccount for TestParentAccount<'a, 'b, 't> {
+        const VERSION: u8 = 0;
     }
 
     impl<'a, 'b, 't> ProgramAccount<'a> for TestParentAccount<'a, 'b, 't> {
@@ -1,24 +1,42 @@
 use crate::bytes::{
-    usize_as_u32_safe, BorshSerDeSized, BorshSerDeSizedEnum, ElusivOption, SizedType,
+    max, usize_as_u32_safe, BorshSerDeSized, BorshSerDeSizedEnum, ElusivOption, SizedType,
 };
 use crate::fields::{G2HomProjective, Wrap, G1A, G2A};
 use crate::processor::{ProofRequest, MAX_MT_COUNT};
-use crate::proof::verifier::VerificationStep;
-use crate::state::program_account::PDAAccountData;
+use crate::proof::verifier::{
+    VerificationStep, BATCH_ACCUMULATION_RAM_FQ_SIZE, COMBINED_MILLER_LOOP_RAM_FQ12_SIZE,
+    COMBINED_MILLER_LOOP_RAM_FQ2_SIZE, COMBINED_MILLER_LOOP_RAM_FQ6_SIZE,
+    FINAL_EXPONENTIATION_RAM_FQ12_SIZE, FINAL_EXPONENTIATION_RAM_FQ6_SIZE,
+    PREPARE_PUBLIC_INPUTS_RAM_FQ_SIZE,
+};
+use crate::state::program_account::{MigratableAccount, PDAAccountData, SizedAccount};
 use crate::token::Lamports;
-use crate::types::{Lazy, LazyField, RawU256, U256};
+use crate::types::{Lazy, LazyArray, LazyField, RawU256, U256};
 use ark_bn254::{Fq, Fq12, Fq2, Fq6};
 use borsh::{BorshDeserialize, BorshSerialize};
 use elusiv_computation::RAM;
 use elusiv_derive::{BorshSerDeSized, EnumVariantIndex};
 use elusiv_proc_macros::elusiv_account;
 use solana_program::entrypoint::ProgramResult;
+use solana_program::program_error::ProgramError;
 use solana_program::pubkey::Pubkey;
 
-pub type RAMFq<'a> = LazyRAM<'a, Fq, 6>;
-pub type RAMFq2<'a> = LazyRAM<'a, Fq2, 10>;
-pub type RAMFq6<'a> = LazyRAM<'a, Fq6, 3>;
-pub type RAMFq12<'a> = LazyRAM<'a, Fq12, 7>;
+// The RAM sizes are the minimum sizes required by the public input preparation and the `elusiv_computations` partial computations
+const RAM_FQ_SIZE: usize = PREPARE_PUBLIC_INPUTS_RAM_FQ_SIZE;
+const RAM_FQ2_SIZE: usize = COMBINED_MILLER_LOOP_RAM_FQ2_SIZE;
+const RAM_FQ6_SIZE: usize = max(
+    COMBINED_MILLER_LOOP_RAM_FQ6_SIZE,
+    FINAL_EXPONENTIATION_RAM_FQ6_SIZE,
+);
+const RAM_FQ12_SIZE: usize = max(
+    COMBINED_MILLER_LOOP_RAM_FQ12_SIZE,
+    FINAL_EXPONENTIATION_RAM_FQ12_SIZE,
+);
+
+pub type RAMFq<'a> = LazyRAM<'a, Fq, RAM_FQ_SIZE>;
+pub type RAMFq2<'a> = LazyRAM<'a, Fq2, RAM_FQ2_SIZE>;
+pub type RAMFq6<'a> = LazyRAM<'a, Fq6, RAM_FQ6_SIZE>;
+pub type RAMFq12<'a> = LazyRAM<'a, Fq12, RAM_FQ12_SIZE>;
 pub type RAMG2A<'a> = LazyRAM<'a, G2A, 1>;
 
 const MAX_PUBLIC_INPUTS_COUNT: usize = 14;
@@ -45,7 +63,13 @@ pub enum VerificationState {
 /// # Note
 ///
 /// Exists only temporarily for verifying a single proof and is closed afterwards.
-#[elusiv_account(partial_computation: true, eager_type: true)]
+///
+/// # Versions
+///
+/// - `0`: without `batch` and the trailing `VerificationAccountData` fields starting at `recipient_callback`
+/// - `1`: added `batch` and the `VerificationAccountData` fields
+/// - `2`: RAMs sized by the public input preparation and the `elusiv_computations` (instead of hand-sized RAMs)
+#[elusiv_account(partial_computation: true, eager_type: true, version: 2)]
 pub struct VerificationAccount {
     #[no_getter]
     #[no_setter]
@@ -100,6 +124,9 @@ pub struct VerificationAccount {
     #[no_getter]
     pub request: ProofRequest,
     pub tree_indices: [u32; MAX_MT_COUNT],
+
+    /// The [`BatchVerificationAccount`] this proof is verified in (if any)
+    pub batch: ElusivOption<Pubkey>,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, BorshSerDeSized, PartialEq, Clone, Default)]
@@ -133,6 +160,170 @@ pub struct VerificationAccountData {
 
     /// The expected associated-token-account-rent in `token_id`-Token
     pub associated_token_account_rent: u64,
+
+    /// Payload passed to the recipient program after the transfer (if the recipient is a program)
+    pub recipient_callback: ElusivOption<RecipientCallback>,
+
+    /// The part of the amount that has already been transferred to the recipient (sends exceeding the withdrawal rate limit are split across windows)
+    pub transferred_amount: u64,
+
+    /// The associated-token-account-rent in `token_id`-Token deducted from the amount, as determined by the first partial transfer
+    pub transferred_associated_token_account_rent: ElusivOption<u64>,
+
+    /// The slot at which the verification has been finalized (starts the [`crate::processor::RECIPIENT_CALLBACK_TIMEOUT_SLOTS`])
+    pub finalized_slot: u64,
+}
+
+pub const MAX_RECIPIENT_CALLBACK_PAYLOAD_LEN: usize = 128;
+pub const MAX_RECIPIENT_CALLBACK_WRITABLE_ACCOUNTS: usize = 4;
+
+/// A bounded payload with which the recipient program of a send is invoked
+///
+/// # Note
+///
+/// Forwarded accounts are only passed as writable to the recipient program if they are contained in `writable_accounts`.
+#[derive(BorshDeserialize, BorshSerialize, BorshSerDeSized, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "elusiv-client", derive(Debug))]
+pub struct RecipientCallback {
+    len: u8,
+    payload: [u8; MAX_RECIPIENT_CALLBACK_PAYLOAD_LEN],
+    writable_accounts_count: u8,
+    writable_accounts: [RawU256; MAX_RECIPIENT_CALLBACK_WRITABLE_ACCOUNTS],
+}
+
+impl RecipientCallback {
+    pub fn new(payload: &[u8], writable_accounts: &[U256]) -> Option<Self> {
+        if payload.len() > MAX_RECIPIENT_CALLBACK_PAYLOAD_LEN
+            || writable_accounts.len() > MAX_RECIPIENT_CALLBACK_WRITABLE_ACCOUNTS
+        {
+            return None;
+        }
+
+        let mut callback = RecipientCallback {
+            len: payload.len() as u8,
+            payload: [0; MAX_RECIPIENT_CALLBACK_PAYLOAD_LEN],
+            writable_accounts_count: writable_accounts.len() as u8,
+            writable_accounts: [RawU256::ZERO; MAX_RECIPIENT_CALLBACK_WRITABLE_ACCOUNTS],
+        };
+        callback.payload[..payload.len()].copy_from_slice(payload);
+        for (i, account) in writable_accounts.iter().enumerate() {
+            callback.writable_accounts[i] = RawU256::new(*account);
+        }
+
+        Some(callback)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len as usize]
+    }
+
+    /// Returns true if the recipient program is allowed to write to `account`
+    pub fn is_writable(&self, account: &Pubkey) -> bool {
+        self.writable_accounts[..self.writable_accounts_count as usize]
+            .iter()
+            .any(|writable_account| writable_account.skip_mr() == account.to_bytes())
+    }
+}
+
+/// The size of the hand-sized RAMs before version `2`
+const LEGACY_RAMS_SIZE: usize =
+    <Wrap<Fq>>::SIZE * 6 + <Wrap<Fq2>>::SIZE * 10 + <Wrap<Fq6>>::SIZE * 3 + <Wrap<Fq12>>::SIZE * 7;
+
+const RAMS_SIZE: usize = RAMFq::SIZE + RAMFq2::SIZE + RAMFq6::SIZE + RAMFq12::SIZE;
+
+/// The size of the [`VerificationAccountData`] fields added in version `1`
+const DATA_V1_FIELDS_SIZE: usize =
+    <ElusivOption<RecipientCallback>>::SIZE + u64::SIZE + <ElusivOption<u64>>::SIZE + u64::SIZE;
+
+/// The size of the fields preceded by the RAMs (`is_verified`, `other_data`, `request`, `tree_indices`, `batch`)
+const RAMS_SUFFIX_SIZE: usize = <ElusivOption<bool>>::SIZE
+    + VerificationAccountData::SIZE
+    + ProofRequest::SIZE
+    + u32::SIZE * MAX_MT_COUNT
+    + <ElusivOption<Pubkey>>::SIZE;
+
+impl<'a> VerificationAccount<'a> {
+    const RAMS_OFFSET: usize = Self::SIZE - RAMS_SUFFIX_SIZE - RAMS_SIZE;
+
+    /// The offset of the computation values (`prepared_inputs`, `r`, `f`, `alt_b`, `coeff_index`)
+    const COMPUTATION_VALUES_OFFSET: usize = Self::RAMS_OFFSET
+        - G1A::SIZE
+        - G2HomProjective::SIZE
+        - <Wrap<Fq12>>::SIZE
+        - G2A::SIZE
+        - u8::SIZE;
+
+    const STEP_OFFSET: usize = PDAAccountData::SIZE
+        + 3 * u32::SIZE
+        + MAX_PREPARE_INPUTS_INSTRUCTIONS * u16::SIZE
+        + u32::SIZE;
+}
+
+impl<'a> MigratableAccount for VerificationAccount<'a> {
+    fn size_at_version(version: u8) -> Option<usize> {
+        match version {
+            0 => Some(
+                Self::SIZE - RAMS_SIZE + LEGACY_RAMS_SIZE
+                    - DATA_V1_FIELDS_SIZE
+                    - <ElusivOption<Pubkey>>::SIZE,
+            ),
+            1 => Some(Self::SIZE - RAMS_SIZE + LEGACY_RAMS_SIZE),
+            2 => Some(Self::SIZE),
+            _ => None,
+        }
+    }
+
+    fn migrate_from(version: u8, data: &mut [u8]) -> ProgramResult {
+        match version {
+            // Inserts the trailing `VerificationAccountData` fields and `batch` (`ElusivOption::None`)
+            0 => {
+                let request_offset = Self::RAMS_OFFSET
+                    + LEGACY_RAMS_SIZE
+                    + <ElusivOption<bool>>::SIZE
+                    + VerificationAccountData::SIZE;
+                let legacy_request_offset = request_offset - DATA_V1_FIELDS_SIZE;
+                let request_and_tree_indices_size = ProofRequest::SIZE + u32::SIZE * MAX_MT_COUNT;
+
+                data.copy_within(
+                    legacy_request_offset..legacy_request_offset + request_and_tree_indices_size,
+                    request_offset,
+                );
+                data[legacy_request_offset..request_offset].fill(0);
+                data[request_offset + request_and_tree_indices_size..].fill(0);
+
+                Ok(())
+            }
+
+            // The RAM layout changed, so an unfinished proof verification is restarted (the proof and public inputs are kept)
+            1 => {
+                let legacy_suffix_offset = Self::RAMS_OFFSET + LEGACY_RAMS_SIZE;
+                let suffix_offset = Self::RAMS_OFFSET + RAMS_SIZE;
+                data.copy_within(
+                    legacy_suffix_offset..legacy_suffix_offset + RAMS_SUFFIX_SIZE,
+                    suffix_offset,
+                );
+                data[Self::RAMS_OFFSET..suffix_offset].fill(0);
+                data[suffix_offset + RAMS_SUFFIX_SIZE..].fill(0);
+
+                let is_verified = <ElusivOption<bool>>::try_from_slice(
+                    &data[suffix_offset..suffix_offset + <ElusivOption<bool>>::SIZE],
+                )?;
+                if is_verified.option().is_none() {
+                    // `instruction` and `round`
+                    data[PDAAccountData::SIZE..PDAAccountData::SIZE + 2 * u32::SIZE].fill(0);
+
+                    let mut slice = &mut data[Self::STEP_OFFSET..];
+                    VerificationStep::PublicInputPreparation.serialize(&mut slice)?;
+
+                    data[Self::COMPUTATION_VALUES_OFFSET..Self::RAMS_OFFSET].fill(0);
+                }
+
+                Ok(())
+            }
+
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
 }
 
 impl<'a> VerificationAccount<'a> {
@@ -248,8 +439,7 @@ where
         match &self.data[i] {
             Some(v) => *v,
             None => {
-                let data = &self.source[i * <Wrap<N>>::SIZE..(i + 1) * <Wrap<N>>::SIZE];
-                let v = <Wrap<N>>::try_from_slice(data).unwrap();
+                let v = <Wrap<N>>::read_sized(&self.source[i * <Wrap<N>>::SIZE..]).unwrap();
                 self.data[i] = Some(v.0);
                 self.data[i].unwrap()
             }
@@ -300,14 +490,104 @@ where
         for (i, &change) in self.changes.iter().enumerate() {
             if change {
                 if let Some(value) = self.data[i] {
-                    let mut slice =
-                        &mut self.source[i * <Wrap<N>>::SIZE..(i + 1) * <Wrap<N>>::SIZE];
-                    BorshSerialize::serialize(&Wrap(value), &mut slice)?;
+                    Wrap(value).write_sized(&mut self.source[i * <Wrap<N>>::SIZE..])?;
                 }
             }
         }
         Ok(())
     }
+
+    /// Number of elements accessed so far (including sub-function-frames)
+    #[cfg(test)]
+    pub fn usage(&self) -> usize {
+        self.data.len()
+    }
+}
+
+pub const MAX_BATCH_VERIFICATION_MEMBERS: usize = 8;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, BorshSerDeSized, EnumVariantIndex, Debug, Clone, PartialEq, Eq,
+)]
+pub enum BatchVerificationState {
+    /// Members can be added
+    Collecting,
+
+    /// Members are scaled and accumulated (in the order of their member index)
+    Accumulating,
+
+    /// The single Miller loop over the pairs of all members is computed
+    MillerLoop,
+
+    /// The leader performs the single final exponentiation for all members
+    FinalExponentiation,
+}
+
+pub type BatchRAMFq<'a> = LazyRAM<'a, Fq, BATCH_ACCUMULATION_RAM_FQ_SIZE>;
+
+/// Account used for verifying multiple proofs (with the same verifying key) with a single Miller loop and a single final exponentiation
+///
+/// # Notes
+///
+/// - the member proofs are randomly scaled and accumulated into the batch account, the member [`VerificationAccount`]s are not modified
+/// - the batch account computes one multi-pairing Miller loop over all members, the first member (the leader) performs the final exponentiation
+/// - if the batch is invalid, all members are released and can be verified individually
+/// - members can be evicted before the final exponentiation (which restarts the accumulation)
+#[elusiv_account(eager_type: true)]
+pub struct BatchVerificationAccount {
+    #[no_getter]
+    #[no_setter]
+    pda_data: PDAAccountData,
+
+    pub vkey_id: u32,
+    pub state: BatchVerificationState,
+
+    pub members_count: u32,
+    pub members: [ElusivOption<Pubkey>; MAX_BATCH_VERIFICATION_MEMBERS],
+    pub finalized: [bool; MAX_BATCH_VERIFICATION_MEMBERS],
+
+    /// Hash over all member proofs, used to derive the random scalars
+    pub transcript: U256,
+
+    /// The result of the leader's final exponentiation (valid for all members)
+    pub is_verified: ElusivOption<bool>,
+
+    /// The number of members that have been scaled and accumulated
+    pub accumulated_count: u32,
+    pub(crate) accumulation_round: u32,
+
+    /// Batch Miller loop progress
+    pub(crate) round: u32,
+    pub(crate) coeff_index: u32,
+
+    #[lazy]
+    pub(crate) ram_fq: BatchRAMFq<'a>,
+
+    /// The scaled `A` of each member
+    #[lazy]
+    pub(crate) a: LazyArray<'a, G1A, MAX_BATCH_VERIFICATION_MEMBERS>,
+    #[lazy]
+    pub(crate) b: LazyArray<'a, G2A, MAX_BATCH_VERIFICATION_MEMBERS>,
+    #[lazy]
+    pub(crate) r: LazyArray<'a, G2HomProjective, MAX_BATCH_VERIFICATION_MEMBERS>,
+
+    /// The aggregated scaled prepared inputs of all members
+    #[lazy]
+    pub(crate) prepared_inputs: Lazy<'a, G1A>,
+
+    /// The aggregated scaled `C` of all members
+    #[lazy]
+    pub(crate) c: Lazy<'a, G1A>,
+
+    #[lazy]
+    pub(crate) f: Lazy<'a, Wrap<Fq12>>,
+}
+
+impl<'a> BatchVerificationAccount<'a> {
+    pub fn member_index(&self, member: &Pubkey) -> Option<usize> {
+        (0..self.get_members_count() as usize)
+            .find(|&i| self.get_members(i).option() == Some(*member))
+    }
 }
 
 #[elusiv_account]
@@ -330,16 +610,19 @@ mod tests {
     use super::*;
     use crate::{
         fields::{u256_from_str, u256_from_str_skip_mr},
+        macros::zero_program_account,
         state::{metadata::CommitmentMetadata, program_account::ProgramAccount},
         types::{
             InputCommitment, JoinSplitPublicInputs, OptionalFee, PublicInputs, SendPublicInputs,
         },
     };
-    use elusiv_types::SizedAccount;
+    use ark_bn254::G1Affine;
+    use ark_ec::AffineCurve;
+    use elusiv_types::VersionedAccount;
 
     #[test]
     fn test_setup_verification_account() {
-        let mut data = vec![0; VerificationAccount::SIZE];
+        zero_program_account!(internal VerificationAccount, data);
         let mut verification_account = VerificationAccount::new(&mut data).unwrap();
 
         let public_inputs = SendPublicInputs {
@@ -446,7 +729,7 @@ mod tests {
 
     #[test]
     fn test_check_vector_size() {
-        let mut data = vec![0; VerificationAccount::SIZE];
+        zero_program_account!(internal VerificationAccount, data);
         let account = VerificationAccount::new(&mut data).unwrap();
         let mut ram = account.ram_fq12;
 
@@ -465,4 +748,231 @@ mod tests {
         assert_eq!(ram.data.len(), 3);
         assert_eq!(ram.changes.len(), 3);
     }
+
+    #[test]
+    fn test_recipient_callback() {
+        let callback = RecipientCallback::new(&[1, 2, 3], &[]).unwrap();
+        assert_eq!(callback.payload(), &[1, 2, 3]);
+
+        let callback = RecipientCallback::new(&[], &[]).unwrap();
+        assert!(callback.payload().is_empty());
+
+        let payload = [7; MAX_RECIPIENT_CALLBACK_PAYLOAD_LEN];
+        let callback = RecipientCallback::new(&payload, &[]).unwrap();
+        assert_eq!(callback.payload(), &payload);
+
+        assert!(
+            RecipientCallback::new(&[0; MAX_RECIPIENT_CALLBACK_PAYLOAD_LEN + 1], &[]).is_none()
+        );
+    }
+
+    #[test]
+    fn test_recipient_callback_writable_accounts() {
+        let writable = Pubkey::new_unique();
+        let callback = RecipientCallback::new(&[1], &[writable.to_bytes()]).unwrap();
+        assert!(callback.is_writable(&writable));
+        assert!(!callback.is_writable(&Pubkey::new_unique()));
+
+        let callback = RecipientCallback::new(&[1], &[]).unwrap();
+        assert!(!callback.is_writable(&writable));
+        assert!(!callback.is_writable(&Pubkey::new_from_array([0; 32])));
+
+        assert!(RecipientCallback::new(
+            &[1],
+            &[[1; 32]; MAX_RECIPIENT_CALLBACK_WRITABLE_ACCOUNTS + 1]
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_verification_account_sizes() {
+        assert_eq!(
+            VerificationAccount::size_at_version(0).unwrap() + DATA_V1_FIELDS_SIZE + 33,
+            VerificationAccount::size_at_version(1).unwrap()
+        );
+        assert_eq!(
+            VerificationAccount::size_at_version(1).unwrap() + RAMS_SIZE,
+            VerificationAccount::size_at_version(2).unwrap() + LEGACY_RAMS_SIZE
+        );
+        assert_eq!(
+            VerificationAccount::size_at_version(2).unwrap(),
+            VerificationAccount::SIZE
+        );
+        assert_eq!(VerificationAccount::size_at_version(3), None);
+    }
+
+    /// Converts the data of a [`VerificationAccount`] into the layout at `version` (the fields added after `version` have to be zero)
+    fn legacy_verification_account(data: &[u8], version: u8) -> Vec<u8> {
+        let rams_offset = VerificationAccount::RAMS_OFFSET;
+        let suffix_offset = rams_offset + RAMS_SIZE;
+        let data_end = suffix_offset + <ElusivOption<bool>>::SIZE + VerificationAccountData::SIZE;
+        let tree_indices_end = data_end + ProofRequest::SIZE + u32::SIZE * MAX_MT_COUNT;
+
+        let mut legacy = data[..rams_offset].to_vec();
+        legacy.extend(vec![1; LEGACY_RAMS_SIZE]);
+        if version == 0 {
+            legacy.extend(&data[suffix_offset..data_end - DATA_V1_FIELDS_SIZE]);
+            legacy.extend(&data[data_end..tree_indices_end]);
+        } else {
+            legacy.extend(&data[suffix_offset..]);
+        }
+        legacy[PDAAccountData::VERSION_OFFSET] = version;
+
+        assert_eq!(
+            legacy.len(),
+            VerificationAccount::size_at_version(version).unwrap()
+        );
+        legacy
+    }
+
+    /// Migrates the data one version at a time (like `migrate_pda_account`)
+    fn migrate_verification_account(legacy: &[u8]) -> Vec<u8> {
+        let mut data = legacy.to_vec();
+        for version in legacy[PDAAccountData::VERSION_OFFSET]..VerificationAccount::VERSION {
+            let size = VerificationAccount::size_at_version(version).unwrap();
+            let next_size = VerificationAccount::size_at_version(version + 1).unwrap();
+            assert_eq!(data.len(), size);
+
+            data.resize(size.max(next_size), 0);
+            VerificationAccount::migrate_from(version, &mut data).unwrap();
+            data.truncate(next_size);
+            data[PDAAccountData::VERSION_OFFSET] = version + 1;
+        }
+        data
+    }
+
+    #[test]
+    fn test_verification_account_migrate_restarts_computation() {
+        let request = ProofRequest::Send(SendPublicInputs {
+            join_split: JoinSplitPublicInputs {
+                input_commitments: vec![InputCommitment {
+                    root: Some(RawU256::new(u256_from_str("22"))),
+                    nullifier_hash: RawU256::new(u256_from_str_skip_mr("333")),
+                }],
+                output_commitment: RawU256::new(u256_from_str_skip_mr("44444")),
+                recent_commitment_index: 456,
+                fee_version: 0,
+                amount: 666666,
+                fee: 123,
+                optional_fee: OptionalFee::default(),
+                token_id: 0,
+                metadata: CommitmentMetadata::default(),
+            },
+            hashed_inputs: u256_from_str_skip_mr("7777777"),
+            recipient_is_associated_token_account: true,
+            solana_pay_transfer: false,
+        });
+        let other_data = VerificationAccountData {
+            fee_payer: RawU256::new([1; 32]),
+            recipient_wallet: ElusivOption::Some(RawU256::new([2; 32])),
+            network_fee: 999,
+            ..Default::default()
+        };
+
+        zero_program_account!(internal VerificationAccount, data);
+        {
+            let mut verification_account = VerificationAccount::new(&mut data).unwrap();
+            verification_account
+                .setup(
+                    other_data.fee_payer,
+                    false,
+                    &[RawU256::new([3; 32])],
+                    &vec![1, 2, 3],
+                    1,
+                    request.clone(),
+                    [7, 8],
+                )
+                .unwrap();
+            verification_account.set_other_data(&other_data);
+            verification_account.set_state(&VerificationState::ProofSetup);
+            verification_account.set_step(&VerificationStep::CombinedMillerLoop);
+            verification_account.set_instruction(&5);
+            verification_account.set_round(&17);
+            verification_account.set_coeff_index(&3);
+            verification_account
+                .prepared_inputs
+                .set(G1A(G1Affine::prime_subgroup_generator()));
+        }
+        let expected_prefix = data[..VerificationAccount::COMPUTATION_VALUES_OFFSET].to_vec();
+
+        let account_data = data;
+        for version in 0..VerificationAccount::VERSION {
+            let mut data =
+                migrate_verification_account(&legacy_verification_account(&account_data, version));
+            let verification_account = VerificationAccount::new(&mut data).unwrap();
+
+            assert_eq!(
+                verification_account.get_step(),
+                VerificationStep::PublicInputPreparation
+            );
+            assert_eq!(verification_account.get_instruction(), 0);
+            assert_eq!(verification_account.get_round(), 0);
+            assert_eq!(verification_account.get_coeff_index(), 0);
+            assert_eq!(
+                verification_account.get_state(),
+                VerificationState::ProofSetup
+            );
+            assert_eq!(verification_account.get_vkey_id(), 1);
+            assert_eq!(verification_account.get_prepare_inputs_instructions(2), 3);
+            assert_eq!(
+                verification_account.get_public_input(0),
+                RawU256::new([3; 32])
+            );
+            assert_eq!(verification_account.get_is_verified().option(), None);
+            assert_eq!(verification_account.get_other_data(), other_data);
+            assert_eq!(verification_account.get_request(), request);
+            assert_eq!(verification_account.all_tree_indices(), [7, 8]);
+            assert_eq!(verification_account.get_batch().option(), None);
+
+            // Computation values and RAMs are zeroed
+            assert!(data[VerificationAccount::COMPUTATION_VALUES_OFFSET
+                ..VerificationAccount::RAMS_OFFSET + RAMS_SIZE]
+                .iter()
+                .all(|&b| b == 0));
+            assert_eq!(
+                data[..PDAAccountData::SIZE],
+                expected_prefix[..PDAAccountData::SIZE]
+            );
+            assert_eq!(
+                data[PDAAccountData::SIZE + 2 * u32::SIZE..VerificationAccount::STEP_OFFSET],
+                expected_prefix
+                    [PDAAccountData::SIZE + 2 * u32::SIZE..VerificationAccount::STEP_OFFSET]
+            );
+            assert_eq!(
+                data[VerificationAccount::STEP_OFFSET + 1
+                    ..VerificationAccount::COMPUTATION_VALUES_OFFSET],
+                expected_prefix[VerificationAccount::STEP_OFFSET + 1..]
+            );
+        }
+    }
+
+    #[test]
+    fn test_verification_account_migrate_verified() {
+        zero_program_account!(internal VerificationAccount, data);
+        {
+            let mut verification_account = VerificationAccount::new(&mut data).unwrap();
+            verification_account.set_state(&VerificationState::InsertNullifiers);
+            verification_account.set_step(&VerificationStep::FinalExponentiation);
+            verification_account.set_instruction(&9);
+            verification_account.set_is_verified(&ElusivOption::Some(true));
+        }
+
+        let account_data = data;
+        for version in 0..VerificationAccount::VERSION {
+            let mut data =
+                migrate_verification_account(&legacy_verification_account(&account_data, version));
+            let verification_account = VerificationAccount::new(&mut data).unwrap();
+
+            assert_eq!(
+                verification_account.get_step(),
+                VerificationStep::FinalExponentiation
+            );
+            assert_eq!(verification_account.get_instruction(), 9);
+            assert_eq!(
+                verification_account.get_state(),
+                VerificationState::InsertNullifiers
+            );
+            assert_eq!(verification_account.get_is_verified().option(), Some(true));
+        }
+    }
 }
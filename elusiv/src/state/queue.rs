@@ -152,6 +152,37 @@ pub trait RingQueue {
         Ok(value)
     }
 
+    /// Returns an iterator over all elements in the queue (in FIFO order)
+    fn iter(&self) -> RingQueueIter<'_, Self>
+    where
+        Self: Sized,
+    {
+        RingQueueIter {
+            queue: self,
+            ptr: self.get_head(),
+            remaining: self.len(),
+        }
+    }
+
+    /// Try to read the first `count` elements in the queue without removing them
+    fn peek_range(&self, count: u32) -> Result<Vec<Self::N>, ProgramError>
+    where
+        Self: Sized,
+    {
+        guard!(self.len() >= count, InvalidQueueAccess);
+        Ok(self.iter().take(count as usize).collect())
+    }
+
+    /// Try to remove the first `count` elements from the queue
+    fn dequeue_n(&mut self, count: u32) -> Result<Vec<Self::N>, ProgramError>
+    where
+        Self: Sized,
+    {
+        let values = self.peek_range(count)?;
+        self.remove(count)?;
+        Ok(values)
+    }
+
     fn remove(&mut self, count: u32) -> Result<(), ProgramError> {
         let head = self.get_head();
         guard!(self.len() >= count, InvalidQueueAccess);
@@ -199,6 +230,33 @@ pub trait RingQueue {
     }
 }
 
+/// Iterator over the elements of a [`RingQueue`] (see [`RingQueue::iter`])
+pub struct RingQueueIter<'a, Q: RingQueue> {
+    queue: &'a Q,
+    ptr: u32,
+    remaining: u32,
+}
+
+impl<'a, Q: RingQueue> Iterator for RingQueueIter<'a, Q> {
+    type Item = Q::N;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let value = self.queue.get_data(self.ptr as usize);
+        self.ptr = (self.ptr + 1) % Q::SIZE;
+        self.remaining -= 1;
+
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -406,6 +464,61 @@ mod tests {
         queue.remove(1).unwrap();
     }
 
+    #[test]
+    fn test_iter() {
+        test_queue!(queue, 5, 3, 3);
+        assert_eq!(queue.iter().count(), 0);
+
+        for i in 0..4 {
+            queue.enqueue(i).unwrap();
+        }
+        assert_eq!(queue.iter().collect::<Vec<_>>(), [0, 1, 2, 3]);
+
+        queue.dequeue_first().unwrap();
+        queue.enqueue(4).unwrap();
+        assert_eq!(queue.iter().collect::<Vec<_>>(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_peek_range() {
+        test_queue!(queue, 10, 8, 8);
+        assert_eq!(queue.peek_range(0), Ok(vec![]));
+        assert_eq!(
+            queue.peek_range(1),
+            Err(ElusivError::InvalidQueueAccess.into())
+        );
+
+        for i in 0..5 {
+            queue.enqueue(i).unwrap();
+        }
+        assert_eq!(queue.peek_range(3), Ok(vec![0, 1, 2]));
+        assert_eq!(queue.peek_range(5), Ok(vec![0, 1, 2, 3, 4]));
+        assert_eq!(
+            queue.peek_range(6),
+            Err(ElusivError::InvalidQueueAccess.into())
+        );
+        assert_eq!(queue.len(), 5);
+    }
+
+    #[test]
+    fn test_dequeue_n() {
+        test_queue!(queue, 10, 8, 8);
+        for i in 0..5 {
+            queue.enqueue(i).unwrap();
+        }
+
+        // The queue is not modified on failure
+        assert_eq!(
+            queue.dequeue_n(6),
+            Err(ElusivError::InvalidQueueAccess.into())
+        );
+        assert_eq!(queue.len(), 5);
+
+        assert_eq!(queue.dequeue_n(2), Ok(vec![0, 1]));
+        assert_eq!(queue.dequeue_n(3), Ok(vec![2, 3, 4]));
+        assert!(queue.is_empty());
+    }
+
     #[test]
     fn test_clear_queue() {
         test_queue!(queue, 13, 0, 0);
@@ -1,8 +1,13 @@
 use super::program_account::*;
 use crate::bytes::*;
 use crate::macros::{elusiv_account, two_pow};
+use crate::state::governor::RootHistoryParameters;
 use crate::types::U256;
+#[cfg(feature = "elusiv-client")]
+use crate::{error::ElusivError, macros::guard};
 use borsh::{BorshDeserialize, BorshSerialize};
+use elusiv_derive::BorshSerDeSized;
+use elusiv_utils::MATH_ERR;
 use solana_program::entrypoint::ProgramResult;
 use solana_program::program_error::ProgramError;
 use solana_program::pubkey::Pubkey;
@@ -11,8 +16,15 @@ use solana_program::pubkey::Pubkey;
 ///
 /// # Note
 ///
-/// We define the height by the number of leaves, so a tree with `2^n` leaves has height `n`.
-pub const MT_HEIGHT: u32 = 20;
+/// - We define the height by the number of leaves, so a tree with `2^n` leaves has height `n`.
+/// - The `small-mt` feature reduces the height to [`SMALL_MT_HEIGHT`] for tests (there are no circuits and verifying keys for this height, so the feature can only be combined with `test-unit` or `test-bpf`).
+#[cfg(not(feature = "small-mt"))]
+pub const MT_HEIGHT: u32 = DEFAULT_MT_HEIGHT;
+#[cfg(feature = "small-mt")]
+pub const MT_HEIGHT: u32 = SMALL_MT_HEIGHT;
+
+pub const DEFAULT_MT_HEIGHT: u32 = 20;
+pub const SMALL_MT_HEIGHT: u32 = 12;
 
 /// Number of all nodes in the MT
 pub const MT_SIZE: usize = mt_size(MT_HEIGHT);
@@ -24,15 +36,32 @@ pub const fn mt_size(height: u32) -> usize {
 /// Number of all commitments (leaves) in the MT
 pub const MT_COMMITMENT_COUNT: usize = two_pow!(MT_HEIGHT);
 
-/// Since before submitting a proof request the current root can change, we store the [`HISTORY_ARRAY_SIZE`] previous ones
-pub const HISTORY_ARRAY_SIZE: usize = 100;
+/// The size of the root history stored by the [`StorageAccount`] before version `2` (see [`RootHistoryAccount`])
+const LEGACY_HISTORY_SIZE: usize = 100 * U256::SIZE;
 
-pub const VALUES_PER_STORAGE_SUB_ACCOUNT: usize = 83_887;
+const MAX_VALUES_PER_STORAGE_SUB_ACCOUNT: usize = 83_887;
+pub const VALUES_PER_STORAGE_SUB_ACCOUNT: usize = if MT_SIZE < MAX_VALUES_PER_STORAGE_SUB_ACCOUNT {
+    MT_SIZE
+} else {
+    MAX_VALUES_PER_STORAGE_SUB_ACCOUNT
+};
 const ACCOUNTS_COUNT: usize = div_ceiling_usize(MT_SIZE, VALUES_PER_STORAGE_SUB_ACCOUNT);
 
-#[cfg(test)]
+#[cfg(all(test, not(feature = "small-mt")))]
 const_assert_eq!(ACCOUNTS_COUNT, 25);
 
+#[cfg(all(test, feature = "small-mt"))]
+const_assert_eq!(ACCOUNTS_COUNT, 1);
+
+#[cfg(test)]
+const_assert_eq!(MT_COMMITMENT_COUNT, 1 << MT_HEIGHT);
+#[cfg(test)]
+const_assert!(VALUES_PER_STORAGE_SUB_ACCOUNT <= MAX_VALUES_PER_STORAGE_SUB_ACCOUNT);
+#[cfg(test)]
+const_assert!(ACCOUNTS_COUNT * VALUES_PER_STORAGE_SUB_ACCOUNT >= MT_SIZE);
+#[cfg(test)]
+const_assert!((ACCOUNTS_COUNT - 1) * VALUES_PER_STORAGE_SUB_ACCOUNT < MT_SIZE);
+
 pub struct StorageChildAccount;
 
 impl ChildAccount for StorageChildAccount {
@@ -44,7 +73,13 @@ impl ChildAccount for StorageChildAccount {
 /// # Note
 ///
 /// The MT is stored linearly as an array with the first element being the root.
-#[elusiv_account(parent_account: { child_account_count: ACCOUNTS_COUNT, child_account: StorageChildAccount }, eager_type: true)]
+///
+/// # Versions
+///
+/// - `0`: without `claimed_commitment_ptr`
+/// - `1`: added `claimed_commitment_ptr`
+/// - `2`: moved the `active_mt_root_history` into the [`RootHistoryAccount`]
+#[elusiv_account(parent_account: { child_account_count: ACCOUNTS_COUNT, child_account: StorageChildAccount }, eager_type: true, version: 2)]
 pub struct StorageAccount {
     #[no_getter]
     #[no_setter]
@@ -60,19 +95,69 @@ pub struct StorageAccount {
     /// The amount of archived MTs
     archived_count: u32,
 
-    /// Stores the last [`HISTORY_ARRAY_SIZE`] roots of the active tree (including the current root)
-    pub active_mt_root_history: [U256; HISTORY_ARRAY_SIZE],
     pub mt_roots_count: u32, // required since we batch insert commitments
+
+    /// Points to the first commitment that is not yet claimed by a [`crate::state::commitment::CommitmentHashingAccount`]
+    claimed_commitment_ptr: u32,
+}
+
+impl<'a, 'b, 't> MigratableAccount for StorageAccount<'a, 'b, 't> {
+    fn size_at_version(version: u8) -> Option<usize> {
+        match version {
+            0 => Some(Self::SIZE + LEGACY_HISTORY_SIZE - u32::SIZE),
+            1 => Some(Self::SIZE + LEGACY_HISTORY_SIZE),
+            2 => Some(Self::SIZE),
+            _ => None,
+        }
+    }
+
+    fn migrate_from(version: u8, data: &mut [u8]) -> ProgramResult {
+        match version {
+            // A zeroed `claimed_commitment_ptr` falls back to the `next_commitment_ptr`
+            0 => Ok(()),
+
+            // The `active_mt_root_history` preceded the trailing `mt_roots_count` and `claimed_commitment_ptr`
+            1 => {
+                let history_offset = Self::SIZE - 2 * u32::SIZE;
+                data.copy_within(
+                    history_offset + LEGACY_HISTORY_SIZE..Self::SIZE + LEGACY_HISTORY_SIZE,
+                    history_offset,
+                );
+                Ok(())
+            }
+
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
 }
 
 impl<'a, 'b, 't> StorageAccount<'a, 'b, 't> {
     pub fn reset(&mut self) {
         self.set_next_commitment_ptr(&0);
+        self.set_claimed_commitment_ptr(&0);
         self.set_mt_roots_count(&0);
+    }
 
-        for i in 0..self.active_mt_root_history.len() {
-            self.active_mt_root_history[i] = 0;
-        }
+    /// Returns the position of the next batch that can be claimed for hashing
+    ///
+    /// # Note
+    ///
+    /// All commitments in `[next_commitment_ptr; next_claimable_commitment_ptr)` are being hashed concurrently.
+    pub fn next_claimable_commitment_ptr(&self) -> u32 {
+        std::cmp::max(
+            self.get_next_commitment_ptr(),
+            self.get_claimed_commitment_ptr(),
+        )
+    }
+
+    pub fn claim_commitments(&mut self, count: usize) {
+        let ptr = self.next_claimable_commitment_ptr();
+        self.set_claimed_commitment_ptr(&(ptr + usize_as_u32_safe(count)));
+    }
+
+    /// Returns true if no batch is being hashed concurrently
+    pub fn no_claimed_batches(&self) -> bool {
+        self.next_claimable_commitment_ptr() == self.get_next_commitment_ptr()
     }
 
     pub fn is_full(&self) -> bool {
@@ -124,18 +209,34 @@ impl<'a, 'b, 't> StorageAccount<'a, 'b, 't> {
         self.get_node(0, 0)
     }
 
-    /// A root is valid if it's the current root or inside of the active_mt_root_history array
-    pub fn is_root_valid(&self, root: &U256) -> bool {
-        let max_history_roots =
-            std::cmp::min(self.get_mt_roots_count() as usize, HISTORY_ARRAY_SIZE);
+    /// Inserts the current root into the `root_history_account`
+    pub fn push_root_to_history(
+        &mut self,
+        root_history_account: &mut RootHistoryAccount,
+        slot: u64,
+    ) -> ProgramResult {
+        let root = self.get_root()?;
+        root_history_account.push_root(&root, self.get_trees_count(), slot)?;
+        self.set_mt_roots_count(&(self.get_mt_roots_count() + 1));
+
+        Ok(())
+    }
 
-        // TODO: remove this, has become redundant
+    /// A root is valid if it's the current root or a root of the active MT in the `root_history_account` (see [`RootHistoryAccount::contains_root`])
+    pub fn is_root_valid(
+        &self,
+        root: &U256,
+        root_history_account: &RootHistoryAccount,
+        parameters: &RootHistoryParameters,
+        current_slot: u64,
+    ) -> bool {
         if let Ok(current_root) = self.get_root() {
-            return *root == current_root;
+            if *root == current_root {
+                return true;
+            }
         }
 
-        max_history_roots > 0
-            && contains(root, &self.active_mt_root_history[..max_history_roots * 32])
+        root_history_account.contains_root(root, self.get_trees_count(), parameters, current_slot)
     }
 
     #[allow(clippy::needless_range_loop)]
@@ -154,6 +255,252 @@ impl<'a, 'b, 't> StorageAccount<'a, 'b, 't> {
     }
 }
 
+/// The capacity of the [`RootHistoryAccount`] (the depth in use is configured by the [`crate::state::governor::GovernorAccount`])
+pub const MAX_ROOT_HISTORY_DEPTH: usize = 4096;
+
+/// The default number of previous roots that are accepted for proofs
+///
+/// # Note
+///
+/// During a deposit burst the root can change many times per slot, so the depth is chosen generously.
+pub const DEFAULT_ROOT_HISTORY_DEPTH: u32 = 1024;
+
+/// The default number of slots after its insertion during which a previous root is accepted
+///
+/// # Note
+///
+/// With ~400ms per slot this corresponds to roughly ten minutes.
+pub const DEFAULT_ROOT_HISTORY_SLOT_WINDOW: u64 = 1500;
+
+const ROOT_HISTORY_BUCKETS_COUNT: usize = 1024;
+const ROOT_HISTORY_ACCOUNTS_COUNT: usize = 1;
+
+/// A root in the [`RootHistoryAccount`]
+#[derive(BorshDeserialize, BorshSerialize, BorshSerDeSized, PartialEq, Eq, Clone)]
+#[cfg_attr(any(test, feature = "elusiv-client"), derive(Debug))]
+pub struct RootHistoryEntry {
+    pub root: U256,
+    pub mt_index: u32,
+
+    /// The slot in which the root has been inserted
+    pub slot: u64,
+
+    /// The position (plus one) of the previous root in the same bucket (zero if there is none)
+    pub previous: u32,
+}
+
+pub struct RootHistoryChildAccount;
+
+impl ChildAccount for RootHistoryChildAccount {
+    const INNER_SIZE: usize = MAX_ROOT_HISTORY_DEPTH * RootHistoryEntry::SIZE;
+}
+
+/// Stores the previous roots of the MTs, since before submitting a proof request the current root can change
+///
+/// # Note
+///
+/// - The roots are stored in a ring of [`MAX_ROOT_HISTORY_DEPTH`] entries in the child-account.
+/// - Each root is also linked into one of [`ROOT_HISTORY_BUCKETS_COUNT`] buckets (selected by the root's lowest bytes), so a lookup only visits the roots of a single bucket.
+#[elusiv_account(parent_account: { child_account_count: ROOT_HISTORY_ACCOUNTS_COUNT, child_account: RootHistoryChildAccount }, eager_type: true)]
+pub struct RootHistoryAccount {
+    #[no_getter]
+    #[no_setter]
+    pda_data: PDAAccountData,
+    pubkeys: [ElusivOption<Pubkey>; ROOT_HISTORY_ACCOUNTS_COUNT],
+
+    /// The amount of all roots ever inserted
+    pub roots_count: u32,
+
+    /// The position (plus one) of the latest root in each bucket (zero for an empty bucket)
+    bucket_heads: [u32; ROOT_HISTORY_BUCKETS_COUNT],
+}
+
+impl<'a, 'b, 't> RootHistoryAccount<'a, 'b, 't> {
+    fn bucket(root: &U256) -> usize {
+        u16::from_le_bytes([root[0], root[1]]) as usize % ROOT_HISTORY_BUCKETS_COUNT
+    }
+
+    pub fn get_entry(&self, position: u32) -> Result<RootHistoryEntry, ProgramError> {
+        let index = position as usize % MAX_ROOT_HISTORY_DEPTH;
+        self.execute_on_child_account(0, |data| {
+            RootHistoryEntry::try_from_slice(
+                &data[index * RootHistoryEntry::SIZE..(index + 1) * RootHistoryEntry::SIZE],
+            )
+        })?
+        .map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// Inserts `root` (overwriting the oldest root)
+    pub fn push_root(&mut self, root: &U256, mt_index: u32, slot: u64) -> ProgramResult {
+        let position = self.get_roots_count();
+        let bucket = Self::bucket(root);
+        let entry = RootHistoryEntry {
+            root: *root,
+            mt_index,
+            slot,
+            previous: self.get_bucket_heads(bucket),
+        };
+
+        let index = position as usize % MAX_ROOT_HISTORY_DEPTH;
+        self.execute_on_child_account_mut(0, |data| {
+            let mut slice =
+                &mut data[index * RootHistoryEntry::SIZE..(index + 1) * RootHistoryEntry::SIZE];
+            BorshSerialize::serialize(&entry, &mut slice)
+        })??;
+
+        self.set_bucket_heads(bucket, &(position.checked_add(1).ok_or(MATH_ERR)?));
+        self.set_roots_count(&(position.checked_add(1).ok_or(MATH_ERR)?));
+
+        Ok(())
+    }
+
+    /// Returns true if `root` of the MT with the index `mt_index` is one of the last `parameters.depth` roots and has been inserted at most `parameters.slot_window` slots before `current_slot`
+    pub fn contains_root(
+        &self,
+        root: &U256,
+        mt_index: u32,
+        parameters: &RootHistoryParameters,
+        current_slot: u64,
+    ) -> bool {
+        // Since the depth never exceeds the capacity, the positions starting at `oldest_position` have not been overwritten
+        let depth = std::cmp::min(parameters.depth, usize_as_u32_safe(MAX_ROOT_HISTORY_DEPTH));
+        let oldest_position = self.get_roots_count().saturating_sub(depth);
+
+        // The positions in a bucket are strictly decreasing
+        let mut head = self.get_bucket_heads(Self::bucket(root));
+        while head > oldest_position {
+            let entry = match self.get_entry(head - 1) {
+                Ok(entry) => entry,
+                Err(_) => return false,
+            };
+
+            if entry.root == *root && entry.mt_index == mt_index {
+                return current_slot <= entry.slot.saturating_add(parameters.slot_window);
+            }
+
+            head = entry.previous;
+        }
+
+        false
+    }
+}
+
+/// The tag of the program log emitted for every [`MerkleTreeInsertionEvent`]
+pub const MT_INSERTION_EVENT_TAG: &[u8] = b"elusiv:mt-insertion";
+
+/// A batch of leaves inserted into the active MT
+///
+/// # Note
+///
+/// - The event is emitted as program log data (see [`MerkleTreeInsertionEvent::emit`]), so the leaves of closed MTs can be recovered without storing them on-chain.
+/// - All openings of a closed MT can be rebuilt from its events (see [`closed_mt_opening`]).
+#[derive(BorshDeserialize, BorshSerialize, PartialEq, Eq, Clone)]
+#[cfg_attr(any(test, feature = "elusiv-client"), derive(Debug))]
+pub struct MerkleTreeInsertionEvent {
+    pub mt_index: u32,
+
+    /// The index of the first leaf of the batch in the MT
+    pub leaves_index: u32,
+    pub leaves: Vec<U256>,
+
+    /// The root of the MT after the insertion
+    pub root: U256,
+}
+
+impl MerkleTreeInsertionEvent {
+    pub fn emit(&self) {
+        solana_program::log::sol_log_data(&[MT_INSERTION_EVENT_TAG, &self.try_to_vec().unwrap()]);
+    }
+
+    /// Parses the fields of a program log data entry (or returns [`None`] for other logs)
+    #[cfg(feature = "elusiv-client")]
+    pub fn parse(fields: &[&[u8]]) -> Option<Self> {
+        match fields {
+            [tag, data] if *tag == MT_INSERTION_EVENT_TAG => Self::try_from_slice(data).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Collects the leaves of the MT with the index `mt_index` from its (ordered) [`MerkleTreeInsertionEvent`]s
+#[cfg(feature = "elusiv-client")]
+pub fn mt_leaves_from_events(
+    events: &[MerkleTreeInsertionEvent],
+    mt_index: u32,
+) -> Result<Vec<U256>, ProgramError> {
+    let mut leaves = Vec::new();
+    for event in events.iter().filter(|event| event.mt_index == mt_index) {
+        // A missing event would shift all following leaves
+        guard!(
+            event.leaves_index as usize == leaves.len(),
+            ElusivError::MissingValue
+        );
+        leaves.extend_from_slice(&event.leaves);
+    }
+
+    Ok(leaves)
+}
+
+/// Rebuilds the opening of the leaf at `index` of a closed MT and verifies it against the MT's root
+#[cfg(feature = "elusiv-client")]
+pub fn closed_mt_opening(
+    leaves: &[U256],
+    index: usize,
+    nullifier_account: &super::nullifier::NullifierAccount,
+) -> Result<[U256; MT_HEIGHT as usize], ProgramError> {
+    let (opening, root) = mt_opening_from_leaves(leaves, index)?;
+    guard!(
+        root == nullifier_account.get_root(),
+        ElusivError::InvalidMerkleRoot
+    );
+
+    Ok(opening)
+}
+
+/// Computes the opening of the leaf at `index` and the root of the MT containing `leaves`
+///
+/// # Note
+///
+/// The opening has the same layout as [`StorageAccount::get_mt_opening`] (starting with the leaf's sibling).
+#[cfg(feature = "elusiv-client")]
+pub fn mt_opening_from_leaves(
+    leaves: &[U256],
+    index: usize,
+) -> Result<([U256; MT_HEIGHT as usize], U256), ProgramError> {
+    use crate::commitment::poseidon_hash::full_poseidon2_hash;
+    use crate::fields::{fr_to_u256_le, u256_to_fr_skip_mr};
+    use ark_bn254::Fr;
+
+    guard!(index < leaves.len(), ElusivError::MissingValue);
+    guard!(
+        leaves.len() <= MT_COMMITMENT_COUNT,
+        ElusivError::InvalidInstructionData
+    );
+
+    let mut opening = [[0; 32]; MT_HEIGHT as usize];
+    let mut layer: Vec<Fr> = leaves.iter().map(u256_to_fr_skip_mr).collect();
+    let mut index = index;
+
+    for (level, sibling) in opening.iter_mut().enumerate() {
+        let empty = u256_to_fr_skip_mr(&EMPTY_TREE[level]);
+        let node = |layer: &[Fr], i: usize| layer.get(i).copied().unwrap_or(empty);
+
+        *sibling = fr_to_u256_le(&node(&layer, index ^ 1));
+
+        layer = (0..layer.len().div_ceil(2))
+            .map(|i| full_poseidon2_hash(node(&layer, 2 * i), node(&layer, 2 * i + 1)))
+            .collect();
+        index >>= 1;
+    }
+
+    let root = layer
+        .first()
+        .map(fr_to_u256_le)
+        .unwrap_or(EMPTY_TREE[MT_HEIGHT as usize]);
+
+    Ok((opening, root))
+}
+
 pub fn mt_array_index(index: usize, level: usize) -> usize {
     assert!(index < two_pow!(usize_as_u32_safe(level)));
     two_pow!(usize_as_u32_safe(level)) - 1 + index
@@ -169,7 +516,20 @@ fn use_default_value(index: usize, level: usize, next_leaf_ptr: usize) -> bool {
 /// # Note
 ///
 /// All values are in mr-form.
-pub const EMPTY_TREE: [U256; MT_HEIGHT as usize + 1] = [
+pub const EMPTY_TREE: [U256; MT_HEIGHT as usize + 1] = empty_tree();
+
+const fn empty_tree<const N: usize>() -> [U256; N] {
+    let mut tree = [[0; 32]; N];
+    let mut i = 0;
+    while i < N {
+        tree[i] = DEFAULT_EMPTY_TREE[i];
+        i += 1;
+    }
+    tree
+}
+
+/// The empty tree values for the [`DEFAULT_MT_HEIGHT`] (prefixes of it are the empty trees of lower heights)
+const DEFAULT_EMPTY_TREE: [U256; DEFAULT_MT_HEIGHT as usize + 1] = [
     [
         130, 154, 1, 250, 228, 248, 226, 43, 27, 76, 165, 173, 91, 84, 165, 131, 78, 224, 152, 167,
         123, 115, 91, 213, 116, 49, 167, 101, 109, 41, 161, 8,
@@ -269,12 +629,48 @@ mod tests {
     use super::*;
     use crate::{
         commitment::poseidon_hash::full_poseidon2_hash,
-        fields::{u256_from_str, u256_to_fr_skip_mr},
+        fields::{fr_to_u256_le, u256_from_str, u256_to_fr_skip_mr, u64_to_scalar},
         macros::parent_account,
+        state::nullifier::NullifierAccount,
     };
     use ark_bn254::Fr;
+    use elusiv_types::{ProgramAccount, VersionedAccount};
     use std::str::FromStr;
 
+    #[test]
+    fn test_storage_account_sizes() {
+        assert_eq!(
+            StorageAccount::size_at_version(0).unwrap() + u32::SIZE,
+            StorageAccount::size_at_version(1).unwrap()
+        );
+        assert_eq!(
+            StorageAccount::size_at_version(1).unwrap(),
+            StorageAccount::SIZE + 100 * U256::SIZE
+        );
+        assert_eq!(
+            StorageAccount::size_at_version(2).unwrap(),
+            StorageAccount::SIZE
+        );
+        assert_eq!(StorageAccount::size_at_version(3), None);
+    }
+
+    #[test]
+    fn test_storage_account_migrate_root_history() {
+        let mut data = vec![0; StorageAccount::size_at_version(1).unwrap()];
+        let len = data.len();
+        data[len - 8..len - 4].copy_from_slice(&7u32.to_le_bytes());
+        data[len - 4..].copy_from_slice(&9u32.to_le_bytes());
+        data[len - 8 - LEGACY_HISTORY_SIZE..len - 8].fill(1);
+
+        StorageAccount::migrate_from(1, &mut data).unwrap();
+        data.truncate(StorageAccount::SIZE);
+        data[PDAAccountData::VERSION_OFFSET] = StorageAccount::VERSION;
+
+        let storage_account = StorageAccount::new(&mut data).unwrap();
+        assert_eq!(storage_account.get_mt_roots_count(), 7);
+        assert_eq!(storage_account.get_claimed_commitment_ptr(), 9);
+    }
+
     #[test]
     fn test_mt_array_index() {
         assert_eq!(0, mt_array_index(0, 0));
@@ -288,6 +684,11 @@ mod tests {
         assert_eq!(6, mt_array_index(3, 2));
     }
 
+    #[test]
+    fn test_mt_height_derived_consts() {
+        assert_eq!(EMPTY_TREE[..], DEFAULT_EMPTY_TREE[..=MT_HEIGHT as usize]);
+    }
+
     #[test]
     fn test_empty_root_raw() {
         assert_eq!(empty_root_raw().reduce(), EMPTY_TREE[MT_HEIGHT as usize]);
@@ -435,7 +836,231 @@ mod tests {
     #[test]
     fn test_is_root_valid() {
         parent_account!(storage_account, StorageAccount);
-        assert!(storage_account.is_root_valid(&EMPTY_TREE[MT_HEIGHT as usize]));
-        assert!(!storage_account.is_root_valid(&[0; 32]));
+        parent_account!(root_history_account, RootHistoryAccount);
+        let parameters = RootHistoryParameters::default();
+
+        assert!(storage_account.is_root_valid(
+            &EMPTY_TREE[MT_HEIGHT as usize],
+            &root_history_account,
+            &parameters,
+            0
+        ));
+        assert!(!storage_account.is_root_valid(&[0; 32], &root_history_account, &parameters, 0));
+    }
+
+    #[test]
+    fn test_is_root_valid_root_history() {
+        parent_account!(mut storage_account, StorageAccount);
+        parent_account!(mut root_history_account, RootHistoryAccount);
+        let parameters = RootHistoryParameters {
+            depth: 10,
+            slot_window: 100,
+        };
+
+        let slot = 1_000;
+        let old_root = storage_account.get_root().unwrap();
+        storage_account
+            .push_root_to_history(&mut root_history_account, slot)
+            .unwrap();
+
+        let new_root = u256_from_str("123");
+        storage_account.set_node(&new_root, 0, 0).unwrap();
+        storage_account.set_next_commitment_ptr(&1);
+        storage_account
+            .push_root_to_history(&mut root_history_account, slot + 1)
+            .unwrap();
+
+        assert_eq!(storage_account.get_mt_roots_count(), 2);
+        assert_eq!(root_history_account.get_roots_count(), 2);
+        assert_eq!(
+            root_history_account.get_entry(1).unwrap(),
+            RootHistoryEntry {
+                root: new_root,
+                mt_index: 0,
+                slot: slot + 1,
+                previous: 0,
+            }
+        );
+
+        let is_root_valid = |storage_account: &StorageAccount,
+                             root_history_account: &RootHistoryAccount,
+                             root: &U256,
+                             current_slot: u64| {
+            storage_account.is_root_valid(root, root_history_account, &parameters, current_slot)
+        };
+
+        // Previous root inside of the slot window
+        assert!(is_root_valid(
+            &storage_account,
+            &root_history_account,
+            &old_root,
+            slot
+        ));
+        assert!(is_root_valid(
+            &storage_account,
+            &root_history_account,
+            &old_root,
+            slot + 100
+        ));
+
+        // Previous root expired
+        assert!(!is_root_valid(
+            &storage_account,
+            &root_history_account,
+            &old_root,
+            slot + 101
+        ));
+
+        // The current root never expires
+        assert!(is_root_valid(
+            &storage_account,
+            &root_history_account,
+            &new_root,
+            u64::MAX
+        ));
+
+        // Oldest root is outside of the depth
+        for i in 0..parameters.depth - 2 {
+            root_history_account
+                .push_root(&u256_from_str(&(1000 + i).to_string()), 0, slot)
+                .unwrap();
+        }
+        assert!(is_root_valid(
+            &storage_account,
+            &root_history_account,
+            &old_root,
+            slot
+        ));
+        root_history_account.push_root(&[0; 32], 0, slot).unwrap();
+        assert!(!is_root_valid(
+            &storage_account,
+            &root_history_account,
+            &old_root,
+            slot
+        ));
+
+        // Roots of a closed MT are not valid for the active MT
+        let closed_root = u256_from_str("456");
+        root_history_account
+            .push_root(&closed_root, 0, slot)
+            .unwrap();
+        storage_account.set_trees_count(&1);
+        storage_account.reset();
+        assert_eq!(storage_account.get_mt_roots_count(), 0);
+        assert!(!is_root_valid(
+            &storage_account,
+            &root_history_account,
+            &closed_root,
+            slot
+        ));
+    }
+
+    #[test]
+    fn test_root_history_bucket_collisions() {
+        parent_account!(mut root_history_account, RootHistoryAccount);
+        let parameters = RootHistoryParameters {
+            depth: MAX_ROOT_HISTORY_DEPTH as u32,
+            slot_window: u64::MAX,
+        };
+
+        // All roots in the same bucket
+        let root = |i: u8| {
+            let mut root = [0; 32];
+            root[31] = i;
+            root
+        };
+        for i in 0..10 {
+            root_history_account.push_root(&root(i), 0, 0).unwrap();
+        }
+        assert_eq!(root_history_account.get_entry(9).unwrap().previous, 9);
+
+        for i in 0..10 {
+            assert!(root_history_account.contains_root(&root(i), 0, &parameters, 0));
+        }
+        assert!(!root_history_account.contains_root(&root(10), 0, &parameters, 0));
+        assert!(!root_history_account.contains_root(&root(0), 1, &parameters, 0));
+
+        // Entries are overwritten after `MAX_ROOT_HISTORY_DEPTH` roots
+        for _ in 0..MAX_ROOT_HISTORY_DEPTH - 9 {
+            root_history_account.push_root(&[1; 32], 0, 0).unwrap();
+        }
+        assert!(!root_history_account.contains_root(&root(0), 0, &parameters, 0));
+        assert!(root_history_account.contains_root(&root(1), 0, &parameters, 0));
+    }
+
+    #[test]
+    fn test_mt_opening_from_leaves() {
+        let leaves: Vec<Fr> = (1..=3).map(u64_to_scalar).collect();
+        let empty = |level: usize| u256_to_fr_skip_mr(&EMPTY_TREE[level]);
+
+        let left = full_poseidon2_hash(leaves[0], leaves[1]);
+        let right = full_poseidon2_hash(leaves[2], empty(0));
+        let mut root = full_poseidon2_hash(left, right);
+        for level in 2..MT_HEIGHT as usize {
+            root = full_poseidon2_hash(root, empty(level));
+        }
+
+        let leaves: Vec<U256> = leaves.iter().map(fr_to_u256_le).collect();
+        let (opening, computed_root) = mt_opening_from_leaves(&leaves, 2).unwrap();
+        assert_eq!(computed_root, fr_to_u256_le(&root));
+        assert_eq!(opening[0], EMPTY_TREE[0]);
+        assert_eq!(opening[1], fr_to_u256_le(&left));
+        assert_eq!(opening[2..], EMPTY_TREE[2..MT_HEIGHT as usize]);
+
+        assert_eq!(
+            mt_opening_from_leaves(&leaves, 3),
+            Err(ElusivError::MissingValue.into())
+        );
+
+        // The leaves are collected from the insertion events
+        let events = [
+            MerkleTreeInsertionEvent {
+                mt_index: 0,
+                leaves_index: 0,
+                leaves: leaves[..2].to_vec(),
+                root: [0; 32],
+            },
+            MerkleTreeInsertionEvent {
+                mt_index: 1,
+                leaves_index: 0,
+                leaves: vec![[1; 32]],
+                root: [0; 32],
+            },
+            MerkleTreeInsertionEvent {
+                mt_index: 0,
+                leaves_index: 2,
+                leaves: leaves[2..].to_vec(),
+                root: fr_to_u256_le(&root),
+            },
+        ];
+        assert_eq!(mt_leaves_from_events(&events, 0).unwrap(), leaves);
+        assert_eq!(
+            mt_leaves_from_events(&events[1..], 0),
+            Err(ElusivError::MissingValue.into())
+        );
+
+        let fields = [MT_INSERTION_EVENT_TAG, &events[0].try_to_vec().unwrap()[..]];
+        assert_eq!(
+            MerkleTreeInsertionEvent::parse(&fields),
+            Some(events[0].clone())
+        );
+        assert_eq!(MerkleTreeInsertionEvent::parse(&fields[..1]), None);
+        assert_eq!(
+            MerkleTreeInsertionEvent::parse(&[b"other", fields[1]]),
+            None
+        );
+
+        // The opening is verified against the root of the closed MT
+        parent_account!(mut nullifier_account, NullifierAccount);
+        assert_eq!(
+            closed_mt_opening(&leaves, 2, &nullifier_account),
+            Err(ElusivError::InvalidMerkleRoot.into())
+        );
+
+        nullifier_account.set_root(&fr_to_u256_le(&root));
+        assert_eq!(
+            closed_mt_opening(&leaves, 2, &nullifier_account).unwrap(),
+            opening
+        );
     }
 }
@@ -1,6 +1,8 @@
+use crate::bytes::BorshSerDeSized;
+use crate::proof::vkey::VerifyingKeyLayout;
 use elusiv_proc_macros::elusiv_account;
-use elusiv_types::{ChildAccount, ElusivOption, PDAAccountData};
-use solana_program::pubkey::Pubkey;
+use elusiv_types::{ChildAccount, ElusivOption, MigratableAccount, PDAAccountData, SizedAccount};
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
 
 pub struct VKeyChildAccount;
 
@@ -9,7 +11,12 @@ impl ChildAccount for VKeyChildAccount {
 }
 
 /// Account used for storing a single immutable [`VerifyingKey`]
-#[elusiv_account(parent_account: { child_account_count: 2, child_account: VKeyChildAccount }, eager_type: true)]
+///
+/// # Versions
+///
+/// - `0`: without `layout`
+/// - `1`: added `layout`
+#[elusiv_account(parent_account: { child_account_count: 2, child_account: VKeyChildAccount }, eager_type: true, version: 1)]
 pub struct VKeyAccount {
     #[no_getter]
     #[no_setter]
@@ -20,6 +27,28 @@ pub struct VKeyAccount {
     pub authority: ElusivOption<Pubkey>,
     pub is_frozen: bool,
     pub version: u32,
+
+    /// The [`VerifyingKeyLayout`] of the binary data of all versions
+    pub layout: VerifyingKeyLayout,
+}
+
+impl<'a, 'b, 't> MigratableAccount for VKeyAccount<'a, 'b, 't> {
+    fn size_at_version(version: u8) -> Option<usize> {
+        match version {
+            0 => Some(Self::SIZE - VerifyingKeyLayout::SIZE),
+            1 => Some(Self::SIZE),
+            _ => None,
+        }
+    }
+
+    fn migrate_from(version: u8, _data: &mut [u8]) -> ProgramResult {
+        match version {
+            // A zeroed `layout` is the legacy `VerifyingKeyLayout::Window8`
+            0 => Ok(()),
+
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
 }
 
 impl<'a, 'b, 't> VKeyAccount<'a, 'b, 't> {
@@ -27,3 +56,49 @@ impl<'a, 'b, 't> VKeyAccount<'a, 'b, 't> {
         self.get_version() != 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macros::zero_program_account;
+    use elusiv_types::{ProgramAccount, VersionedAccount};
+
+    #[test]
+    fn test_vkey_account_sizes() {
+        assert_eq!(
+            VKeyAccount::size_at_version(0).unwrap() + 1,
+            VKeyAccount::size_at_version(1).unwrap()
+        );
+        assert_eq!(VKeyAccount::size_at_version(1).unwrap(), VKeyAccount::SIZE);
+        assert_eq!(VKeyAccount::size_at_version(2), None);
+    }
+
+    #[test]
+    fn test_vkey_account_migrate_layout() {
+        zero_program_account!(internal VKeyAccount, data);
+        {
+            let mut vkey_account = VKeyAccount::new(&mut data).unwrap();
+            vkey_account.set_public_inputs_count(&14);
+            vkey_account.set_authority(&ElusivOption::Some(Pubkey::new_from_array([1; 32])));
+            vkey_account.set_version(&2);
+            vkey_account.set_layout(&VerifyingKeyLayout::Window4);
+        }
+
+        // Legacy layout
+        data.truncate(VKeyAccount::size_at_version(0).unwrap());
+        data.resize(VKeyAccount::SIZE, 0);
+        data[PDAAccountData::VERSION_OFFSET] = 0;
+
+        VKeyAccount::migrate_from(0, &mut data).unwrap();
+        data[PDAAccountData::VERSION_OFFSET] = VKeyAccount::VERSION;
+
+        let vkey_account = VKeyAccount::new(&mut data).unwrap();
+        assert_eq!(
+            vkey_account.get_authority().option(),
+            Some(Pubkey::new_from_array([1; 32]))
+        );
+        assert_eq!(vkey_account.get_public_inputs_count(), 14);
+        assert_eq!(vkey_account.get_version(), 2);
+        assert_eq!(vkey_account.get_layout(), VerifyingKeyLayout::Window8);
+    }
+}
@@ -48,6 +48,7 @@ impl RawU256 {
 }
 
 #[derive(BorshDeserialize, BorshSerialize, BorshSerDeSized, PartialEq, Eq, Clone, Copy, Debug)]
+#[zero_copy]
 pub struct OrdU256(pub U256);
 
 impl PartialOrd for OrdU256 {
@@ -96,8 +97,7 @@ impl<'a, N: BorshSerDeSized + Copy> LazyField<'a> for Lazy<'a, N> {
             return;
         }
 
-        let mut slice = &mut self.data[..];
-        BorshSerialize::serialize(&self.value.unwrap(), &mut slice).unwrap();
+        self.value.unwrap().write_sized(self.data).unwrap();
     }
 }
 
@@ -106,7 +106,7 @@ impl<'a, N: BorshSerDeSized + Copy> Lazy<'a, N> {
         match &self.value {
             Some(v) => *v,
             None => {
-                self.value = Some(N::try_from_slice(self.data).unwrap());
+                self.value = Some(N::read_sized(self.data).unwrap());
                 self.value.unwrap()
             }
         }
@@ -152,9 +152,7 @@ impl<'a, N: BorshSerDeSized + Copy, const SIZE: usize> LazyField<'a> for LazyArr
 
         for i in 0..SIZE {
             if let Some(value) = self.values[i] {
-                let offset = i * N::SIZE;
-                let mut slice = &mut self.data[offset..offset + N::SIZE];
-                BorshSerialize::serialize(&value, &mut slice).unwrap();
+                value.write_sized(&mut self.data[i * N::SIZE..]).unwrap();
             }
         }
     }
@@ -165,8 +163,7 @@ impl<'a, N: BorshSerDeSized + Copy, const SIZE: usize> LazyArray<'a, N, SIZE> {
         match &self.values[index] {
             Some(v) => *v,
             None => {
-                let offset = index * N::SIZE;
-                let v = N::try_from_slice(&self.data[offset..offset + N::SIZE]).unwrap();
+                let v = N::read_sized(&self.data[index * N::SIZE..]).unwrap();
                 self.values[index] = Some(v);
                 v
             }
@@ -216,13 +213,13 @@ impl<'a, N: BorshSerDeSized + Clone, const CAPACITY: usize> LazyField<'a>
 
 impl<'a, N: BorshSerDeSized + Clone, const CAPACITY: usize> JITArray<'a, N, CAPACITY> {
     pub fn get(&mut self, index: usize) -> N {
-        N::try_from_slice(&self.data[index * N::SIZE..(index + 1) * N::SIZE]).unwrap()
+        N::read_sized(&self.data[index * N::SIZE..]).unwrap()
     }
 
     pub fn set(&mut self, index: usize, value: &N) {
-        let offset = index * N::SIZE;
-        let mut slice = &mut self.data[offset..offset + N::SIZE];
-        BorshSerialize::serialize(value, &mut slice).unwrap();
+        value
+            .write_sized(&mut self.data[index * N::SIZE..])
+            .unwrap();
     }
 }
 
@@ -401,37 +398,127 @@ pub struct SendPublicInputs {
     pub hashed_inputs: U256,
 }
 
+/// Prefix of the versioned `hashed_inputs` encoding (a `recipient` with these bytes is rejected)
+pub const HASHED_INPUTS_V1_DOMAIN: &[u8; 32] = b"elusiv-hashed-inputs-version-one";
+
 #[allow(clippy::too_many_arguments)]
 pub fn generate_hashed_inputs(
     recipient: &U256,
     identifier: &U256,
     iv: &U256,
     encrypted_owner: &U256,
-    transaction_reference: &U256,
+    transaction_references: &[U256],
     is_associated_token_account: bool,
     metadata: &CommitmentMetadata,
     optional_fee: &OptionalFee,
     memo: &Option<Vec<u8>>,
+    encrypted_metadata: &[u8],
+    recipient_callback: &Option<Vec<u8>>,
+    recipient_callback_writable_accounts: &[U256],
 ) -> U256 {
-    let mut data = recipient.to_vec();
+    let data = hashed_inputs_preimage(
+        recipient,
+        identifier,
+        iv,
+        encrypted_owner,
+        transaction_references,
+        is_associated_token_account,
+        metadata,
+        optional_fee,
+        memo,
+        encrypted_metadata,
+        recipient_callback,
+        recipient_callback_writable_accounts,
+    );
+    let mut hash = solana_program::hash::hash(&data).to_bytes();
+
+    // mask the lower 253 bits
+    hash[31] &= 0b11111;
+    hash
+}
+
+/// Encodes the inputs hashed into the `hashed_inputs`
+///
+/// # Notes
+///
+/// - Sends with at most one reference, no encrypted metadata and no recipient callback use the original encoding (starting with the `recipient`).
+/// - All other sends use the versioned encoding, which starts with [`HASHED_INPUTS_V1_DOMAIN`] and length-prefixes the references and every variable-length field.
+/// - The `recipient_callback_writable_accounts` are only encoded for sends with a recipient callback.
+#[allow(clippy::too_many_arguments)]
+fn hashed_inputs_preimage(
+    recipient: &U256,
+    identifier: &U256,
+    iv: &U256,
+    encrypted_owner: &U256,
+    transaction_references: &[U256],
+    is_associated_token_account: bool,
+    metadata: &CommitmentMetadata,
+    optional_fee: &OptionalFee,
+    memo: &Option<Vec<u8>>,
+    encrypted_metadata: &[u8],
+    recipient_callback: &Option<Vec<u8>>,
+    recipient_callback_writable_accounts: &[U256],
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    let is_versioned = transaction_references.len() > 1
+        || !encrypted_metadata.is_empty()
+        || recipient_callback.is_some();
+
+    if is_versioned {
+        data.extend(HASHED_INPUTS_V1_DOMAIN);
+    }
+
+    data.extend(recipient);
     data.extend(identifier);
     data.extend(iv);
     data.extend(encrypted_owner);
-    data.extend(transaction_reference);
+
+    if is_versioned {
+        data.push(transaction_references.len() as u8);
+    } else if transaction_references.is_empty() {
+        // No reference is represented by a single zero-reference
+        data.extend([0; 32]);
+    }
+    for transaction_reference in transaction_references {
+        data.extend(transaction_reference);
+    }
+
     data.extend([u8::from(is_associated_token_account)]);
     data.extend(metadata);
     data.extend(optional_fee.collector.to_bytes());
     data.extend(optional_fee.amount.to_le_bytes());
 
-    if let Some(memo) = memo {
-        data.extend(memo);
+    if !is_versioned {
+        if let Some(memo) = memo {
+            data.extend(memo);
+        }
+
+        return data;
     }
 
-    let mut hash = solana_program::hash::hash(&data).to_bytes();
+    let extend_optional = |data: &mut Vec<u8>, value: &Option<Vec<u8>>| match value {
+        Some(value) => {
+            data.push(1);
+            data.extend((value.len() as u16).to_le_bytes());
+            data.extend(value);
+        }
+        None => data.push(0),
+    };
 
-    // mask the lower 253 bits
-    hash[31] &= 0b11111;
-    hash
+    extend_optional(&mut data, memo);
+    data.extend((encrypted_metadata.len() as u16).to_le_bytes());
+    data.extend(encrypted_metadata);
+    extend_optional(&mut data, recipient_callback);
+
+    // The forwarded accounts the recipient program is allowed to write to
+    if recipient_callback.is_some() {
+        data.push(recipient_callback_writable_accounts.len() as u8);
+        for account in recipient_callback_writable_accounts {
+            data.extend(account);
+        }
+    }
+
+    data
 }
 
 /// https://github.com/elusiv-privacy/circuits/blob/master/circuits/main/migrate_unary.circom
@@ -560,9 +647,15 @@ impl PublicInputs for MigratePublicInputs {
     }
 }
 
+/// Computes the fee of `public_inputs` (the fee itself is a public input, so we iterate until it is stable)
+///
+/// # Notes
+///
+/// `layout` has to be the [`crate::proof::vkey::VerifyingKeyLayout`] of the deployed [`crate::state::vkey::VKeyAccount`].
 #[cfg(feature = "elusiv-client")]
 pub fn compute_fee_rec<V: crate::proof::vkey::VerifyingKeyInfo, P: PublicInputs>(
     public_inputs: &mut P,
+    layout: crate::proof::vkey::VerifyingKeyLayout,
     program_fee: &crate::state::fee::ProgramFee,
     price: &crate::token::TokenPrice,
 ) {
@@ -571,6 +664,7 @@ pub fn compute_fee_rec<V: crate::proof::vkey::VerifyingKeyInfo, P: PublicInputs>
             crate::proof::verifier::prepare_public_inputs_instructions(
                 &public_inputs.public_signals_skip_mr(),
                 V::public_inputs_count(),
+                layout,
             )
             .len(),
             0,
@@ -583,17 +677,23 @@ pub fn compute_fee_rec<V: crate::proof::vkey::VerifyingKeyInfo, P: PublicInputs>
 
     if fee != public_inputs.join_split_inputs().fee {
         public_inputs.set_fee(fee);
-        compute_fee_rec::<V, P>(public_inputs, program_fee, price)
+        compute_fee_rec::<V, P>(public_inputs, layout, program_fee, price)
     }
 }
 
 #[cfg(feature = "elusiv-client")]
 pub fn compute_fee_rec_lamports<V: crate::proof::vkey::VerifyingKeyInfo, P: PublicInputs>(
     public_inputs: &mut P,
+    layout: crate::proof::vkey::VerifyingKeyLayout,
     program_fee: &crate::state::fee::ProgramFee,
 ) {
     use crate::token::TokenPrice;
-    compute_fee_rec::<V, P>(public_inputs, program_fee, &TokenPrice::new_lamports())
+    compute_fee_rec::<V, P>(
+        public_inputs,
+        layout,
+        program_fee,
+        &TokenPrice::new_lamports(),
+    )
 }
 
 pub fn u256_to_le_limbs(v: U256) -> [u64; 4] {
@@ -1024,6 +1124,28 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_zero_copy_layout() {
+        fn assert_layout<N: BorshSerDeSized + PartialEq + std::fmt::Debug>(v: N) {
+            let bytes = v.try_to_vec().unwrap();
+            let mut zero_copy_bytes = vec![0; N::SIZE];
+            v.write_sized(&mut zero_copy_bytes).unwrap();
+
+            assert_eq!(zero_copy_bytes, bytes);
+            assert_eq!(N::read_sized(&bytes).unwrap(), v);
+        }
+
+        assert_layout(0x12u8);
+        assert_layout(0x1234u16);
+        assert_layout(0x12345678u32);
+        assert_layout(u64::MAX - 1);
+        assert_layout(u128::MAX / 3);
+        assert_layout([3u32, 2, 1]);
+        assert_layout::<U256>(u256_from_str_skip_mr("123456789"));
+        assert_layout(OrdU256(u256_from_str_skip_mr("987654321")));
+        assert_layout(crate::map::ElusivMapPtr(0xABCD));
+    }
+
     #[test]
     fn test_compute_hashed_inputs() {
         let recipient = u256_from_str_skip_mr(
@@ -1057,11 +1179,14 @@ mod test {
                 &identifier,
                 &iv,
                 &encrypted_owner,
-                &solana_pay_id,
+                &[solana_pay_id],
                 is_associated_token_account,
                 &metadata,
                 &optional_fee,
-                &None
+                &None,
+                &[],
+                &None,
+                &[],
             ),
             expected
         );
@@ -1077,13 +1202,332 @@ mod test {
                 &identifier,
                 &iv,
                 &encrypted_owner,
-                &solana_pay_id,
+                &[solana_pay_id],
                 is_associated_token_account,
                 &metadata,
                 &optional_fee,
-                &memo
+                &memo,
+                &[],
+                &None,
+                &[],
             ),
             expected
         );
+
+        // The encrypted metadata is bound to the hash
+        let hash = |encrypted_metadata: &[u8]| {
+            generate_hashed_inputs(
+                &recipient,
+                &identifier,
+                &iv,
+                &encrypted_owner,
+                &[solana_pay_id],
+                is_associated_token_account,
+                &metadata,
+                &optional_fee,
+                &memo,
+                encrypted_metadata,
+                &None,
+                &[],
+            )
+        };
+        assert_ne!(hash(&[1, 2, 3]), expected);
+        assert_ne!(hash(&[1, 2, 3]), hash(&[1, 2, 4]));
+        assert_ne!(hash(&[1, 2, 3]), hash(&[1, 2]));
+
+        // Different splits of the same bytes into the memo and the encrypted metadata result in different hashes
+        let split_hash = |memo: Option<Vec<u8>>, encrypted_metadata: &[u8]| {
+            generate_hashed_inputs(
+                &recipient,
+                &identifier,
+                &iv,
+                &encrypted_owner,
+                &[solana_pay_id],
+                is_associated_token_account,
+                &metadata,
+                &optional_fee,
+                &memo,
+                encrypted_metadata,
+                &None,
+                &[],
+            )
+        };
+        assert_ne!(
+            split_hash(Some(vec![9]), &[5]),
+            split_hash(Some(vec![9, 1, 0, 5]), &[])
+        );
+        assert_ne!(
+            split_hash(Some(vec![9, 1, 0, 5]), &[5]),
+            split_hash(Some(vec![9]), &[5, 1, 0, 5])
+        );
+        assert_ne!(split_hash(None, &[1, 0, 5]), split_hash(Some(vec![]), &[5]));
+
+        // Multiple references are bound in order
+        let references_hash = |transaction_references: &[U256]| {
+            generate_hashed_inputs(
+                &recipient,
+                &identifier,
+                &iv,
+                &encrypted_owner,
+                transaction_references,
+                is_associated_token_account,
+                &metadata,
+                &optional_fee,
+                &memo,
+                &[],
+                &None,
+                &[],
+            )
+        };
+        assert_eq!(references_hash(&[solana_pay_id]), expected);
+        assert_ne!(references_hash(&[solana_pay_id, recipient]), expected);
+        assert_ne!(
+            references_hash(&[solana_pay_id, recipient]),
+            references_hash(&[recipient, solana_pay_id])
+        );
+
+        // The recipient callback is bound to the hash
+        let callback_hash = |recipient_callback: Option<Vec<u8>>| {
+            generate_hashed_inputs(
+                &recipient,
+                &identifier,
+                &iv,
+                &encrypted_owner,
+                &[solana_pay_id],
+                is_associated_token_account,
+                &metadata,
+                &optional_fee,
+                &memo,
+                &[],
+                &recipient_callback,
+                &[],
+            )
+        };
+        assert_eq!(callback_hash(None), expected);
+        assert_ne!(callback_hash(Some(vec![])), expected);
+        assert_ne!(callback_hash(Some(vec![1, 2, 3])), expected);
+        assert_ne!(
+            callback_hash(Some(vec![1, 2, 3])),
+            callback_hash(Some(vec![1, 2]))
+        );
+    }
+
+    /// The inputs of [`hashed_inputs_preimage`]
+    #[derive(PartialEq, Debug, Clone)]
+    struct HashedInputs {
+        recipient: U256,
+        identifier: U256,
+        iv: U256,
+        encrypted_owner: U256,
+        transaction_references: Vec<U256>,
+        is_associated_token_account: bool,
+        metadata: CommitmentMetadata,
+        optional_fee: OptionalFee,
+        memo: Option<Vec<u8>>,
+        encrypted_metadata: Vec<u8>,
+        recipient_callback: Option<Vec<u8>>,
+        recipient_callback_writable_accounts: Vec<U256>,
+    }
+
+    impl HashedInputs {
+        fn preimage(&self) -> Vec<u8> {
+            hashed_inputs_preimage(
+                &self.recipient,
+                &self.identifier,
+                &self.iv,
+                &self.encrypted_owner,
+                &self.transaction_references,
+                self.is_associated_token_account,
+                &self.metadata,
+                &self.optional_fee,
+                &self.memo,
+                &self.encrypted_metadata,
+                &self.recipient_callback,
+                &self.recipient_callback_writable_accounts,
+            )
+        }
+
+        /// Parses the versioned encoding (all bytes need to be consumed)
+        fn parse_v1(data: &[u8]) -> Option<Self> {
+            fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+                if data.len() < len {
+                    return None;
+                }
+                let (value, rest) = data.split_at(len);
+                *data = rest;
+                Some(value)
+            }
+            fn take_u256(data: &mut &[u8]) -> Option<U256> {
+                take(data, 32).map(|v| v.try_into().unwrap())
+            }
+            fn take_vec(data: &mut &[u8]) -> Option<Vec<u8>> {
+                let len = u16::from_le_bytes(take(data, 2)?.try_into().unwrap());
+                take(data, len as usize).map(|v| v.to_vec())
+            }
+            fn take_optional(data: &mut &[u8]) -> Option<Option<Vec<u8>>> {
+                match take(data, 1)?[0] {
+                    0 => Some(None),
+                    1 => Some(Some(take_vec(data)?)),
+                    _ => None,
+                }
+            }
+
+            let data = &mut &data[..];
+            if take_u256(data)? != *HASHED_INPUTS_V1_DOMAIN {
+                return None;
+            }
+
+            let recipient = take_u256(data)?;
+            let identifier = take_u256(data)?;
+            let iv = take_u256(data)?;
+            let encrypted_owner = take_u256(data)?;
+            let references_count = take(data, 1)?[0];
+            let transaction_references = (0..references_count)
+                .map(|_| take_u256(data))
+                .collect::<Option<Vec<U256>>>()?;
+            let is_associated_token_account = take(data, 1)?[0] == 1;
+            let metadata = take(data, CommitmentMetadata::SIZE)?.try_into().unwrap();
+            let optional_fee = OptionalFee {
+                collector: Pubkey::new_from_array(take_u256(data)?),
+                amount: u64::from_le_bytes(take(data, 8)?.try_into().unwrap()),
+            };
+            let memo = take_optional(data)?;
+            let encrypted_metadata = take_vec(data)?;
+            let recipient_callback = take_optional(data)?;
+            let recipient_callback_writable_accounts = match recipient_callback {
+                Some(_) => {
+                    let count = take(data, 1)?[0];
+                    (0..count)
+                        .map(|_| take_u256(data))
+                        .collect::<Option<Vec<U256>>>()?
+                }
+                None => Vec::new(),
+            };
+
+            if !data.is_empty() {
+                return None;
+            }
+
+            Some(Self {
+                recipient,
+                identifier,
+                iv,
+                encrypted_owner,
+                transaction_references,
+                is_associated_token_account,
+                metadata,
+                optional_fee,
+                memo,
+                encrypted_metadata,
+                recipient_callback,
+                recipient_callback_writable_accounts,
+            })
+        }
+    }
+
+    #[test]
+    fn test_hashed_inputs_preimage_reparse() {
+        let base = HashedInputs {
+            recipient: [1; 32],
+            identifier: [2; 32],
+            iv: [3; 32],
+            encrypted_owner: [4; 32],
+            transaction_references: vec![[5; 32]],
+            is_associated_token_account: false,
+            metadata: [6; CommitmentMetadata::SIZE],
+            optional_fee: OptionalFee {
+                collector: Pubkey::new_from_array([7; 32]),
+                amount: 8,
+            },
+            memo: None,
+            encrypted_metadata: Vec::new(),
+            recipient_callback: None,
+            recipient_callback_writable_accounts: Vec::new(),
+        };
+        let mutate = |f: &dyn Fn(&mut HashedInputs)| {
+            let mut inputs = base.clone();
+            f(&mut inputs);
+            inputs
+        };
+
+        let legacy = [
+            base.clone(),
+            mutate(&|i| i.transaction_references = vec![]),
+            mutate(&|i| i.memo = Some(vec![9, 1, 0, 5])),
+        ];
+        let versioned = [
+            mutate(&|i| i.transaction_references = vec![[5; 32], [1; 32]]),
+            mutate(&|i| i.transaction_references = vec![[1; 32], [5; 32]]),
+            mutate(&|i| i.encrypted_metadata = vec![5]),
+            mutate(&|i| {
+                i.memo = Some(vec![9]);
+                i.encrypted_metadata = vec![5];
+            }),
+            mutate(&|i| {
+                i.memo = Some(vec![9, 1, 0, 5]);
+                i.encrypted_metadata = vec![5];
+            }),
+            mutate(&|i| {
+                i.memo = Some(vec![]);
+                i.encrypted_metadata = vec![5];
+            }),
+            mutate(&|i| i.recipient_callback = Some(vec![])),
+            mutate(&|i| i.recipient_callback = Some(vec![0, 1, 0])),
+            mutate(&|i| {
+                i.recipient_callback = Some(vec![0, 1, 0]);
+                i.recipient_callback_writable_accounts = vec![[1; 32]];
+            }),
+            mutate(&|i| {
+                i.recipient_callback = Some(vec![0, 1, 0, 1]);
+                i.recipient_callback_writable_accounts = vec![[1; 32], [2; 32]];
+            }),
+            mutate(&|i| {
+                i.recipient = *HASHED_INPUTS_V1_DOMAIN;
+                i.transaction_references = vec![];
+                i.recipient_callback = Some(vec![1, 2]);
+            }),
+            mutate(&|i| {
+                i.transaction_references = vec![[5; 32], [1; 32], [2; 32], [3; 32]];
+                i.memo = Some(vec![1; 100]);
+                i.encrypted_metadata = vec![2; 256];
+                i.recipient_callback = Some(vec![3; 64]);
+                i.recipient_callback_writable_accounts = vec![[4; 32]; 4];
+            }),
+        ];
+
+        // The versioned encoding can be parsed back into its inputs
+        for inputs in &versioned {
+            assert_eq!(
+                HashedInputs::parse_v1(&inputs.preimage()).as_ref(),
+                Some(inputs)
+            );
+        }
+        for inputs in &legacy {
+            assert_eq!(HashedInputs::parse_v1(&inputs.preimage()), None);
+        }
+
+        // The legacy encoding is unchanged
+        let mut legacy_preimage = [1; 32].to_vec();
+        legacy_preimage.extend([2; 32]);
+        legacy_preimage.extend([3; 32]);
+        legacy_preimage.extend([4; 32]);
+        legacy_preimage.extend([5; 32]);
+        legacy_preimage.push(0);
+        legacy_preimage.extend([6; CommitmentMetadata::SIZE]);
+        legacy_preimage.extend([7; 32]);
+        legacy_preimage.extend(8u64.to_le_bytes());
+        assert_eq!(base.preimage(), legacy_preimage);
+
+        // All preimages are distinct
+        let preimages: Vec<Vec<u8>> = legacy
+            .iter()
+            .chain(versioned.iter())
+            .map(|inputs| inputs.preimage())
+            .collect();
+        for (i, a) in preimages.iter().enumerate() {
+            for b in &preimages[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
     }
 }
@@ -4,23 +4,39 @@ mod common;
 
 use borsh::BorshSerialize;
 use common::*;
+use elusiv::buffer::RingBuffer;
+use elusiv::fields::u64_to_u256_skip_mr;
 use elusiv::instruction::*;
-use elusiv::processor::CommitmentHashRequest;
+use elusiv::processor::{CommitmentHashRequest, MigratableAccountKind};
+use elusiv::proof::verifier::VerificationStep;
+use elusiv::proof::vkey::VerifyingKeyLayout;
 use elusiv::state::commitment::{
-    BaseCommitmentBufferAccount, CommitmentHashingAccount, CommitmentQueue, CommitmentQueueAccount,
+    BaseCommitmentBufferAccount, CommitmentBufferAccount, CommitmentHashingAccount,
+    CommitmentQueue, CommitmentQueueAccount, COMMITMENT_BUFFER_LEN,
 };
 use elusiv::state::program_account::PDAOffset;
 use elusiv::state::queue::{Queue, RingQueue};
 use elusiv::state::{
-    fee::FeeAccount,
-    governor::{FeeCollectorAccount, GovernorAccount, PoolAccount},
-    nullifier::{NullifierAccount, NullifierChildAccount},
-    program_account::{PDAAccount, PDAAccountData, ProgramAccount, SizedAccount},
-    storage::{StorageAccount, MT_COMMITMENT_COUNT},
+    fee::{FeeAccount, ProgramFee},
+    governor::{
+        FeeCollectorAccount, GovernorAccount, PoolAccount, RateLimits, RootHistoryParameters,
+    },
+    nullifier::{NullifierAccount, NullifierChildAccount, NullifierMap},
+    program_account::{
+        MigratableAccount, PDAAccount, PDAAccountData, ProgramAccount, SizedAccount,
+        VersionedAccount,
+    },
+    proof::VerificationAccount,
+    storage::{RootHistoryAccount, StorageAccount, MT_COMMITMENT_COUNT},
+    vkey::{VKeyAccount, VKeyAccountEager},
 };
 use elusiv::token::SPL_TOKEN_COUNT;
-use elusiv_types::split_child_account_data_mut;
+use elusiv::types::OrdU256;
+use elusiv_types::{
+    child_account_size, split_child_account_data_mut, BorshSerDeSized, ElusivOption, ParentAccount,
+};
 use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::pubkey::Pubkey;
 use solana_program_test::*;
 use solana_sdk::signer::Signer;
 
@@ -28,7 +44,7 @@ use solana_sdk::signer::Signer;
 async fn test_setup_initial_accounts() {
     let mut test = start_test_with_setup().await;
 
-    async fn assert_account<T: PDAAccount + SizedAccount>(
+    async fn assert_account<T: PDAAccount + SizedAccount + VersionedAccount>(
         test: &mut ElusivProgramTest,
         pda_offset: PDAOffset,
     ) {
@@ -44,7 +60,7 @@ async fn test_setup_initial_accounts() {
         // Check pda account fields
         let data = PDAAccountData::new(&data).unwrap();
         assert_eq!(data.bump_seed, T::find(pda_offset).1);
-        assert_eq!(data.version, 0);
+        assert_eq!(data.version, T::VERSION);
     }
 
     assert_account::<GovernorAccount>(&mut test, None).await;
@@ -56,6 +72,7 @@ async fn test_setup_initial_accounts() {
     assert_account::<BaseCommitmentBufferAccount>(&mut test, None).await;
 
     assert_account::<StorageAccount>(&mut test, None).await;
+    assert_account::<RootHistoryAccount>(&mut test, None).await;
 }
 
 #[tokio::test]
@@ -135,6 +152,330 @@ async fn test_setup_fee_account() {
     .await;
 }
 
+fn fixture_rate_limits() -> RateLimits {
+    let mut rate_limits = RateLimits {
+        window_slots: 100,
+        ..Default::default()
+    };
+    rate_limits.deposit_caps[0] = 1;
+    rate_limits.withdrawal_caps[1] = 2;
+    rate_limits
+}
+
+fn fixture_root_history() -> RootHistoryParameters {
+    RootHistoryParameters {
+        depth: 3,
+        slot_window: 4,
+    }
+}
+
+/// Governor data in the layout of `version`
+fn governor_fixture(version: u8, program_fee: &ProgramFee) -> Vec<u8> {
+    let mut fixture = Vec::new();
+    PDAAccountData {
+        bump_seed: GovernorAccount::find(None).1,
+        version,
+    }
+    .serialize(&mut fixture)
+    .unwrap();
+    0u32.serialize(&mut fixture).unwrap();
+    program_fee.serialize(&mut fixture).unwrap();
+    7u32.serialize(&mut fixture).unwrap();
+
+    // Trailing `program_version`
+    if version == 0 {
+        123u32.serialize(&mut fixture).unwrap();
+    }
+
+    // Caps with the `window_start` and the volumes of all tokens
+    if version >= 2 {
+        let rate_limits = fixture_rate_limits();
+        rate_limits.window_slots.serialize(&mut fixture).unwrap();
+        5u64.serialize(&mut fixture).unwrap();
+        rate_limits.deposit_caps.serialize(&mut fixture).unwrap();
+        rate_limits.withdrawal_caps.serialize(&mut fixture).unwrap();
+        for _ in 0..rate_limits.deposit_caps.len() * 2 {
+            u64::MAX.serialize(&mut fixture).unwrap();
+        }
+    }
+
+    if version >= 3 {
+        fixture_root_history().serialize(&mut fixture).unwrap();
+    }
+
+    assert_eq!(
+        fixture.len(),
+        GovernorAccount::size_at_version(version).unwrap()
+    );
+    fixture
+}
+
+#[tokio::test]
+async fn test_migrate_governor_account() {
+    let mut test = start_test_with_setup().await;
+    let genesis_fee = genesis_fee(&mut test).await;
+    let governor_pubkey = GovernorAccount::find(None).0;
+    let payer = test.payer();
+    let migrate_ix = |pda_offset: PDAOffset| {
+        ElusivInstruction::migrate_account_instruction(
+            MigratableAccountKind::Governor,
+            None,
+            pda_offset,
+            WritableSignerAccount(payer),
+            WritableUserAccount(governor_pubkey),
+        )
+    };
+
+    let fixture = governor_fixture(GovernorAccount::VERSION - 1, &genesis_fee);
+
+    // Outdated accounts cannot be loaded
+    assert!(GovernorAccount::new(&mut fixture.clone()).is_err());
+
+    // Invalid PDA
+    test.set_program_account_rent_exempt(&elusiv::id(), &governor_pubkey, &fixture)
+        .await;
+    test.ix_should_fail_simple(migrate_ix(Some(1))).await;
+
+    let mut data = test
+        .migrate_fixture::<GovernorAccount>(&elusiv::id(), None, None, &fixture, migrate_ix(None))
+        .await;
+
+    let governor = GovernorAccount::new(&mut data).unwrap();
+    assert_eq!(governor.get_fee_version(), 0);
+    assert_eq!(governor.get_program_fee(), genesis_fee);
+    assert_eq!(governor.get_commitment_batching_rate(), 7);
+    assert_eq!(governor.get_rate_limits(), fixture_rate_limits());
+    assert_eq!(governor.get_root_history(), fixture_root_history());
+    assert!(test.lamports(&governor_pubkey).await.0 >= test.rent(GovernorAccount::SIZE).await.0);
+
+    // Already migrated
+    test.ix_should_fail_simple(nonce_instruction(migrate_ix(None)))
+        .await;
+
+    // Migration across multiple versions
+    test.set_program_account_rent_exempt(
+        &elusiv::id(),
+        &governor_pubkey,
+        &governor_fixture(0, &genesis_fee),
+    )
+    .await;
+    test.ix_should_succeed_simple(nonce_instruction(migrate_ix(None)))
+        .await;
+
+    pda_account!(governor, GovernorAccount, None, None, test);
+    assert_eq!(governor.get_program_fee(), genesis_fee);
+    assert_eq!(governor.get_commitment_batching_rate(), 7);
+    assert_eq!(governor.get_rate_limits(), RateLimits::default());
+    assert_eq!(
+        governor.get_root_history(),
+        RootHistoryParameters::default()
+    );
+    assert_eq!(
+        test.data(&governor_pubkey).await.len(),
+        GovernorAccount::SIZE
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_commitment_buffer_account() {
+    let mut test = start_test_with_setup().await;
+    let buffer_pubkey = CommitmentBufferAccount::find(None).0;
+    let payer = test.payer();
+
+    // Legacy buffer containing three commitments
+    let mut fixture = Vec::new();
+    PDAAccountData {
+        bump_seed: CommitmentBufferAccount::find(None).1,
+        version: 0,
+    }
+    .serialize(&mut fixture)
+    .unwrap();
+    for i in 0..COMMITMENT_BUFFER_LEN {
+        let value = if i < 3 { [i as u8 + 1; 32] } else { [0; 32] };
+        value.serialize(&mut fixture).unwrap();
+    }
+    3u32.serialize(&mut fixture).unwrap();
+    3u32.serialize(&mut fixture).unwrap();
+    assert_eq!(
+        fixture.len(),
+        CommitmentBufferAccount::size_at_version(0).unwrap()
+    );
+
+    let mut data = test
+        .migrate_fixture::<CommitmentBufferAccount>(
+            &elusiv::id(),
+            None,
+            None,
+            &fixture,
+            ElusivInstruction::migrate_account_instruction(
+                MigratableAccountKind::CommitmentBuffer,
+                None,
+                None,
+                WritableSignerAccount(payer),
+                WritableUserAccount(buffer_pubkey),
+            ),
+        )
+        .await;
+
+    // Legacy commitments never expire
+    let buffer = CommitmentBufferAccount::new(&mut data).unwrap();
+    assert_eq!(buffer.len(), 3);
+    assert_eq!(buffer.ptr(), 3);
+    for i in 0..3 {
+        assert_eq!(buffer.find_position(&[i as u8 + 1; 32]), Some(i));
+        assert_eq!(buffer.slot(i), u64::MAX);
+    }
+}
+
+#[tokio::test]
+async fn test_migrate_storage_account() {
+    let mut test = start_test_with_setup().await;
+    let storage_accounts = setup_storage_account(&mut test).await;
+    let storage_pubkey = StorageAccount::find(None).0;
+    let payer = test.payer();
+
+    test.set_pda_account::<StorageAccount, _>(&elusiv::id(), None, None, |data| {
+        let mut storage_account = StorageAccount::new(data).unwrap();
+        storage_account.set_next_commitment_ptr(&5);
+        storage_account.set_mt_roots_count(&3);
+    })
+    .await;
+
+    // Legacy storage account with the `active_mt_root_history` and without the `claimed_commitment_ptr`
+    let data = test.data(&storage_pubkey).await;
+    let mut fixture = data[..StorageAccount::SIZE - 2 * 4].to_vec();
+    fixture.extend([1; 100 * 32]);
+    fixture.extend(3u32.to_le_bytes());
+    assert_eq!(fixture.len(), StorageAccount::size_at_version(0).unwrap());
+    fixture[PDAAccountData::VERSION_OFFSET] = 0;
+
+    let mut data = test
+        .migrate_fixture::<StorageAccount>(
+            &elusiv::id(),
+            None,
+            None,
+            &fixture,
+            ElusivInstruction::migrate_account_instruction(
+                MigratableAccountKind::Storage,
+                None,
+                None,
+                WritableSignerAccount(payer),
+                WritableUserAccount(storage_pubkey),
+            ),
+        )
+        .await;
+
+    let storage_account = StorageAccount::new(&mut data).unwrap();
+    assert_eq!(storage_account.get_next_commitment_ptr(), 5);
+    assert_eq!(storage_account.get_mt_roots_count(), 3);
+    assert_eq!(storage_account.next_claimable_commitment_ptr(), 5);
+    assert!(storage_account.no_claimed_batches());
+    for (i, pubkey) in storage_accounts.iter().enumerate() {
+        assert_eq!(storage_account.get_child_pubkey(i), Some(*pubkey));
+    }
+}
+
+#[tokio::test]
+async fn test_migrate_vkey_account() {
+    let mut test = start_test_with_setup().await;
+    let (vkey_pubkey, bump) = VKeyAccount::find(Some(0));
+    let payer = test.payer();
+    let sub_account_pubkey = Pubkey::new_unique();
+
+    // Legacy vkey account without the `layout`
+    let mut fixture = VKeyAccountEager {
+        pda_data: PDAAccountData {
+            bump_seed: bump,
+            version: 0,
+        },
+        pubkeys: [Some(sub_account_pubkey).into(), None.into()],
+        public_inputs_count: 14,
+        is_frozen: true,
+        authority: ElusivOption::None,
+        version: 1,
+        layout: VerifyingKeyLayout::Window8,
+    }
+    .try_to_vec()
+    .unwrap();
+    fixture.truncate(VKeyAccount::size_at_version(0).unwrap());
+
+    let mut data = test
+        .migrate_fixture::<VKeyAccount>(
+            &elusiv::id(),
+            None,
+            Some(0),
+            &fixture,
+            ElusivInstruction::migrate_account_instruction(
+                MigratableAccountKind::VKey,
+                None,
+                Some(0),
+                WritableSignerAccount(payer),
+                WritableUserAccount(vkey_pubkey),
+            ),
+        )
+        .await;
+
+    let vkey_account = VKeyAccount::new(&mut data).unwrap();
+    assert_eq!(vkey_account.get_child_pubkey(0), Some(sub_account_pubkey));
+    assert_eq!(vkey_account.get_public_inputs_count(), 14);
+    assert!(vkey_account.get_is_frozen());
+    assert_eq!(vkey_account.get_version(), 1);
+    assert_eq!(vkey_account.get_layout(), VerifyingKeyLayout::Window8);
+}
+
+#[tokio::test]
+async fn test_migrate_verification_account() {
+    let mut test = start_test_with_setup().await;
+    let payer = test.payer();
+    let (verification_pubkey, bump) = VerificationAccount::find_with_pubkey(payer, Some(0));
+
+    // Legacy verification account with the tree indices `[1, 2]` (followed by `batch`)
+    let version = VerificationAccount::VERSION - 1;
+    let mut fixture = vec![0; VerificationAccount::size_at_version(version).unwrap()];
+    fixture[0] = bump;
+    fixture[PDAAccountData::VERSION_OFFSET] = version;
+    let len = fixture.len() - <ElusivOption<Pubkey>>::SIZE;
+    fixture[len - 8..len - 4].copy_from_slice(&1u32.to_le_bytes());
+    fixture[len - 4..len].copy_from_slice(&2u32.to_le_bytes());
+
+    let migrate_ix = |pda_pubkey: Pubkey| {
+        ElusivInstruction::migrate_account_instruction(
+            MigratableAccountKind::Verification,
+            Some(pda_pubkey),
+            Some(0),
+            WritableSignerAccount(payer),
+            WritableUserAccount(verification_pubkey),
+        )
+    };
+
+    // Outdated accounts cannot be loaded
+    assert!(VerificationAccount::new(&mut fixture.clone()).is_err());
+
+    // Invalid PDA
+    test.set_program_account_rent_exempt(&elusiv::id(), &verification_pubkey, &fixture)
+        .await;
+    test.ix_should_fail_simple(migrate_ix(Pubkey::new_unique()))
+        .await;
+
+    let mut data = test
+        .migrate_fixture::<VerificationAccount>(
+            &elusiv::id(),
+            Some(payer),
+            Some(0),
+            &fixture,
+            migrate_ix(payer),
+        )
+        .await;
+
+    let verification_account = VerificationAccount::new(&mut data).unwrap();
+    assert_eq!(verification_account.all_tree_indices(), [1, 2]);
+    assert_eq!(
+        verification_account.get_step(),
+        VerificationStep::PublicInputPreparation
+    );
+    assert_eq!(verification_account.get_batch().option(), None);
+}
+
 #[tokio::test]
 async fn test_setup_pda_accounts_invalid_pda() {
     let mut test = start_test().await;
@@ -184,6 +525,89 @@ async fn test_open_new_merkle_tree_duplicate() {
     .await;
 }
 
+#[tokio::test]
+async fn test_migrate_nullifier_child_account() {
+    let mut test = start_test().await;
+    let child_accounts = create_merkle_tree(&mut test, 0).await;
+    let child_account = child_accounts[1];
+    let payer = test.payer();
+    let migrate_ix = |child_index: u32| {
+        ElusivInstruction::migrate_nullifier_child_account_instruction(
+            0,
+            child_index,
+            WritableSignerAccount(payer),
+            WritableUserAccount(child_account),
+        )
+    };
+    let build_ix = || {
+        ElusivInstruction::build_nullifier_child_account_index_instruction(
+            0,
+            1,
+            WritableUserAccount(child_account),
+        )
+    };
+
+    // Child-account in the legacy format
+    let values: Vec<_> = (0..1000)
+        .rev()
+        .map(|i| OrdU256(u64_to_u256_skip_mr(i * 2)))
+        .collect();
+    let mut fixture = test.data(&child_account).await;
+    {
+        let mut map = NullifierMap::new(&mut fixture[1..]);
+        map.start_index_migration();
+        for value in &values {
+            map.try_insert_default(*value).unwrap();
+        }
+    }
+    fixture.truncate(child_account_size(NullifierMap::LEGACY_SIZE));
+    test.set_account_rent_exempt(&child_account, &fixture, &elusiv::id())
+        .await;
+
+    // Index cannot be built before the reallocation
+    test.ix_should_fail_simple(build_ix()).await;
+
+    // Invalid child-account index
+    test.ix_should_fail_simple(migrate_ix(0)).await;
+
+    test.ix_should_succeed_simple(migrate_ix(1)).await;
+    assert_eq!(
+        test.data(&child_account).await.len(),
+        NullifierChildAccount::SIZE
+    );
+    assert!(
+        test.lamports(&child_account).await.0 >= test.rent(NullifierChildAccount::SIZE).await.0
+    );
+
+    // Already migrated
+    test.ix_should_fail_simple(nonce_instruction(migrate_ix(1)))
+        .await;
+
+    let mut instructions_count = 0;
+    loop {
+        let mut data = test.data(&child_account).await;
+        if NullifierMap::new(&mut data[1..]).is_indexed() {
+            break;
+        }
+
+        test.ix_should_succeed_simple(nonce_instruction(build_ix()))
+            .await;
+        instructions_count += 1;
+    }
+    assert!(instructions_count > 1);
+
+    // Index is complete
+    test.ix_should_fail_simple(nonce_instruction(build_ix()))
+        .await;
+
+    let mut data = test.data(&child_account).await;
+    let mut map = NullifierMap::new(&mut data[1..]);
+    for value in values {
+        assert!(map.contains(&value).is_some());
+    }
+    assert!(map.contains(&OrdU256(u64_to_u256_skip_mr(1))).is_none());
+}
+
 #[tokio::test]
 async fn test_reset_active_mt() {
     let mut test = start_test().await;
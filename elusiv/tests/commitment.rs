@@ -6,7 +6,8 @@ use ark_ff::Zero;
 use common::*;
 use elusiv::{
     commitment::{
-        commitment_hash_computation_instructions, commitments_per_batch,
+        commitment_hash_computation_instructions, commitment_hash_computation_round_compute_units,
+        commitments_per_batch,
         poseidon_hash::{full_poseidon2_hash, BinarySpongeHashingState},
         BaseCommitmentHashComputation, COMMITMENT_HASH_COMPUTE_BUDGET,
     },
@@ -14,13 +15,17 @@ use elusiv::{
     instruction::{
         ElusivInstruction, SignerAccount, UserAccount, WritableSignerAccount, WritableUserAccount,
     },
-    processor::{program_token_account_address, BaseCommitmentHashRequest, CommitmentHashRequest},
+    processor::{
+        deposit_metadata_hash, program_token_account_address, BaseCommitmentHashRequest,
+        CommitmentHashRequest,
+    },
     state::{
+        apa::{apa_proposal_account, apa_target_map_account, WARDEN_NETWORK_PROGRAM_ID},
         commitment::{
-            BaseCommitmentHashingAccount, CommitmentHashingAccount, CommitmentQueue,
-            CommitmentQueueAccount,
+            commitment_hashing_account_offset, BaseCommitmentHashingAccount,
+            CommitmentHashingAccount, CommitmentQueue, CommitmentQueueAccount,
         },
-        governor::{FeeCollectorAccount, GovernorAccount, PoolAccount},
+        governor::{FeeCollectorAccount, GovernorAccount, PoolAccount, RateLimitAccount},
         metadata::{CommitmentMetadata, MetadataQueue, MetadataQueueAccount},
         program_account::{PDAAccount, ProgramAccount, SizedAccount},
         queue::{Queue, RingQueue},
@@ -31,7 +36,9 @@ use elusiv::{
 };
 use elusiv_computation::PartialComputation;
 use elusiv_types::{tokens::Price, BorshSerDeSized};
-use solana_program::{native_token::LAMPORTS_PER_SOL, pubkey::Pubkey, system_program};
+use solana_program::{
+    instruction::AccountMeta, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey, system_program,
+};
 use solana_program_test::*;
 
 async fn enqueue_commitments(
@@ -77,7 +84,9 @@ async fn test_store_base_commitment_lamports_transfer() {
     let pool = PoolAccount::find(None).0;
     let fee_collector = FeeCollectorAccount::find(None).0;
 
-    let request = base_commitment_request(
+    let encrypted_metadata_accounts = setup_encrypted_metadata_account(&mut test).await;
+
+    let mut request = base_commitment_request(
         "8337064132573119120838379738103457054645361649757131991036638108422638197362",
         "139214303935475888711984321184227760578793579443975701453971046059378311483",
         0,
@@ -87,6 +96,8 @@ async fn test_store_base_commitment_lamports_transfer() {
         0,
     );
     let metadata = CommitmentMetadata::default();
+    let encrypted_metadata = vec![7; 64];
+    request.metadata_hash = deposit_metadata_hash(&metadata, &encrypted_metadata);
 
     let fee = genesis_fee(&mut test).await;
     let subvention = fee.base_commitment_subvention.0;
@@ -121,6 +132,7 @@ async fn test_store_base_commitment_lamports_transfer() {
             hashing_account_bump,
             request.clone(),
             metadata,
+            encrypted_metadata.clone(),
             SignerAccount(client.pubkey),
             WritableUserAccount(client.pubkey),
             WritableSignerAccount(warden.pubkey),
@@ -129,31 +141,477 @@ async fn test_store_base_commitment_lamports_transfer() {
             WritableUserAccount(fee_collector),
             UserAccount(sol_price_account),
             UserAccount(sol_price_account),
+            &writable_user_accounts(&encrypted_metadata_accounts[..1]),
             UserAccount(system_program::id()),
+            &[], // clients are not required to supply the APA accounts
         ),
         &[&client.keypair, &warden.keypair],
     )
     .await;
 
-    // Client has zero lamports
-    assert_eq!(0, client.lamports(&mut test).await);
-
-    // Fee collector has network-fee as lamports
-    assert_eq!(
-        network_fee,
-        test.pda_lamports(&fee_collector, FeeCollectorAccount::SIZE)
-            .await
-            .0
-    );
-
-    // Pool has request.amount + computation_fee as lamports
-    assert_eq!(
-        request.amount + computation_fee,
-        test.pda_lamports(&pool, PoolAccount::SIZE).await.0
-    );
-
-    // Warden has computation_fee lamports
-    assert_eq!(computation_fee, warden.lamports(&mut test).await);
+    // Client has zero lamports
+    assert_eq!(0, client.lamports(&mut test).await);
+
+    // Fee collector has network-fee as lamports
+    assert_eq!(
+        network_fee,
+        test.pda_lamports(&fee_collector, FeeCollectorAccount::SIZE)
+            .await
+            .0
+    );
+
+    // Pool has request.amount + computation_fee as lamports
+    assert_eq!(
+        request.amount + computation_fee,
+        test.pda_lamports(&pool, PoolAccount::SIZE).await.0
+    );
+
+    // Warden has computation_fee lamports
+    assert_eq!(computation_fee, warden.lamports(&mut test).await);
+
+    // The encrypted metadata is available for scanning
+    encrypted_metadata_account(None, &mut test, |encrypted_metadata_account| {
+        assert_eq!(
+            encrypted_metadata_account
+                .get_encrypted_metadata(0)
+                .unwrap(),
+            (request.commitment.reduce(), encrypted_metadata.clone())
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_store_base_commitment_deposit_rate_limit() {
+    let mut test = start_test_with_setup().await;
+    let client = test.new_actor().await;
+    let warden = test.new_actor().await;
+
+    let request = base_commitment_request(
+        "8337064132573119120838379738103457054645361649757131991036638108422638197362",
+        "139214303935475888711984321184227760578793579443975701453971046059378311483",
+        0,
+        1_000_000_000,
+        LAMPORTS_TOKEN_ID,
+        0,
+        0,
+    );
+    let metadata = CommitmentMetadata::default();
+
+    let fee = genesis_fee(&mut test).await;
+    let subvention = fee.base_commitment_subvention.0;
+    let computation_fee = (fee.base_commitment_hash_computation_fee()
+        + fee.commitment_hash_computation_fee(request.min_batching_rate))
+    .unwrap()
+    .0;
+    let network_fee = fee.base_commitment_network_fee.calc(request.amount);
+    let hashing_account_rent = test.rent(BaseCommitmentHashingAccount::SIZE).await;
+
+    client
+        .airdrop(
+            0,
+            request.amount + computation_fee + network_fee - subvention,
+            &mut test,
+        )
+        .await;
+    warden
+        .airdrop(0, computation_fee + hashing_account_rent.0, &mut test)
+        .await;
+    test.airdrop(
+        &FeeCollectorAccount::find(None).0,
+        Lamports(subvention).into_token_strict(),
+    )
+    .await;
+
+    let sol_price_account = test.token_to_usd_price_pyth_account(0);
+    let store_ix = |rate_limit_account: Option<Pubkey>| {
+        let mut ix = ElusivInstruction::store_base_commitment_instruction(
+            0,
+            BaseCommitmentHashingAccount::find(Some(0)).1,
+            request.clone(),
+            metadata,
+            Vec::new(),
+            SignerAccount(client.pubkey),
+            WritableUserAccount(client.pubkey),
+            WritableSignerAccount(warden.pubkey),
+            WritableUserAccount(warden.pubkey),
+            WritableUserAccount(PoolAccount::find(None).0),
+            WritableUserAccount(FeeCollectorAccount::find(None).0),
+            UserAccount(sol_price_account),
+            UserAccount(sol_price_account),
+            &[],
+            UserAccount(system_program::id()),
+            &[],
+        );
+        if let Some(rate_limit_account) = rate_limit_account {
+            ix.accounts
+                .push(AccountMeta::new(rate_limit_account, false));
+        }
+        ix.accounts.push(AccountMeta::new_readonly(
+            apa_target_map_account(&client.pubkey).0,
+            false,
+        ));
+        ix
+    };
+    let rate_limit_account = RateLimitAccount::find(Some(LAMPORTS_TOKEN_ID.into())).0;
+
+    // Deposit cap exceeded
+    set_rate_limits(&mut test, 100, LAMPORTS_TOKEN_ID, request.amount - 1, 0).await;
+    test.ix_should_fail(
+        store_ix(Some(rate_limit_account)),
+        &[&client.keypair, &warden.keypair],
+    )
+    .await;
+
+    // Missing `RateLimitAccount`
+    set_rate_limits(&mut test, 100, LAMPORTS_TOKEN_ID, request.amount, 0).await;
+    test.ix_should_fail(store_ix(None), &[&client.keypair, &warden.keypair])
+        .await;
+
+    test.ix_should_succeed(
+        store_ix(Some(rate_limit_account)),
+        &[&client.keypair, &warden.keypair],
+    )
+    .await;
+
+    pda_account!(
+        rate_limit,
+        RateLimitAccount,
+        None,
+        Some(LAMPORTS_TOKEN_ID.into()),
+        test
+    );
+    assert_eq!(rate_limit.get_window().deposit_volume, request.amount);
+}
+
+#[tokio::test]
+async fn test_store_base_commitment_cpi() {
+    let mut test = start_test_with_setup().await;
+
+    let pool = PoolAccount::find(None).0;
+    let fee_collector = FeeCollectorAccount::find(None).0;
+
+    // The sender is a PDA of the invoking program
+    let seed = b"payroll";
+    let (sender, _) = mock_program::cpi_signer(seed);
+
+    let request = base_commitment_request(
+        "8337064132573119120838379738103457054645361649757131991036638108422638197362",
+        "139214303935475888711984321184227760578793579443975701453971046059378311483",
+        0,
+        1_000_000_000,
+        LAMPORTS_TOKEN_ID,
+        0,
+        0,
+    );
+    let metadata = CommitmentMetadata::default();
+
+    let fee = genesis_fee(&mut test).await;
+    let subvention = fee.base_commitment_subvention.0;
+    let computation_fee = (fee.base_commitment_hash_computation_fee()
+        + fee.commitment_hash_computation_fee(request.min_batching_rate))
+    .unwrap()
+    .0;
+    let network_fee = fee.base_commitment_network_fee.calc(request.amount);
+    let hashing_account_rent = test.rent(BaseCommitmentHashingAccount::SIZE).await;
+
+    test.airdrop_lamports(
+        &sender,
+        request.amount + computation_fee + network_fee + hashing_account_rent.0,
+    )
+    .await;
+    test.airdrop(&fee_collector, Lamports(subvention).into_token_strict())
+        .await;
+
+    let store_ix = elusiv::cpi::store_base_commitment_cpi_instruction(
+        0,
+        request.clone(),
+        metadata,
+        Vec::new(),
+        sender,
+        sender,
+        None,
+        false,
+        None,
+    )
+    .unwrap();
+
+    // The sender has to sign
+    let mut unsigned_store_ix = store_ix.clone();
+    for account in unsigned_store_ix.accounts.iter_mut() {
+        account.is_signer = false;
+    }
+    test.ix_should_fail_simple(unsigned_store_ix).await;
+
+    test.ix_should_succeed_simple(mock_program::cpi_instruction(seed, store_ix))
+        .await;
+
+    pda_account!(
+        hash_account,
+        BaseCommitmentHashingAccount,
+        None,
+        Some(0),
+        test
+    );
+    assert_eq!(hash_account.get_fee_payer(), sender.to_bytes());
+
+    // Sender only keeps the subvention
+    assert_eq!(subvention, test.lamports(&sender).await.0);
+    assert_eq!(
+        network_fee,
+        test.pda_lamports(&fee_collector, FeeCollectorAccount::SIZE)
+            .await
+            .0
+    );
+    assert_eq!(
+        request.amount + computation_fee,
+        test.pda_lamports(&pool, PoolAccount::SIZE).await.0
+    );
+
+    // The warden network performs the hash computation
+    for _ in 0..BaseCommitmentHashComputation::IX_COUNT {
+        test.tx_should_succeed_simple(&[
+            request_compute_units(BaseCommitmentHashComputation::COMPUTE_BUDGET_PER_IX),
+            ElusivInstruction::compute_base_commitment_hash_instruction(0),
+        ])
+        .await;
+    }
+
+    test.ix_should_succeed_simple(
+        ElusivInstruction::finalize_base_commitment_hash_instruction(
+            0,
+            0,
+            WritableUserAccount(sender),
+        ),
+    )
+    .await;
+
+    // Sender is refunded the rent and the base commitment hash computation fee
+    assert_eq!(
+        subvention + hashing_account_rent.0 + fee.base_commitment_hash_computation_fee().0,
+        test.lamports(&sender).await.0
+    );
+}
+
+#[tokio::test]
+async fn test_store_base_commitment_cpi_token() {
+    let mut test = start_test_with_setup().await;
+    test.create_spl_token(USDC_TOKEN_ID).await;
+    enable_program_token_account::<PoolAccount>(&mut test, USDC_TOKEN_ID, None).await;
+    enable_program_token_account::<FeeCollectorAccount>(&mut test, USDC_TOKEN_ID, None).await;
+
+    let pool_account = program_token_account_address::<PoolAccount>(USDC_TOKEN_ID, None).unwrap();
+    let fee_collector_account =
+        program_token_account_address::<FeeCollectorAccount>(USDC_TOKEN_ID, None).unwrap();
+
+    // The sender is a PDA of the invoking program and passed twice (as `sender` and `fee_payer`)
+    let seed = b"payroll";
+    let (sender, _) = mock_program::cpi_signer(seed);
+    let sender_account = test.create_spl_token_account(&sender, USDC_TOKEN_ID).await;
+
+    let sol_usd_price = Price {
+        price: 41,
+        conf: 0,
+        expo: 0,
+    };
+    let usdc_usd_price = Price {
+        price: 1,
+        conf: 0,
+        expo: 0,
+    };
+    test.set_token_to_usd_price_pyth(0, sol_usd_price).await;
+    test.set_token_to_usd_price_pyth(USDC_TOKEN_ID, usdc_usd_price)
+        .await;
+
+    let request = base_commitment_request(
+        "8337064132573119120838379738103457054645361649757131991036638108422638197362",
+        "139214303935475888711984321184227760578793579443975701453971046059378311483",
+        0,
+        1_000_000,
+        USDC_TOKEN_ID,
+        0,
+        0,
+    );
+
+    let price =
+        TokenPrice::new_from_sol_price(sol_usd_price, usdc_usd_price, USDC_TOKEN_ID).unwrap();
+    let fee = genesis_fee(&mut test).await;
+    let subvention = fee
+        .base_commitment_subvention
+        .into_token(&price, USDC_TOKEN_ID)
+        .unwrap();
+    let computation_fee = (fee.base_commitment_hash_computation_fee()
+        + fee.commitment_hash_computation_fee(request.min_batching_rate))
+    .unwrap();
+    let network_fee = fee.base_commitment_network_fee.calc(request.amount);
+    let hashing_account_rent = test.rent(BaseCommitmentHashingAccount::SIZE).await;
+
+    // The token fee is transferred from the sender to itself, so only the amount and network fee are required
+    test.airdrop(
+        &sender_account,
+        Token::new(USDC_TOKEN_ID, request.amount + network_fee),
+    )
+    .await;
+    test.airdrop_lamports(&sender, computation_fee.0 + hashing_account_rent.0)
+        .await;
+    test.airdrop(&fee_collector_account, subvention).await;
+
+    let store_ix = elusiv::cpi::store_base_commitment_cpi_instruction(
+        0,
+        request.clone(),
+        CommitmentMetadata::default(),
+        Vec::new(),
+        sender,
+        sender_account,
+        None,
+        false,
+        None,
+    )
+    .unwrap();
+    test.ix_should_succeed_simple(mock_program::cpi_instruction(seed, store_ix))
+        .await;
+
+    // Sender keeps the subvention (tokens) and paid the computation fee and rent (lamports)
+    assert_eq!(subvention.amount(), test.spl_balance(&sender_account).await);
+    assert_eq!(0, test.lamports(&sender).await.0);
+
+    assert_eq!(network_fee, test.spl_balance(&fee_collector_account).await);
+    assert_eq!(request.amount, test.spl_balance(&pool_account).await);
+    assert_eq!(
+        computation_fee.0,
+        test.pda_lamports(&PoolAccount::find(None).0, PoolAccount::SIZE)
+            .await
+            .0
+    );
+
+    pda_account!(
+        hash_account,
+        BaseCommitmentHashingAccount,
+        None,
+        Some(0),
+        test
+    );
+    assert_eq!(hash_account.get_fee_payer(), sender.to_bytes());
+}
+
+#[tokio::test]
+async fn test_store_base_commitment_apa_restrictions() {
+    let mut test = start_test_with_setup().await;
+    let client = test.new_actor().await;
+    let warden = test.new_actor().await;
+
+    let request = base_commitment_request(
+        "8337064132573119120838379738103457054645361649757131991036638108422638197362",
+        "139214303935475888711984321184227760578793579443975701453971046059378311483",
+        0,
+        1_000_000_000,
+        LAMPORTS_TOKEN_ID,
+        0,
+        0,
+    );
+
+    let fee = genesis_fee(&mut test).await;
+    let subvention = fee.base_commitment_subvention.0;
+    let computation_fee = (fee.base_commitment_hash_computation_fee()
+        + fee.commitment_hash_computation_fee(request.min_batching_rate))
+    .unwrap()
+    .0;
+    let network_fee = fee.base_commitment_network_fee.calc(request.amount);
+    let hashing_account_rent = test.rent(BaseCommitmentHashingAccount::SIZE).await;
+
+    client
+        .airdrop(
+            0,
+            request.amount + computation_fee + network_fee - subvention,
+            &mut test,
+        )
+        .await;
+    warden
+        .airdrop(0, computation_fee + hashing_account_rent.0, &mut test)
+        .await;
+    test.airdrop(
+        &FeeCollectorAccount::find(None).0,
+        Lamports(subvention).into_token_strict(),
+    )
+    .await;
+
+    let sol_price_account = test.token_to_usd_price_pyth_account(0);
+    let (apa_target_map, _) = apa_target_map_account(&client.pubkey);
+    let store_ix = |apa_accounts: &[UserAccount]| {
+        ElusivInstruction::store_base_commitment_instruction(
+            0,
+            BaseCommitmentHashingAccount::find(Some(0)).1,
+            request.clone(),
+            CommitmentMetadata::default(),
+            Vec::new(),
+            SignerAccount(client.pubkey),
+            WritableUserAccount(client.pubkey),
+            WritableSignerAccount(warden.pubkey),
+            WritableUserAccount(warden.pubkey),
+            WritableUserAccount(PoolAccount::find(None).0),
+            WritableUserAccount(FeeCollectorAccount::find(None).0),
+            UserAccount(sol_price_account),
+            UserAccount(sol_price_account),
+            &[],
+            UserAccount(system_program::id()),
+            apa_accounts,
+        )
+    };
+
+    // Outcast
+    test.set_apa_proposal(
+        &WARDEN_NETWORK_PROGRAM_ID,
+        &client.pubkey,
+        0,
+        MockApaLevel::Outcast,
+        None,
+    )
+    .await;
+    let apa_proposal = apa_proposal_account(0).0;
+    test.ix_should_fail(
+        store_ix(&[UserAccount(apa_target_map), UserAccount(apa_proposal)]),
+        &[&client.keypair, &warden.keypair],
+    )
+    .await;
+
+    // Omitted APA accounts
+    test.ix_should_fail(store_ix(&[]), &[&client.keypair, &warden.keypair])
+        .await;
+
+    // Target map of a different target
+    test.ix_should_fail(
+        store_ix(&[
+            UserAccount(apa_target_map_account(&warden.pubkey).0),
+            UserAccount(apa_proposal),
+        ]),
+        &[&client.keypair, &warden.keypair],
+    )
+    .await;
+
+    // Invalid proposal account
+    test.ix_should_fail(
+        store_ix(&[
+            UserAccount(apa_target_map),
+            UserAccount(apa_target_map_account(&warden.pubkey).0),
+        ]),
+        &[&client.keypair, &warden.keypair],
+    )
+    .await;
+
+    // Outcast for a different token
+    test.set_apa_proposal(
+        &WARDEN_NETWORK_PROGRAM_ID,
+        &client.pubkey,
+        0,
+        MockApaLevel::Outcast,
+        Some(USDC_TOKEN_ID),
+    )
+    .await;
+    test.ix_should_succeed(
+        store_ix(&[UserAccount(apa_target_map), UserAccount(apa_proposal)]),
+        &[&client.keypair, &warden.keypair],
+    )
+    .await;
 }
 
 #[tokio::test]
@@ -237,6 +695,7 @@ async fn test_store_base_commitment_token_transfer() {
             hashing_account_bump,
             request.clone(),
             metadata,
+            Vec::new(),
             SignerAccount(client.pubkey),
             WritableUserAccount(client.get_token_account(USDC_TOKEN_ID)),
             WritableSignerAccount(warden.pubkey),
@@ -245,7 +704,9 @@ async fn test_store_base_commitment_token_transfer() {
             WritableUserAccount(fee_collector_account),
             UserAccount(sol_price_account),
             UserAccount(token_price_account),
+            &[],
             UserAccount(spl_token::id()),
+            &[UserAccount(apa_target_map_account(&client.pubkey).0)],
         ),
         &[&client.keypair, &warden.keypair],
     )
@@ -316,6 +777,8 @@ async fn test_base_commitment_lamports() {
         0,
         request0.clone(),
         metadata,
+        Vec::new(),
+        None,
         client.pubkey,
         warden_a.pubkey,
     );
@@ -366,6 +829,7 @@ async fn test_base_commitment_lamports() {
             hashing_account_bump,
             request0.clone(),
             metadata,
+            Vec::new(),
             SignerAccount(client.pubkey),
             WritableUserAccount(client.pubkey),
             WritableSignerAccount(warden_a.pubkey),
@@ -374,7 +838,9 @@ async fn test_base_commitment_lamports() {
             WritableUserAccount(fee_collector),
             UserAccount(system_program::id()),
             UserAccount(system_program::id()),
+            &[],
             UserAccount(system_program::id()),
+            &[UserAccount(apa_target_map_account(&client.pubkey).0)],
         ),
         &[&client.keypair, &warden_a.keypair],
     )
@@ -387,6 +853,7 @@ async fn test_base_commitment_lamports() {
             hashing_account_bump,
             request0.clone(),
             metadata,
+            Vec::new(),
             SignerAccount(client.pubkey),
             WritableUserAccount(client.pubkey),
             WritableSignerAccount(warden_a.pubkey),
@@ -395,7 +862,9 @@ async fn test_base_commitment_lamports() {
             WritableUserAccount(pool),
             UserAccount(system_program::id()),
             UserAccount(system_program::id()),
+            &[],
             UserAccount(system_program::id()),
+            &[UserAccount(apa_target_map_account(&client.pubkey).0)],
         ),
         &[&client.keypair, &warden_a.keypair],
     )
@@ -453,6 +922,8 @@ async fn test_base_commitment_lamports() {
             0,
             request1.clone(),
             metadata,
+            Vec::new(),
+            None,
             client.pubkey,
             warden_a.pubkey,
         ),
@@ -466,6 +937,8 @@ async fn test_base_commitment_lamports() {
             1,
             request0.clone(),
             metadata,
+            Vec::new(),
+            None,
             client.pubkey,
             warden_b.pubkey,
         ),
@@ -479,6 +952,8 @@ async fn test_base_commitment_lamports() {
             1,
             request1.clone(),
             metadata,
+            Vec::new(),
+            None,
             client.pubkey,
             warden_b.pubkey,
         ),
@@ -517,6 +992,13 @@ async fn test_base_commitment_lamports() {
         WritableUserAccount(warden_a.pubkey),
     );
 
+    let mut profile = ComputeUnitProfile::new(
+        "base_commitment_hash",
+        BaseCommitmentHashComputation::COMPUTE_BUDGET_PER_IX,
+        &BaseCommitmentHashComputation::INSTRUCTION_ROUNDS,
+        BaseCommitmentHashComputation::ROUND_COMPUTE_UNITS,
+    );
+
     // Compute each base_commitment_hash
     for _ in 0..BaseCommitmentHashComputation::IX_COUNT {
         // Finalization will always fail before completion
@@ -530,12 +1012,18 @@ async fn test_base_commitment_lamports() {
         }
 
         // Success for correct compute budget
-        test.tx_should_succeed_simple(&[
-            request_compute_units(required_compute_budget),
-            compute_ix.clone(),
-        ])
-        .await;
+        profile
+            .tx_should_succeed(
+                &mut test,
+                &[
+                    request_compute_units(required_compute_budget),
+                    compute_ix.clone(),
+                ],
+                &[],
+            )
+            .await;
     }
+    profile.finish();
 
     // No compensation for the warden
     assert_eq!(0, warden_a.lamports(&mut test).await);
@@ -682,6 +1170,7 @@ async fn test_base_commitment_token() {
             hashing_account_bump,
             request.clone(),
             metadata,
+            Vec::new(),
             SignerAccount(client.pubkey),
             WritableUserAccount(client.get_token_account(USDC_TOKEN_ID)),
             WritableSignerAccount(warden.pubkey),
@@ -690,7 +1179,9 @@ async fn test_base_commitment_token() {
             WritableUserAccount(fee_collector_account),
             UserAccount(sol_price_account),
             UserAccount(token_price_account),
+            &[],
             UserAccount(spl_token::id()),
+            &[UserAccount(apa_target_map_account(&client.pubkey).0)],
         ),
         &[&client.keypair, &warden.keypair],
     )
@@ -763,6 +1254,7 @@ pub fn base_commitment_request(
         token_id,
         fee_version,
         min_batching_rate,
+        metadata_hash: deposit_metadata_hash(&CommitmentMetadata::default(), &[]),
     }
 }
 
@@ -772,6 +1264,7 @@ async fn test_single_commitment() {
     let warden = test.new_actor().await;
 
     setup_storage_account(&mut test).await;
+    let root_history_accounts = setup_root_history_account(&mut test).await;
     setup_metadata_account(&mut test).await;
 
     let storage_accounts = storage_accounts(&mut test).await;
@@ -816,8 +1309,11 @@ async fn test_single_commitment() {
 
     // Init succeeds
     test.tx_should_succeed_simple(&[
-        ElusivInstruction::init_commitment_hash_setup_instruction(false, &[]),
+        ElusivInstruction::init_commitment_hash_setup_instruction(false, 0, &[]),
         ElusivInstruction::init_commitment_hash_instruction(
+            0,
+            false,
+            0,
             false,
             &writable_user_accounts(&metadata_accounts),
         ),
@@ -845,8 +1341,11 @@ async fn test_single_commitment() {
 
     // Second init fails, since a hashing is already active
     test.tx_should_fail_simple(&[
-        ElusivInstruction::init_commitment_hash_setup_instruction(false, &[]),
+        ElusivInstruction::init_commitment_hash_setup_instruction(false, 0, &[]),
         ElusivInstruction::init_commitment_hash_instruction(
+            0,
+            false,
+            0,
             false,
             &writable_user_accounts(&metadata_accounts),
         ),
@@ -854,10 +1353,13 @@ async fn test_single_commitment() {
     .await;
 
     let finalize_ix = ElusivInstruction::finalize_commitment_hash_instruction(
+        0,
         &writable_user_accounts(&storage_accounts),
+        &writable_user_accounts(&root_history_accounts),
     );
 
     let compute_ix = ElusivInstruction::compute_commitment_hash_instruction(
+        0,
         0,
         0,
         WritableSignerAccount(warden.pubkey),
@@ -934,9 +1436,18 @@ async fn test_single_commitment() {
             hash = full_poseidon2_hash(hash, u256_to_fr_skip_mr(&EMPTY_TREE[i]));
         }
         assert_eq!(fr_to_u256_le(&hash), s.get_root().unwrap());
+    })
+    .await;
 
-        // Root should be equal to first mt_root_history value
-        assert_eq!(s.get_root().unwrap(), s.get_active_mt_root_history(0));
+    // Root should be equal to the first root history entry
+    root_history_account(None, &mut test, |r| {
+        assert_eq!(r.get_roots_count(), 1);
+        assert_eq!(
+            r.get_entry(0).unwrap().root,
+            u256_from_str(
+                "11500204619817968836204864831937045342731531929677521260156990135685848035575"
+            )
+        );
     })
     .await;
 
@@ -1016,6 +1527,7 @@ async fn test_commitment_correct_storage_account_insertion() {
     let mut test = start_test_with_setup().await;
 
     setup_storage_account(&mut test).await;
+    let root_history_accounts = setup_root_history_account(&mut test).await;
     let storage_accounts = storage_accounts(&mut test).await;
 
     let len = commitment_hash_computation_instructions(0).len() as u32;
@@ -1034,7 +1546,9 @@ async fn test_commitment_correct_storage_account_insertion() {
         .await;
 
         test.ix_should_succeed_simple(ElusivInstruction::finalize_commitment_hash_instruction(
+            0,
             &writable_user_accounts(&storage_accounts),
+            &writable_user_accounts(&root_history_accounts),
         ))
         .await;
     }
@@ -1057,6 +1571,7 @@ async fn test_commitment_hash_multiple_commitments_zero_batch() {
     let warden = test.new_actor().await;
 
     setup_storage_account(&mut test).await;
+    let root_history_accounts = setup_root_history_account(&mut test).await;
     setup_metadata_account(&mut test).await;
 
     let storage_accounts = storage_accounts(&mut test).await;
@@ -1140,9 +1655,13 @@ async fn test_commitment_hash_multiple_commitments_zero_batch() {
         test.tx_should_succeed_simple(&[
             ElusivInstruction::init_commitment_hash_setup_instruction(
                 false,
+                0,
                 &user_accounts(&storage_accounts),
             ),
             ElusivInstruction::init_commitment_hash_instruction(
+                0,
+                false,
+                0,
                 false,
                 &writable_user_accounts(&metadata_accounts),
             ),
@@ -1154,6 +1673,7 @@ async fn test_commitment_hash_multiple_commitments_zero_batch() {
                 &[
                     request_compute_units(COMMITMENT_HASH_COMPUTE_BUDGET),
                     ElusivInstruction::compute_commitment_hash_instruction(
+                        0,
                         0,
                         0,
                         WritableSignerAccount(warden.pubkey),
@@ -1165,7 +1685,9 @@ async fn test_commitment_hash_multiple_commitments_zero_batch() {
         }
 
         test.ix_should_succeed_simple(ElusivInstruction::finalize_commitment_hash_instruction(
+            0,
             &writable_user_accounts(&storage_accounts),
+            &writable_user_accounts(&root_history_accounts),
         ))
         .await;
 
@@ -1214,6 +1736,7 @@ async fn test_commitment_hash_with_batching_rate(
     let warden = test.new_actor().await;
 
     setup_storage_account(&mut test).await;
+    let root_history_accounts = setup_root_history_account(&mut test).await;
     setup_metadata_account(&mut test).await;
 
     let storage_accounts = storage_accounts(&mut test).await;
@@ -1248,33 +1771,50 @@ async fn test_commitment_hash_with_batching_rate(
     test.tx_should_succeed_simple(&[
         ElusivInstruction::init_commitment_hash_setup_instruction(
             false,
+            0,
             &user_accounts(&storage_accounts),
         ),
         ElusivInstruction::init_commitment_hash_instruction(
+            0,
+            false,
+            0,
             false,
             &writable_user_accounts(&metadata_accounts),
         ),
     ])
     .await;
 
+    let mut profile = ComputeUnitProfile::new(
+        &format!("commitment_hash_batching_rate_{}", batching_rate),
+        COMMITMENT_HASH_COMPUTE_BUDGET,
+        commitment_hash_computation_instructions(batching_rate),
+        commitment_hash_computation_round_compute_units(batching_rate),
+    );
+
     for _ in commitment_hash_computation_instructions(batching_rate).iter() {
-        test.tx_should_succeed(
-            &[
-                request_compute_units(COMMITMENT_HASH_COMPUTE_BUDGET),
-                ElusivInstruction::compute_commitment_hash_instruction(
-                    0,
-                    0,
-                    WritableSignerAccount(warden.pubkey),
-                ),
-            ],
-            &[&warden.keypair],
-        )
-        .await;
+        profile
+            .tx_should_succeed(
+                &mut test,
+                &[
+                    request_compute_units(COMMITMENT_HASH_COMPUTE_BUDGET),
+                    ElusivInstruction::compute_commitment_hash_instruction(
+                        0,
+                        0,
+                        0,
+                        WritableSignerAccount(warden.pubkey),
+                    ),
+                ],
+                &[&warden.keypair],
+            )
+            .await;
     }
+    profile.finish();
 
     for _ in 0..=batching_rate {
         test.ix_should_succeed_simple(ElusivInstruction::finalize_commitment_hash_instruction(
+            0,
             &writable_user_accounts(&storage_accounts),
+            &writable_user_accounts(&root_history_accounts),
         ))
         .await;
     }
@@ -1313,6 +1853,20 @@ async fn test_commitment_hash_with_batching_rate(
     assert!(metadata_queue.is_empty());
 }
 
+#[tokio::test]
+async fn test_commitment_hash_batching_rate_zero() {
+    test_commitment_hash_with_batching_rate(
+        0,
+        &[u256_from_str(
+            "17695089122606640046122050453568281484908329551111425943069599106344573268591",
+        )],
+        Some(u256_from_str(
+            "9067782498943005972697481747658603367081340211439558541654633405673676102857",
+        )),
+    )
+    .await;
+}
+
 #[tokio::test]
 async fn test_commitment_hash_batching_rate_one() {
     test_commitment_hash_with_batching_rate(
@@ -1465,3 +2019,254 @@ async fn test_commitment_hash_batching_rate_four() {
     )
     .await;
 }
+
+#[tokio::test]
+async fn test_concurrent_commitment_hashing() {
+    let mut test = start_test_with_setup().await;
+    let warden = test.new_actor().await;
+
+    setup_storage_account(&mut test).await;
+    let root_history_accounts = setup_root_history_account(&mut test).await;
+    setup_metadata_account(&mut test).await;
+
+    let storage_accounts = storage_accounts(&mut test).await;
+    let metadata_accounts = metadata_accounts(&mut test).await;
+
+    let pool = PoolAccount::find(None).0;
+    test.airdrop_lamports(&pool, LAMPORTS_PER_SOL * 100).await;
+
+    // Index zero is the single instance account
+    let payer = test.payer();
+    test.ix_should_fail_simple(
+        ElusivInstruction::open_commitment_hashing_account_instruction(
+            0,
+            WritableSignerAccount(payer),
+        ),
+    )
+    .await;
+
+    test.ix_should_succeed_simple(
+        ElusivInstruction::open_commitment_hashing_account_instruction(
+            1,
+            WritableSignerAccount(payer),
+        ),
+    )
+    .await;
+
+    // Two batches with batching-rate one
+    let batching_rate = 1;
+    let commitments: Vec<U256> = (1..=4)
+        .map(|i| fr_to_u256_le(&u64_to_scalar_skip_mr(i)))
+        .collect();
+    let requests: Vec<CommitmentHashRequest> = commitments
+        .iter()
+        .map(|c| CommitmentHashRequest {
+            commitment: *c,
+            fee_version: 0,
+            min_batching_rate: batching_rate,
+        })
+        .collect();
+
+    enqueue_commitments(
+        &mut test,
+        &requests,
+        Some(&vec![[0; CommitmentMetadata::SIZE]; requests.len()]),
+    )
+    .await;
+
+    // Both batches are claimed (the siblings are not yet known)
+    for hashing_account_index in 0..2 {
+        test.ix_should_succeed_simple(ElusivInstruction::init_commitment_hash_instruction(
+            0,
+            false,
+            hashing_account_index,
+            false,
+            &writable_user_accounts(&metadata_accounts),
+        ))
+        .await;
+    }
+
+    for hashing_account_index in 0..2 {
+        pda_account!(
+            hashing_account,
+            CommitmentHashingAccount,
+            None,
+            commitment_hashing_account_offset(hashing_account_index),
+            test
+        );
+        assert!(hashing_account.get_is_active());
+        assert!(!hashing_account.get_setup());
+        assert_eq!(
+            hashing_account.get_ordering() as usize,
+            hashing_account_index as usize * commitments_per_batch(batching_rate)
+        );
+    }
+
+    let compute_ix = |hashing_account_index: u32| {
+        ElusivInstruction::compute_commitment_hash_instruction(
+            0,
+            0,
+            hashing_account_index,
+            WritableSignerAccount(warden.pubkey),
+        )
+    };
+    let setup_ix = |hashing_account_index: u32| {
+        ElusivInstruction::init_commitment_hash_setup_instruction(
+            false,
+            hashing_account_index,
+            &user_accounts(&storage_accounts),
+        )
+    };
+    let finalize_ix = |hashing_account_index: u32| {
+        ElusivInstruction::finalize_commitment_hash_instruction(
+            hashing_account_index,
+            &writable_user_accounts(&storage_accounts),
+            &writable_user_accounts(&root_history_accounts),
+        )
+    };
+
+    // The HT of the second batch is computed concurrently (first ix only contains HT rounds)
+    test.tx_should_succeed(
+        &[
+            request_compute_units(COMMITMENT_HASH_COMPUTE_BUDGET),
+            compute_ix(1),
+        ],
+        &[&warden.keypair],
+    )
+    .await;
+
+    // The hashes above the HT require the first batch to be inserted
+    test.tx_should_fail(
+        &[
+            request_compute_units(COMMITMENT_HASH_COMPUTE_BUDGET),
+            compute_ix(1),
+        ],
+        &[&warden.keypair],
+    )
+    .await;
+    test.ix_should_fail_simple(setup_ix(1)).await;
+
+    // First batch
+    test.ix_should_succeed_simple(setup_ix(0)).await;
+    for _ in commitment_hash_computation_instructions(batching_rate).iter() {
+        test.tx_should_succeed(
+            &[
+                request_compute_units(COMMITMENT_HASH_COMPUTE_BUDGET),
+                compute_ix(0),
+            ],
+            &[&warden.keypair],
+        )
+        .await;
+    }
+
+    // Finalization is serialized
+    test.ix_should_fail_simple(finalize_ix(1)).await;
+    for _ in 0..=batching_rate {
+        test.ix_should_succeed_simple(finalize_ix(0)).await;
+    }
+
+    // Second batch
+    test.ix_should_succeed_simple(setup_ix(1)).await;
+    for _ in commitment_hash_computation_instructions(batching_rate)
+        .iter()
+        .skip(1)
+    {
+        test.tx_should_succeed(
+            &[
+                request_compute_units(COMMITMENT_HASH_COMPUTE_BUDGET),
+                compute_ix(1),
+            ],
+            &[&warden.keypair],
+        )
+        .await;
+    }
+    for _ in 0..=batching_rate {
+        test.ix_should_succeed_simple(finalize_ix(1)).await;
+    }
+
+    // Verify all commitments and root
+    let leaves: Vec<Fr> = commitments.iter().map(u256_to_fr_skip_mr).collect();
+    let mut hash = full_poseidon2_hash(
+        full_poseidon2_hash(leaves[0], leaves[1]),
+        full_poseidon2_hash(leaves[2], leaves[3]),
+    );
+    for empty_node in EMPTY_TREE.iter().take(MT_HEIGHT as usize).skip(2) {
+        hash = full_poseidon2_hash(hash, u256_to_fr_skip_mr(empty_node));
+    }
+
+    storage_account(None, &mut test, |s: &StorageAccount| {
+        for (i, commitment) in commitments.iter().enumerate() {
+            assert_eq!(s.get_node(i, MT_HEIGHT as usize).unwrap(), *commitment);
+        }
+        assert_eq!(s.get_root().unwrap(), fr_to_u256_le(&hash));
+        assert_eq!(s.get_next_commitment_ptr(), commitments.len() as u32);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_adaptive_commitment_batching_rate() {
+    let mut test = start_test_with_setup().await;
+
+    setup_storage_account(&mut test).await;
+    setup_metadata_account(&mut test).await;
+
+    let metadata_accounts = metadata_accounts(&mut test).await;
+
+    let fee = genesis_fee(&mut test).await;
+    let pool = PoolAccount::find(None).0;
+    let fee_collector = FeeCollectorAccount::find(None).0;
+    test.airdrop_lamports(&pool, LAMPORTS_PER_SOL * 100).await;
+
+    // Five commitments, each paid for with a batching rate of zero
+    let requests: Vec<CommitmentHashRequest> = (1..=5)
+        .map(|i| CommitmentHashRequest {
+            commitment: fr_to_u256_le(&u64_to_scalar_skip_mr(i)),
+            fee_version: 0,
+            min_batching_rate: 0,
+        })
+        .collect();
+
+    enqueue_commitments(
+        &mut test,
+        &requests,
+        Some(&vec![[0; CommitmentMetadata::SIZE]; requests.len()]),
+    )
+    .await;
+
+    let pool_lamports = test.pda_lamports(&pool, PoolAccount::SIZE).await.0;
+    let fee_collector_lamports = test
+        .pda_lamports(&fee_collector, FeeCollectorAccount::SIZE)
+        .await
+        .0;
+
+    test.ix_should_succeed_simple(ElusivInstruction::init_commitment_hash_instruction(
+        0,
+        false,
+        0,
+        true,
+        &writable_user_accounts(&metadata_accounts),
+    ))
+    .await;
+
+    // The largest complete batch is claimed
+    pda_account!(hashing_account, CommitmentHashingAccount, None, None, test);
+    assert_eq!(hashing_account.get_batching_rate(), 2);
+
+    queue!(queue, CommitmentQueue, test);
+    assert_eq!(queue.len(), 1);
+
+    // The overpaid commitment hash fees are credited to the fee collector
+    let surplus = fee.commitment_hash_computation_fee_surplus(&[0; 4], 2).0;
+    assert!(surplus > 0);
+    assert_eq!(
+        pool_lamports - surplus,
+        test.pda_lamports(&pool, PoolAccount::SIZE).await.0
+    );
+    assert_eq!(
+        fee_collector_lamports + surplus,
+        test.pda_lamports(&fee_collector, FeeCollectorAccount::SIZE)
+            .await
+            .0
+    );
+}
@@ -7,25 +7,47 @@ use elusiv::{
     proof::verifier::{CombinedMillerLoop, FinalExponentiation},
     state::{
         fee::{BasisPointFee, ProgramFee},
-        metadata::MetadataAccount,
+        governor::{GovernorAccount, RateLimitAccount},
+        metadata::{EncryptedMetadataAccount, MetadataAccount},
         nullifier::NullifierAccount,
-        storage::StorageAccount,
+        storage::{RootHistoryAccount, StorageAccount},
     },
     types::U256,
 };
-use elusiv_computation::PartialComputation;
+use elusiv_computation::{PartialComputation, COMPUTE_UNIT_PADDING};
 pub use elusiv_test::*;
 use elusiv_types::{
-    elusiv_token, Lamports, PDAAccount, PDAOffset, WritableSignerAccount, WritableUserAccount,
+    elusiv_token, Lamports, PDAAccount, PDAAccountData, PDAOffset, ProgramAccount, SizedAccount,
+    VersionedAccount, WritableSignerAccount, WritableUserAccount,
+};
+use regex::Regex;
+use serde_json::json;
+use solana_sdk::signature::Keypair;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
 };
-use std::str::FromStr;
 
 pub async fn start_test() -> ElusivProgramTest {
-    ElusivProgramTest::start(&[(
-        String::from("elusiv"),
-        elusiv::id(),
-        processor!(elusiv::process_instruction),
-    )])
+    compile_mock_program();
+
+    ElusivProgramTest::start(&[
+        (
+            String::from("elusiv"),
+            elusiv::id(),
+            processor!(elusiv::process_instruction),
+        ),
+        (
+            String::from("mock_program"),
+            mock_program::CPI_MOCK_PROGRAM_ID,
+            processor!(mock_program::process_instruction),
+        ),
+        (
+            String::from("mock_program"),
+            mock_program::CALLBACK_MOCK_PROGRAM_ID,
+            processor!(mock_program::process_instruction),
+        ),
+    ])
     .await
 }
 
@@ -63,6 +85,10 @@ pub fn initial_single_instance_pdas(payer: Pubkey) -> Vec<Instruction> {
         ElusivInstruction::setup_governor_account_instruction(WritableSignerAccount(payer)),
         ElusivInstruction::open_single_instance_accounts_instruction(WritableSignerAccount(payer)),
         ElusivInstruction::create_new_accounts_v1_instruction(WritableSignerAccount(payer)),
+        ElusivInstruction::open_encrypted_metadata_account_instruction(WritableSignerAccount(
+            payer,
+        )),
+        ElusivInstruction::open_root_history_account_instruction(WritableSignerAccount(payer)),
     ]
 }
 
@@ -75,6 +101,38 @@ pub async fn setup_fee(test: &mut ElusivProgramTest, fee_version: u32, program_f
     test.ix_should_succeed_simple(ix).await;
 }
 
+/// Configures the caps of `token_id` and opens its `RateLimitAccount`
+///
+/// # Note
+///
+/// The accounts are set directly, since `SetRateLimits` requires the program's keypair.
+pub async fn set_rate_limits(
+    test: &mut ElusivProgramTest,
+    window_slots: u64,
+    token_id: u16,
+    deposit_cap: u64,
+    withdrawal_cap: u64,
+) {
+    test.set_pda_account::<GovernorAccount, _>(&elusiv::id(), None, None, |data| {
+        let mut governor = GovernorAccount::new(data).unwrap();
+        let mut rate_limits = governor.get_rate_limits();
+        rate_limits.window_slots = window_slots;
+        rate_limits.deposit_caps[token_id as usize] = deposit_cap;
+        rate_limits.withdrawal_caps[token_id as usize] = withdrawal_cap;
+        governor.set_rate_limits(&rate_limits);
+    })
+    .await;
+
+    let (pubkey, bump) = RateLimitAccount::find(Some(token_id.into()));
+    if test.account_does_not_exist(&pubkey).await {
+        let mut data = vec![0; RateLimitAccount::SIZE];
+        data[0] = bump;
+        data[PDAAccountData::VERSION_OFFSET] = RateLimitAccount::VERSION;
+        test.set_program_account_rent_exempt(&elusiv::id(), &pubkey, &data)
+            .await;
+    }
+}
+
 macro_rules! setup_parent_account {
     ($fn_id: ident, $ty: ty, $instruction: ident) => {
         pub async fn $fn_id(test: &mut ElusivProgramTest) -> Vec<Pubkey> {
@@ -106,6 +164,18 @@ setup_parent_account!(
     enable_metadata_child_account_instruction
 );
 
+setup_parent_account!(
+    setup_encrypted_metadata_account,
+    EncryptedMetadataAccount,
+    enable_encrypted_metadata_child_account_instruction
+);
+
+setup_parent_account!(
+    setup_root_history_account,
+    RootHistoryAccount,
+    enable_root_history_child_account_instruction
+);
+
 pub async fn create_merkle_tree(test: &mut ElusivProgramTest, mt_index: u32) -> Vec<Pubkey> {
     let mut instructions = vec![ElusivInstruction::open_nullifier_account_instruction(
         mt_index,
@@ -140,6 +210,7 @@ macro_rules! child_accounts_getter_simple {
 
 child_accounts_getter_simple!(storage_accounts, StorageAccount);
 child_accounts_getter_simple!(metadata_accounts, MetadataAccount);
+child_accounts_getter_simple!(root_history_accounts, RootHistoryAccount);
 
 pub async fn nullifier_accounts(test: &mut ElusivProgramTest, mt_index: u32) -> Vec<Pubkey> {
     let mut data = test.data(&NullifierAccount::find(Some(mt_index)).0).await;
@@ -240,6 +311,8 @@ macro_rules! parent_account {
 parent_account!(storage_account, StorageAccount);
 parent_account!(nullifier_account, NullifierAccount);
 parent_account!(metadata_account, MetadataAccount);
+parent_account!(encrypted_metadata_account, EncryptedMetadataAccount);
+parent_account!(root_history_account, RootHistoryAccount);
 
 pub fn u256_from_str(str: &str) -> U256 {
     fr_to_u256_le(&ark_bn254::Fr::from_str(str).unwrap())
@@ -270,3 +343,238 @@ pub async fn enable_program_token_account<A: PDAAccount>(
     );
     test.process_transaction(&[ix], &[]).await.unwrap();
 }
+
+/// The compute unit limit of an instruction in a transaction without a compute budget request
+pub const DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT: u32 = 200_000;
+
+/// Compute units consumed by a single `sol_log_compute_units` call (the syscall base cost)
+const LOG_COMPUTE_UNITS_COST: u64 = 100;
+
+/// Measures the compute units of every round of a partial computation
+///
+/// # Notes
+///
+/// Requires the BPF program compiled with the `compute-unit-profiling` feature (`build.sh test --test-kind compute-units`).
+/// Without the feature, the transactions are only executed and no report is written.
+pub struct ComputeUnitProfile {
+    name: String,
+    compute_budget_per_ix: u32,
+    instruction_rounds: Vec<u8>,
+    round_compute_units: Vec<u32>,
+    instructions: Vec<InstructionProfile>,
+}
+
+struct InstructionProfile {
+    consumed_compute_units: u64,
+    round_compute_units: Vec<u64>,
+}
+
+impl ComputeUnitProfile {
+    pub fn new(
+        name: &str,
+        compute_budget_per_ix: u32,
+        instruction_rounds: &[u8],
+        round_compute_units: &[u32],
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            compute_budget_per_ix,
+            instruction_rounds: instruction_rounds.to_vec(),
+            round_compute_units: round_compute_units.to_vec(),
+            instructions: Vec::new(),
+        }
+    }
+
+    /// Executes a transaction and records the compute units of all rounds performed by its instructions
+    pub async fn tx_should_succeed(
+        &mut self,
+        test: &mut ElusivProgramTest,
+        ixs: &[Instruction],
+        signers: &[&Keypair],
+    ) {
+        if cfg!(feature = "compute-unit-profiling") {
+            let logs = test.simulate_transaction_logs(ixs, signers).await;
+            self.record(&logs);
+        }
+
+        test.tx_should_succeed(ixs, signers).await;
+    }
+
+    fn record(&mut self, logs: &[String]) {
+        let invoke = format!("Program {} invoke [1]", elusiv::id());
+        let consumed = Regex::new(&format!(
+            r"^Program {} consumed (\d+) of \d+ compute units$",
+            elusiv::id()
+        ))
+        .unwrap();
+        let remaining = Regex::new(r"^Program consumption: (\d+) units remaining$").unwrap();
+
+        let mut boundaries: Vec<u64> = Vec::new();
+        for log in logs {
+            if *log == invoke {
+                boundaries.clear();
+            } else if let Some(captures) = remaining.captures(log) {
+                boundaries.push(captures[1].parse().unwrap());
+            } else if let Some(captures) = consumed.captures(log) {
+                // Instructions that do not perform any rounds are skipped
+                if boundaries.is_empty() {
+                    continue;
+                }
+
+                let instruction = self.instructions.len();
+                assert!(
+                    instruction < self.instruction_rounds.len(),
+                    "{}: more instructions than expected",
+                    self.name
+                );
+                assert_eq!(
+                    boundaries.len(),
+                    self.instruction_rounds[instruction] as usize + 1,
+                    "{}: unexpected round count in instruction {} (truncated logs?)",
+                    self.name,
+                    instruction
+                );
+
+                self.instructions.push(InstructionProfile {
+                    consumed_compute_units: captures[1].parse().unwrap(),
+                    round_compute_units: boundaries
+                        .windows(2)
+                        .map(|w| w[0] - w[1] - LOG_COMPUTE_UNITS_COST)
+                        .collect(),
+                });
+                boundaries.clear();
+            }
+        }
+    }
+
+    /// Writes the JSON report and fails if any instruction comes within [`COMPUTE_UNIT_PADDING`] of its compute budget
+    ///
+    /// # Notes
+    ///
+    /// The report is written to `$COMPUTE_UNIT_REPORT_DIR/<name>.json` (defaults to `target/compute-units`).
+    pub fn finish(self) {
+        if !cfg!(feature = "compute-unit-profiling") {
+            return;
+        }
+
+        assert_eq!(
+            self.instructions.len(),
+            self.instruction_rounds.len(),
+            "{}: not all instructions have been profiled",
+            self.name
+        );
+
+        let compute_unit_limit = (self.compute_budget_per_ix - COMPUTE_UNIT_PADDING) as u64;
+        let mut start_round = 0;
+        let mut instructions = Vec::new();
+        let mut exceeding_instructions = Vec::new();
+
+        for (i, instruction) in self.instructions.iter().enumerate() {
+            let rounds = instruction.round_compute_units.len();
+            let annotated = &self.round_compute_units[start_round..start_round + rounds];
+
+            // Removes the profiling overhead (one log before each round and one after the last round)
+            let measured =
+                instruction.consumed_compute_units - (rounds as u64 + 1) * LOG_COMPUTE_UNITS_COST;
+            let measured_rounds: u64 = instruction.round_compute_units.iter().sum();
+
+            if measured > compute_unit_limit {
+                exceeding_instructions.push(i);
+            }
+
+            instructions.push(json!({
+                "instruction": i,
+                "start_round": start_round,
+                "rounds": rounds,
+                "annotated_compute_units": annotated.iter().sum::<u32>(),
+                "measured_compute_units": measured,
+                "measured_overhead_compute_units": measured.saturating_sub(measured_rounds),
+                "round_compute_units": annotated
+                    .iter()
+                    .zip(&instruction.round_compute_units)
+                    .enumerate()
+                    .map(|(r, (annotated, measured))| json!({
+                        "round": start_round + r,
+                        "annotated": annotated,
+                        "measured": measured,
+                    }))
+                    .collect::<Vec<_>>(),
+            }));
+
+            start_round += rounds;
+        }
+
+        let report = json!({
+            "computation": self.name,
+            "compute_budget_per_ix": self.compute_budget_per_ix,
+            "compute_unit_limit": compute_unit_limit,
+            "total_rounds": start_round,
+            "annotated_compute_units": self.round_compute_units.iter().sum::<u32>(),
+            "measured_compute_units": self
+                .instructions
+                .iter()
+                .map(|i| i.round_compute_units.iter().sum::<u64>())
+                .sum::<u64>(),
+            "exceeding_instructions": exceeding_instructions,
+            "instructions": instructions,
+        });
+
+        write_compute_unit_report(&self.name, &report);
+
+        assert!(
+            exceeding_instructions.is_empty(),
+            "{}: instructions {:?} exceed the compute unit limit of {}",
+            self.name,
+            exceeding_instructions,
+            compute_unit_limit
+        );
+    }
+}
+
+/// Simulates a transaction and returns the compute units consumed by each of its (top-level) elusiv instructions (which all need to succeed)
+///
+/// # Notes
+///
+/// The compute units are only representative for the BPF program (`build.sh test --test-kind compute-units`).
+pub async fn simulate_compute_units(
+    test: &mut ElusivProgramTest,
+    ixs: &[Instruction],
+    signers: &[&Keypair],
+) -> Vec<u64> {
+    let consumed = Regex::new(&format!(
+        r"^Program {} consumed (\d+) of \d+ compute units$",
+        elusiv::id()
+    ))
+    .unwrap();
+
+    let failed = format!("Program {} failed", elusiv::id());
+
+    let logs = test.simulate_transaction_logs(ixs, signers).await;
+    assert!(
+        !logs.iter().any(|log| log.starts_with(&failed)),
+        "Instruction failed: {:?}",
+        logs
+    );
+
+    logs.iter()
+        .filter_map(|log| consumed.captures(log))
+        .map(|captures| captures[1].parse().unwrap())
+        .collect()
+}
+
+/// Writes a JSON compute unit report to `$COMPUTE_UNIT_REPORT_DIR/<name>.json` (defaults to `target/compute-units`)
+pub fn write_compute_unit_report(name: &str, report: &serde_json::Value) {
+    let dir = std::env::var("COMPUTE_UNIT_REPORT_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("target")
+                .join("compute-units")
+        });
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join(format!("{}.json", name)),
+        serde_json::to_string_pretty(report).unwrap(),
+    )
+    .unwrap();
+}
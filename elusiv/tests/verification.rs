@@ -5,22 +5,29 @@ mod common;
 use borsh::{BorshDeserialize, BorshSerialize};
 use common::*;
 use elusiv::bytes::{BorshSerDeSized, ElusivOption};
+use elusiv::cpi::recipient_callback_accounts;
 use elusiv::fields::{u64_to_u256, u64_to_u256_skip_mr};
 use elusiv::instruction::{
     ElusivInstruction, SignerAccount, UserAccount, WritableSignerAccount, WritableUserAccount,
 };
-use elusiv::processor::{program_token_account_address, FinalizeSendData, ProofRequest};
+use elusiv::processor::{
+    program_token_account_address, recipient_callback_vault, FinalizeSendData, ProofRequest,
+    COMPUTE_VERIFICATION_IX_COUNT, RECIPIENT_CALLBACK_TIMEOUT_SLOTS,
+};
 use elusiv::proof::verifier::{
     prepare_public_inputs_instructions, proof_from_str, CombinedMillerLoop, FinalExponentiation,
     VerificationStep,
 };
 use elusiv::proof::vkey::{SendQuadraVKey, VerifyingKeyInfo};
+use elusiv::state::apa::{apa_proposal_account, apa_target_map_account, WARDEN_NETWORK_PROGRAM_ID};
 use elusiv::state::commitment::CommitmentQueue;
 use elusiv::state::fee::ProgramFee;
-use elusiv::state::governor::{FeeCollectorAccount, PoolAccount};
+use elusiv::state::governor::{FeeCollectorAccount, PoolAccount, RateLimitAccount};
 use elusiv::state::metadata::{CommitmentMetadata, MetadataQueue};
 use elusiv::state::nullifier::{NullifierAccount, NullifierMap, NULLIFIERS_PER_ACCOUNT};
-use elusiv::state::program_account::{PDAAccount, PDAAccountData, ProgramAccount, SizedAccount};
+use elusiv::state::program_account::{
+    PDAAccount, PDAAccountData, ProgramAccount, SizedAccount, VersionedAccount,
+};
 use elusiv::state::proof::{VerificationAccount, VerificationState};
 use elusiv::state::queue::RingQueue;
 use elusiv::state::storage::{empty_root_raw, StorageAccount, MT_HEIGHT};
@@ -34,11 +41,13 @@ use elusiv::types::{
     JoinSplitPublicInputs, OptionalFee, OrdU256, Proof, PublicInputs, RawProof, RawU256,
     SendPublicInputs, JOIN_SPLIT_MAX_N_ARITY, U256,
 };
-use elusiv_computation::PartialComputation;
+use elusiv_computation::{PartialComputation, COMPUTE_UNIT_PADDING, MAX_COMPUTE_UNIT_LIMIT};
 use elusiv_types::tokens::Price;
 use elusiv_types::ParentAccount;
 use elusiv_utils::two_pow;
-use solana_program::instruction::Instruction;
+use mock_program::CALLBACK_MOCK_PROGRAM_ID;
+use serde_json::json;
+use solana_program::instruction::{AccountMeta, Instruction};
 use solana_program::native_token::LAMPORTS_PER_SOL;
 use solana_program::program_pack::Pack;
 use solana_program::pubkey::Pubkey;
@@ -51,6 +60,7 @@ async fn start_verification_test() -> ElusivProgramTest {
     let mut test = start_test_with_setup().await;
 
     setup_storage_account(&mut test).await;
+    setup_root_history_account(&mut test).await;
     create_merkle_tree(&mut test, 0).await;
     create_merkle_tree(&mut test, 1).await;
 
@@ -65,11 +75,20 @@ struct FullSendRequest {
 
 impl FullSendRequest {
     fn update_fee_lamports(&mut self, fee: &ProgramFee) {
-        compute_fee_rec_lamports::<SendQuadraVKey, _>(&mut self.public_inputs, fee);
+        compute_fee_rec_lamports::<SendQuadraVKey, _>(
+            &mut self.public_inputs,
+            SendQuadraVKey::LAYOUT,
+            fee,
+        );
     }
 
     fn update_fee_token(&mut self, fee: &ProgramFee, price: &TokenPrice) {
-        compute_fee_rec::<SendQuadraVKey, _>(&mut self.public_inputs, fee, price)
+        compute_fee_rec::<SendQuadraVKey, _>(
+            &mut self.public_inputs,
+            SendQuadraVKey::LAYOUT,
+            fee,
+            price,
+        )
     }
 }
 
@@ -227,6 +246,9 @@ struct ExtraData {
     metadata: CommitmentMetadata,
     optional_fee: OptionalFee,
     memo: Option<Vec<u8>>,
+    encrypted_metadata: Vec<u8>,
+    recipient_callback: Option<Vec<u8>>,
+    recipient_callback_writable_accounts: Vec<U256>,
 }
 
 impl Default for ExtraData {
@@ -243,6 +265,9 @@ impl Default for ExtraData {
             metadata: CommitmentMetadata::default(),
             optional_fee: OptionalFee::default(),
             memo: None,
+            encrypted_metadata: Vec::new(),
+            recipient_callback: None,
+            recipient_callback_writable_accounts: Vec::new(),
         }
     }
 }
@@ -254,11 +279,14 @@ impl ExtraData {
             &self.identifier,
             &self.iv,
             &self.encrypted_owner,
-            &self.reference,
+            &[self.reference],
             self.is_associated_token_account,
             &self.metadata,
             &self.optional_fee,
             &self.memo,
+            &self.encrypted_metadata,
+            &self.recipient_callback,
+            &self.recipient_callback_writable_accounts,
         )
     }
 
@@ -282,6 +310,7 @@ async fn init_verification_simple(
     test: &mut ElusivProgramTest,
 ) {
     let nullifier_accounts = nullifier_accounts(test, 0).await;
+    let root_history_accounts = root_history_accounts(test).await;
 
     test.tx_should_succeed_simple(&[
         ElusivInstruction::init_verification_instruction(
@@ -295,6 +324,7 @@ async fn init_verification_simple(
             UserAccount(Pubkey::new_from_array(identifier)),
             &user_accounts(&[nullifier_accounts[0]]),
             &[],
+            &user_accounts(&root_history_accounts),
         ),
         ElusivInstruction::init_verification_transfer_fee_sol_instruction(0, test.payer()),
         ElusivInstruction::init_verification_proof_instruction(
@@ -355,13 +385,14 @@ async fn setup_vkey_account<VKey: VerifyingKeyInfo>(
     let data = VKeyAccountEager {
         pda_data: PDAAccountData {
             bump_seed: bump,
-            version: 0,
+            version: VKeyAccount::VERSION,
         },
         pubkeys: [Some(sub_account_pubkey).into(), None.into()],
         public_inputs_count: VKey::PUBLIC_INPUTS_COUNT,
         is_frozen: true,
         authority: ElusivOption::None,
         version: 1,
+        layout: VKey::LAYOUT,
     }
     .try_to_vec()
     .unwrap();
@@ -443,6 +474,7 @@ async fn test_init_proof_signers() {
     let warden = test.new_actor().await;
     let warden2 = test.new_actor().await;
     let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let root_history_accounts = root_history_accounts(&mut test).await;
     setup_vkey_account::<SendQuadraVKey>(&mut test).await;
 
     let fee = genesis_fee(&mut test).await;
@@ -484,6 +516,7 @@ async fn test_init_proof_signers() {
             UserAccount(Pubkey::new_unique()),
             &user_accounts(&[nullifier_accounts[0]]),
             &[],
+            &user_accounts(&root_history_accounts),
         ),
         &[&warden.keypair],
     )
@@ -553,6 +586,7 @@ async fn test_init_proof_lamports() {
     let mut test = start_verification_test().await;
     let warden = test.new_actor().await;
     let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let root_history_accounts = root_history_accounts(&mut test).await;
     setup_vkey_account::<SendQuadraVKey>(&mut test).await;
 
     let fee = genesis_fee(&mut test).await;
@@ -592,6 +626,7 @@ async fn test_init_proof_lamports() {
                 UserAccount(Pubkey::new_unique()),
                 &user_accounts(&[nullifier_accounts[0]]),
                 &[],
+                &user_accounts(&root_history_accounts),
             )
         };
 
@@ -742,6 +777,7 @@ async fn test_init_proof_token() {
 
     let nullifier_duplicate_account = request.public_inputs.join_split.nullifier_duplicate_pda().0;
     let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let root_history_accounts = root_history_accounts(&mut test).await;
 
     let verification_account_rent = test.rent(VerificationAccount::SIZE).await;
     let nullifier_duplicate_account_rent = test.rent(PDAAccountData::SIZE).await;
@@ -775,6 +811,7 @@ async fn test_init_proof_token() {
             UserAccount(Pubkey::new_unique()),
             &user_accounts(&[nullifier_accounts[0]]),
             &[],
+            &user_accounts(&root_history_accounts),
         ),
         &[&warden.keypair],
     )
@@ -838,6 +875,7 @@ async fn test_finalize_proof_lamports() {
     let warden = test.new_actor().await;
     let optional_fee_collector = test.new_actor().await;
     let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let root_history_accounts = root_history_accounts(&mut test).await;
     let fee = genesis_fee(&mut test).await;
     setup_vkey_account::<SendQuadraVKey>(&mut test).await;
 
@@ -848,19 +886,24 @@ async fn test_finalize_proof_lamports() {
     };
     let extra_data = ExtraData {
         optional_fee: request.public_inputs.join_split.optional_fee.clone(),
+        encrypted_metadata: vec![7; 64],
         ..Default::default()
     };
     request.public_inputs.hashed_inputs = extra_data.hash();
     request.update_fee_lamports(&fee);
+    let encrypted_metadata_accounts = setup_encrypted_metadata_account(&mut test).await;
 
     let pool = PoolAccount::find(None).0;
     let fee_collector = FeeCollectorAccount::find(None).0;
     let nullifier_duplicate_account = request.public_inputs.join_split.nullifier_duplicate_pda().0;
 
     let public_inputs = request.public_inputs.public_signals_skip_mr();
-    let input_preparation_tx_count =
-        prepare_public_inputs_instructions(&public_inputs, SendQuadraVKey::public_inputs_count())
-            .len();
+    let input_preparation_tx_count = prepare_public_inputs_instructions(
+        &public_inputs,
+        SendQuadraVKey::public_inputs_count(),
+        SendQuadraVKey::LAYOUT,
+    )
+    .len();
     let subvention = fee.proof_subvention;
     let proof_verification_fee = fee.proof_verification_computation_fee(input_preparation_tx_count);
     let commitment_hash_fee = fee.commitment_hash_computation_fee(0);
@@ -896,6 +939,7 @@ async fn test_finalize_proof_lamports() {
                 UserAccount(Pubkey::new_from_array(extra_data.identifier)),
                 &user_accounts(&[nullifier_accounts[0]]),
                 &[],
+                &user_accounts(&root_history_accounts),
             ),
             ElusivInstruction::init_verification_transfer_fee_sol_instruction(0, warden.pubkey),
             ElusivInstruction::init_verification_proof_instruction(
@@ -941,6 +985,7 @@ async fn test_finalize_proof_lamports() {
                 total_amount: request.public_inputs.join_split.total_amount(),
                 encrypted_owner: extra_data.encrypted_owner,
                 iv: extra_data.iv,
+                encrypted_metadata: extra_data.encrypted_metadata.clone(),
                 ..Default::default()
             },
             false,
@@ -948,6 +993,8 @@ async fn test_finalize_proof_lamports() {
             UserAccount(identifier),
             UserAccount(reference),
             UserAccount(warden.pubkey),
+            &writable_user_accounts(&encrypted_metadata_accounts[..1]),
+            &[], // clients are not required to supply the APA accounts
         );
     let finalize_verification_send_nullifier_instruction =
         ElusivInstruction::finalize_verification_insert_nullifier_instruction(
@@ -963,6 +1010,7 @@ async fn test_finalize_proof_lamports() {
             WritableUserAccount(recipient),
             WritableUserAccount(optional_fee_collector.pubkey),
             WritableUserAccount(nullifier_duplicate_account),
+            &[],
         );
 
     // IMPORTANT: Pool already contains subvention (so we airdrop commitment_hash_fee - subvention)
@@ -1054,6 +1102,21 @@ async fn test_finalize_proof_lamports() {
         metadata_queue.view_first().unwrap(),
         request.public_inputs.join_split.metadata
     );
+
+    // The encrypted metadata is available for scanning
+    encrypted_metadata_account(None, &mut test, |encrypted_metadata_account| {
+        assert_eq!(encrypted_metadata_account.get_entries_count(), 1);
+        assert_eq!(
+            encrypted_metadata_account
+                .get_encrypted_metadata(0)
+                .unwrap(),
+            (
+                request.public_inputs.join_split.output_commitment.reduce(),
+                extra_data.encrypted_metadata.clone()
+            )
+        );
+    })
+    .await;
 }
 
 #[tokio::test]
@@ -1064,6 +1127,7 @@ async fn test_finalize_proof_token() {
     enable_program_token_account::<FeeCollectorAccount>(&mut test, USDC_TOKEN_ID, None).await;
     setup_vkey_account::<SendQuadraVKey>(&mut test).await;
     let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let root_history_accounts = root_history_accounts(&mut test).await;
     let fee = genesis_fee(&mut test).await;
 
     let mut recipient = test.new_actor().await;
@@ -1117,9 +1181,12 @@ async fn test_finalize_proof_token() {
     let nullifier_duplicate_account = request.public_inputs.join_split.nullifier_duplicate_pda().0;
 
     let public_inputs = request.public_inputs.public_signals_skip_mr();
-    let input_preparation_tx_count =
-        prepare_public_inputs_instructions(&public_inputs, SendQuadraVKey::public_inputs_count())
-            .len();
+    let input_preparation_tx_count = prepare_public_inputs_instructions(
+        &public_inputs,
+        SendQuadraVKey::public_inputs_count(),
+        SendQuadraVKey::LAYOUT,
+    )
+    .len();
     let subvention = fee
         .proof_subvention
         .into_token(&price, USDC_TOKEN_ID)
@@ -1169,6 +1236,7 @@ async fn test_finalize_proof_token() {
                 UserAccount(Pubkey::new_from_array(extra_data.identifier)),
                 &user_accounts(&[nullifier_accounts[0]]),
                 &[],
+                &user_accounts(&root_history_accounts),
             ),
             ElusivInstruction::init_verification_transfer_fee_instruction(
                 0,
@@ -1221,6 +1289,10 @@ async fn test_finalize_proof_token() {
             UserAccount(identifier),
             UserAccount(reference),
             UserAccount(warden.pubkey),
+            &[],
+            &[UserAccount(
+                apa_target_map_account(&recipient_token_account).0,
+            )],
         );
     let finalize_verification_send_nullifier_instruction =
         ElusivInstruction::finalize_verification_insert_nullifier_instruction(
@@ -1241,6 +1313,7 @@ async fn test_finalize_proof_token() {
             WritableUserAccount(optional_fee_collector.get_token_account(USDC_TOKEN_ID)),
             WritableUserAccount(nullifier_duplicate_account),
             UserAccount(spl_token::id()),
+            &[],
         );
 
     // IMPORTANT: Pool already contains subvention (so we airdrop commitment_hash_fee - subvention)
@@ -1342,6 +1415,7 @@ async fn test_finalize_proof_skip_nullifier_pda() {
     let warden = test.new_actor().await;
     let recipient = test.new_actor().await;
     let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let root_history_accounts = root_history_accounts(&mut test).await;
 
     let fee = genesis_fee(&mut test).await;
     let mut request = send_request(0);
@@ -1383,6 +1457,7 @@ async fn test_finalize_proof_skip_nullifier_pda() {
                 UserAccount(Pubkey::new_from_array(extra_data.identifier)),
                 &user_accounts(&[nullifier_accounts[0]]),
                 &[],
+                &user_accounts(&root_history_accounts),
             ),
             ElusivInstruction::init_verification_transfer_fee_sol_instruction(
                 v_index,
@@ -1433,6 +1508,8 @@ async fn test_finalize_proof_skip_nullifier_pda() {
                 UserAccount(identifier),
                 UserAccount(reference),
                 UserAccount(warden.pubkey),
+                &[],
+                &[UserAccount(apa_target_map_account(&recipient.pubkey).0)],
             ),
             ElusivInstruction::finalize_verification_insert_nullifier_instruction(
                 v_index,
@@ -1446,6 +1523,7 @@ async fn test_finalize_proof_skip_nullifier_pda() {
                 WritableUserAccount(recipient.pubkey),
                 WritableUserAccount(Pubkey::new_unique()),
                 WritableUserAccount(nullifier_duplicate_account),
+                &[],
             ),
         ];
 
@@ -1494,6 +1572,7 @@ async fn test_finalize_proof_commitment_index() {
     let warden = test.new_actor().await;
     let recipient = test.new_actor().await;
     let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let root_history_accounts = root_history_accounts(&mut test).await;
     let pool = PoolAccount::find(None).0;
     let fee_collector = FeeCollectorAccount::find(None).0;
 
@@ -1528,6 +1607,7 @@ async fn test_finalize_proof_commitment_index() {
             UserAccount(Pubkey::new_from_array(extra_data.identifier)),
             &user_accounts(&[nullifier_accounts[0]]),
             &[],
+            &user_accounts(&root_history_accounts),
         ),
         ElusivInstruction::init_verification_transfer_fee_sol_instruction(0, warden.pubkey),
         ElusivInstruction::init_verification_proof_instruction(
@@ -1552,12 +1632,17 @@ async fn test_finalize_proof_commitment_index() {
                     commitment_index,
                     encrypted_owner: extra_data.encrypted_owner,
                     iv: extra_data.iv,
+                    encrypted_metadata: extra_data.encrypted_metadata.clone(),
+                    recipient_callback: None,
+                    recipient_callback_writable_accounts: Vec::new(),
                 },
                 false,
                 UserAccount(recipient.pubkey),
                 UserAccount(identifier),
                 UserAccount(reference),
                 UserAccount(warden.pubkey),
+                &[],
+                &[UserAccount(apa_target_map_account(&recipient.pubkey).0)],
             ),
             ElusivInstruction::finalize_verification_insert_nullifier_instruction(
                 0,
@@ -1571,6 +1656,7 @@ async fn test_finalize_proof_commitment_index() {
                 WritableUserAccount(recipient.pubkey),
                 WritableUserAccount(Pubkey::new_unique()),
                 WritableUserAccount(nullifier_duplicate_account),
+                &[],
             ),
         ]
     };
@@ -1600,6 +1686,122 @@ async fn test_finalize_proof_commitment_index() {
         .await;
 }
 
+#[tokio::test]
+async fn test_finalize_proof_apa_restrictions() {
+    let mut test = start_verification_test().await;
+    setup_vkey_account::<SendQuadraVKey>(&mut test).await;
+    let warden = test.new_actor().await;
+    let recipient = test.new_actor().await;
+    let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let root_history_accounts = root_history_accounts(&mut test).await;
+    let pool = PoolAccount::find(None).0;
+    let fee_collector = FeeCollectorAccount::find(None).0;
+
+    let mut request = send_request(0);
+    let extra_data = ExtraData {
+        recipient: recipient.pubkey.to_bytes(),
+        ..Default::default()
+    };
+    request.public_inputs.hashed_inputs = extra_data.hash();
+    request.update_fee_lamports(&genesis_fee(&mut test).await);
+
+    let nullifier_duplicate_account = request.public_inputs.join_split.nullifier_duplicate_pda().0;
+    let identifier = Pubkey::new_from_array(extra_data.identifier);
+    let reference = Pubkey::new_from_array(extra_data.reference);
+
+    warden
+        .airdrop(LAMPORTS_TOKEN_ID, LAMPORTS_PER_SOL, &mut test)
+        .await;
+    test.airdrop_lamports(&fee_collector, LAMPORTS_PER_SOL)
+        .await;
+    test.airdrop_lamports(&pool, LAMPORTS_PER_SOL * 1000).await;
+
+    test.tx_should_succeed(
+        &[
+            ElusivInstruction::init_verification_instruction(
+                0,
+                SendQuadraVKey::VKEY_ID,
+                [0, 1],
+                ProofRequest::Send(request.public_inputs.clone()),
+                false,
+                WritableSignerAccount(warden.pubkey),
+                WritableUserAccount(nullifier_duplicate_account),
+                UserAccount(identifier),
+                &user_accounts(&[nullifier_accounts[0]]),
+                &[],
+                &user_accounts(&root_history_accounts),
+            ),
+            ElusivInstruction::init_verification_transfer_fee_sol_instruction(0, warden.pubkey),
+            ElusivInstruction::init_verification_proof_instruction(
+                0,
+                request.proof,
+                SignerAccount(warden.pubkey),
+            ),
+        ],
+        &[&warden.keypair],
+    )
+    .await;
+    skip_computation(warden.pubkey, 0, true, &mut test).await;
+
+    let apa_target_map = apa_target_map_account(&recipient.pubkey).0;
+    let apa_proposal = apa_proposal_account(0).0;
+    let finalize_ix = |apa_accounts: &[UserAccount]| {
+        ElusivInstruction::finalize_verification_send_instruction(
+            0,
+            FinalizeSendData {
+                total_amount: request.public_inputs.join_split.total_amount(),
+                encrypted_owner: extra_data.encrypted_owner,
+                iv: extra_data.iv,
+                ..Default::default()
+            },
+            false,
+            UserAccount(recipient.pubkey),
+            UserAccount(identifier),
+            UserAccount(reference),
+            UserAccount(warden.pubkey),
+            &[],
+            apa_accounts,
+        )
+    };
+
+    // Outcast
+    test.set_apa_proposal(
+        &WARDEN_NETWORK_PROGRAM_ID,
+        &recipient.pubkey,
+        0,
+        MockApaLevel::Outcast,
+        None,
+    )
+    .await;
+    test.ix_should_fail_simple(finalize_ix(&[
+        UserAccount(apa_target_map),
+        UserAccount(apa_proposal),
+    ]))
+    .await;
+
+    // Omitted APA accounts
+    test.ix_should_fail_simple(finalize_ix(&[])).await;
+
+    // Omitted proposal account
+    test.ix_should_fail_simple(finalize_ix(&[UserAccount(apa_target_map)]))
+        .await;
+
+    // Outcast for a different token
+    test.set_apa_proposal(
+        &WARDEN_NETWORK_PROGRAM_ID,
+        &recipient.pubkey,
+        0,
+        MockApaLevel::Outcast,
+        Some(USDC_TOKEN_ID),
+    )
+    .await;
+    test.ix_should_succeed_simple(finalize_ix(&[
+        UserAccount(apa_target_map),
+        UserAccount(apa_proposal),
+    ]))
+    .await;
+}
+
 #[tokio::test]
 async fn test_associated_token_account() {
     let mut test = start_verification_test().await;
@@ -1648,6 +1850,7 @@ async fn test_associated_token_account() {
 
     let nullifier_duplicate_account = request.public_inputs.join_split.nullifier_duplicate_pda().0;
     let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let root_history_accounts = root_history_accounts(&mut test).await;
 
     let verification_account_rent = test.rent(VerificationAccount::SIZE).await;
     let nullifier_duplicate_account_rent = test.rent(PDAAccountData::SIZE).await;
@@ -1683,6 +1886,7 @@ async fn test_associated_token_account() {
             UserAccount(Pubkey::new_from_array(extra_data.identifier)),
             &user_accounts(&[nullifier_accounts[0]]),
             &[],
+            &user_accounts(&root_history_accounts),
         ),
         &[&warden.keypair],
     )
@@ -1736,6 +1940,8 @@ async fn test_associated_token_account() {
                 UserAccount(Pubkey::new_from_array(extra_data.identifier)),
                 UserAccount(Pubkey::new_from_array(extra_data.reference)),
                 UserAccount(warden.pubkey),
+                &[],
+                &[UserAccount(apa_target_map_account(&recipient_wallet).0)],
             ),
             ElusivInstruction::finalize_verification_insert_nullifier_instruction(
                 0,
@@ -1754,6 +1960,7 @@ async fn test_associated_token_account() {
                 WritableUserAccount(Pubkey::new_unique()),
                 WritableUserAccount(nullifier_duplicate_account),
                 UserAccount(mint),
+                &[],
             ),
         ]
     };
@@ -1870,6 +2077,7 @@ async fn test_compute_proof_verifcation_invalid_proof() {
     let (_, vkey_sub_account) = setup_vkey_account::<SendQuadraVKey>(&mut test).await;
     let warden = test.new_actor().await;
     let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let root_history_accounts = root_history_accounts(&mut test).await;
     let fee = genesis_fee(&mut test).await;
     let mut request = send_request(0);
     request.update_fee_token(&fee, &TokenPrice::new_lamports());
@@ -1878,9 +2086,12 @@ async fn test_compute_proof_verifcation_invalid_proof() {
     let nullifier_duplicate_account = request.public_inputs.join_split.nullifier_duplicate_pda().0;
 
     let public_inputs = request.public_inputs.public_signals_skip_mr();
-    let input_preparation_tx_count =
-        prepare_public_inputs_instructions(&public_inputs, SendQuadraVKey::public_inputs_count())
-            .len();
+    let input_preparation_tx_count = prepare_public_inputs_instructions(
+        &public_inputs,
+        SendQuadraVKey::public_inputs_count(),
+        SendQuadraVKey::LAYOUT,
+    )
+    .len();
     let subvention = fee.proof_subvention;
     let commitment_hash_fee = fee.commitment_hash_computation_fee(0);
     let verification_account_rent = test.rent(VerificationAccount::SIZE).await;
@@ -1910,6 +2121,7 @@ async fn test_compute_proof_verifcation_invalid_proof() {
                 UserAccount(Pubkey::new_unique()),
                 &user_accounts(&[nullifier_accounts[0]]),
                 &[],
+                &user_accounts(&root_history_accounts),
             ),
             ElusivInstruction::init_verification_transfer_fee_sol_instruction(0, warden.pubkey),
             ElusivInstruction::init_verification_proof_instruction(
@@ -1973,9 +2185,19 @@ async fn test_compute_proof_verifcation_invalid_proof() {
     assert_eq!(v_acc.get_step(), VerificationStep::CombinedMillerLoop);
 
     // Combined miller loop
+    // (an invalid proof performs the same rounds as a valid one, so we also profile the compute units)
+    let mut profile = ComputeUnitProfile::new(
+        "combined_miller_loop",
+        CombinedMillerLoop::COMPUTE_BUDGET_PER_IX,
+        &CombinedMillerLoop::INSTRUCTION_ROUNDS,
+        CombinedMillerLoop::ROUND_COMPUTE_UNITS,
+    );
     for _ in 0..CombinedMillerLoop::TX_COUNT {
-        test.tx_should_succeed_simple(&instructions).await;
+        profile
+            .tx_should_succeed(&mut test, &instructions, &[])
+            .await;
     }
+    profile.finish();
 
     pda_account!(
         v_acc,
@@ -1988,9 +2210,18 @@ async fn test_compute_proof_verifcation_invalid_proof() {
     assert_eq!(v_acc.get_step(), VerificationStep::FinalExponentiation);
 
     // Final exponentiation
+    let mut profile = ComputeUnitProfile::new(
+        "final_exponentiation",
+        FinalExponentiation::COMPUTE_BUDGET_PER_IX,
+        &FinalExponentiation::INSTRUCTION_ROUNDS,
+        FinalExponentiation::ROUND_COMPUTE_UNITS,
+    );
     for _ in 0..FinalExponentiation::TX_COUNT {
-        test.tx_should_succeed_simple(&instructions).await;
+        profile
+            .tx_should_succeed(&mut test, &instructions, &[])
+            .await;
     }
+    profile.finish();
 
     pda_account!(
         v_acc,
@@ -2003,6 +2234,114 @@ async fn test_compute_proof_verifcation_invalid_proof() {
     assert_eq!(v_acc.get_step(), VerificationStep::FinalExponentiation);
 }
 
+/// Measures the compute units of all `verify_partial` transactions of a proof verification
+///
+/// # Notes
+///
+/// - An invalid proof performs the same rounds as a valid one.
+/// - The budgets are only asserted for the BPF program (`build.sh test --test-kind compute-units`).
+#[tokio::test]
+async fn test_verify_partial_compute_units() {
+    let compute_unit_limit = (MAX_COMPUTE_UNIT_LIMIT - COMPUTE_UNIT_PADDING) as u64;
+
+    let mut test = start_verification_test().await;
+    let (_, vkey_sub_account) = setup_vkey_account::<SendQuadraVKey>(&mut test).await;
+
+    let mut request = send_request(0);
+    let extra_data = ExtraData::default();
+    request.public_inputs.hashed_inputs = extra_data.hash();
+    request.update_fee_lamports(&genesis_fee(&mut test).await);
+    test.airdrop_lamports(&FeeCollectorAccount::find(None).0, LAMPORTS_PER_SOL)
+        .await;
+    init_verification_simple(
+        &request.proof,
+        &request.public_inputs,
+        extra_data.identifier,
+        &mut test,
+    )
+    .await;
+
+    let mut instructions = vec![
+        request_compute_units(MAX_COMPUTE_UNIT_LIMIT),
+        ComputeBudgetInstruction::set_compute_unit_price(0),
+    ];
+    instructions.extend((2..COMPUTE_VERIFICATION_IX_COUNT).map(|_| {
+        ElusivInstruction::compute_verification_instruction(
+            0,
+            SendQuadraVKey::VKEY_ID,
+            UserAccount(test.payer()),
+            &[UserAccount(vkey_sub_account)],
+        )
+    }));
+
+    let mut steps: Vec<(&str, Vec<u64>)> = vec![
+        ("public_input_preparation", Vec::new()),
+        ("combined_miller_loop", Vec::new()),
+        ("final_exponentiation", Vec::new()),
+    ];
+    loop {
+        pda_account!(
+            v_acc,
+            VerificationAccount,
+            Some(test.payer()),
+            Some(0),
+            test
+        );
+        if v_acc.get_is_verified().option().is_some() {
+            break;
+        }
+        let step = match v_acc.get_step() {
+            VerificationStep::PublicInputPreparation => 0,
+            VerificationStep::CombinedMillerLoop => 1,
+            VerificationStep::FinalExponentiation => 2,
+            VerificationStep::Batched => panic!(),
+        };
+
+        let compute_units = simulate_compute_units(&mut test, &instructions, &[]).await;
+        steps[step].1.push(compute_units.iter().sum());
+        test.tx_should_succeed_simple(&instructions).await;
+    }
+
+    pda_account!(
+        v_acc,
+        VerificationAccount,
+        Some(test.payer()),
+        Some(0),
+        test
+    );
+    assert_eq!(v_acc.get_is_verified().option(), Some(false));
+    assert_eq!(steps[1].1.len(), CombinedMillerLoop::TX_COUNT);
+    assert_eq!(steps[2].1.len(), FinalExponentiation::TX_COUNT);
+
+    if cfg!(feature = "compute-unit-profiling") {
+        write_compute_unit_report(
+            "verify_partial",
+            &json!({
+                "compute_unit_limit": compute_unit_limit,
+                "steps": steps
+                    .iter()
+                    .map(|(name, txs)| json!({
+                        "step": name,
+                        "transactions": txs.len(),
+                        "total_compute_units": txs.iter().sum::<u64>(),
+                        "max_compute_units": txs.iter().max(),
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+        );
+
+        for (name, txs) in steps {
+            assert!(
+                txs.iter()
+                    .all(|&compute_units| compute_units <= compute_unit_limit),
+                "{}: transactions exceed the compute unit limit of {}",
+                name,
+                compute_unit_limit
+            );
+        }
+    }
+}
+
 #[tokio::test]
 async fn test_enforced_finalization_order() {
     let mut test = start_verification_test().await;
@@ -2044,6 +2383,10 @@ async fn test_enforced_finalization_order() {
             UserAccount(extra_data.identifier()),
             UserAccount(extra_data.reference()),
             UserAccount(test.payer()),
+            &[],
+            &[UserAccount(
+                apa_target_map_account(&extra_data.recipient()).0,
+            )],
         );
     let finalize_verification_send_nullifier_instruction =
         ElusivInstruction::finalize_verification_insert_nullifier_instruction(
@@ -2059,6 +2402,7 @@ async fn test_enforced_finalization_order() {
             WritableUserAccount(extra_data.recipient()),
             WritableUserAccount(Pubkey::new_unique()),
             WritableUserAccount(nullifier_duplicate_account),
+            &[],
         );
 
     set_verification_state(test.payer(), 0, VerificationState::ProofSetup, &mut test).await;
@@ -2126,6 +2470,7 @@ async fn nullifier_finalization_test(number_of_start_nullifiers: u64, input_comm
     };
     compute_fee_rec_lamports::<SendQuadraVKey, _>(
         &mut public_inputs,
+        SendQuadraVKey::LAYOUT,
         &genesis_fee(&mut test).await,
     );
     let nullifier_duplicate_account = public_inputs.join_split.nullifier_duplicate_pda().0;
@@ -2156,6 +2501,8 @@ async fn nullifier_finalization_test(number_of_start_nullifiers: u64, input_comm
             UserAccount(identifier),
             UserAccount(reference),
             UserAccount(test.payer()),
+            &[],
+            &[UserAccount(apa_target_map_account(&recipient).0)],
         ),
     ];
 
@@ -2205,6 +2552,7 @@ async fn nullifier_finalization_test(number_of_start_nullifiers: u64, input_comm
             WritableUserAccount(recipient),
             WritableUserAccount(Pubkey::new_unique()),
             WritableUserAccount(nullifier_duplicate_account),
+            &[],
         ),
     );
 
@@ -2222,6 +2570,167 @@ async fn test_finalization_nullifier_insertions() {
     }
 }
 
+/// Measures the compute units per nullifier insertion at 10%, 50% and 99% fill of a child-account
+///
+/// # Notes
+///
+/// - Each child-account is first measured during the index migration (legacy mid-ptr tree), then after building its 255 index-ptrs.
+/// - The insertions maintain the index-ptrs, so their cost is included in the measured compute units.
+/// - The budgets are only asserted for the BPF program (`build.sh test --test-kind compute-units`).
+#[tokio::test]
+async fn test_nullifier_insertion_compute_units() {
+    const SAMPLES: u64 = 8;
+    let compute_unit_limit = (DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT - COMPUTE_UNIT_PADDING) as u64;
+
+    let mut test = start_verification_test().await;
+    setup_vkey_account::<SendQuadraVKey>(&mut test).await;
+    let child_account = nullifier_accounts(&mut test, 0).await[0];
+
+    let mut request = send_request(0);
+    let extra_data = ExtraData::default();
+    request.public_inputs.hashed_inputs = extra_data.hash();
+    request.update_fee_lamports(&genesis_fee(&mut test).await);
+    init_verification_simple(
+        &request.proof,
+        &request.public_inputs,
+        extra_data.identifier,
+        &mut test,
+    )
+    .await;
+    skip_computation(test.payer(), 0, true, &mut test).await;
+
+    let insert_ix = ElusivInstruction::finalize_verification_insert_nullifier_instruction(
+        0,
+        UserAccount(test.payer()),
+        Some(0),
+        &writable_user_accounts(&[child_account]),
+    );
+    let build_index_ix = ElusivInstruction::build_nullifier_child_account_index_instruction(
+        0,
+        0,
+        WritableUserAccount(child_account),
+    );
+
+    let mut fills = Vec::new();
+    for fill in [10, 50, 99] {
+        // Prefills the child-account with even values (and starts the index migration)
+        let n = NULLIFIERS_PER_ACCOUNT as u64 * fill / 100;
+        let mut data = test.data(&child_account).await;
+        {
+            let mut map = NullifierMap::new(&mut data[1..]);
+            map.reset();
+            map.start_index_migration();
+            for i in (0..n).rev() {
+                map.try_insert_default(OrdU256(u64_to_u256(i * 2 + 2)))
+                    .unwrap();
+            }
+        }
+        test.set_account_rent_exempt(&child_account, &data, &elusiv::id())
+            .await;
+
+        let mut mid_ptr_compute_units =
+            insertion_compute_units(&mut test, &request, &insert_ix, n, SAMPLES).await;
+
+        let mut build_index_compute_units = Vec::new();
+        loop {
+            let mut data = test.data(&child_account).await;
+            if NullifierMap::new(&mut data[1..]).is_indexed() {
+                break;
+            }
+
+            build_index_compute_units.extend(
+                simulate_compute_units(&mut test, std::slice::from_ref(&build_index_ix), &[]).await,
+            );
+            test.ix_should_succeed_simple(nonce_instruction(build_index_ix.clone()))
+                .await;
+        }
+
+        let mut index_compute_units =
+            insertion_compute_units(&mut test, &request, &insert_ix, n, SAMPLES).await;
+
+        mid_ptr_compute_units.sort();
+        index_compute_units.sort();
+        fills.push(json!({
+            "fill": fill,
+            "nullifiers": n,
+            "mid_ptr_tree": {
+                "median_compute_units": mid_ptr_compute_units[SAMPLES as usize / 2],
+                "max_compute_units": mid_ptr_compute_units.last(),
+            },
+            "index_tree": {
+                "median_compute_units": index_compute_units[SAMPLES as usize / 2],
+                "max_compute_units": index_compute_units.last(),
+            },
+            "build_index_instructions": build_index_compute_units.len(),
+            "build_index_max_compute_units": build_index_compute_units.iter().max(),
+        }));
+
+        if cfg!(feature = "compute-unit-profiling") {
+            assert!(
+                *index_compute_units.last().unwrap() <= compute_unit_limit,
+                "{}% fill: nullifier insertion exceeds the compute unit limit of {}",
+                fill,
+                compute_unit_limit
+            );
+            assert!(
+                build_index_compute_units
+                    .iter()
+                    .all(|&compute_units| compute_units <= compute_unit_limit),
+                "{}% fill: index building exceeds the compute unit limit of {}",
+                fill,
+                compute_unit_limit
+            );
+        }
+    }
+
+    if cfg!(feature = "compute-unit-profiling") {
+        write_compute_unit_report(
+            "nullifier_insertion",
+            &json!({
+                "compute_unit_limit": compute_unit_limit,
+                "samples": SAMPLES,
+                "fills": fills,
+            }),
+        );
+    }
+}
+
+/// Simulates the insertion of `samples` odd nullifier hashes spread over a child-account prefilled with `n` even values
+async fn insertion_compute_units(
+    test: &mut ElusivProgramTest,
+    request: &FullSendRequest,
+    insert_ix: &Instruction,
+    n: u64,
+    samples: u64,
+) -> Vec<u64> {
+    let mut compute_units = Vec::new();
+    for j in 0..samples {
+        let mut public_inputs = request.public_inputs.clone();
+        public_inputs.join_split.input_commitments[0].nullifier_hash =
+            RawU256::new(u64_to_u256_skip_mr((j * n / samples) * 2 + 1));
+
+        test.set_pda_account::<VerificationAccount, _>(
+            &elusiv::id(),
+            Some(test.payer()),
+            Some(0),
+            |data| {
+                let mut verification_account = VerificationAccount::new(data).unwrap();
+                verification_account.set_request(&ProofRequest::Send(public_inputs));
+                verification_account.set_state(&VerificationState::InsertNullifiers);
+            },
+        )
+        .await;
+
+        compute_units.extend(
+            simulate_compute_units(test, &[request_max_compute_units(), insert_ix.clone()], &[])
+                .await,
+        );
+    }
+    assert_eq!(compute_units.len(), samples as usize);
+
+    compute_units
+}
+
 async fn finalize_instructions(
     test: &mut ElusivProgramTest,
     request: &FullSendRequest,
@@ -2246,6 +2755,10 @@ async fn finalize_instructions(
             UserAccount(extra_data.identifier()),
             UserAccount(*reference),
             UserAccount(*signer),
+            &[],
+            &[UserAccount(
+                apa_target_map_account(&extra_data.recipient()).0,
+            )],
         ),
         ElusivInstruction::finalize_verification_insert_nullifier_instruction(
             0,
@@ -2259,6 +2772,7 @@ async fn finalize_instructions(
             WritableUserAccount(extra_data.recipient()),
             WritableUserAccount(Pubkey::new_unique()),
             WritableUserAccount(request.public_inputs.join_split.nullifier_duplicate_pda().0),
+            &[],
         ),
     ]
 }
@@ -2347,6 +2861,319 @@ async fn test_isolated_memo() {
     .await;
 }
 
+#[tokio::test]
+async fn test_recipient_callback() {
+    let mut test = start_verification_test().await;
+    setup_vkey_account::<SendQuadraVKey>(&mut test).await;
+
+    let program_id = CALLBACK_MOCK_PROGRAM_ID;
+    let payload = vec![1, 2, 3, 4];
+    let record = Pubkey::new_unique();
+    let mut request = send_request(0);
+    let extra_data = ExtraData {
+        recipient: program_id.to_bytes(),
+        recipient_callback: Some(payload.clone()),
+        recipient_callback_writable_accounts: vec![record.to_bytes()],
+        ..Default::default()
+    };
+    request.public_inputs.hashed_inputs = extra_data.hash();
+    request.update_fee_lamports(&genesis_fee(&mut test).await);
+
+    test.airdrop_lamports(&FeeCollectorAccount::find(None).0, LAMPORTS_PER_SOL)
+        .await;
+    test.airdrop_lamports(&PoolAccount::find(None).0, LAMPORTS_PER_SOL * 1000)
+        .await;
+
+    init_verification_simple(
+        &request.proof,
+        &request.public_inputs,
+        extra_data.identifier,
+        &mut test,
+    )
+    .await;
+    skip_computation(test.payer(), 0, true, &mut test).await;
+    set_verification_state(test.payer(), 0, VerificationState::ProofSetup, &mut test).await;
+
+    test.set_account_rent_exempt(&record, &[0; 8], &program_id)
+        .await;
+
+    let payer = test.payer();
+    let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let vault = recipient_callback_vault(&program_id).0;
+    let finalize_instructions =
+        |recipient_callback: Option<Vec<u8>>,
+         recipient: Pubkey,
+         callback_accounts: Vec<AccountMeta>| {
+            let mut transfer_instruction =
+                ElusivInstruction::finalize_verification_transfer_lamports_instruction(
+                    0,
+                    WritableSignerAccount(payer),
+                    WritableUserAccount(recipient),
+                    WritableUserAccount(Pubkey::new_unique()),
+                    WritableUserAccount(
+                        request.public_inputs.join_split.nullifier_duplicate_pda().0,
+                    ),
+                    &[],
+                );
+            transfer_instruction.accounts.extend(callback_accounts);
+
+            vec![
+                ElusivInstruction::finalize_verification_send_instruction(
+                    0,
+                    FinalizeSendData {
+                        total_amount: request.public_inputs.join_split.total_amount(),
+                        encrypted_owner: extra_data.encrypted_owner,
+                        iv: extra_data.iv,
+                        recipient_callback,
+                        recipient_callback_writable_accounts: extra_data
+                            .recipient_callback_writable_accounts
+                            .clone(),
+                        ..Default::default()
+                    },
+                    false,
+                    UserAccount(program_id),
+                    UserAccount(extra_data.identifier()),
+                    UserAccount(extra_data.reference()),
+                    UserAccount(payer),
+                    &[],
+                    &[UserAccount(apa_target_map_account(&program_id).0)],
+                ),
+                ElusivInstruction::finalize_verification_insert_nullifier_instruction(
+                    0,
+                    UserAccount(payer),
+                    Some(0),
+                    &writable_user_accounts(&[nullifier_accounts[0]]),
+                ),
+                transfer_instruction,
+            ]
+        };
+    let callback_accounts =
+        recipient_callback_accounts(program_id, &[AccountMeta::new(record, false)]);
+
+    // Missing payload
+    test.tx_should_fail_simple(&finalize_instructions(
+        None,
+        vault,
+        callback_accounts.clone(),
+    ))
+    .await;
+
+    // Invalid payload
+    test.tx_should_fail_simple(&finalize_instructions(
+        Some(vec![1, 2, 3]),
+        vault,
+        callback_accounts.clone(),
+    ))
+    .await;
+
+    // Funds have to flow to the vault of the recipient program
+    test.tx_should_fail_simple(&finalize_instructions(
+        Some(payload.clone()),
+        Pubkey::new_unique(),
+        callback_accounts.clone(),
+    ))
+    .await;
+
+    // Missing callback accounts
+    test.tx_should_fail_simple(&finalize_instructions(
+        Some(payload.clone()),
+        vault,
+        Vec::new(),
+    ))
+    .await;
+
+    // Invalid callback authority
+    let mut invalid_callback_accounts = callback_accounts.clone();
+    invalid_callback_accounts[1].pubkey = Pubkey::new_unique();
+    test.tx_should_fail_simple(&finalize_instructions(
+        Some(payload.clone()),
+        vault,
+        invalid_callback_accounts,
+    ))
+    .await;
+
+    test.tx_should_succeed_simple(&finalize_instructions(
+        Some(payload.clone()),
+        vault,
+        callback_accounts,
+    ))
+    .await;
+
+    assert_eq!(
+        request.public_inputs.join_split.amount,
+        test.lamports(&vault).await.0
+    );
+    assert_eq!(&test.data(&record).await[..5], &[4, 1, 2, 3, 4]);
+}
+
+#[tokio::test]
+async fn test_recipient_callback_timeout() {
+    let mut test = start_verification_test().await;
+    setup_vkey_account::<SendQuadraVKey>(&mut test).await;
+
+    let program_id = CALLBACK_MOCK_PROGRAM_ID;
+    let payload = vec![1, 2, 3, 4];
+    let record = Pubkey::new_unique();
+    let mut request = send_request(0);
+    let extra_data = ExtraData {
+        recipient: program_id.to_bytes(),
+        recipient_callback: Some(payload.clone()),
+        recipient_callback_writable_accounts: vec![record.to_bytes()],
+        ..Default::default()
+    };
+    request.public_inputs.hashed_inputs = extra_data.hash();
+    request.update_fee_lamports(&genesis_fee(&mut test).await);
+
+    test.airdrop_lamports(&FeeCollectorAccount::find(None).0, LAMPORTS_PER_SOL)
+        .await;
+    test.airdrop_lamports(&PoolAccount::find(None).0, LAMPORTS_PER_SOL * 1000)
+        .await;
+
+    init_verification_simple(
+        &request.proof,
+        &request.public_inputs,
+        extra_data.identifier,
+        &mut test,
+    )
+    .await;
+    skip_computation(test.payer(), 0, true, &mut test).await;
+    set_verification_state(test.payer(), 0, VerificationState::ProofSetup, &mut test).await;
+
+    test.set_account_rent_exempt(&record, &[0; 8], &program_id)
+        .await;
+
+    let payer = test.payer();
+    let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let vault = recipient_callback_vault(&program_id).0;
+
+    test.tx_should_succeed_simple(&[
+        ElusivInstruction::finalize_verification_send_instruction(
+            0,
+            FinalizeSendData {
+                total_amount: request.public_inputs.join_split.total_amount(),
+                encrypted_owner: extra_data.encrypted_owner,
+                iv: extra_data.iv,
+                recipient_callback: Some(payload),
+                recipient_callback_writable_accounts: extra_data
+                    .recipient_callback_writable_accounts
+                    .clone(),
+                ..Default::default()
+            },
+            false,
+            UserAccount(program_id),
+            UserAccount(extra_data.identifier()),
+            UserAccount(extra_data.reference()),
+            UserAccount(payer),
+            &[],
+            &[UserAccount(apa_target_map_account(&program_id).0)],
+        ),
+        ElusivInstruction::finalize_verification_insert_nullifier_instruction(
+            0,
+            UserAccount(payer),
+            Some(0),
+            &writable_user_accounts(&[nullifier_accounts[0]]),
+        ),
+    ])
+    .await;
+
+    let transfer_ix = ElusivInstruction::finalize_verification_transfer_lamports_instruction(
+        0,
+        WritableSignerAccount(payer),
+        WritableUserAccount(vault),
+        WritableUserAccount(Pubkey::new_unique()),
+        WritableUserAccount(request.public_inputs.join_split.nullifier_duplicate_pda().0),
+        &[],
+    );
+
+    // The callback can only be skipped after the timeout
+    test.ix_should_fail_simple(transfer_ix.clone()).await;
+
+    test.context()
+        .warp_to_slot(RECIPIENT_CALLBACK_TIMEOUT_SLOTS + 100)
+        .unwrap();
+    test.ix_should_succeed_simple(nonce_instruction(transfer_ix))
+        .await;
+
+    // Funds are released into the vault without invoking the recipient program
+    assert_eq!(
+        request.public_inputs.join_split.amount,
+        test.lamports(&vault).await.0
+    );
+    assert_eq!(test.data(&record).await, vec![0; 8]);
+}
+
+#[tokio::test]
+async fn test_withdrawal_rate_limit() {
+    let mut test = start_verification_test().await;
+    setup_vkey_account::<SendQuadraVKey>(&mut test).await;
+
+    let mut request = send_request(0);
+    let extra_data = ExtraData::default();
+    request.public_inputs.hashed_inputs = extra_data.hash();
+    request.update_fee_lamports(&genesis_fee(&mut test).await);
+
+    let amount = request.public_inputs.join_split.amount;
+    let withdrawal_cap = amount / 2 + 1;
+
+    test.airdrop_lamports(&FeeCollectorAccount::find(None).0, LAMPORTS_PER_SOL)
+        .await;
+    test.airdrop_lamports(&PoolAccount::find(None).0, LAMPORTS_PER_SOL * 1000)
+        .await;
+
+    set_rate_limits(&mut test, 100, LAMPORTS_TOKEN_ID, 0, withdrawal_cap).await;
+
+    init_verification_simple(
+        &request.proof,
+        &request.public_inputs,
+        extra_data.identifier,
+        &mut test,
+    )
+    .await;
+    skip_computation(test.payer(), 0, true, &mut test).await;
+    set_verification_state(test.payer(), 0, VerificationState::ProofSetup, &mut test).await;
+
+    let payer = test.payer();
+    let recipient = extra_data.recipient();
+    let verification_account = VerificationAccount::find_with_pubkey(payer, Some(0)).0;
+    let finalize_ixs = finalize_instructions(
+        &mut test,
+        &request,
+        &extra_data,
+        &extra_data.reference(),
+        &payer,
+        None,
+    )
+    .await;
+    let mut transfer_ix = finalize_ixs[2].clone();
+    transfer_ix.accounts.push(AccountMeta::new(
+        RateLimitAccount::find(Some(LAMPORTS_TOKEN_ID.into())).0,
+        false,
+    ));
+
+    // Missing `RateLimitAccount`
+    test.tx_should_fail_simple(&finalize_ixs).await;
+
+    // Only the withdrawal cap is transferred in the current window
+    test.tx_should_succeed_simple(&[
+        finalize_ixs[0].clone(),
+        finalize_ixs[1].clone(),
+        transfer_ix.clone(),
+    ])
+    .await;
+    assert_eq!(test.lamports(&recipient).await.0, withdrawal_cap);
+    assert!(test.account_does_exist(&verification_account).await);
+
+    // Rate limit exceeded
+    test.ix_should_fail_simple(transfer_ix.clone()).await;
+
+    // The remainder is transferred in the next window
+    test.context().warp_to_slot(200).unwrap();
+    test.ix_should_succeed_simple(nonce_instruction(transfer_ix))
+        .await;
+    assert_eq!(test.lamports(&recipient).await.0, amount);
+    assert!(test.account_does_not_exist(&verification_account).await);
+}
+
 #[tokio::test]
 async fn test_solana_pay_lamports() {
     let mut test = start_verification_test().await;
@@ -2598,6 +3425,7 @@ async fn test_solana_pay_tokens() {
     enable_program_token_account::<FeeCollectorAccount>(&mut test, USDC_TOKEN_ID, None).await;
     setup_vkey_account::<SendQuadraVKey>(&mut test).await;
     let nullifier_accounts = nullifier_accounts(&mut test, 0).await;
+    let root_history_accounts = root_history_accounts(&mut test).await;
     let fee = genesis_fee(&mut test).await;
 
     let mut recipient = test.new_actor().await;
@@ -2665,6 +3493,7 @@ async fn test_solana_pay_tokens() {
                 UserAccount(Pubkey::new_from_array(extra_data.identifier)),
                 &user_accounts(&[nullifier_accounts[0]]),
                 &[],
+                &user_accounts(&root_history_accounts),
             ),
             ElusivInstruction::init_verification_transfer_fee_instruction(
                 0,
@@ -2703,6 +3532,10 @@ async fn test_solana_pay_tokens() {
             UserAccount(extra_data.identifier()),
             UserAccount(extra_data.reference()),
             UserAccount(warden.pubkey),
+            &[],
+            &[UserAccount(
+                apa_target_map_account(&recipient_token_account).0,
+            )],
         ),
         ElusivInstruction::finalize_verification_insert_nullifier_instruction(
             0,
@@ -2721,6 +3554,7 @@ async fn test_solana_pay_tokens() {
             WritableUserAccount(Pubkey::new_unique()),
             WritableUserAccount(nullifier_duplicate_account),
             UserAccount(spl_token::id()),
+            &[],
         ),
     ];
 
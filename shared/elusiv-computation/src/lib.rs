@@ -11,6 +11,10 @@ pub trait PartialComputation<const INSTRUCTION_COUNT: usize> {
 
     /// All required compute units
     const TOTAL_COMPUTE_UNITS: u32;
+
+    /// Annotated compute units of each round (used for packing the rounds into instructions)
+    const ROUND_COMPUTE_UNITS: &'static [u32];
+
     const COMPUTE_BUDGET_PER_IX: u32;
 }
 
@@ -54,44 +58,148 @@ pub struct PartialComputationResult {
 
 #[cfg(feature = "compute-unit-optimization")]
 /// Generates instructions (batching of multiple computation rounds) to fit a partial computation in the MAX_COMPUTE_UNIT_LIMIT
+///
+/// # Notes
+///
+/// The instructions are sent in transactions of `MAX_COMPUTE_UNIT_LIMIT / max_cus` instructions.
+/// We pack the rounds into the minimal number of transactions (DP over the contiguous rounds) and afterwards split the rounds of each transaction into its instructions.
+/// Each single instruction stays within `max_cus - COMPUTE_UNIT_PADDING` compute units.
 pub fn compute_unit_optimization(round_costs: Vec<u32>, max_cus: u32) -> PartialComputationResult {
-    let max_cus = max_cus - COMPUTE_UNIT_PADDING;
-    let mut instructions = Vec::new();
+    let ixs_per_tx = (MAX_COMPUTE_UNIT_LIMIT / max_cus).max(1) as usize;
+    let ix_compute_units = (max_cus - COMPUTE_UNIT_PADDING) as u64;
+    let rounds_count = round_costs.len();
 
-    let mut rounds = 0;
-    let mut start_round = 0;
-    let mut compute_units = 0;
-    let mut total_compute_units = 0;
+    // Every transaction consists of exactly `ixs_per_tx` instructions (each performing at least one round)
+    let min_tx_rounds = ixs_per_tx.min(rounds_count);
 
-    for r in round_costs {
-        if compute_units + r > max_cus {
-            instructions.push(rounds);
+    let mut prefix_sums = vec![0u64; rounds_count + 1];
+    for (i, &r) in round_costs.iter().enumerate() {
+        prefix_sums[i + 1] = prefix_sums[i] + r as u64;
+    }
 
-            start_round += rounds;
-            rounds = 1;
-            compute_units = r;
-        } else {
-            rounds += 1;
-            compute_units += r;
-        }
+    // `ix_ends[i]`: the end of the largest instruction starting at round `i`
+    let ix_ends: Vec<usize> = (0..rounds_count)
+        .map(|start| {
+            let end = (start + 1..=rounds_count.min(start + u8::MAX as usize))
+                .take_while(|&end| prefix_sums[end] - prefix_sums[start] <= ix_compute_units)
+                .last();
+            end.unwrap_or_else(|| {
+                panic!(
+                    "Round {} exceeds the instruction budget of {} compute units",
+                    start, ix_compute_units
+                )
+            })
+        })
+        .collect();
 
-        total_compute_units += r;
+    // `tx_ends[i]`: the end of the largest transaction starting at round `i` (greedily packed instructions)
+    let tx_ends: Vec<usize> = (0..rounds_count)
+        .map(|start| {
+            (0..ixs_per_tx).fold(start, |end, _| {
+                if end < rounds_count {
+                    ix_ends[end]
+                } else {
+                    end
+                }
+            })
+        })
+        .collect();
+    let tx_fits = |start: usize, end: usize| end - start >= min_tx_rounds && end <= tx_ends[start];
+
+    // `tx_counts[i]`: minimal number of transactions required for the rounds `i..`
+    let mut tx_counts: Vec<Option<usize>> = vec![None; rounds_count + 1];
+    tx_counts[rounds_count] = Some(0);
+    for start in (0..rounds_count).rev() {
+        tx_counts[start] = (start + min_tx_rounds..=rounds_count)
+            .take_while(|&end| tx_fits(start, end))
+            .filter_map(|end| tx_counts[end])
+            .min()
+            .map(|c| c + 1);
     }
+    assert!(
+        tx_counts[0].is_some(),
+        "Rounds cannot be packed into transactions of {} instructions",
+        ixs_per_tx
+    );
 
-    if rounds > 0 {
-        instructions.push(rounds);
+    // Each transaction performs as many rounds as possible (for a single instruction per transaction this matches a greedy packing)
+    let mut instructions = Vec::new();
+    let mut start = 0;
+    while start < rounds_count {
+        let remaining = tx_counts[start].unwrap() - 1;
+        let end = (start + min_tx_rounds..=rounds_count)
+            .take_while(|&end| tx_fits(start, end))
+            .filter(|&end| tx_counts[end] == Some(remaining))
+            .last()
+            .unwrap();
+
+        let tx_instructions = split_rounds(&round_costs[start..end], ixs_per_tx.min(end - start));
+        let mut ix_start = start;
+        for &rounds in &tx_instructions {
+            let ix_end = ix_start + rounds as usize;
+            assert!(prefix_sums[ix_end] - prefix_sums[ix_start] <= ix_compute_units);
+            ix_start = ix_end;
+        }
+
+        instructions.extend(tx_instructions);
+        start = end;
     }
 
-    let total_rounds = start_round + rounds;
+    let total_rounds = rounds_count as u32;
     assert!(total_rounds <= u16::MAX as u32); // assert this since `VerificationAccount` saves rounds as u16
 
     PartialComputationResult {
         instructions,
-        total_compute_units,
+        total_compute_units: round_costs.iter().sum(),
         total_rounds,
     }
 }
 
+#[cfg(feature = "compute-unit-optimization")]
+/// Splits the rounds of a transaction into `ixs` instructions, minimizing the compute units of the most expensive instruction
+fn split_rounds(round_costs: &[u32], ixs: usize) -> Vec<u32> {
+    let rounds_count = round_costs.len();
+    let max_ix_rounds = u8::MAX as usize;
+
+    let mut prefix_sums = vec![0u64; rounds_count + 1];
+    for (i, &r) in round_costs.iter().enumerate() {
+        prefix_sums[i + 1] = prefix_sums[i] + r as u64;
+    }
+
+    // `costs[ix][end]`: minimal maximum instruction cost for performing the rounds `..end` in `ix + 1` instructions
+    let mut costs = vec![vec![u64::MAX; rounds_count + 1]; ixs];
+    let mut starts = vec![vec![0; rounds_count + 1]; ixs];
+    let first_ix_rounds = rounds_count.min(max_ix_rounds);
+    costs[0][1..=first_ix_rounds].copy_from_slice(&prefix_sums[1..=first_ix_rounds]);
+    for ix in 1..ixs {
+        for end in ix + 1..=rounds_count {
+            for start in end.saturating_sub(max_ix_rounds).max(ix)..end {
+                if costs[ix - 1][start] == u64::MAX {
+                    continue;
+                }
+
+                let cost = costs[ix - 1][start].max(prefix_sums[end] - prefix_sums[start]);
+                if cost < costs[ix][end] {
+                    costs[ix][end] = cost;
+                    starts[ix][end] = start;
+                }
+            }
+        }
+    }
+    assert!(costs[ixs - 1][rounds_count] != u64::MAX);
+
+    let mut instructions = vec![0; ixs];
+    let mut end = rounds_count;
+    for ix in (0..ixs).rev() {
+        let start = starts[ix][end];
+        instructions[ix] = (end - start) as u32;
+        end = start;
+    }
+
+    instructions
+}
+
+/// Greedily packs the rounds into instructions of at most `max_cus - COMPUTE_UNIT_PADDING` compute units each
 pub fn compute_unit_instructions(round_costs: Vec<u32>, max_cus: u32) -> Vec<u32> {
     let max_cus = max_cus - COMPUTE_UNIT_PADDING;
     let mut instructions = Vec::new();
@@ -117,3 +225,106 @@ pub fn compute_unit_instructions(round_costs: Vec<u32>, max_cus: u32) -> Vec<u32
 
     instructions
 }
+
+#[cfg(test)]
+#[cfg(feature = "compute-unit-optimization")]
+mod tests {
+    use super::*;
+
+    fn tx_count(instructions: usize, max_cus: u32) -> usize {
+        let ixs_per_tx = (MAX_COMPUTE_UNIT_LIMIT / max_cus) as usize;
+        let txs = instructions / ixs_per_tx;
+        if txs * ixs_per_tx < instructions {
+            txs + 1
+        } else {
+            txs
+        }
+    }
+
+    fn pseudo_random_costs(count: usize, min: u32, max: u32) -> Vec<u32> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..count)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                min + (state >> 33) as u32 % (max - min)
+            })
+            .collect()
+    }
+
+    fn assert_valid_packing(round_costs: &[u32], max_cus: u32, result: &PartialComputationResult) {
+        let ixs_per_tx = (MAX_COMPUTE_UNIT_LIMIT / max_cus) as usize;
+
+        assert_eq!(result.total_rounds as usize, round_costs.len());
+        assert_eq!(result.instructions.iter().sum::<u32>(), result.total_rounds);
+        assert_eq!(result.instructions.len() % ixs_per_tx, 0);
+        assert!(result
+            .instructions
+            .iter()
+            .all(|&r| r > 0 && r <= u8::MAX as u32));
+
+        let mut round = 0;
+        for &rounds in &result.instructions {
+            let compute_units: u32 = round_costs[round..round + rounds as usize].iter().sum();
+            assert!(compute_units <= max_cus - COMPUTE_UNIT_PADDING);
+            round += rounds as usize;
+        }
+    }
+
+    #[test]
+    fn test_compute_unit_optimization() {
+        // A transaction would fit 13 rounds, each instruction however only fits two rounds
+        let round_costs = vec![100_000; 130];
+        let result = compute_unit_optimization(round_costs.clone(), 280_000);
+
+        assert_valid_packing(&round_costs, 280_000, &result);
+        assert!(result.instructions.iter().all(|&r| r == 2));
+        assert_eq!(tx_count(result.instructions.len(), 280_000), 13);
+        assert_eq!(
+            tx_count(
+                compute_unit_instructions(round_costs, 280_000).len(),
+                280_000
+            ),
+            13
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_compute_unit_optimization_round_exceeds_instruction_budget() {
+        compute_unit_optimization(vec![100_000, 280_000, 100_000], 280_000);
+    }
+
+    #[test]
+    fn test_compute_unit_optimization_single_ix_per_tx() {
+        // With a single instruction per transaction the greedy packing is optimal
+        let round_costs = pseudo_random_costs(500, 1_000, 150_000);
+        let result = compute_unit_optimization(round_costs.clone(), 1_300_000);
+
+        assert_valid_packing(&round_costs, 1_300_000, &result);
+        assert_eq!(
+            result.instructions,
+            compute_unit_instructions(round_costs, 1_300_000)
+        );
+    }
+
+    #[test]
+    fn test_compute_unit_optimization_greedy_comparison() {
+        for (count, min, max, max_cus) in [
+            (2000, 5_000, 90_000, 250_000),
+            (1000, 50_000, 200_000, 250_000),
+            (700, 1_000, 30_000, 100_000),
+            (300, 10_000, 400_000, 700_000),
+        ] {
+            let round_costs = pseudo_random_costs(count, min, max);
+            let result = compute_unit_optimization(round_costs.clone(), max_cus);
+            assert_valid_packing(&round_costs, max_cus, &result);
+
+            let greedy = compute_unit_instructions(round_costs, max_cus);
+            assert!(
+                tx_count(result.instructions.len(), max_cus) <= tx_count(greedy.len(), max_cus)
+            );
+        }
+    }
+}
@@ -72,10 +72,17 @@ pub fn impl_borsh_serde_sized(ast: &syn::DeriveInput) -> TokenStream {
         syn::Data::Struct(s) => {
             sizes.push(size_of_fields(&s.fields));
             let size: TokenStream = sizes.iter().fold(quote! {}, |acc, x| quote! { #acc #x });
+            let zero_copy = if is_zero_copy(ast) {
+                impl_zero_copy_fields(&s.fields)
+            } else {
+                quote! {}
+            };
 
             quote! {
                 impl #impl_generics elusiv_types::bytes::BorshSerDeSized for #ident #ty_generics #where_clause {
                     const SIZE: usize = #size;
+
+                    #zero_copy
                 }
             }
         }
@@ -85,6 +92,67 @@ pub fn impl_borsh_serde_sized(ast: &syn::DeriveInput) -> TokenStream {
     }
 }
 
+/// Structs with the `#[zero_copy]` attribute (de)serialize each field with its own (possibly zero-copy) backend
+fn is_zero_copy(ast: &syn::DeriveInput) -> bool {
+    ast.attrs.iter().any(|attr| attr.path.is_ident("zero_copy"))
+}
+
+fn impl_zero_copy_fields(fields: &Fields) -> TokenStream {
+    let mut reads = quote! {};
+    let mut writes = quote! {};
+    let mut idents = Vec::new();
+
+    for (i, field) in fields.iter().enumerate() {
+        let field_ty = &field.ty;
+        let var = quote::format_ident!("field_{}", i);
+        let member = match &field.ident {
+            Some(ident) => quote! { #ident },
+            None => {
+                let index = syn::Index::from(i);
+                quote! { #index }
+            }
+        };
+
+        reads.extend(quote! {
+            let #var = <#field_ty as elusiv_types::bytes::BorshSerDeSized>::read_sized(&data[offset..])?;
+            offset += <#field_ty as elusiv_types::bytes::BorshSerDeSized>::SIZE;
+        });
+        writes.extend(quote! {
+            <#field_ty as elusiv_types::bytes::BorshSerDeSized>::write_sized(&self.#member, &mut data[offset..])?;
+            offset += <#field_ty as elusiv_types::bytes::BorshSerDeSized>::SIZE;
+        });
+        idents.push((member, var));
+    }
+
+    let construction = match fields {
+        Fields::Named(_) => {
+            let fields = idents.iter().map(|(member, var)| quote! { #member: #var });
+            quote! { Self { #(#fields),* } }
+        }
+        Fields::Unnamed(_) => {
+            let vars = idents.iter().map(|(_, var)| var);
+            quote! { Self(#(#vars),*) }
+        }
+        Fields::Unit => quote! { Self },
+    };
+
+    quote! {
+        #[allow(unused_assignments, unused_mut, unused_variables)]
+        fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+            let mut offset = 0;
+            #reads
+            Ok(#construction)
+        }
+
+        #[allow(unused_assignments, unused_mut, unused_variables)]
+        fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+            let mut offset = 0;
+            #writes
+            Ok(())
+        }
+    }
+}
+
 pub fn impl_borsh_serde_placeholder(ast: &syn::DeriveInput) -> TokenStream {
     let ident = &ast.ident.clone();
     let (impl_generics, ty_generics, where_clause) = &ast.generics.split_for_impl();
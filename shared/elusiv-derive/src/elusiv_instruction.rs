@@ -125,13 +125,27 @@ pub fn impl_elusiv_instruction(ast: &syn::DeriveInput) -> proc_macro2::TokenStre
                 let mut account: TokenStream = sub_attrs[0].0.parse().unwrap();
                 let mut account_init = Vec::new(); // used for creating the instruction objects with the abi-feature
 
-                accounts.extend(quote! {
-                    let #account = &solana_program::account_info::next_account_info(account_info_iter)?;
-                });
+                // Remaining accounts are passed as a slice of all accounts following in the `account_info_iter`
+                let is_remaining = contains_key(&sub_attrs, "remaining");
+                if is_remaining {
+                    assert!(
+                        attr_name == ACC_ATTR,
+                        "'remaining' can only be used with 'acc'"
+                    );
+
+                    accounts.extend(quote! {
+                        let #account: Vec<&solana_program::account_info::AccountInfo> = account_info_iter.collect();
+                    });
+                } else {
+                    accounts.extend(quote! {
+                        let #account = &solana_program::account_info::next_account_info(account_info_iter)?;
+                    });
+                }
 
                 // Signer check
                 let is_signer = contains_key(&sub_attrs, "signer");
                 if is_signer {
+                    assert!(!is_remaining, "'remaining' accounts cannot be signers");
                     accounts.extend(quote!{
                         if !#account.is_signer { return Err(solana_program::program_error::ProgramError::MissingRequiredSignature) }
                     });
@@ -148,6 +162,7 @@ pub fn impl_elusiv_instruction(ast: &syn::DeriveInput) -> proc_macro2::TokenStre
                 // Ownership check
                 let is_owned = contains_key(&sub_attrs, "owned");
                 if is_owned {
+                    assert!(!is_remaining, "'remaining' accounts cannot be owned");
                     accounts.extend(quote!{
                         if *#account.owner != crate::ID { return Err(solana_program::program_error::ProgramError::IllegalOwner) }
                     });
@@ -185,10 +200,20 @@ pub fn impl_elusiv_instruction(ast: &syn::DeriveInput) -> proc_macro2::TokenStre
                 match attr_name.as_str() {
                     // `AccountInfo` (usage: <name>)
                     ACC_ATTR => {
-                        user_accounts.extend(quote! { #account: #user_account_type, });
-                        account_init.push(quote!{
-                            accounts.push(solana_program::instruction::AccountMeta::#account_init_fn(#account.0, #is_signer));
-                        });
+                        if is_remaining {
+                            user_accounts.extend(quote! { #account: &[#user_account_type], });
+                            account_init.push(quote!{
+                                for account in #account {
+                                    accounts.push(solana_program::instruction::AccountMeta::#account_init_fn(account.0, #is_signer));
+                                }
+                            });
+                            account = quote! { &#account };
+                        } else {
+                            user_accounts.extend(quote! { #account: #user_account_type, });
+                            account_init.push(quote!{
+                                accounts.push(solana_program::instruction::AccountMeta::#account_init_fn(#account.0, #is_signer));
+                            });
+                        }
                     }
 
                     // System program `AccountInfo` (usage: <name> <key = ..>)
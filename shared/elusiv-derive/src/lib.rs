@@ -40,6 +40,7 @@ use pda_account::*;
 ///         - `account_info`: returns an `AccountInfo` object (only relevant for PDAs)
 ///         - `include_child_accounts`: the `Type` has to implement the `crate::state::program_account::ParentAccount` trait and up to `Type::COUNT + 1` accounts can be matched (but at least 1)
 ///         - `skip_abi`: can be used to add manual pda_offsets in the abi
+///         - `remaining`: (only `acc`) all remaining accounts are passed as a slice of `AccountInfo`s (has to be the last account attribute)
 ///
 /// # Other attributes
 /// - Each variant can also be equipped with any other kind of attributes (cfg or do documentation).
@@ -62,7 +63,13 @@ pub fn elusiv_instruction(input: proc_macro::TokenStream) -> proc_macro::TokenSt
     impl_elusiv_instruction(&ast).into()
 }
 
-#[proc_macro_derive(BorshSerDeSized)]
+/// Derives [`elusiv_types::bytes::BorshSerDeSized`]
+///
+/// # Zero-copy
+///
+/// Structs with the `#[zero_copy]` attribute (de)serialize each field with its own [`elusiv_types::bytes::BorshSerDeSized::read_sized`] and [`elusiv_types::bytes::BorshSerDeSized::write_sized`] instead of Borsh.
+/// This is only valid for structs whose Borsh representation is the concatenation of their fields (no custom Borsh implementations).
+#[proc_macro_derive(BorshSerDeSized, attributes(zero_copy))]
 pub fn borsh_serde_sized(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
     impl_borsh_serde_sized(&ast).into()
@@ -128,6 +128,7 @@ pub fn impl_elusiv_account(ast: &syn::DeriveInput, attrs: TokenStream) -> TokenS
     let mut eager_defs = quote!();
     let mut eager_init = quote!();
     let mut use_eager_type = false;
+    let mut version = quote!(0);
 
     // 'a lifetime for the `ProgramAccount` impl
     let program_account_lifetime = quote!('a);
@@ -238,6 +239,11 @@ pub fn impl_elusiv_account(ast: &syn::DeriveInput, attrs: TokenStream) -> TokenS
                 use_eager_type = true;
             }
 
+            // The version of the data layout (defaults to zero)
+            "version" => {
+                version = attr.value;
+            }
+
             any => panic!("Invalid attribute '{}'", any),
         }
     }
@@ -500,6 +506,11 @@ pub fn impl_elusiv_account(ast: &syn::DeriveInput, attrs: TokenStream) -> TokenS
                     return Err(solana_program::program_error::ProgramError::InvalidAccountData)
                 }
 
+                // Accounts with an outdated layout need to be migrated before being used
+                if data[elusiv_types::accounts::PDAAccountData::VERSION_OFFSET] != <Self as elusiv_types::accounts::VersionedAccount>::VERSION {
+                    return Err(solana_program::program_error::ProgramError::InvalidAccountData)
+                }
+
                 #fields_split
 
                 Ok(Self { #field_idents })
@@ -510,6 +521,10 @@ pub fn impl_elusiv_account(ast: &syn::DeriveInput, attrs: TokenStream) -> TokenS
             const SIZE: usize = #account_size;
         }
 
+        impl < #lifetimes > elusiv_types::accounts::VersionedAccount for #ident < #lifetimes > {
+            const VERSION: u8 = #version;
+        }
+
         // Test to verify the account to be of valid PDA-size (10 KiB)
         #[cfg(test)]
         mod #account_size_test {
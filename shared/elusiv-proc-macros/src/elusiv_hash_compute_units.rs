@@ -40,6 +40,11 @@ pub fn impl_elusiv_hash_compute_units(attrs: TokenStream) -> TokenStream {
         });
     }
 
+    let round_compute_units = rounds.iter().fold(quote! {}, |acc, &cus| {
+        let cus: TokenStream = cus.to_string().parse().unwrap();
+        quote! { #acc #cus, }
+    });
+
     let max_compute_budget = MAX_COMPUTE_UNIT_LIMIT - COMPUTE_UNIT_PADDING - reduction.unwrap_or(0);
     let result = compute_unit_optimization(rounds, max_compute_budget);
 
@@ -61,7 +66,29 @@ pub fn impl_elusiv_hash_compute_units(attrs: TokenStream) -> TokenStream {
             const INSTRUCTION_ROUNDS: [u8; #size] = [ #instructions ];
             const TOTAL_ROUNDS: u32 = #total_rounds;
             const TOTAL_COMPUTE_UNITS: u32 = #total_compute_units;
+            const ROUND_COMPUTE_UNITS: &'static [u32] = &[ #round_compute_units ];
             const COMPUTE_BUDGET_PER_IX: u32 = #max_cus;
         }
     }
 }
+
+pub fn impl_elusiv_commitment_hash_compute_units(attrs: TokenStream) -> TokenStream {
+    let attrs = sub_attrs_prepare(attrs.to_string());
+    let attrs: Vec<&str> = attrs.split(',').collect();
+
+    let id = attrs[0];
+    let mt_height: usize = attrs[1].parse().unwrap();
+    let max_batching_rate: usize = attrs[2].parse().unwrap();
+    assert!(max_batching_rate <= mt_height);
+
+    (0..=max_batching_rate).fold(quote! {}, |acc, batching_rate| {
+        // The hash-sub-tree of the batch followed by the hashes from its root to the MT-root
+        let hashes = (1 << batching_rate) - 1 + mt_height - batching_rate;
+        let computation = impl_elusiv_hash_compute_units(
+            format!("{}<{}>, {}", id, batching_rate, hashes)
+                .parse()
+                .unwrap(),
+        );
+        quote! { #acc #computation }
+    })
+}
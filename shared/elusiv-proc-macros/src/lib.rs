@@ -8,7 +8,9 @@ mod repeat;
 mod utils;
 
 use elusiv_account::impl_elusiv_account;
-use elusiv_hash_compute_units::impl_elusiv_hash_compute_units;
+use elusiv_hash_compute_units::{
+    impl_elusiv_commitment_hash_compute_units, impl_elusiv_hash_compute_units,
+};
 use parse_tokens::impl_parse_tokens;
 use program_id::{impl_declare_program_id, impl_program_id};
 use repeat::impl_repeat;
@@ -18,7 +20,8 @@ use syn::{parse_macro_input, DeriveInput};
 ///
 /// # Notes
 ///
-/// Automatically also derives [`elusiv_types::PDAAccount`]
+/// - Automatically also derives [`elusiv_types::PDAAccount`].
+/// - Implements [`elusiv_types::VersionedAccount`] with the layout version `version: <u8>` (defaults to zero).
 #[proc_macro_attribute]
 pub fn elusiv_account(
     args: proc_macro::TokenStream,
@@ -37,6 +40,18 @@ pub fn elusiv_hash_compute_units(input: proc_macro::TokenStream) -> proc_macro::
     impl_elusiv_hash_compute_units(input.into()).into()
 }
 
+/// Creates `elusiv_computation::PartialComputation` implementations of `Name<BATCHING_RATE>` for the commitment hashes of a MT
+///
+/// # Usage
+/// - `elusiv_commitment_hash_compute_units!(<name>, <MT_HEIGHT>, <MAX_BATCHING_RATE>)`
+/// - every batching rate in `[0; MAX_BATCHING_RATE]` computes `2^BATCHING_RATE - 1 + MT_HEIGHT - BATCHING_RATE` hashes
+#[proc_macro]
+pub fn elusiv_commitment_hash_compute_units(
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    impl_elusiv_commitment_hash_compute_units(input.into()).into()
+}
+
 /// Repeates an expression count times
 ///
 /// # Usage
@@ -69,7 +84,7 @@ pub fn elusiv_tokens(_: proc_macro::TokenStream) -> proc_macro::TokenStream {
 /// ```
 #[proc_macro]
 pub fn program_id(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    impl_program_id(input.to_string()).into()
+    impl_program_id(program_name(input)).into()
 }
 
 /// Parses `Id.toml` and implements [`solana_program::declare_id`]
@@ -87,5 +102,10 @@ pub fn program_id(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 /// ```
 #[proc_macro]
 pub fn declare_program_id(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    impl_declare_program_id(input.to_string()).into()
+    impl_declare_program_id(program_name(input)).into()
+}
+
+/// Program names containing hyphens are tokenized as multiple idents and puncts
+fn program_name(input: proc_macro::TokenStream) -> String {
+    input.to_string().split_whitespace().collect()
 }
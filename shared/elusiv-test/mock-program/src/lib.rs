@@ -1,8 +1,121 @@
-use solana_program::{pubkey::Pubkey, account_info::AccountInfo, entrypoint::ProgramResult};
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
 
 #[cfg(not(feature = "no-entrypoint"))]
 solana_program::entrypoint!(process_instruction);
 
-pub fn process_instruction(_: &Pubkey, _: &[AccountInfo], _: &[u8]) -> ProgramResult {
+/// If the mock program is deployed with this id, it forwards instructions using `invoke_signed`
+pub const CPI_MOCK_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
+    32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17, 16, 15, 14, 13, 12, 11, 10, 9,
+    8, 7, 6, 5, 4, 3, 2, 1,
+]);
+
+/// If the mock program is deployed with this id, it records the payload of recipient callbacks
+pub const CALLBACK_MOCK_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
+    64, 63, 62, 61, 60, 59, 58, 57, 56, 55, 54, 53, 52, 51, 50, 49, 48, 47, 46, 45, 44, 43, 42, 41,
+    40, 39, 38, 37, 36, 35, 34, 33,
+]);
+
+/// Any other id results in a no-op program
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    if *program_id == CALLBACK_MOCK_PROGRAM_ID {
+        return record_callback(program_id, accounts, data);
+    }
+
+    if *program_id != CPI_MOCK_PROGRAM_ID {
+        return Ok(());
+    }
+
+    let (program, forwarded_accounts) = accounts
+        .split_first()
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let (seed_len, data) = data
+        .split_first()
+        .ok_or(ProgramError::InvalidInstructionData)?;
+    if data.len() < *seed_len as usize {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let (seed, data) = data.split_at(*seed_len as usize);
+    let (signer, bump) = cpi_signer(seed);
+
+    let instruction = Instruction {
+        program_id: *program.key,
+        accounts: forwarded_accounts
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: *account.key,
+                is_signer: account.is_signer || *account.key == signer,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data: data.to_vec(),
+    };
+
+    invoke_signed(&instruction, accounts, &[&[seed, &[bump]]])
+}
+
+/// Writes the length-prefixed payload into the record account
+///
+/// # Notes
+///
+/// - Expects the callback authority (signer), the recipient and the record account (owned by the mock program).
+/// - An empty record account (or one that is too small) results in an error.
+fn record_callback(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    if accounts.len() < 3 {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    let authority = &accounts[0];
+    let record = &accounts[2];
+
+    if !authority.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if record.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    let mut record = record.try_borrow_mut_data()?;
+    if record.len() < data.len() + 1 {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    record[0] = data.len() as u8;
+    record[1..=data.len()].copy_from_slice(data);
+
     Ok(())
-}
\ No newline at end of file
+}
+
+/// The PDA of the mock program that signs the forwarded instruction
+pub fn cpi_signer(seed: &[u8]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[seed], &CPI_MOCK_PROGRAM_ID)
+}
+
+/// Wraps `instruction` so that it's invoked by the mock program with the PDA of `seed` as signer
+pub fn cpi_instruction(seed: &[u8], instruction: Instruction) -> Instruction {
+    let (signer, _) = cpi_signer(seed);
+
+    let mut accounts = vec![AccountMeta::new_readonly(instruction.program_id, false)];
+    accounts.extend(instruction.accounts.into_iter().map(|account| AccountMeta {
+        is_signer: account.is_signer && account.pubkey != signer,
+        ..account
+    }));
+
+    let mut data = vec![seed.len() as u8];
+    data.extend(seed);
+    data.extend(instruction.data);
+
+    Instruction {
+        program_id: CPI_MOCK_PROGRAM_ID,
+        accounts,
+        data,
+    }
+}
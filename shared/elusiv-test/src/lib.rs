@@ -5,8 +5,8 @@ use elusiv_types::tokens::{
     elusiv_token, pyth_price_account_data, Lamports, Price, SPLToken, Token, TOKENS,
 };
 use elusiv_types::{
-    EagerAccount, EagerAccountRepr, PDAAccount, PDAOffset, ParentAccount, SizedAccount,
-    UserAccount, WritableUserAccount,
+    EagerAccount, EagerAccountRepr, PDAAccount, PDAAccountData, PDAOffset, ParentAccount,
+    SizedAccount, UserAccount, VersionedAccount, WritableUserAccount,
 };
 use solana_program::program_pack::Pack;
 use solana_program::{
@@ -124,6 +124,33 @@ impl ElusivProgramTest {
             .await
     }
 
+    /// Simulates a transaction and returns its logs, without committing the transaction
+    ///
+    /// # Notes
+    ///
+    /// The preflight simulation only returns the logs of failing transactions.
+    /// So we append an instruction that always fails (transferring more lamports than exist).
+    pub async fn simulate_transaction_logs(
+        &mut self,
+        instructions: &[Instruction],
+        signing_keypairs: &[&Keypair],
+    ) -> Vec<String> {
+        let mut instructions = instructions.to_vec();
+        instructions.push(system_instruction::transfer(
+            &self.payer(),
+            &Pubkey::new_unique(),
+            u64::MAX,
+        ));
+
+        match self
+            .process_transaction(&instructions, signing_keypairs)
+            .await
+        {
+            Err(BanksClientError::SimulationError { logs, .. }) => logs,
+            _ => panic!("Transaction simulation did not return any logs"),
+        }
+    }
+
     pub fn context(&mut self) -> &mut ProgramTestContext {
         &mut self.context
     }
@@ -228,6 +255,47 @@ impl ElusivProgramTest {
             .await;
     }
 
+    /// Mocks the `ApaTargetMapAccount` and `ApaProposalAccount` of the elusiv-warden-network for `target`
+    pub async fn set_apa_proposal(
+        &mut self,
+        warden_network_program_id: &Pubkey,
+        target: &Pubkey,
+        proposal_id: u32,
+        level: MockApaLevel,
+        token_constraint: Option<u16>,
+    ) {
+        let (target_map, target_map_bump) = Pubkey::find_program_address(
+            &[b"ApaTargetMap", &target.to_bytes()],
+            warden_network_program_id,
+        );
+        let (proposal, proposal_bump) = Pubkey::find_program_address(
+            &[b"ApaProposal", &proposal_id.to_le_bytes()],
+            warden_network_program_id,
+        );
+
+        let mut data = vec![target_map_bump, 0, 1];
+        data.extend(proposal_id.to_le_bytes());
+        self.set_account_rent_exempt(&target_map, &data, warden_network_program_id)
+            .await;
+
+        let mut data = vec![proposal_bump, 0];
+        data.extend(self.payer().to_bytes()); // proponent
+        data.push(0); // proponent_role
+        data.extend(0u64.to_le_bytes()); // timestamp
+        data.extend(target.to_bytes());
+        data.push(level as u8);
+        match token_constraint {
+            Some(token_id) => {
+                data.push(1);
+                data.extend(token_id.to_le_bytes());
+            }
+            None => data.extend([0; 3]),
+        }
+        data.extend([0; 8 + 512]); // reason
+        self.set_account_rent_exempt(&proposal, &data, warden_network_program_id)
+            .await;
+    }
+
     pub fn token_to_usd_price_pyth_account(&mut self, token_id: u16) -> Pubkey {
         TOKENS[token_id as usize].pyth_usd_price_key
     }
@@ -532,6 +600,36 @@ impl ElusivProgramTest {
             .await;
     }
 
+    /// Stores the `fixture` data of the previous version of `A` in the PDA and migrates it using `migrate_ix`
+    ///
+    /// # Note
+    ///
+    /// Returns the migrated account data.
+    pub async fn migrate_fixture<A: SizedAccount + PDAAccount + VersionedAccount>(
+        &mut self,
+        program_id: &Pubkey,
+        pda_pubkey: Option<Pubkey>,
+        pda_offset: PDAOffset,
+        fixture: &[u8],
+        migrate_ix: Instruction,
+    ) -> Vec<u8> {
+        assert_eq!(
+            PDAAccountData::new(fixture).unwrap().version + 1,
+            A::VERSION
+        );
+
+        let address = A::find_with_pubkey_optional(pda_pubkey, pda_offset).0;
+        self.set_program_account_rent_exempt(program_id, &address, fixture)
+            .await;
+        self.ix_should_succeed_simple(migrate_ix).await;
+
+        let data = self.data(&address).await;
+        assert_eq!(data.len(), A::SIZE);
+        assert_eq!(PDAAccountData::new(&data).unwrap().version, A::VERSION);
+
+        data
+    }
+
     pub async fn child_accounts<'a, P: ParentAccount<'a, 'a, 'a> + PDAAccount>(
         &mut self,
         data: &'a mut [u8],
@@ -568,6 +666,15 @@ impl ElusivProgramTest {
     }
 }
 
+/// Mirrors `elusiv_warden_network::apa::ApaLevel`
+#[repr(u8)]
+#[derive(Clone, Copy)]
+pub enum MockApaLevel {
+    Flag1,
+    Flag2,
+    Outcast,
+}
+
 pub fn user_accounts(pubkeys: &[Pubkey]) -> Vec<UserAccount> {
     pubkeys.iter().map(|p| UserAccount(*p)).collect()
 }
@@ -327,6 +327,30 @@ pub trait PDAAccount {
     }
 }
 
+/// An account whose data layout is versioned through [`PDAAccountData::version`]
+///
+/// # Note
+///
+/// Loading an account with a different version results in an error, older accounts need to be migrated first.
+pub trait VersionedAccount {
+    /// The current version of the data layout
+    const VERSION: u8;
+}
+
+/// A [`VersionedAccount`] whose data can be migrated from previous versions of its layout
+pub trait MigratableAccount: VersionedAccount + SizedAccount {
+    /// Returns the size of the account data at `version` (or [`None`] if the layout never existed)
+    fn size_at_version(version: u8) -> Option<usize>;
+
+    /// Transforms `data` from the layout at `version` into the layout at `version + 1`
+    ///
+    /// # Notes
+    ///
+    /// - `data` is large enough to hold both layouts, trailing bytes not used by the layout at `version` are zeroed.
+    /// - The [`PDAAccountData::version`] is updated by the caller.
+    fn migrate_from(version: u8, data: &mut [u8]) -> ProgramResult;
+}
+
 pub trait ComputationAccount: PDAAccount {
     fn instruction(&self) -> u32;
     fn round(&self) -> u32;
@@ -337,11 +361,14 @@ pub trait ComputationAccount: PDAAccount {
 pub struct PDAAccountData {
     pub bump_seed: u8,
 
-    /// Used for future account migrations
+    /// The layout version of the account data (see [`VersionedAccount`])
     pub version: u8,
 }
 
 impl PDAAccountData {
+    /// The offset of [`PDAAccountData::version`] in the account data
+    pub const VERSION_OFFSET: usize = 1;
+
     pub fn new(data: &[u8]) -> Result<Self, std::io::Error> {
         PDAAccountData::try_from_slice(&data[..Self::SIZE])
     }
@@ -1,8 +1,40 @@
 use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::{bytes_of, pod_read_unaligned, Pod};
 use solana_program::pubkey::Pubkey;
 
+/// A type with a fixed-size Borsh representation
+///
+/// # Note
+///
+/// [`BorshSerDeSized::read_sized`] and [`BorshSerDeSized::write_sized`] default to Borsh.
+/// Fixed-layout POD types override them with zero-copy implementations that use the same byte layout.
 pub trait BorshSerDeSized: BorshSerialize + BorshDeserialize {
     const SIZE: usize;
+
+    /// Deserializes a value from the first [`BorshSerDeSized::SIZE`] bytes of `data`
+    fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+        Self::try_from_slice(&data[..Self::SIZE])
+    }
+
+    /// Serializes the value into the first [`BorshSerDeSized::SIZE`] bytes of `data`
+    fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+        let mut slice = &mut data[..Self::SIZE];
+        BorshSerialize::serialize(self, &mut slice)
+    }
+}
+
+// The zero-copy representations equal the (little-endian) Borsh representations only on little-endian targets
+#[cfg(not(target_endian = "little"))]
+compile_error!("zero-copy (de)serialization requires a little-endian target");
+
+/// Reads a [`Pod`] value from the first `size_of::<T>()` bytes of `data` (without any alignment requirements)
+pub fn read_pod<T: Pod>(data: &[u8]) -> T {
+    pod_read_unaligned(&data[..std::mem::size_of::<T>()])
+}
+
+/// Writes a [`Pod`] value into the first `size_of::<T>()` bytes of `data`
+pub fn write_pod<T: Pod>(value: &T, data: &mut [u8]) {
+    data[..std::mem::size_of::<T>()].copy_from_slice(bytes_of(value))
 }
 
 pub trait SizedType {
@@ -42,15 +74,49 @@ macro_rules! impl_borsh_sized {
     };
 }
 
+/// Implements [`BorshSerDeSized`] with zero-copy (de)serialization for a [`Pod`] type
+#[macro_export]
+macro_rules! impl_borsh_sized_zero_copy {
+    ($ty: ty) => {
+        impl BorshSerDeSized for $ty {
+            const SIZE: usize = std::mem::size_of::<$ty>();
+
+            fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+                Ok($crate::bytes::read_pod(data))
+            }
+
+            fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+                $crate::bytes::write_pod(self, data);
+                Ok(())
+            }
+        }
+    };
+}
+
 impl<E: BorshSerDeSized + Default + Copy, const N: usize> BorshSerDeSized for [E; N] {
     const SIZE: usize = E::SIZE * N;
+
+    fn read_sized(data: &[u8]) -> std::io::Result<Self> {
+        let mut v = [E::default(); N];
+        for (i, e) in v.iter_mut().enumerate() {
+            *e = E::read_sized(&data[i * E::SIZE..])?;
+        }
+        Ok(v)
+    }
+
+    fn write_sized(&self, data: &mut [u8]) -> std::io::Result<()> {
+        for (i, e) in self.iter().enumerate() {
+            e.write_sized(&mut data[i * E::SIZE..])?;
+        }
+        Ok(())
+    }
 }
 
-impl_borsh_sized!(u8, 1);
-impl_borsh_sized!(u16, 2);
-impl_borsh_sized!(u32, 4);
-impl_borsh_sized!(u64, 8);
-impl_borsh_sized!(u128, 16);
+impl_borsh_sized_zero_copy!(u8);
+impl_borsh_sized_zero_copy!(u16);
+impl_borsh_sized_zero_copy!(u32);
+impl_borsh_sized_zero_copy!(u64);
+impl_borsh_sized_zero_copy!(u128);
 
 impl_borsh_sized!(bool, 1);
 impl_borsh_sized!(std::net::Ipv4Addr, 4);
@@ -1,12 +1,18 @@
 pub mod macros;
 
 use elusiv_types::{
-    accounts::{PDAAccount, PDAAccountData, SizedAccount},
+    accounts::{MigratableAccount, PDAAccount, PDAAccountData, SizedAccount, VersionedAccount},
     PDAOffset,
 };
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, program::invoke_signed,
-    program_error::ProgramError, pubkey::Pubkey, rent::Rent, system_instruction, sysvar::Sysvar,
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+    sysvar::Sysvar,
 };
 
 #[cfg(feature = "sdk")]
@@ -24,7 +30,7 @@ macro_rules! signers_seeds {
     };
 }
 
-pub fn open_pda_account_with_offset<'a, T: PDAAccount + SizedAccount>(
+pub fn open_pda_account_with_offset<'a, T: PDAAccount + VersionedAccount + SizedAccount>(
     program_id: &Pubkey,
     payer: &AccountInfo<'a>,
     pda_account: &AccountInfo<'a>,
@@ -42,7 +48,7 @@ pub fn open_pda_account_with_offset<'a, T: PDAAccount + SizedAccount>(
     )
 }
 
-pub fn open_pda_account_without_offset<'a, T: PDAAccount + SizedAccount>(
+pub fn open_pda_account_without_offset<'a, T: PDAAccount + VersionedAccount + SizedAccount>(
     program_id: &Pubkey,
     payer: &AccountInfo<'a>,
     pda_account: &AccountInfo<'a>,
@@ -51,7 +57,10 @@ pub fn open_pda_account_without_offset<'a, T: PDAAccount + SizedAccount>(
     open_pda_account::<T>(program_id, payer, pda_account, None, None, bump, T::SIZE)
 }
 
-pub fn open_pda_account_with_associated_pubkey<'a, T: PDAAccount + SizedAccount>(
+pub fn open_pda_account_with_associated_pubkey<
+    'a,
+    T: PDAAccount + VersionedAccount + SizedAccount,
+>(
     program_id: &Pubkey,
     payer: &AccountInfo<'a>,
     pda_account: &AccountInfo<'a>,
@@ -70,7 +79,7 @@ pub fn open_pda_account_with_associated_pubkey<'a, T: PDAAccount + SizedAccount>
     )
 }
 
-pub fn open_pda_account<'a, T: PDAAccount>(
+pub fn open_pda_account<'a, T: PDAAccount + VersionedAccount>(
     program_id: &Pubkey,
     payer: &AccountInfo<'a>,
     pda_account: &AccountInfo<'a>,
@@ -103,6 +112,7 @@ pub fn open_pda_account<'a, T: PDAAccount>(
         pda_account,
         account_size,
         bump,
+        T::VERSION,
         &signers_seeds,
     )
 }
@@ -113,6 +123,7 @@ pub fn create_pda_account<'a>(
     pda_account: &AccountInfo<'a>,
     account_size: usize,
     bump: u8,
+    version: u8,
     signers_seeds: &[&[u8]],
 ) -> ProgramResult {
     // We require the test-unit feature since cfg!(test) does not work in deps
@@ -144,7 +155,7 @@ pub fn create_pda_account<'a>(
     borsh::BorshSerialize::serialize(
         &PDAAccountData {
             bump_seed: bump,
-            version: 0,
+            version,
         },
         &mut data,
     )?;
@@ -152,6 +163,84 @@ pub fn create_pda_account<'a>(
     Ok(())
 }
 
+/// Migrates the data of `account` to the current [`VersionedAccount::VERSION`] (one version at a time)
+///
+/// # Notes
+///
+/// - The caller has to verify that `account` is a PDA of type `T`.
+/// - `payer` funds the additional rent of a growing account (lamports of a shrinking account remain in the account).
+/// - A single migration step can only grow an account by [`solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE`].
+pub fn migrate_pda_account<'a, T: MigratableAccount>(
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+) -> ProgramResult {
+    let mut version = PDAAccountData::new(&account.data.borrow())?.version;
+    guard!(version < T::VERSION, ProgramError::InvalidAccountData);
+
+    while version < T::VERSION {
+        let size = T::size_at_version(version).ok_or(ProgramError::InvalidAccountData)?;
+        let next_size = T::size_at_version(version + 1).ok_or(ProgramError::InvalidAccountData)?;
+        guard!(account.data_len() == size, ProgramError::InvalidAccountData);
+
+        if next_size > size {
+            account.realloc(next_size, true)?;
+        }
+
+        T::migrate_from(version, &mut account.data.borrow_mut()[..])?;
+
+        if next_size < size {
+            account.realloc(next_size, false)?;
+        }
+
+        version += 1;
+        account.data.borrow_mut()[PDAAccountData::VERSION_OFFSET] = version;
+    }
+
+    fund_rent_exemption(payer, account, T::SIZE)
+}
+
+/// Reallocates a program owned account to a larger `size` (the additional data is zero-initialized)
+///
+/// # Notes
+///
+/// - `payer` funds the additional rent.
+/// - An account can only grow by [`solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE`] per instruction.
+pub fn grow_account<'a>(
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    size: usize,
+) -> ProgramResult {
+    guard!(account.data_len() < size, ProgramError::InvalidAccountData);
+
+    account.realloc(size, true)?;
+    fund_rent_exemption(payer, account, size)
+}
+
+/// Transfers the lamports missing for the rent-exemption of an account with `size` from `payer`
+fn fund_rent_exemption<'a>(
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    size: usize,
+) -> ProgramResult {
+    if cfg!(feature = "test-unit") {
+        return Ok(());
+    }
+
+    let lamports_required = Rent::get()?.minimum_balance(size);
+    if account.lamports() < lamports_required {
+        invoke(
+            &system_instruction::transfer(
+                payer.key,
+                account.key,
+                lamports_required - account.lamports(),
+            ),
+            &[payer.clone(), account.clone()],
+        )?;
+    }
+
+    Ok(())
+}
+
 pub fn transfer_with_system_program<'a>(
     source: &AccountInfo<'a>,
     destination: &AccountInfo<'a>,